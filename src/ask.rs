@@ -0,0 +1,46 @@
+use std::io::{self, IsTerminal, Read, Write};
+
+use crate::chat::ChatContext;
+use crate::error::Result;
+
+/// Sends `question`, with whatever is piped into stdin after it, to the
+/// model from the config or `--model`, and prints the answer as it streams
+/// in. Nothing of the editor is started.
+pub fn run(question: Option<&str>) -> Result<()> {
+    let mut prompt = question.unwrap_or_default().to_string();
+
+    let stdin = io::stdin();
+    if !stdin.is_terminal() {
+        let mut piped = String::new();
+        stdin.lock().read_to_string(&mut piped)?;
+        if !piped.trim().is_empty() {
+            if !prompt.is_empty() {
+                prompt.push_str("\n\n");
+            }
+            prompt.push_str(&piped);
+        }
+    }
+    if prompt.trim().is_empty() {
+        return Err("Nothing to ask, pass a question or pipe one in".into());
+    }
+
+    let chat_context = ChatContext::new()?;
+    let model = chat_context.model.clone();
+    let runtime = tokio::runtime::Runtime::new()?;
+
+    let mut stdout = io::stdout();
+    let mut ends_with_newline = false;
+    runtime.block_on(chat_context.stream_to_api(model, &prompt, |chunk| {
+        // A closed pipe, e.g. `| head`, just stops the output
+        let _ = stdout.write_all(chunk.as_bytes());
+        let _ = stdout.flush();
+        if !chunk.is_empty() {
+            ends_with_newline = chunk.ends_with('\n');
+        }
+    }))?;
+
+    if !ends_with_newline {
+        let _ = writeln!(stdout);
+    }
+    Ok(())
+}
@@ -0,0 +1,127 @@
+use chrono::{DateTime, Local};
+use std::collections::VecDeque;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::error::Error;
+
+// Entries kept for the log viewer, the file keeps everything
+const MAX_ENTRIES: usize = 500;
+const LOG_FILE: &str = "rusty_ai_error.log";
+
+#[derive(Debug, Clone)]
+pub enum LogEvent {
+    Sent { chars: usize, stream: bool },
+    Done { latency: Duration, chars: usize },
+    Failed {
+        latency: Duration,
+        status: Option<u16>,
+        error: String,
+    },
+    Cancelled { latency: Duration },
+}
+
+/// One line of the request log
+#[derive(Debug, Clone)]
+pub struct LogEntry {
+    pub time: DateTime<Local>,
+    pub request: u64,
+    pub model: String,
+    pub event: LogEvent,
+}
+
+impl LogEntry {
+    /// e.g. `14:02:11 #3 ollama/gemma3 done 2.41s, 812 chars`
+    pub fn line(&self) -> String {
+        let event = match &self.event {
+            LogEvent::Sent { chars, stream } => {
+                let mode = if *stream { "streaming" } else { "sent" };
+                format!("{} {} chars", mode, chars)
+            }
+            LogEvent::Done { latency, chars } => {
+                format!("done {:.2}s, {} chars", latency.as_secs_f64(), chars)
+            }
+            LogEvent::Failed {
+                latency,
+                status,
+                error,
+            } => {
+                let status = status.map(|s| format!("HTTP {} ", s)).unwrap_or_default();
+                format!("failed {}{:.2}s: {}", status, latency.as_secs_f64(), error)
+            }
+            LogEvent::Cancelled { latency } => {
+                format!("cancelled after {:.2}s", latency.as_secs_f64())
+            }
+        };
+
+        format!(
+            "{} #{} {} {}",
+            self.time.format("%H:%M:%S"),
+            self.request,
+            self.model,
+            event
+        )
+    }
+
+    pub fn is_error(&self) -> bool {
+        matches!(self.event, LogEvent::Failed { .. })
+    }
+}
+
+/// Ring buffer of request events, shared with the request threads. Every
+/// entry is also appended to the error log file.
+#[derive(Debug, Clone, Default)]
+pub struct RequestLog {
+    entries: Arc<Mutex<VecDeque<LogEntry>>>,
+}
+
+impl RequestLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&self, request: u64, model: &str, event: LogEvent) {
+        let entry = LogEntry {
+            time: Local::now(),
+            request,
+            model: model.to_string(),
+            event,
+        };
+
+        // Best effort, the viewer still has the entry
+        if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(LOG_FILE) {
+            let date = entry.time.format("%Y-%m-%d");
+            let _ = writeln!(file, "{} {}", date, entry.line());
+        }
+
+        if let Ok(mut entries) = self.entries.lock() {
+            if entries.len() == MAX_ENTRIES {
+                entries.pop_front();
+            }
+            entries.push_back(entry);
+        }
+    }
+
+    /// Oldest first
+    pub fn entries(&self) -> Vec<LogEntry> {
+        match self.entries.lock() {
+            Ok(entries) => entries.iter().cloned().collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+/// HTTP status of a failed request, when the error carries one. genai only
+/// puts it in its debug output, e.g. `ResponseFailedStatus { status: 401, .. }`.
+pub fn http_status(error: &Error) -> Option<u16> {
+    if let Error::Http(e) = error {
+        return e.status().map(|s| s.as_u16());
+    }
+
+    let debug = format!("{:?}", error);
+    let (_, rest) = debug.split_once("status: ")?;
+    let code: String = rest.chars().take_while(char::is_ascii_digit).collect();
+    code.parse().ok().filter(|code| (100..600).contains(code))
+}
@@ -1,26 +1,285 @@
+use crate::chat::models::{self, OllamaModel};
+use crate::chat::params::GenerationParams;
+use crate::chat::session;
+use crate::chat::trim;
+use crate::chat::usage::Usage;
 use crate::chat::{ChatContext, Model};
+use crate::config;
+use crate::editor::messages::{Level, Messages};
 use crate::editor::RequestState;
-use crate::error::Result;
+use crate::error::{Error, Result};
+use crate::files::encoding::{Decoder, Encoding};
+use crate::files::line_ending::{LineEnding, Normalizer};
+use crate::shell;
 use once_cell::sync::Lazy;
-use std::fs::OpenOptions;
-use std::io::Write;
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::path::PathBuf;
 use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
 use tokio::runtime::Runtime;
+use tokio_util::sync::CancellationToken;
+
+pub mod log;
+
+use log::{LogEvent, RequestLog};
 
 // Shared Tokio runtime
 static RUNTIME: Lazy<Runtime> =
     Lazy::new(|| Runtime::new().expect("Failed to create Tokio runtime"));
 
+// Finished requests kept for the status panel
+const MAX_FINISHED_REQUESTS: usize = 5;
+// Bytes read per step while loading a file
+const LOAD_CHUNK_SIZE: usize = 256 * 1024;
+// Read text the editor hasn't taken yet, reading pauses above it so a slow
+// frame doesn't leave the whole file in memory twice
+const MAX_PENDING_LOAD: usize = 4 * 1024 * 1024;
+// Wait before looking again whether the editor has caught up
+const LOAD_BACKOFF: Duration = Duration::from_millis(10);
+// Wait for the Ollama server to list its models
+const OLLAMA_TIMEOUT: Duration = Duration::from_secs(2);
+// Length limit of an inline completion, a few lines of code
+const COMPLETION_MAX_TOKENS: u32 = 128;
+
 pub struct AsyncCommandHandler {
     editor_state: Arc<Mutex<EditorState>>,
     chat_context: ChatContext,
+    // Cancels the in-flight request; replaced for every new request
+    cancel_token: CancellationToken,
+
+    // Requests run one at a time, so answers land in the order they were asked
+    queue: VecDeque<QueuedRequest>,
+    // Started and not yet released by the editor with `finish_current`
+    running: Option<u64>,
+    // Prompt of the running request when it went to a single model, so its
+    // answer can be regenerated
+    running_prompt: Option<String>,
+    // Model of the running request for the answer header
+    running_label: Option<String>,
+    // Buffer the running request came from
+    running_origin: Option<Origin>,
+    // When the running request started and how long it may take
+    running_since: Option<(Instant, Option<Duration>)>,
+    // The last request to a single model, sent again by `retry`
+    last_sent: Option<Retry>,
+    next_id: u64,
+    // Stops the file load in progress
+    load_cancel: CancellationToken,
+    // Kills the shell command in `EditorState::shell`
+    shell_cancel: CancellationToken,
+    // Sent, finished and failed requests for the log viewer
+    log: RequestLog,
+    // Token batches of the answer being streamed, at most one per frame
+    stream: Option<mpsc::Receiver<String>>,
+}
+
+struct QueuedRequest {
+    id: u64,
+    content: String,
+    model: Model,
+    // `provider/name`, as in the panel and the log
+    label: String,
+    stream: bool,
+    // Every model asked when the same question goes to several at once,
+    // the first one is `id` above
+    fan_out: Vec<FanOutTarget>,
+    // Messages dropped to fit the context window, summarized in front of
+    // `content` before it's sent
+    earlier: Option<String>,
+    origin: Origin,
+}
+
+// What's needed to send a request again after it timed out
+struct Retry {
+    content: String,
+    model: Model,
+    stream: bool,
+    earlier: Option<String>,
+    origin: Origin,
+}
+
+struct FanOutTarget {
+    id: u64,
+    chat_context: ChatContext,
+    label: String,
+}
+
+impl QueuedRequest {
+    fn ids(&self) -> Vec<u64> {
+        if self.fan_out.is_empty() {
+            vec![self.id]
+        } else {
+            self.fan_out.iter().map(|target| target.id).collect()
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RequestStatus {
+    Queued,
+    Running,
+    Done,
+    Cancelled,
+    Error(String),
+}
+
+/// One line of the request status panel
+#[derive(Debug, Clone)]
+pub struct RequestInfo {
+    pub id: u64,
+    pub model: String,
+    pub status: RequestStatus,
+    /// Buffer that asked, the answer goes there even when another one is
+    /// open by then
+    pub origin: Origin,
+}
+
+/// Buffer a request came from: its id, and its file for when the buffer
+/// is closed or holds another file by the time the answer comes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Origin {
+    pub buffer: u64,
+    pub file: String,
 }
 
 // Define a struct to hold shared editor state that can be accessed from async contexts
 pub struct EditorState {
     pub request_state: RequestState,
     pub api_response: Option<ApiResponse>,
+    // Queued, running and recently finished requests, oldest first
+    pub requests: Vec<RequestInfo>,
+    // Ollama model download in progress
+    pub pull: Option<PullProgress>,
+    // File being read into the buffer
+    pub load: Option<FileLoad>,
+    // Bottom line messages and their history
+    pub messages: Messages,
+    // Generated titles with the path of their session, not applied yet
+    pub titles: Vec<(PathBuf, String)>,
+    // Installed Ollama models, or why they couldn't be listed, not picked
+    // up yet
+    pub ollama_models: Option<std::result::Result<Vec<OllamaModel>, String>>,
+    // Inline completion with the id it was asked with, not shown yet
+    pub completion: Option<(u64, String)>,
+    // Dropped messages and their summary, reused until more are dropped
+    pub summary: Option<(String, String)>,
+    // Shell command whose output goes with the next prompt
+    pub shell: Option<ShellRun>,
+    // Estimated tokens and cost per provider, this run's and the month's
+    pub usage: Usage,
+}
+
+/// A shell command run in the background. `output` is set when it's done.
+#[derive(Debug, Clone)]
+pub struct ShellRun {
+    pub command: String,
+    pub output: Option<String>,
+    pub purpose: ShellPurpose,
+}
+
+/// What the output of a shell command is for
+#[derive(Debug, Clone)]
+pub enum ShellPurpose {
+    /// Attached to the next prompt
+    Attach,
+    /// `cargo check` whose errors are sent to be fixed
+    Fix,
+    /// Goes in as a fenced block at a char index of the buffer that ran it
+    Insert(Origin, usize),
+}
+
+/// A file read in the background. The editor moves `text` into the buffer
+/// as it arrives, so the UI stays responsive.
+#[derive(Debug, Clone)]
+pub struct FileLoad {
+    pub path: String,
+    pub loaded: u64,
+    pub total: u64,
+    // Read and not yet taken by the editor
+    pub text: String,
+    /// Detected from the first bytes read, Latin-1 once `restarted`
+    pub encoding: Encoding,
+    /// Line breaks of the text so far, which has `\n` only
+    pub line_ending: LineEnding,
+    /// The file is read again from the start in another encoding, the
+    /// text taken so far is dropped
+    pub restarted: bool,
+    pub finished: bool,
+    pub error: Option<String>,
+}
+
+impl FileLoad {
+    fn new(path: &str) -> Self {
+        Self {
+            path: path.to_string(),
+            loaded: 0,
+            total: 0,
+            text: String::new(),
+            encoding: Encoding::default(),
+            line_ending: LineEnding::default(),
+            restarted: false,
+            finished: false,
+            error: None,
+        }
+    }
+
+    /// e.g. `loading big.md 12.5 MB / 40.0 MB (31%)`
+    pub fn label(&self) -> String {
+        let percent = (self.loaded * 100).checked_div(self.total).unwrap_or(0);
+        format!(
+            "loading {} {} / {} ({}%)",
+            self.path,
+            format_bytes(self.loaded),
+            format_bytes(self.total),
+            percent
+        )
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Download state of an Ollama model, shown in the status line
+#[derive(Debug, Clone)]
+pub struct PullProgress {
+    pub model: String,
+    pub status: String,
+    pub completed: u64,
+    pub total: u64,
+}
+
+impl PullProgress {
+    fn new(model: &str) -> Self {
+        Self {
+            model: model.to_string(),
+            status: "starting".to_string(),
+            completed: 0,
+            total: 0,
+        }
+    }
+
+    /// e.g. `pulling gemma3:27b 42%`, or the server's status between layers
+    pub fn label(&self) -> String {
+        match (self.completed * 100).checked_div(self.total) {
+            Some(percent) => format!("pulling {} {}%", self.model, percent),
+            None => format!("pulling {}: {}", self.model, self.status),
+        }
+    }
 }
 
 pub struct ApiResponse {
@@ -33,11 +292,63 @@ impl EditorState {
         Self {
             request_state: RequestState::Idle,
             api_response: None,
+            requests: Vec::new(),
+            pull: None,
+            load: None,
+            messages: Messages::default(),
+            titles: Vec::new(),
+            ollama_models: None,
+            completion: None,
+            summary: None,
+            shell: None,
+            usage: Usage::load(),
         }
     }
 
+    /// Counts a finished request in the usage stats, with a warning when it
+    /// takes the month over budget
+    pub fn record_usage(&mut self, label: &str, sent: usize, received: usize) {
+        if let Some(warning) = self.usage.record(label, sent, received) {
+            self.messages.push(Level::Warning, warning);
+        }
+    }
+
+    /// Ends request `id` with `error`. A timeout gets a state of its own,
+    /// so the status line can offer a retry.
+    fn fail_request(&mut self, id: u64, error: &Error) {
+        let text = error.to_string();
+        self.request_state = match error {
+            Error::TimedOut(limit) => RequestState::TimedOut(limit.as_secs()),
+            _ => RequestState::Error(text.clone()),
+        };
+        self.messages.push(Level::Error, format!("Request failed: {}", text));
+        self.set_request_status(id, RequestStatus::Error(text.clone()));
+        self.api_response = Some(ApiResponse {
+            content: String::new(),
+            error: Some(text),
+        });
+    }
+
+    /// Shows `error` on the message line. The request state is left alone,
+    /// a running request keeps running.
     pub fn set_error(&mut self, error: String) {
-        self.request_state = RequestState::Error(error);
+        self.messages.push(Level::Error, error);
+    }
+
+    fn set_request_status(&mut self, id: u64, status: RequestStatus) {
+        if let Some(request) = self.requests.iter_mut().find(|r| r.id == id) {
+            request.status = status;
+        }
+
+        // Forget the oldest finished requests
+        let finished = |r: &RequestInfo| {
+            !matches!(r.status, RequestStatus::Queued | RequestStatus::Running)
+        };
+        while self.requests.iter().filter(|r| finished(r)).count() > MAX_FINISHED_REQUESTS {
+            if let Some(i) = self.requests.iter().position(finished) {
+                self.requests.remove(i);
+            }
+        }
     }
 }
 
@@ -46,6 +357,451 @@ impl AsyncCommandHandler {
         Self {
             editor_state,
             chat_context,
+            cancel_token: CancellationToken::new(),
+            queue: VecDeque::new(),
+            running: None,
+            running_prompt: None,
+            running_label: None,
+            running_origin: None,
+            running_since: None,
+            last_sent: None,
+            next_id: 1,
+            load_cancel: CancellationToken::new(),
+            shell_cancel: CancellationToken::new(),
+            log: RequestLog::new(),
+            stream: None,
+        }
+    }
+
+    /// Adds a request to the queue. It starts with `start_next` once the
+    /// ones before it are done. A summary of `earlier` goes in front of the
+    /// content when it's sent, the answer goes to the buffer of `origin`.
+    pub fn enqueue(
+        &mut self,
+        content: String,
+        model: Model,
+        stream: bool,
+        earlier: Option<String>,
+        origin: Origin,
+    ) {
+        // Early validation
+        if content.is_empty() {
+            if let Ok(mut state) = self.editor_state.lock() {
+                state.set_error("Cannot send empty buffer. Please write the question".to_owned());
+            }
+            return;
+        }
+
+        let id = self.next_id;
+        self.next_id += 1;
+        let label = format!("{}/{}", model.provider(), self.chat_context.model_name(&model));
+
+        if let Ok(mut state) = self.editor_state.lock() {
+            state.requests.push(RequestInfo {
+                id,
+                model: label.clone(),
+                status: RequestStatus::Queued,
+                origin: origin.clone(),
+            });
+        }
+
+        self.queue.push_back(QueuedRequest {
+            id,
+            content,
+            model,
+            label,
+            stream,
+            fan_out: Vec::new(),
+            earlier,
+            origin,
+        });
+    }
+
+    /// Queues one question for several `provider/name` models. They're asked
+    /// at the same time and their answers come back together, each under a
+    /// `## <model>` heading. Answers aren't streamed.
+    pub fn enqueue_fan_out(&mut self, content: String, entries: &[String], origin: Origin) -> Result<()> {
+        if content.is_empty() {
+            return Err("Cannot send empty buffer. Please write the question".into());
+        }
+
+        let mut targets = Vec::new();
+        for entry in entries {
+            let mut chat_context = self.chat_context.clone();
+            chat_context.select_model(entry)?;
+
+            targets.push(FanOutTarget {
+                id: self.next_id,
+                label: chat_context.model_entry(),
+                chat_context,
+            });
+            self.next_id += 1;
+        }
+        let Some(first) = targets.first() else {
+            return Err("No fan_out models in the config".into());
+        };
+
+        if let Ok(mut state) = self.editor_state.lock() {
+            for target in &targets {
+                state.requests.push(RequestInfo {
+                    id: target.id,
+                    model: target.label.clone(),
+                    status: RequestStatus::Queued,
+                    origin: origin.clone(),
+                });
+            }
+        }
+
+        self.queue.push_back(QueuedRequest {
+            id: first.id,
+            content,
+            model: first.chat_context.model.clone(),
+            label: first.label.clone(),
+            stream: false,
+            fan_out: targets,
+            earlier: None,
+            origin,
+        });
+        Ok(())
+    }
+
+    /// Starts the next queued request unless one is still running or its
+    /// answer wasn't released yet. Returns whether a request started.
+    pub fn start_next(&mut self) -> bool {
+        if self.running.is_some() {
+            return false;
+        }
+        let Some(request) = self.queue.pop_front() else {
+            return false;
+        };
+
+        self.running = Some(request.id);
+        self.running_prompt = request.fan_out.is_empty().then(|| request.content.clone());
+        self.running_label = Some(if request.fan_out.is_empty() {
+            request.label.clone()
+        } else {
+            "fan-out".to_string()
+        });
+        self.running_origin = Some(request.origin.clone());
+        let timeout = config::get().request_timeout(request.model.provider());
+        self.running_since = Some((Instant::now(), timeout));
+        self.last_sent = request.fan_out.is_empty().then(|| Retry {
+            content: request.content.clone(),
+            model: request.model.clone(),
+            stream: request.stream,
+            earlier: request.earlier.clone(),
+            origin: request.origin.clone(),
+        });
+        if let Ok(mut state) = self.editor_state.lock() {
+            for id in request.ids() {
+                state.set_request_status(id, RequestStatus::Running);
+            }
+        }
+
+        let chars = request.content.chars().count();
+        if request.fan_out.is_empty() {
+            let event = LogEvent::Sent {
+                chars,
+                stream: request.stream,
+            };
+            self.log.push(request.id, &request.label, event);
+        }
+        for target in &request.fan_out {
+            let event = LogEvent::Sent {
+                chars,
+                stream: false,
+            };
+            self.log.push(target.id, &target.label, event);
+        }
+
+        if !request.fan_out.is_empty() {
+            self.fan_out_to_api(request);
+        } else if request.stream {
+            self.stream_to_api(request);
+        } else {
+            self.send_to_api(request);
+        }
+
+        true
+    }
+
+    /// Called by the editor once it has applied everything the running
+    /// request produced, so the next one may start.
+    pub fn finish_current(&mut self) {
+        self.running = None;
+        self.running_prompt = None;
+        self.running_label = None;
+        self.running_origin = None;
+        self.running_since = None;
+        self.stream = None;
+    }
+
+    /// How long the running request has been going, and its timeout
+    pub fn running_since(&self) -> Option<(Duration, Option<Duration>)> {
+        self.running_since
+            .map(|(started, timeout)| (started.elapsed(), timeout))
+    }
+
+    /// Queues the last request to a single model again, e.g. after it
+    /// timed out. Returns whether there was one.
+    pub fn retry(&mut self) -> bool {
+        let Some(retry) = self.last_sent.take() else {
+            return false;
+        };
+        self.enqueue(retry.content, retry.model, retry.stream, retry.earlier, retry.origin);
+        true
+    }
+
+    /// Prompt of the running request, unless it went to several models
+    pub fn running_prompt(&self) -> Option<&str> {
+        self.running_prompt.as_deref()
+    }
+
+    /// `provider/name` of the running request, `fan-out` for several models
+    pub fn running_label(&self) -> Option<&str> {
+        self.running_label.as_deref()
+    }
+
+    /// Buffer the running request came from
+    pub fn running_origin(&self) -> Option<&Origin> {
+        self.running_origin.as_ref()
+    }
+
+    /// Whether an answer is being streamed and not yet released
+    pub fn is_streaming(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    /// Streamed text that arrived since the last call
+    pub fn stream_batches(&self) -> String {
+        match &self.stream {
+            Some(stream) => stream.try_iter().collect(),
+            None => String::new(),
+        }
+    }
+
+    pub fn has_pending(&self) -> bool {
+        self.running.is_some() || !self.queue.is_empty()
+    }
+
+    pub fn log(&self) -> &RequestLog {
+        &self.log
+    }
+
+    /// Replaces the context used by future requests, e.g. after picking a model.
+    pub fn set_chat_context(&mut self, chat_context: ChatContext) {
+        self.chat_context = chat_context;
+    }
+
+    /// Looks up the local Ollama models with size and quantization in the
+    /// background. The list, or why there's none, goes to
+    /// `EditorState::ollama_models`.
+    pub fn fetch_ollama_models(&self) {
+        let state_ref = Arc::clone(&self.editor_state);
+
+        RUNTIME.spawn(async move {
+            let result = tokio::time::timeout(OLLAMA_TIMEOUT, models::ollama_model_details()).await;
+            let models = match result {
+                Ok(Ok(models)) => Ok(models),
+                Ok(Err(e)) => Err(e.to_string()),
+                Err(_) => Err("Ollama did not answer in time".to_string()),
+            };
+
+            if let Ok(mut state) = state_ref.lock() {
+                state.ollama_models = Some(models);
+            }
+        });
+    }
+
+    /// Asks the current model for a title of the session at `path`, in the
+    /// background and outside the request queue
+    pub fn generate_title(&self, path: PathBuf, conversation: &str) {
+        let mut chat_context = self.chat_context.clone();
+        chat_context.system_prompt = session::TITLE_SYSTEM_PROMPT.to_string();
+        // A stop sequence or low max tokens of the user's could cut it off
+        chat_context.params = GenerationParams::default();
+        let model = chat_context.model.clone();
+        let prompt = session::title_prompt(conversation);
+        let state_ref = Arc::clone(&self.editor_state);
+
+        thread::spawn(move || {
+            let result = RUNTIME.block_on(chat_context.send_to_api(model, &prompt));
+
+            if let Ok(mut state) = state_ref.lock() {
+                match result {
+                    Ok(title) => state.titles.push((path, title)),
+                    Err(e) => state
+                        .messages
+                        .push(Level::Warning, format!("Can't generate a title: {}", e)),
+                }
+            }
+        });
+    }
+
+    /// Asks the current model for a short inline completion, in the
+    /// background and outside the request queue. The answer replaces any
+    /// earlier one in `EditorState::completion`.
+    pub fn complete(&self, id: u64, system_prompt: &str, prompt: String) {
+        let mut chat_context = self.chat_context.clone();
+        chat_context.system_prompt = system_prompt.to_string();
+        chat_context.params = GenerationParams {
+            max_tokens: Some(COMPLETION_MAX_TOKENS),
+            ..GenerationParams::default()
+        };
+        let model = chat_context.model.clone();
+        let state_ref = Arc::clone(&self.editor_state);
+
+        thread::spawn(move || {
+            let result = RUNTIME.block_on(chat_context.send_to_api(model, &prompt));
+
+            if let Ok(mut state) = state_ref.lock() {
+                match result {
+                    Ok(text) => state.completion = Some((id, text)),
+                    Err(e) => state
+                        .messages
+                        .push(Level::Warning, format!("Can't complete: {}", e)),
+                }
+            }
+        });
+    }
+
+    /// Downloads an Ollama model in the background, one at a time. Progress
+    /// is kept in `EditorState::pull` until it's done.
+    pub fn pull_model(&self, name: String) {
+        if let Ok(mut state) = self.editor_state.lock() {
+            if let Some(pull) = &state.pull {
+                let error = format!("Still pulling {}", pull.model);
+                state.set_error(error);
+                return;
+            }
+            state.pull = Some(PullProgress::new(&name));
+        }
+
+        let state_ref = Arc::clone(&self.editor_state);
+
+        RUNTIME.spawn(async move {
+            let progress_ref = Arc::clone(&state_ref);
+            let result = models::pull_ollama_model(&name, |status, completed, total| {
+                if let Ok(mut state) = progress_ref.lock() {
+                    if let Some(pull) = &mut state.pull {
+                        pull.status = status.to_string();
+                        pull.completed = completed.unwrap_or(0);
+                        pull.total = total.unwrap_or(0);
+                    }
+                }
+            })
+            .await;
+
+            if let Ok(mut state) = state_ref.lock() {
+                state.pull = None;
+                if let Err(e) = result {
+                    state.set_error(format!("Can't pull {}: {}", name, e));
+                }
+            }
+        });
+    }
+
+    /// Runs a shell command in the background, one at a time. Its output is
+    /// kept in `EditorState::shell` until the editor picks it up.
+    pub fn run_shell(&mut self, command: String, purpose: ShellPurpose) {
+        if let Ok(mut state) = self.editor_state.lock() {
+            if let Some(run) = &state.shell {
+                let error = format!("Still running `{}`", run.command);
+                state.set_error(error);
+                return;
+            }
+            state.shell = Some(ShellRun {
+                command: command.clone(),
+                output: None,
+                purpose,
+            });
+        }
+
+        // The output of a command still running would land in this one's place
+        self.shell_cancel.cancel();
+        self.shell_cancel = CancellationToken::new();
+        let cancel_token = self.shell_cancel.clone();
+        let state_ref = Arc::clone(&self.editor_state);
+        RUNTIME.spawn(async move {
+            let Some(output) = shell::run(&command, &cancel_token).await else {
+                return;
+            };
+
+            if let Ok(mut state) = state_ref.lock() {
+                if let Some(run) = &mut state.shell {
+                    run.output = Some(output);
+                }
+            }
+        });
+    }
+
+    /// Kills the running shell command, its output is dropped. Returns the
+    /// command, if one ran.
+    pub fn cancel_shell(&mut self) -> Option<String> {
+        self.shell_cancel.cancel();
+        let mut state = self.editor_state.lock().ok()?;
+        state.shell.take().map(|run| run.command)
+    }
+
+    /// Reads a file in chunks in the background, replacing a load that's
+    /// still running. Progress and text go to `EditorState::load`.
+    pub fn load_file(&mut self, path: String) {
+        self.load_cancel.cancel();
+        self.load_cancel = CancellationToken::new();
+        let cancel_token = self.load_cancel.clone();
+
+        if let Ok(mut state) = self.editor_state.lock() {
+            state.load = Some(FileLoad::new(&path));
+        }
+
+        let state_ref = Arc::clone(&self.editor_state);
+        RUNTIME.spawn(async move {
+            let read = tokio::select! {
+                _ = cancel_token.cancelled() => return,
+                res = read_chunks(&path, &state_ref, &cancel_token) => res,
+            };
+
+            // Checked under the lock, a newer load may have replaced this one
+            if let Ok(mut state) = state_ref.lock() {
+                if cancel_token.is_cancelled() {
+                    return;
+                }
+                if let Some(load) = &mut state.load {
+                    load.finished = true;
+                    load.error = read.err().map(|e| e.to_string());
+                }
+            }
+        });
+    }
+
+    /// Aborts the in-flight request and puts the state back to Idle.
+    /// With `discard` set, tokens not yet appended to the buffer are dropped
+    /// and the queued requests are cancelled as well.
+    pub fn cancel_request(&mut self, discard: bool) {
+        self.cancel_token.cancel();
+
+        if let Ok(mut state) = self.editor_state.lock() {
+            state.request_state = RequestState::Idle;
+            state.api_response = None;
+            // Only the running request, or its fan-out, is marked Running
+            let running: Vec<u64> = state
+                .requests
+                .iter()
+                .filter(|r| r.status == RequestStatus::Running)
+                .map(|r| r.id)
+                .collect();
+            for id in running {
+                state.set_request_status(id, RequestStatus::Cancelled);
+            }
+
+            if discard {
+                self.stream = None;
+                for request in self.queue.drain(..) {
+                    for id in request.ids() {
+                        state.set_request_status(id, RequestStatus::Cancelled);
+                    }
+                }
+            }
         }
     }
 
@@ -71,89 +827,467 @@ impl AsyncCommandHandler {
         });
     }
 
-    pub fn send_to_api(&self, content: String, ai_model: Model) {
-        // Early validation
-        if content.is_empty() {
-            if let Ok(mut state) = self.editor_state.lock() {
-                state.set_error("Cannot send empty buffer. Please write the question".to_owned());
-            }
-            return;
-        }
+    fn send_to_api(&mut self, request: QueuedRequest) {
+        let QueuedRequest {
+            id,
+            content,
+            model: ai_model,
+            label,
+            earlier,
+            ..
+        } = request;
 
         // Set state to processing
         if let Ok(mut state) = self.editor_state.lock() {
             state.request_state = RequestState::Proccessing;
         }
 
-        // Setup logging
-        let mut log = match OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open("rusty_ai_error.log")
-        {
-            Ok(file) => file,
-            Err(e) => {
-                eprintln!("Could not open log file: {}", e);
-                if let Ok(mut state) = self.editor_state.lock() {
-                    state.set_error(format!("Failed to open log file: {}", e));
-                }
-                return;
-            }
-        };
-
-        if let Err(e) = writeln!(log, "Sending to {}", ai_model) {
-            eprintln!("Could not write to log: {}", e);
-        }
+        self.cancel_token = CancellationToken::new();
 
         // Clone the needed references for the thread
         let chat_context = self.chat_context.clone();
-        let content_clone = content.clone();
-        let api_name_clone = ai_model.to_string();
         let state_ref = Arc::clone(&self.editor_state);
+        let cancel_token = self.cancel_token.clone();
+        let log = self.log.clone();
+        let timeout = config::get().request_timeout(ai_model.provider());
 
         // Spawn the worker thread
         thread::spawn(move || {
-            // Execute the async operation in the runtime
-            let result = RUNTIME
-                .block_on(async { chat_context.send_to_api(ai_model, &content_clone).await });
+            let started = Instant::now();
+            let mut sent = 0;
+
+            // Execute the async operation in the runtime, dropping it on cancel
+            let result = RUNTIME.block_on(async {
+                let content = tokio::select! {
+                    _ = cancel_token.cancelled() => return None,
+                    content = with_summary(&chat_context, &ai_model, &state_ref, earlier, content) => content,
+                };
+                sent = content.chars().count();
+                tokio::select! {
+                    _ = cancel_token.cancelled() => None,
+                    _ = expire(timeout) => Some(Err(Error::TimedOut(timeout.unwrap_or_default()))),
+                    res = chat_context.send_to_api(ai_model, &content) => Some(res),
+                }
+            });
+            let latency = started.elapsed();
+
+            // Cancelled: the editor has already reset the state
+            let Some(result) = result else {
+                log.push(id, &label, LogEvent::Cancelled { latency });
+                return;
+            };
 
             // Log and update state based on the result
             match result {
                 Ok(response) => {
-                    // Format the response
-                    let formatted_response = format!("\n\nAssistant\n {}", response);
+                    let chars = response.chars().count();
+                    log.push(id, &label, LogEvent::Done { latency, chars });
 
                     // Update the editor state with the response
                     if let Ok(mut state) = state_ref.lock() {
+                        state.record_usage(&label, sent, chars);
                         state.request_state = RequestState::Idle;
+                        state.set_request_status(id, RequestStatus::Done);
                         state.api_response = Some(ApiResponse {
-                            content: formatted_response,
+                            // The editor puts the header above it
+                            content: response,
                             error: None,
                         });
                     }
                 }
                 Err(e) => {
-                    // Log the error
-                    if let Err(log_err) = writeln!(log, "api error: {:?}", e) {
-                        eprintln!("Failed to write to log: {}", log_err);
-                    }
+                    let status = log::http_status(&e);
+                    let event = LogEvent::Failed {
+                        latency,
+                        status,
+                        error: e.to_string(),
+                    };
+                    log.push(id, &label, event);
 
                     // Update the editor state with the error
                     if let Ok(mut state) = state_ref.lock() {
-                        state.request_state = RequestState::Error(e.to_string());
-                        state.api_response = Some(ApiResponse {
-                            content: String::new(),
-                            error: Some(e.to_string()),
-                        });
+                        if status == Some(429) {
+                            state.usage.record_rate_limit(&label);
+                        }
+                        state.fail_request(id, &e);
+                    }
+                }
+            }
+        });
+    }
+
+    /// Asks every model of a fan-out request on its own thread. Each one's
+    /// status is updated as it finishes; the answers go to the buffer
+    /// together once the last one is in.
+    fn fan_out_to_api(&mut self, request: QueuedRequest) {
+        if let Ok(mut state) = self.editor_state.lock() {
+            state.request_state = RequestState::Proccessing;
+        }
+
+        self.cancel_token = CancellationToken::new();
+
+        let content = Arc::new(request.content);
+        let state_ref = Arc::clone(&self.editor_state);
+        let cancel_token = self.cancel_token.clone();
+
+        let workers: Vec<_> = request
+            .fan_out
+            .into_iter()
+            .map(|target| {
+                let content = Arc::clone(&content);
+                let state_ref = Arc::clone(&state_ref);
+                let cancel_token = cancel_token.clone();
+                let log = self.log.clone();
+
+                thread::spawn(move || {
+                    let started = Instant::now();
+                    let model = target.chat_context.model.clone();
+                    let timeout = config::get().request_timeout(model.provider());
+                    let result = RUNTIME.block_on(async {
+                        tokio::select! {
+                            _ = cancel_token.cancelled() => None,
+                            _ = expire(timeout) => Some(Err(Error::TimedOut(timeout.unwrap_or_default()))),
+                            res = target.chat_context.send_to_api(model, &content) => Some(res),
+                        }
+                    });
+                    let latency = started.elapsed();
+
+                    let Some(result) = result else {
+                        log.push(target.id, &target.label, LogEvent::Cancelled { latency });
+                        return None;
+                    };
+
+                    let sent = content.chars().count();
+                    let (status, received, http_status) = match &result {
+                        Ok(answer) => {
+                            let chars = answer.chars().count();
+                            log.push(target.id, &target.label, LogEvent::Done { latency, chars });
+                            (RequestStatus::Done, Some(chars), None)
+                        }
+                        Err(e) => {
+                            let http_status = log::http_status(e);
+                            let event = LogEvent::Failed {
+                                latency,
+                                status: http_status,
+                                error: e.to_string(),
+                            };
+                            log.push(target.id, &target.label, event);
+                            (RequestStatus::Error(e.to_string()), None, http_status)
+                        }
+                    };
+                    if let Ok(mut state) = state_ref.lock() {
+                        if let Some(received) = received {
+                            state.record_usage(&target.label, sent, received);
+                        } else if http_status == Some(429) {
+                            state.usage.record_rate_limit(&target.label);
+                        }
+                        if !cancel_token.is_cancelled() {
+                            state.set_request_status(target.id, status);
+                        }
+                    }
+
+                    Some((target.label, result))
+                })
+            })
+            .collect();
+
+        thread::spawn(move || {
+            let mut answer = String::new();
+            for worker in workers {
+                let Ok(Some((label, result))) = worker.join() else {
+                    continue;
+                };
+                let text = match result {
+                    Ok(text) => text,
+                    Err(e) => format!("Error: {}", e),
+                };
+                answer.push_str(&format!("\n### {}\n\n{}\n", label, text.trim()));
+            }
+
+            // Checked under the lock so nothing lands after a cancel
+            if let Ok(mut state) = state_ref.lock() {
+                if cancel_token.is_cancelled() {
+                    return;
+                }
+                state.request_state = RequestState::Idle;
+                state.api_response = Some(ApiResponse {
+                    content: answer,
+                    error: None,
+                });
+            }
+        });
+    }
+
+    /// Streaming counterpart of `send_to_api`: tokens go to the editor over
+    /// `stream` as they arrive. They're batched so the editor gets at most
+    /// one batch per frame instead of one per token.
+    fn stream_to_api(&mut self, request: QueuedRequest) {
+        let QueuedRequest {
+            id,
+            content,
+            model: ai_model,
+            label,
+            earlier,
+            ..
+        } = request;
+
+        // Set state to processing, the new channel drops leftovers of a
+        // previous stream
+        if let Ok(mut state) = self.editor_state.lock() {
+            state.request_state = RequestState::Proccessing;
+        }
+        let (tx, rx) = mpsc::channel();
+        self.stream = Some(rx);
+
+        self.cancel_token = CancellationToken::new();
+
+        let chat_context = self.chat_context.clone();
+        let state_ref = Arc::clone(&self.editor_state);
+        let cancel_token = self.cancel_token.clone();
+        let log = self.log.clone();
+        let frame = Duration::from_millis(config::get().frame_duration_ms);
+        let timeout = config::get().request_timeout(ai_model.provider());
+
+        thread::spawn(move || {
+            let started = Instant::now();
+
+            let chunk_token = cancel_token.clone();
+            let mut sent = 0;
+            let mut chars = 0;
+            let mut batch = String::new();
+            let mut last_sent = Instant::now();
+            let last_token = Cell::new(Instant::now());
+            let result = RUNTIME.block_on(async {
+                let content = tokio::select! {
+                    _ = cancel_token.cancelled() => return None,
+                    content = with_summary(&chat_context, &ai_model, &state_ref, earlier, content) => content,
+                };
+                sent = content.chars().count();
+                last_token.set(Instant::now());
+                let stream = chat_context.stream_to_api(ai_model, &content, |chunk| {
+                    last_token.set(Instant::now());
+                    chars += chunk.chars().count();
+                    batch.push_str(chunk);
+                    // Nothing is sent after a cancel
+                    if last_sent.elapsed() >= frame && !chunk_token.is_cancelled() {
+                        let _ = tx.send(std::mem::take(&mut batch));
+                        last_sent = Instant::now();
                     }
+                });
+
+                tokio::select! {
+                    _ = cancel_token.cancelled() => None,
+                    _ = stall(&last_token, timeout) => Some(Err(Error::TimedOut(timeout.unwrap_or_default()))),
+                    res = stream => Some(res),
                 }
+            });
+
+            // The rest goes out before the state says the stream ended
+            if result.is_some() && !batch.is_empty() {
+                let _ = tx.send(batch);
+            }
+
+            let latency = started.elapsed();
+
+            // Cancelled: the editor has already reset the state
+            let Some(result) = result else {
+                log.push(id, &label, LogEvent::Cancelled { latency });
+                return;
+            };
+
+            match result {
+                Ok(()) => {
+                    log.push(id, &label, LogEvent::Done { latency, chars });
+                    if let Ok(mut state) = state_ref.lock() {
+                        state.record_usage(&label, sent, chars);
+                        state.request_state = RequestState::Idle;
+                        state.set_request_status(id, RequestStatus::Done);
+                    }
+                }
+                Err(e) => {
+                    let status = log::http_status(&e);
+                    let event = LogEvent::Failed {
+                        latency,
+                        status,
+                        error: e.to_string(),
+                    };
+                    log.push(id, &label, event);
+
+                    if let Ok(mut state) = state_ref.lock() {
+                        if status == Some(429) {
+                            state.usage.record_rate_limit(&label);
+                        }
+                        state.fail_request(id, &e);
+                    }
+                }
+            }
+        });
+    }
+}
+
+// Resolves once `limit` has passed, never without a limit
+async fn expire(limit: Option<Duration>) {
+    match limit {
+        Some(limit) => tokio::time::sleep(limit).await,
+        None => std::future::pending().await,
+    }
+}
+
+// Resolves once no token arrived for `limit`, never without a limit
+async fn stall(last_token: &Cell<Instant>, limit: Option<Duration>) {
+    let Some(limit) = limit else {
+        return std::future::pending().await;
+    };
+    loop {
+        let idle = last_token.get().elapsed();
+        if idle >= limit {
+            return;
+        }
+        tokio::time::sleep(limit - idle).await;
+    }
+}
+
+// Puts a summary of the messages dropped from the conversation in front of
+// `content`. The last summary is kept and extended as more are dropped; when
+// it can't be made the request goes out without one.
+async fn with_summary(
+    chat_context: &ChatContext,
+    model: &Model,
+    state: &Mutex<EditorState>,
+    earlier: Option<String>,
+    content: String,
+) -> String {
+    let Some(earlier) = earlier else {
+        return content;
+    };
+
+    let cached = state.lock().ok().and_then(|state| state.summary.clone());
+    let prompt = match &cached {
+        Some((dropped, summary)) if *dropped == earlier => {
+            return trim::summary_note(summary) + &content;
+        }
+        // Only the newly dropped messages need reading
+        Some((dropped, summary)) if earlier.starts_with(dropped.as_str()) => {
+            trim::summary_prompt(Some(summary), &earlier[dropped.len()..])
+        }
+        _ => trim::summary_prompt(None, &earlier),
+    };
+
+    let mut summarizer = chat_context.clone();
+    summarizer.system_prompt = trim::SUMMARY_SYSTEM_PROMPT.to_string();
+    summarizer.params = GenerationParams::default();
+
+    match summarizer.send_to_api(model.clone(), &prompt).await {
+        Ok(summary) => {
+            let note = trim::summary_note(&summary);
+            if let Ok(mut state) = state.lock() {
+                state.summary = Some((earlier, summary));
+            }
+            note + &content
+        }
+        Err(e) => {
+            if let Ok(mut state) = state.lock() {
+                state.messages.push(
+                    Level::Warning,
+                    format!("Can't summarize the earlier messages, they're left out: {}", e),
+                );
             }
+            content
+        }
+    }
+}
+
+// Reads `path` into `EditorState::load`, decoded in the encoding the first
+// chunk looks like and with `\n` line breaks. UTF-8 that turns out broken
+// further in is read again as Latin-1, like `encoding::read` does.
+async fn read_chunks(
+    path: &str,
+    state_ref: &Arc<Mutex<EditorState>>,
+    cancel_token: &CancellationToken,
+) -> Result<()> {
+    use std::io::SeekFrom;
+    use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+    let mut file = tokio::fs::File::open(path).await?;
+    let total = file.metadata().await?.len();
+    if let Ok(mut state) = state_ref.lock() {
+        if let Some(load) = &mut state.load {
+            load.total = total;
+        }
+    }
+
+    let mut forced: Option<Encoding> = None;
+    let mut decoder: Option<Decoder> = None;
+    let mut normalizer = Normalizer::default();
+    let mut chunk = vec![0; LOAD_CHUNK_SIZE];
+    loop {
+        while taken_behind(state_ref) {
+            tokio::time::sleep(LOAD_BACKOFF).await;
+        }
+
+        let read = file.read(&mut chunk).await?;
+        let current = decoder.get_or_insert_with(|| {
+            Decoder::new(forced.unwrap_or_else(|| Encoding::detect(&chunk[..read])))
         });
+        // The end checks for a char cut off
+        let decoded = match read {
+            0 => current.finish().map(|()| String::new()),
+            _ => current.push(&chunk[..read]),
+        };
+        let text = match decoded {
+            Ok(text) => normalizer.push(&text),
+            Err(_) if current.encoding() == Encoding::Utf8 => {
+                file.seek(SeekFrom::Start(0)).await?;
+                forced = Some(Encoding::Latin1);
+                decoder = None;
+                normalizer = Normalizer::default();
+                if let Ok(mut state) = state_ref.lock() {
+                    if let Some(load) = &mut state.load {
+                        load.text.clear();
+                        load.loaded = 0;
+                        load.restarted = true;
+                    }
+                }
+                continue;
+            }
+            Err(e) => return Err(e),
+        };
+        if read == 0 {
+            break;
+        }
+
+        if let Ok(mut state) = state_ref.lock() {
+            if cancel_token.is_cancelled() {
+                return Ok(());
+            }
+            if let Some(load) = &mut state.load {
+                load.encoding = current.encoding();
+                load.line_ending = normalizer.line_ending();
+                load.text.push_str(&text);
+                load.loaded += read as u64;
+            }
+        }
+    }
+
+    let rest = normalizer.finish();
+    if !rest.is_empty() {
+        if let Ok(mut state) = state_ref.lock() {
+            if let Some(load) = &mut state.load {
+                load.text.push_str(&rest);
+            }
+        }
     }
 
-    // Future method for LSP requests
-    pub fn request_lsp_completion(&self, _position: (usize, usize)) {
-        // Similar implementation to request_ollama
-        // Will be implemented when needed
+    Ok(())
+}
+
+// Whether the editor has yet to take more than MAX_PENDING_LOAD of the text
+fn taken_behind(state_ref: &Arc<Mutex<EditorState>>) -> bool {
+    match state_ref.lock() {
+        Ok(state) => state
+            .load
+            .as_ref()
+            .is_some_and(|load| load.text.len() > MAX_PENDING_LOAD),
+        Err(_) => false,
     }
 }
@@ -0,0 +1,81 @@
+use std::path::Path;
+
+use crate::files::load_file;
+use crate::Result;
+
+/// A file or command output sent along with the prompt
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    /// File path, or the command that was run
+    pub name: String,
+    pub content: String,
+    pub kind: Kind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// Sent with every prompt until detached
+    File,
+    /// Sent with the next prompt only
+    Command,
+}
+
+impl Attachment {
+    pub fn load(name: &str) -> Result<Self> {
+        Ok(Self {
+            name: name.to_string(),
+            content: load_file(name)?,
+            kind: Kind::File,
+        })
+    }
+
+    /// Output of a shell command, e.g. compiler errors for the model to see
+    pub fn command(command: &str, output: String) -> Self {
+        Self {
+            name: command.to_string(),
+            content: output,
+            kind: Kind::Command,
+        }
+    }
+
+    /// Content as a markdown fence, a file's tagged with its language
+    pub fn to_fenced_block(&self) -> String {
+        match self.kind {
+            Kind::File => format!(
+                "File `{}`:\n```{}\n{}\n```\n\n",
+                self.name,
+                fence_language(&self.name),
+                self.content.trim_end()
+            ),
+            Kind::Command => format!(
+                "Output of `{}`:\n```output\n{}\n```\n\n",
+                self.name,
+                self.content.trim_end()
+            ),
+        }
+    }
+}
+
+/// All attachments, ready to be put in front of the prompt
+pub fn attachments_prompt(attachments: &[Attachment]) -> String {
+    attachments.iter().map(Attachment::to_fenced_block).collect()
+}
+
+// Fence tag for a file name, so the model (and our highlighter) knows the language
+fn fence_language(name: &str) -> &str {
+    let extension = Path::new(name)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .unwrap_or("");
+
+    match extension {
+        "rs" => "rust",
+        "py" => "python",
+        "js" => "javascript",
+        "ts" => "typescript",
+        "md" => "markdown",
+        "sh" => "bash",
+        "yml" => "yaml",
+        other => other,
+    }
+}
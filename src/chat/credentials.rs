@@ -0,0 +1,136 @@
+use std::env;
+use std::fs;
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+
+use crate::config;
+use crate::Result;
+
+use super::Model;
+
+/// Providers that can't be used without an API key
+pub const PROVIDERS: &[Model] = &[Model::OPENAI, Model::ANTROPIC];
+
+/// `<history_dir>/credentials`, one `VARIABLE=key` per line
+pub fn path() -> PathBuf {
+    PathBuf::from(&config::get().history_dir).join("credentials")
+}
+
+/// Exports the keys of the credentials file so genai finds them. Variables
+/// already set in the shell win over the file.
+pub fn load() -> Result<()> {
+    let content = match fs::read_to_string(path()) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+
+    for (var, key) in parse(&content) {
+        let known = PROVIDERS.iter().any(|m| m.api_key_env() == Some(var));
+        if known && !is_var_set(var) {
+            env::set_var(var, key);
+        }
+    }
+
+    Ok(())
+}
+
+/// Uses `key` for requests to `model` until the editor exits
+pub fn set(model: &Model, key: &str) {
+    if let Some(var) = model.api_key_env() {
+        env::set_var(var, key.trim());
+    }
+}
+
+/// Stores `key` in the credentials file, readable by the owner only
+pub fn save(model: &Model, key: &str) -> Result<()> {
+    let Some(var) = model.api_key_env() else {
+        return Ok(());
+    };
+
+    let path = path();
+    let content = match fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut lines: Vec<String> = content
+        .lines()
+        .filter(|line| line.split_once('=').map(|(v, _)| v.trim()) != Some(var))
+        .map(str::to_string)
+        .collect();
+    lines.push(format!("{}={}", var, key.trim()));
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    let mut file = open_private(&path)?;
+    file.write_all(format!("{}\n", lines.join("\n")).as_bytes())?;
+
+    Ok(())
+}
+
+/// True when requests to `model` have a key to go with them
+pub fn is_configured(model: &Model) -> bool {
+    model.api_key_env().map(is_var_set).unwrap_or(true)
+}
+
+/// One line per provider for the AI menu, e.g. `OpenAI: key set`
+pub fn status() -> Vec<String> {
+    PROVIDERS
+        .iter()
+        .map(|model| {
+            let state = if is_configured(model) {
+                "key set"
+            } else {
+                "no key"
+            };
+            format!("{}: {}", name(model), state)
+        })
+        .collect()
+}
+
+pub fn name(model: &Model) -> &'static str {
+    match model {
+        Model::OLLAMA => "Ollama",
+        Model::OPENAI => "OpenAI",
+        Model::ANTROPIC => "Anthropic",
+    }
+}
+
+fn is_var_set(var: &str) -> bool {
+    env::var(var).map(|v| !v.trim().is_empty()).unwrap_or(false)
+}
+
+fn parse(content: &str) -> Vec<(&str, &str)> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| line.split_once('='))
+        .map(|(var, key)| (var.trim(), key.trim()))
+        .collect()
+}
+
+#[cfg(unix)]
+fn open_private(path: &PathBuf) -> Result<fs::File> {
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    let file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    // `mode` only applies to new files
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+
+    Ok(file)
+}
+
+#[cfg(not(unix))]
+fn open_private(path: &PathBuf) -> Result<fs::File> {
+    Ok(fs::File::create(path)?)
+}
@@ -0,0 +1,187 @@
+use std::fs;
+use std::path::PathBuf;
+
+use chrono::Local;
+use ropey::Rope;
+use serde::Serialize;
+
+use super::session;
+use super::transcript;
+use crate::config;
+use crate::syntax::{Style, SyntaxHighlighter};
+use crate::Result;
+
+const STYLESHEET: &str = "
+body { background: #1e1e1e; color: #d4d4d4; font-family: sans-serif; max-width: 60rem; margin: 2rem auto; }
+h2 { font-size: 1rem; color: #9cdcfe; margin-top: 2rem; }
+pre { white-space: pre-wrap; font-family: monospace; padding: 0.5rem 1rem; }
+.assistant pre { background: #262626; }
+.keyword { color: #c586c0; } .function { color: #dcdcaa; } .type { color: #4ec9b0; }
+.string { color: #ce9178; } .number, .constant { color: #b5cea8; } .comment { color: #6a9955; }
+.variable { color: #9cdcfe; } .error { color: #f44747; }
+.heading { color: #569cd6; font-weight: bold; } .bold { font-weight: bold; } .italic { font-style: italic; }
+.inlinecode { color: #ce9178; } .quote { color: #808080; } .link { color: #3794ff; }
+";
+
+/// One turn of the conversation
+#[derive(Debug, Clone, Serialize)]
+pub struct Message {
+    /// `user` or `assistant`
+    pub role: String,
+    pub content: String,
+}
+
+/// A conversation as written to `<history_dir>/exports/<name>.json`
+#[derive(Debug, Clone, Serialize)]
+pub struct Export {
+    pub title: String,
+    /// Model picker entry, e.g. `openai/gpt-4.1-mini`
+    pub model: String,
+    pub created: String,
+    pub exported: String,
+    pub messages: Vec<Message>,
+}
+
+impl Export {
+    /// An untitled conversation is named after its first question
+    pub fn new(title: &str, model: &str, created: &str, conversation: &str) -> Self {
+        let title = if title.is_empty() {
+            session::title_from(conversation)
+        } else {
+            title.to_string()
+        };
+
+        Self {
+            title,
+            model: model.to_string(),
+            created: created.to_string(),
+            exported: Local::now().format("%Y-%m-%d %H:%M").to_string(),
+            messages: messages(conversation),
+        }
+    }
+
+    /// Writes `<name>.json` and `<name>.html` under `<history_dir>/exports`.
+    /// Returns the path of the HTML file.
+    pub fn write(&self, name: &str) -> Result<PathBuf> {
+        let dir = PathBuf::from(&config::get().history_dir).join("exports");
+        fs::create_dir_all(&dir)?;
+
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::write(dir.join(format!("{}.json", name)), json)?;
+
+        let html_path = dir.join(format!("{}.html", name));
+        fs::write(&html_path, self.html())?;
+
+        Ok(html_path)
+    }
+
+    /// Standalone page, code blocks highlighted like in the editor
+    fn html(&self) -> String {
+        let highlighter = SyntaxHighlighter::new().ok();
+        let title = if self.title.is_empty() {
+            "Untitled"
+        } else {
+            self.title.as_str()
+        };
+
+        let mut html = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n<style>{}</style>\n</head>\n<body>\n",
+            escape(title),
+            STYLESHEET
+        );
+        html.push_str(&format!("<h1>{}</h1>\n", escape(title)));
+        html.push_str(&format!(
+            "<p>{} | created {} | exported {}</p>\n",
+            escape(&self.model),
+            escape(&self.created),
+            escape(&self.exported)
+        ));
+
+        for message in &self.messages {
+            let heading = if message.role == "user" { "User" } else { "Assistant" };
+            html.push_str(&format!(
+                "<section class=\"{}\">\n<h2>{}</h2>\n<pre>{}</pre>\n</section>\n",
+                message.role,
+                heading,
+                highlight(highlighter.as_ref(), &message.content)
+            ));
+        }
+
+        html.push_str("</body>\n</html>\n");
+        html
+    }
+}
+
+/// Splits a conversation at its `## User` and `## Assistant` header lines.
+/// Text before the first header is the first question.
+pub fn messages(conversation: &str) -> Vec<Message> {
+    let mut messages: Vec<Message> = Vec::new();
+    let mut role = "user";
+    let mut content = String::new();
+
+    let mut push = |role: &str, content: &str| {
+        let content = content.trim();
+        if !content.is_empty() {
+            messages.push(Message {
+                role: role.to_string(),
+                content: content.to_string(),
+            });
+        }
+    };
+
+    for line in conversation.lines() {
+        match transcript::role(line) {
+            Some(header) => {
+                push(role, &content);
+                content.clear();
+                role = header;
+            }
+            None => {
+                content.push_str(line);
+                content.push('\n');
+            }
+        }
+    }
+    push(role, &content);
+
+    messages
+}
+
+// Escaped text with a `<span>` per highlighted run
+fn highlight(highlighter: Option<&SyntaxHighlighter>, text: &str) -> String {
+    let mut styles = vec![Style::Normal; text.len()];
+    if let Some(highlighter) = highlighter {
+        for (range, style) in highlighter.highlight_buffer(&Rope::from_str(text), None) {
+            let end = range.end.min(text.len());
+            styles[range.start.min(end)..end].fill(style);
+        }
+    }
+
+    let mut html = String::new();
+    let mut current = Style::Normal;
+    for (byte, ch) in text.char_indices() {
+        let style = styles[byte];
+        if style != current {
+            if current != Style::Normal {
+                html.push_str("</span>");
+            }
+            if style != Style::Normal {
+                let class = format!("{:?}", style).to_lowercase();
+                html.push_str(&format!("<span class=\"{}\">", class));
+            }
+            current = style;
+        }
+        html.push_str(&escape(ch.encode_utf8(&mut [0; 4])));
+    }
+    if current != Style::Normal {
+        html.push_str("</span>");
+    }
+    html
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
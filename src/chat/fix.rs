@@ -0,0 +1,107 @@
+use std::collections::HashSet;
+use std::fs;
+
+use serde_json::Value;
+
+/// Run in the working directory, cargo finds the workspace from there
+pub const CHECK_COMMAND: &str = "cargo check --workspace --message-format=json";
+
+// Errors put in one prompt, the first ones usually cause the rest
+const MAX_ERRORS: usize = 10;
+// Source lines shown around an error
+const CONTEXT_LINES: usize = 5;
+
+/// A compiler error and where it points
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    /// The message as the compiler prints it, with its notes
+    pub rendered: String,
+    /// Of the primary span, relative to the workspace
+    pub file: Option<String>,
+    /// 1-based lines of the primary span
+    pub lines: (usize, usize),
+}
+
+/// The errors in the JSON lines of `cargo check --message-format=json`.
+/// Other lines, e.g. cargo's progress on stderr, are skipped. An error
+/// reported for several targets is kept once.
+pub fn errors(output: &str) -> Vec<Diagnostic> {
+    let mut seen = HashSet::new();
+    output
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .filter(|value| value.get("reason").and_then(Value::as_str) == Some("compiler-message"))
+        .filter_map(|value| diagnostic(value.get("message")?))
+        .filter(|diagnostic| seen.insert(diagnostic.rendered.clone()))
+        .collect()
+}
+
+fn diagnostic(message: &Value) -> Option<Diagnostic> {
+    if message.get("level")?.as_str()? != "error" {
+        return None;
+    }
+    let rendered = message.get("rendered")?.as_str()?.to_string();
+
+    let primary = message
+        .get("spans")
+        .and_then(Value::as_array)
+        .and_then(|spans| {
+            spans
+                .iter()
+                .find(|span| span.get("is_primary").and_then(Value::as_bool) == Some(true))
+        });
+    let line = |key: &str| {
+        primary
+            .and_then(|span| span.get(key))
+            .and_then(Value::as_u64)
+            .unwrap_or(0) as usize
+    };
+
+    Some(Diagnostic {
+        rendered,
+        file: primary
+            .and_then(|span| span.get("file_name"))
+            .and_then(Value::as_str)
+            .map(str::to_string),
+        lines: (line("line_start"), line("line_end")),
+    })
+}
+
+/// Asks for a diff fixing `errors`, each with the source around it
+pub fn prompt(errors: &[Diagnostic]) -> String {
+    let mut prompt = String::from(
+        "Fix these errors from `cargo check`. Answer with a unified diff in a ```diff block, \
+        with paths relative to the workspace root.\n\n",
+    );
+
+    for error in errors.iter().take(MAX_ERRORS) {
+        prompt.push_str(&format!("```\n{}\n```\n", error.rendered.trim_end()));
+        if let Some(snippet) = error.file.as_deref().and_then(|file| snippet(file, error.lines)) {
+            prompt.push_str(&snippet);
+        }
+        prompt.push('\n');
+    }
+    if errors.len() > MAX_ERRORS {
+        prompt.push_str(&format!("({} more errors left out)\n", errors.len() - MAX_ERRORS));
+    }
+    prompt
+}
+
+// `File `src/main.rs` lines 3-13:` and the fenced lines
+fn snippet(file: &str, (start, end): (usize, usize)) -> Option<String> {
+    let content = fs::read_to_string(file).ok()?;
+    let lines: Vec<&str> = content.lines().collect();
+    if start == 0 || start > lines.len() {
+        return None;
+    }
+
+    let first = start.saturating_sub(CONTEXT_LINES).max(1);
+    let last = (end.max(start) + CONTEXT_LINES).min(lines.len());
+    Some(format!(
+        "File `{}` lines {}-{}:\n```rust\n{}\n```\n",
+        file,
+        first,
+        last,
+        lines[first - 1..last].join("\n")
+    ))
+}
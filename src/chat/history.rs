@@ -1,5 +1,7 @@
+use super::session::Session;
+use crate::config;
 use crate::error::Result;
-use chrono::Local;
+use crate::files::encoding::{self, Encoding};
 use std::fs::{self, OpenOptions};
 use std::io::{Read, Write};
 use std::path::Path;
@@ -8,36 +10,53 @@ use std::path::Path;
 pub struct History {
     pub root: String,
     pub file_path: String,
+    // Set while the buffer holds a session rather than a plain file
+    pub session: Option<Session>,
 }
 
 impl History {
-    pub fn new() -> Result<Self> {
-        let history_dir = ".rusty";
-
-        let now = Local::now();
-
-        // Format date as dd.mm.yyyy
-        let date_str = now.format("%d.%m.%Y").to_string();
-
-        // Create filename
-        let filename = format!("rusty_{}.md", date_str);
-
-        let file_path = format!("{}/{}", history_dir, filename);
+    /// History backed by a session file
+    pub fn new(session: Session) -> Result<Self> {
+        let history_dir = config::get().history_dir.as_str();
 
         if !Path::new(history_dir).exists() {
             fs::create_dir_all(history_dir)?;
         }
 
-        let _file = OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(&file_path)?;
         Ok(Self {
             root: history_dir.to_owned(),
-            file_path,
+            file_path: session.path.to_string_lossy().into_owned(),
+            session: Some(session),
         })
     }
 
+    /// History for `name` in the same directory. The file is only created on save.
+    pub fn sibling(&self, name: &str) -> Self {
+        Self {
+            root: self.root.clone(),
+            file_path: format!("{}/{}", self.root, name),
+            session: None,
+        }
+    }
+
+    /// History for another file, `file_path` taken as is
+    pub fn with_path(&self, file_path: &str) -> Self {
+        Self {
+            root: self.root.clone(),
+            file_path: file_path.to_string(),
+            session: None,
+        }
+    }
+
+    /// Content of the file, without the session metadata, and how it was
+    /// encoded. Sessions are always UTF-8.
+    pub fn content(&self) -> Result<(String, Encoding)> {
+        match &self.session {
+            Some(session) => Ok((session.load()?, Encoding::Utf8)),
+            None => encoding::read(Path::new(&self.file_path)),
+        }
+    }
+
     pub fn new_file(&mut self, name: String) -> Result<()> {
         let file_path = format!("{}/{}", self.root, name);
 
@@ -47,17 +66,21 @@ impl History {
             .open(&file_path)?;
 
         self.file_path = name;
+        self.session = None;
 
         Ok(())
     }
 
-    pub fn save_file(&mut self, content: String) -> Result<()> {
-        fs::write(&self.file_path, content)?;
+    pub fn save_file(&mut self, content: String, encoding: Encoding) -> Result<()> {
+        match &mut self.session {
+            Some(session) => session.save(&content)?,
+            None => encoding::write(Path::new(&self.file_path), &content, encoding)?,
+        }
 
         Ok(())
     }
 
-    pub fn save_to_file(&mut self, file_name: String, content: String) -> Result<()> {
+    pub fn save_to_file(&mut self, file_name: String, content: String, encoding: Encoding) -> Result<()> {
         let file_path = format!("{}/{}", self.root, file_name);
 
         let _file = OpenOptions::new()
@@ -65,20 +88,17 @@ impl History {
             .write(true)
             .open(&file_path)?;
 
-        fs::write(file_path, content)?;
+        encoding::write(Path::new(&file_path), &content, encoding)?;
 
         self.file_path = file_name;
+        self.session = None;
 
         Ok(())
     }
 
     pub fn current_file_content(&self) -> Result<String> {
         let current_path = format!("{}/{}", self.root, self.file_path);
-
-        let mut file = OpenOptions::new().read(true).open(&current_path)?;
-
-        let mut contents = String::new();
-        file.read_to_string(&mut contents)?;
+        let (contents, _) = encoding::read(Path::new(&current_path))?;
 
         Ok(contents)
     }
@@ -91,6 +111,7 @@ impl History {
         file.read_to_string(&mut contents)?;
 
         self.file_path = file_path;
+        self.session = None;
 
         Ok(contents)
     }
@@ -0,0 +1,180 @@
+use serde_json::{Map, Value};
+
+use super::export::Message;
+use super::transcript;
+use crate::Result;
+
+/// A conversation read from another tool, as editor text
+#[derive(Debug, Clone)]
+pub struct Imported {
+    pub title: String,
+    /// `## User` and `## Assistant` headers, each followed by its message
+    pub transcript: String,
+}
+
+/// Reads a ChatGPT export (`conversations.json` or a single conversation),
+/// an OpenAI style `messages` list, or one of our own exports. Of a
+/// ChatGPT export with several conversations the first, newest one is taken.
+pub fn parse(json: &str) -> Result<Imported> {
+    let value: Value = serde_json::from_str(json).map_err(|e| e.to_string())?;
+
+    let conversation = match &value {
+        Value::Array(items) if items.first().is_some_and(|item| item.get("mapping").is_some()) => {
+            &items[0]
+        }
+        _ => &value,
+    };
+
+    let messages = if let Some(Value::Object(mapping)) = conversation.get("mapping") {
+        chatgpt_messages(conversation, mapping)
+    } else if let Some(Value::Array(messages)) = conversation.get("messages") {
+        api_messages(messages)
+    } else if let Value::Array(messages) = conversation {
+        api_messages(messages)
+    } else {
+        return Err("Not a ChatGPT, OpenAI or rusty_ai conversation".into());
+    };
+    if messages.is_empty() {
+        return Err("The conversation has no messages".into());
+    }
+
+    let title = conversation.get("title").and_then(Value::as_str).unwrap_or("");
+    Ok(Imported {
+        title: title.to_string(),
+        transcript: transcript(&messages),
+    })
+}
+
+/// Messages under `## User` and `## Assistant` headers, the way answers
+/// land in the buffer.
+/// Consecutive messages of one role, e.g. ChatGPT code and text parts,
+/// share a header.
+fn transcript(messages: &[Message]) -> String {
+    let mut text = String::new();
+    let mut role = "";
+    for message in messages {
+        if message.role != role {
+            let header = transcript::plain_header(&message.role);
+            if !text.is_empty() {
+                text.push('\n');
+            }
+            text.push_str(header);
+            text.push('\n');
+            role = &message.role;
+        } else {
+            text.push('\n');
+        }
+        text.push_str(&message.content);
+        text.push('\n');
+    }
+    text
+}
+
+// `[{"role": "user", "content": "..."}]`, where the content may also be a
+// list of `{"type": "text", "text": "..."}` parts
+fn api_messages(items: &[Value]) -> Vec<Message> {
+    items
+        .iter()
+        .filter_map(|item| {
+            let role = item.get("role")?.as_str()?;
+            let content = match item.get("content")? {
+                Value::String(text) => text.clone(),
+                Value::Array(parts) => parts
+                    .iter()
+                    .filter_map(|part| part.get("text")?.as_str())
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+                _ => return None,
+            };
+            message(role, &content)
+        })
+        .collect()
+}
+
+// ChatGPT keeps every edit and regeneration in a tree of nodes; the shown
+// conversation is the path from the root to `current_node`
+fn chatgpt_messages(conversation: &Value, mapping: &Map<String, Value>) -> Vec<Message> {
+    let mut id = conversation
+        .get("current_node")
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .or_else(|| latest_leaf(mapping));
+
+    let mut path = Vec::new();
+    // Bounded, a broken file could link nodes in a circle
+    while let Some(node) = id.as_ref().and_then(|id| mapping.get(id)) {
+        if path.len() > mapping.len() {
+            break;
+        }
+        path.push(node);
+        id = node.get("parent").and_then(Value::as_str).map(str::to_string);
+    }
+    path.reverse();
+
+    path.into_iter()
+        .filter_map(|node| {
+            let item = node.get("message")?;
+            let role = item.get("author")?.get("role")?.as_str()?;
+            message(role, &chatgpt_content(item.get("content")?))
+        })
+        .collect()
+}
+
+// Follows the newest child from the root, for exports without `current_node`
+fn latest_leaf(mapping: &Map<String, Value>) -> Option<String> {
+    let (mut id, _) = mapping
+        .iter()
+        .find(|(_, node)| node.get("parent").is_none_or(Value::is_null))?;
+
+    for _ in 0..mapping.len() {
+        let child = mapping
+            .get(id)
+            .and_then(|node| node.get("children"))
+            .and_then(Value::as_array)
+            .and_then(|children| children.last())
+            .and_then(Value::as_str);
+        match child {
+            Some(child) => id = mapping.get_key_value(child)?.0,
+            None => break,
+        }
+    }
+    Some(id.clone())
+}
+
+// Text parts are markdown already; code parts get a fence
+fn chatgpt_content(content: &Value) -> String {
+    match content.get("content_type").and_then(Value::as_str) {
+        Some("code") => {
+            let language = match content.get("language").and_then(Value::as_str) {
+                Some("unknown") | None => "",
+                Some(language) => language,
+            };
+            let code = content.get("text").and_then(Value::as_str).unwrap_or("");
+            format!("```{}\n{}\n```", language, code.trim_end())
+        }
+        _ => content
+            .get("parts")
+            .and_then(Value::as_array)
+            .map(|parts| {
+                parts
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            })
+            .unwrap_or_default(),
+    }
+}
+
+// System prompts and tool output are left out
+fn message(role: &str, content: &str) -> Option<Message> {
+    let content = content.trim();
+    if !matches!(role, "user" | "assistant") || content.is_empty() {
+        return None;
+    }
+
+    Some(Message {
+        role: role.to_string(),
+        content: content.to_string(),
+    })
+}
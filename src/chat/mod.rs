@@ -1,22 +1,40 @@
+pub mod attachment;
+pub mod credentials;
+pub mod export;
+pub mod fix;
 pub mod history;
+pub mod import;
 
 mod error;
-mod models;
+pub mod models;
+pub mod params;
+mod pins;
+pub mod project;
+pub mod prompt_history;
+pub mod session;
+pub mod system_prompt;
+pub mod tokens;
+pub mod transcript;
+pub mod trim;
+pub mod usage;
 
 use std::env;
 use std::{collections::HashMap, path::PathBuf};
 
-use genai::chat::{ChatMessage, ChatRequest};
+use genai::chat::{ChatMessage, ChatRequest, ChatStreamEvent};
 use genai::Client;
+use tokio_stream::StreamExt;
 
 use ollama_rs::{generation, Ollama};
 use ollama_rs::generation::completion::request::GenerationRequest;
 
+use crate::config;
 use crate::files::{change_dir, list_current_dir, load_file};
 use crate::Result;
 use history::History;
+use params::GenerationParams;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Model {
     OLLAMA,
     OPENAI,
@@ -28,7 +46,7 @@ impl From<Model> for &str {
         match value {
             Model::OLLAMA => "gemma3:27b",
             Model::OPENAI => "gpt-4.1-mini",
-            Model::ANTROPIC => todo!(),
+            Model::ANTROPIC => "claude-3-5-haiku-latest",
         }
     }
 }
@@ -38,7 +56,52 @@ impl From<Model> for String {
         match value {
             Model::OLLAMA => "gemma3:27b".to_owned(),
             Model::OPENAI => "gpt-4o-mini".to_owned(),
-            Model::ANTROPIC => todo!(),
+            Model::ANTROPIC => "claude-3-5-haiku-latest".to_owned(),
+        }
+    }
+}
+
+impl Model {
+    /// Provider prefix used in model picker entries, e.g. `ollama/gemma3:27b`
+    pub fn provider(&self) -> &'static str {
+        match self {
+            Model::OLLAMA => "ollama",
+            Model::OPENAI => "openai",
+            Model::ANTROPIC => "anthropic",
+        }
+    }
+
+    /// Environment variable genai reads the API key from, if the provider needs one
+    pub fn api_key_env(&self) -> Option<&'static str> {
+        match self {
+            Model::OLLAMA => None,
+            Model::OPENAI => Some("OPENAI_API_KEY"),
+            Model::ANTROPIC => Some("ANTHROPIC_API_KEY"),
+        }
+    }
+
+    /// Fails early with a readable message instead of an opaque auth error
+    pub fn check_api_key(&self) -> Result<()> {
+        let Some(var) = self.api_key_env() else {
+            return Ok(());
+        };
+
+        match env::var(var) {
+            Ok(key) if !key.trim().is_empty() => Ok(()),
+            _ => Err(crate::error::Error::Custom(format!(
+                "{} is not set, can't send requests to {} (AI menu: k to set it)",
+                var,
+                credentials::name(self)
+            ))),
+        }
+    }
+
+    pub fn from_provider(provider: &str) -> Option<Model> {
+        match provider {
+            "ollama" => Some(Model::OLLAMA),
+            "openai" => Some(Model::OPENAI),
+            "anthropic" => Some(Model::ANTROPIC),
+            _ => None,
         }
     }
 }
@@ -52,13 +115,65 @@ impl core::fmt::Display for Model {
 #[derive(Debug, Clone)]
 pub struct ChatContext {
     pub model: Model,
+    // Model picked in the model picker, overrides the default of `model`
+    pub model_name: Option<String>,
+    pub system_prompt: String,
+    // Temperature, top_p, max tokens and stop sequences of every request
+    pub params: GenerationParams,
+}
+
+/// Client for the Ollama server from the config
+pub(crate) fn ollama_client() -> Ollama {
+    let config = config::get();
+    Ollama::new(config.ollama_host.clone(), config.ollama_port)
 }
 
 impl ChatContext {
     pub fn new() -> Result<Self> {
-        Ok(ChatContext {
+        let mut chat_context = ChatContext {
             model: Model::OPENAI,
-        })
+            model_name: None,
+            system_prompt: system_prompt::load()?,
+            params: GenerationParams::default(),
+        };
+
+        if let Some(entry) = &config::get().default_model {
+            chat_context.select_model(entry)?;
+        }
+
+        Ok(chat_context)
+    }
+
+    /// Stores a model picker entry (`provider/name`) as the current choice.
+    pub fn select_model(&mut self, entry: &str) -> Result<()> {
+        let (provider, name) = entry
+            .split_once('/')
+            .ok_or_else(|| format!("Invalid model entry: {}", entry))?;
+
+        self.model = Model::from_provider(provider)
+            .ok_or_else(|| format!("Unknown provider: {}", provider))?;
+        self.model_name = Some(name.to_string());
+
+        Ok(())
+    }
+
+    /// Context window of the model requests to `model` will use
+    pub fn context_window(&self, model: &Model) -> usize {
+        tokens::context_window(&self.model_name(model))
+    }
+
+    /// Current choice as a model picker entry, e.g. `openai/gpt-4.1-mini`
+    pub fn model_entry(&self) -> String {
+        format!("{}/{}", self.model.provider(), self.model_name(&self.model))
+    }
+
+    /// Name sent to the provider: the picked one when it belongs to `model`,
+    /// otherwise the provider default.
+    pub fn model_name(&self, model: &Model) -> String {
+        match &self.model_name {
+            Some(name) if &self.model == model => name.clone(),
+            _ => model.clone().into(),
+        }
     }
 
     pub async fn send_to_api(self, model: Model, content: &str) -> Result<String> {
@@ -69,18 +184,32 @@ impl ChatContext {
         }
     }
 
+    /// Same as `send_to_api`, but hands every token to `on_chunk` as soon as it arrives.
+    pub async fn stream_to_api<F>(self, model: Model, content: &str, on_chunk: F) -> Result<()>
+    where
+        F: FnMut(&str),
+    {
+        match model {
+            Model::OLLAMA => self.stream_ollama(model, content, on_chunk).await,
+            Model::OPENAI => self.stream_gen_ai(model, content, on_chunk).await,
+            Model::ANTROPIC => self.stream_gen_ai(model, content, on_chunk).await,
+        }
+    }
+
     async fn request_gen_ai(self, model: Model, content: &str) -> Result<String> {
+        model.check_api_key()?;
+
         let chat_req = ChatRequest::new(vec![
-            ChatMessage::system("Questions related eather to Rust or Go language"),
+            ChatMessage::system(self.system_prompt.as_str()),
             ChatMessage::user(content),
         ]);
 
         let chat_client = Client::default();
+        let options = self.params.chat_options();
 
         let res = chat_client
-            .exec_chat(model.into(), chat_req, None)
-            .await
-            .expect("Big Problem");
+            .exec_chat(&self.model_name(&model), chat_req, Some(&options))
+            .await?;
 
         let answer = res.content_text_as_str().unwrap_or("No answer");
 
@@ -88,14 +217,65 @@ impl ChatContext {
     }
 
     async fn request_ollama(self, model: Model, content: &str) -> Result<String> {
-        let endpoint = match env::var("OLLAMA_ENDPOINT") {
-            Ok(val) => val,
-            Err(e) => return Err(crate::error::Error::Custom(e.to_string())),
-        };
+        let ollama = ollama_client();
 
-        let ollama = Ollama::new("http://localhost".to_string(), 11434);
+        let request = GenerationRequest::new(self.model_name(&model), content)
+            .system(self.system_prompt.as_str())
+            .options(self.params.ollama_options());
 
-        let response =  ollama.generate( GenerationRequest::new(model.into(), content)).await?;
+        let response = ollama.generate(request).await?;
         Ok(response.response)
     }
+
+    async fn stream_gen_ai<F>(self, model: Model, content: &str, mut on_chunk: F) -> Result<()>
+    where
+        F: FnMut(&str),
+    {
+        model.check_api_key()?;
+
+        let chat_req = ChatRequest::new(vec![
+            ChatMessage::system(self.system_prompt.as_str()),
+            ChatMessage::user(content),
+        ]);
+
+        let chat_client = Client::default();
+        let options = self.params.chat_options();
+
+        let mut stream = chat_client
+            .exec_chat_stream(&self.model_name(&model), chat_req, Some(&options))
+            .await?
+            .stream;
+
+        while let Some(event) = stream.next().await {
+            if let ChatStreamEvent::Chunk(chunk) = event? {
+                on_chunk(&chunk.content);
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn stream_ollama<F>(self, model: Model, content: &str, mut on_chunk: F) -> Result<()>
+    where
+        F: FnMut(&str),
+    {
+        let ollama = ollama_client();
+
+        let mut stream = ollama
+            .generate_stream(
+                GenerationRequest::new(self.model_name(&model), content)
+                    .system(self.system_prompt.as_str())
+                    .options(self.params.ollama_options()),
+            )
+            .await?;
+
+        // Each item is a batch of partial responses, one token each
+        while let Some(batch) = stream.next().await {
+            for response in batch? {
+                on_chunk(&response.response);
+            }
+        }
+
+        Ok(())
+    }
 } 
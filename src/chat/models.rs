@@ -0,0 +1,93 @@
+use ollama_rs::Ollama;
+use serde::Deserialize;
+use tokio_stream::StreamExt;
+
+use super::Model;
+use crate::config;
+use crate::Result;
+
+/// Models listed under `models` in the config, as `provider/name`
+pub fn configured_models() -> Result<Vec<String>> {
+    let mut models: Vec<String> = config::get()
+        .models
+        .iter()
+        // Skip providers we can't send requests to
+        .filter(|entry| {
+            entry
+                .split_once('/')
+                .and_then(|(provider, _)| Model::from_provider(provider))
+                .is_some()
+        })
+        .cloned()
+        .collect();
+    models.sort();
+
+    Ok(models)
+}
+
+/// A model installed on the Ollama server
+#[derive(Debug, Clone, Deserialize)]
+pub struct OllamaModel {
+    pub name: String,
+    /// Bytes on disk
+    pub size: u64,
+    #[serde(default)]
+    pub details: OllamaModelDetails,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct OllamaModelDetails {
+    pub parameter_size: String,
+    pub quantization_level: String,
+}
+
+#[derive(Deserialize)]
+struct Tags {
+    models: Vec<OllamaModel>,
+}
+
+impl OllamaModel {
+    /// Picker label, e.g. `gemma3:27b  17.4 GB  27.4B  Q4_K_M`
+    pub fn label(&self) -> String {
+        format!(
+            "{}  {:.1} GB  {}  {}",
+            self.name,
+            self.size as f64 / 1e9,
+            self.details.parameter_size,
+            self.details.quantization_level
+        )
+    }
+}
+
+/// Installed models with size and quantization (`GET /api/tags`). ollama-rs
+/// drops the details, so this one asks the server directly.
+pub async fn ollama_model_details() -> Result<Vec<OllamaModel>> {
+    let config = config::get();
+    let url = format!(
+        "{}:{}/api/tags",
+        config.ollama_host.trim_end_matches('/'),
+        config.ollama_port
+    );
+
+    let tags: Tags = reqwest::get(url).await?.error_for_status()?.json().await?;
+
+    Ok(tags.models)
+}
+
+/// Downloads a model (`POST /api/pull`). `on_progress` gets the status
+/// message and the completed and total bytes of the current layer.
+pub async fn pull_ollama_model<F>(name: &str, mut on_progress: F) -> Result<()>
+where
+    F: FnMut(&str, Option<u64>, Option<u64>),
+{
+    let ollama: Ollama = super::ollama_client();
+    let mut stream = ollama.pull_model_stream(name.to_string(), false).await?;
+
+    while let Some(status) = stream.next().await {
+        let status = status?;
+        on_progress(&status.message, status.completed, status.total);
+    }
+
+    Ok(())
+}
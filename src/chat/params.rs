@@ -0,0 +1,49 @@
+use genai::chat::ChatOptions;
+use ollama_rs::models::ModelOptions;
+
+/// Sampling settings sent with every request. Unset ones leave the
+/// provider's defaults alone.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GenerationParams {
+    pub temperature: Option<f64>,
+    pub top_p: Option<f64>,
+    pub max_tokens: Option<u32>,
+    // Generation ends before any of these would be written
+    pub stop: Vec<String>,
+}
+
+impl GenerationParams {
+    pub fn chat_options(&self) -> ChatOptions {
+        let mut options = ChatOptions::default();
+        if let Some(temperature) = self.temperature {
+            options = options.with_temperature(temperature);
+        }
+        if let Some(top_p) = self.top_p {
+            options = options.with_top_p(top_p);
+        }
+        if let Some(max_tokens) = self.max_tokens {
+            options = options.with_max_tokens(max_tokens);
+        }
+        if !self.stop.is_empty() {
+            options = options.with_stop_sequences(self.stop.clone());
+        }
+        options
+    }
+
+    pub fn ollama_options(&self) -> ModelOptions {
+        let mut options = ModelOptions::default();
+        if let Some(temperature) = self.temperature {
+            options = options.temperature(temperature as f32);
+        }
+        if let Some(top_p) = self.top_p {
+            options = options.top_p(top_p as f32);
+        }
+        if let Some(max_tokens) = self.max_tokens {
+            options = options.num_predict(max_tokens.min(i32::MAX as u32) as i32);
+        }
+        if !self.stop.is_empty() {
+            options = options.stop(self.stop.clone());
+        }
+        options
+    }
+}
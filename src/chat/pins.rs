@@ -0,0 +1,38 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use crate::Result;
+
+/// `<session>.pins.json` next to the session file. Session lists only read
+/// `.md` files, so it doesn't show up as a session of its own.
+fn path(session: &Path) -> PathBuf {
+    session.with_extension("pins.json")
+}
+
+/// Pinned snippets of the session stored at `session`, oldest first
+pub fn load(session: &Path) -> Result<Vec<String>> {
+    match fs::read_to_string(path(session)) {
+        Ok(json) => Ok(serde_json::from_str(&json)?),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Writes the pins, the file goes away with the last one
+pub fn save(session: &Path, pins: &[String]) -> Result<()> {
+    let path = path(session);
+    if pins.is_empty() {
+        return match fs::remove_file(&path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        };
+    }
+
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(&path, serde_json::to_string_pretty(pins)?)?;
+
+    Ok(())
+}
@@ -0,0 +1,86 @@
+use ignore::WalkBuilder;
+use std::path::Path;
+
+use crate::config;
+use crate::Result;
+
+// Keeps the listing of big workspaces from eating the context window
+const MAX_ENTRIES: usize = 300;
+
+/// File tree of the workspace put in front of prompts while enabled, so the
+/// model knows the project layout. The listing is kept until refreshed.
+#[derive(Debug, Clone, Default)]
+pub struct ProjectContext {
+    enabled: bool,
+    listing: Option<String>,
+}
+
+impl ProjectContext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Turns the context on or off, listing the tree the first time
+    pub fn toggle(&mut self) -> Result<()> {
+        self.enabled = !self.enabled;
+        if self.enabled && self.listing.is_none() {
+            self.refresh()?;
+        }
+        Ok(())
+    }
+
+    /// Lists the tree again, e.g. after files were added
+    pub fn refresh(&mut self) -> Result<()> {
+        self.listing = Some(tree(Path::new("."), config::get().project_tree_depth)?);
+        Ok(())
+    }
+
+    /// Text to put in front of the prompt, empty while disabled
+    pub fn prompt(&self) -> String {
+        match &self.listing {
+            Some(listing) if self.enabled => {
+                format!("Project layout:\n```\n{}```\n\n", listing)
+            }
+            _ => String::new(),
+        }
+    }
+}
+
+/// Indented listing of `root` down to `depth` levels, honouring
+/// `.gitignore`. Directories end with `/`.
+fn tree(root: &Path, depth: usize) -> Result<String> {
+    let walker = WalkBuilder::new(root)
+        .require_git(false)
+        .max_depth(Some(depth))
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .build();
+
+    let mut listing = String::new();
+    let mut entries = 0;
+    for entry in walker {
+        let entry = entry.map_err(|e| e.to_string())?;
+        if entry.depth() == 0 {
+            continue;
+        }
+        if entries == MAX_ENTRIES {
+            listing.push_str("...\n");
+            break;
+        }
+
+        let name = entry.file_name().to_string_lossy();
+        let slash = if entry.file_type().is_some_and(|t| t.is_dir()) {
+            "/"
+        } else {
+            ""
+        };
+        let indent = "  ".repeat(entry.depth() - 1);
+        listing.push_str(&format!("{}{}{}\n", indent, name, slash));
+        entries += 1;
+    }
+
+    Ok(listing)
+}
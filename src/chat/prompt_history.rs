@@ -0,0 +1,65 @@
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+
+use crate::config;
+use crate::Result;
+
+// Older prompts are dropped once the file holds this many
+const MAX_PROMPTS: usize = 1000;
+
+/// `<history_dir>/prompt_history`, one JSON string per line so prompts
+/// keep their line breaks
+pub fn path() -> PathBuf {
+    PathBuf::from(&config::get().history_dir).join("prompt_history")
+}
+
+/// Every sent prompt, oldest first. Unreadable lines are skipped.
+pub fn load() -> Result<Vec<String>> {
+    let text = match fs::read_to_string(path()) {
+        Ok(text) => text,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    Ok(text
+        .lines()
+        .filter_map(|line| serde_json::from_str::<String>(line).ok())
+        .collect())
+}
+
+/// Appends a sent prompt. The same prompt sent twice in a row is kept once.
+pub fn push(prompt: &str) -> Result<()> {
+    let prompt = prompt.trim();
+    if prompt.is_empty() {
+        return Ok(());
+    }
+
+    let mut prompts = load()?;
+    if prompts.last().map(String::as_str) == Some(prompt) {
+        return Ok(());
+    }
+
+    let path = path();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+
+    // Rewritten only when it has grown past the limit
+    if prompts.len() >= MAX_PROMPTS {
+        prompts.push(prompt.to_string());
+        let keep = &prompts[prompts.len() - MAX_PROMPTS..];
+        let mut text = String::new();
+        for prompt in keep {
+            text.push_str(&serde_json::to_string(prompt)?);
+            text.push('\n');
+        }
+        fs::write(&path, text)?;
+        return Ok(());
+    }
+
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    let line = serde_json::to_string(prompt)?;
+    writeln!(file, "{}", line)?;
+    Ok(())
+}
@@ -0,0 +1,314 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use chrono::Local;
+
+use super::pins;
+use super::transcript;
+use crate::config::{self, ContextTrim};
+use crate::Result;
+
+const FRONT_MATTER: &str = "---";
+const MAX_TITLE_LEN: usize = 40;
+// Start of a long conversation is enough to name it
+const TITLE_PROMPT_CHARS: usize = 4000;
+
+/// System prompt of title requests
+pub const TITLE_SYSTEM_PROMPT: &str =
+    "You name conversations. Answer with a title of at most six words and nothing else.";
+
+/// One conversation, stored in `<history_dir>/sessions/<timestamp>.md` with a
+/// small front matter block for the metadata.
+#[derive(Debug, Clone)]
+pub struct Session {
+    pub path: PathBuf,
+    pub title: String,
+    /// Model picker entry the conversation was last saved with
+    pub model: String,
+    pub created: String,
+    /// Snippets kept at hand to reuse in prompts, stored next to the file
+    pub pins: Vec<String>,
+    /// Overrides `context_trim` of the config
+    pub context_trim: Option<ContextTrim>,
+    /// Without the `#`, stored as `tags: rust, devops`
+    pub tags: Vec<String>,
+    /// Last write of the file, only known for listed sessions
+    pub modified: Option<SystemTime>,
+}
+
+/// `<history_dir>/sessions`
+pub fn dir() -> PathBuf {
+    PathBuf::from(&config::get().history_dir).join("sessions")
+}
+
+/// Saved sessions, the last modified first
+pub fn list() -> Result<Vec<Session>> {
+    let entries = match fs::read_dir(dir()) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut sessions = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) != Some("md") {
+            continue;
+        }
+
+        let content = fs::read_to_string(&path)?;
+        let mut session = Session::parse(&path, &content).0;
+        session.modified = fs::metadata(&path).and_then(|m| m.modified()).ok();
+        sessions.push(session);
+    }
+
+    // Timestamp file names sort by age when the times are equal
+    sessions.sort_by(|a, b| b.modified.cmp(&a.modified).then_with(|| b.path.cmp(&a.path)));
+
+    Ok(sessions)
+}
+
+impl Session {
+    /// A fresh session. The file is only written on the first save.
+    pub fn new(model: String) -> Self {
+        let now = Local::now();
+        let name = format!("{}.md", now.format("%Y-%m-%d_%H-%M-%S"));
+
+        Self {
+            path: dir().join(name),
+            title: String::new(),
+            model,
+            created: now.format("%Y-%m-%d %H:%M").to_string(),
+            pins: Vec::new(),
+            context_trim: None,
+            tags: Vec::new(),
+            modified: None,
+        }
+    }
+
+    /// Session stored at `path`, fresh metadata when the file doesn't exist yet
+    pub fn open(path: &Path) -> Result<Self> {
+        let mut session = match fs::read_to_string(path) {
+            Ok(content) => Self::parse(path, &content).0,
+            Err(e) if e.kind() == ErrorKind::NotFound => Self {
+                path: path.to_path_buf(),
+                ..Self::new(String::new())
+            },
+            Err(e) => return Err(e.into()),
+        };
+        session.pins = pins::load(path)?;
+
+        Ok(session)
+    }
+
+    /// Splits a session file into its metadata and the conversation. Files
+    /// without front matter are taken as a conversation only.
+    pub fn parse(path: &Path, content: &str) -> (Self, String) {
+        let mut session = Self {
+            path: path.to_path_buf(),
+            title: String::new(),
+            model: String::new(),
+            created: String::new(),
+            pins: Vec::new(),
+            context_trim: None,
+            tags: Vec::new(),
+            modified: None,
+        };
+
+        let Some(rest) = content.strip_prefix(&format!("{}\n", FRONT_MATTER)) else {
+            return (session, content.to_string());
+        };
+        let Some((header, body)) = rest.split_once(&format!("\n{}\n", FRONT_MATTER)) else {
+            return (session, content.to_string());
+        };
+
+        for line in header.lines() {
+            if let Some((key, value)) = line.split_once(':') {
+                let value = value.trim().to_string();
+                match key.trim() {
+                    "title" => session.title = value,
+                    "model" => session.model = value,
+                    "created" => session.created = value,
+                    "context_trim" => session.context_trim = ContextTrim::from_name(&value),
+                    "tags" => session.tags = parse_tags(&value),
+                    _ => {}
+                }
+            }
+        }
+
+        (session, body.to_string())
+    }
+
+    /// Reads the conversation; a session that was never saved is empty
+    pub fn load(&self) -> Result<String> {
+        match fs::read_to_string(&self.path) {
+            Ok(content) => Ok(Self::parse(&self.path, &content).1),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(String::new()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Writes metadata and conversation. An untitled session is named after
+    /// its first question.
+    pub fn save(&mut self, conversation: &str) -> Result<()> {
+        if self.title.is_empty() {
+            self.title = title_from(conversation);
+        }
+        self.write(conversation)
+    }
+
+    /// Renames the session. A saved one gets its front matter rewritten with
+    /// the conversation on disk, unsaved edits stay unsaved. The file keeps
+    /// its timestamp name, the picker sorts by it.
+    pub fn set_title(&mut self, title: &str) -> Result<()> {
+        self.title = clean_title(title);
+        self.write_metadata()
+    }
+
+    /// Sets how the session makes room in a full context window, stored
+    /// right away like a new title
+    pub fn set_context_trim(&mut self, context_trim: ContextTrim) -> Result<()> {
+        self.context_trim = Some(context_trim);
+        self.write_metadata()
+    }
+
+    /// Replaces the tags with the ones in `text`, `#rust #devops` or
+    /// `rust, devops`. Stored right away like a new title.
+    pub fn set_tags(&mut self, text: &str) -> Result<()> {
+        self.tags = parse_tags(text);
+        self.write_metadata()
+    }
+
+    /// Whether the session has `tag`, ignoring case and the `#`
+    pub fn has_tag(&self, tag: &str) -> bool {
+        let tag = tag.trim_start_matches('#');
+        self.tags.iter().any(|t| t.eq_ignore_ascii_case(tag))
+    }
+
+    /// Tags as typed in the tag input: `#rust #devops`
+    pub fn tag_line(&self) -> String {
+        self.tags.iter().map(|tag| format!("#{}", tag)).collect::<Vec<_>>().join(" ")
+    }
+
+    // Front matter of a saved session, with the conversation on disk
+    fn write_metadata(&self) -> Result<()> {
+        if !self.path.exists() {
+            return Ok(());
+        }
+        let conversation = self.load()?;
+        self.write(&conversation)
+    }
+
+    /// Pins `text`, or unpins it when it's pinned already. Returns whether
+    /// it's pinned now.
+    pub fn toggle_pin(&mut self, text: &str) -> Result<bool> {
+        let pinned = match self.pins.iter().position(|pin| pin == text) {
+            Some(index) => {
+                self.pins.remove(index);
+                false
+            }
+            None => {
+                self.pins.push(text.to_string());
+                true
+            }
+        };
+        pins::save(&self.path, &self.pins)?;
+
+        Ok(pinned)
+    }
+
+    fn write(&self, conversation: &str) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+
+        // Only written once it's switched, the config decides until then
+        let context_trim = self
+            .context_trim
+            .map(|trim| format!("context_trim: {}\n", trim.name()))
+            .unwrap_or_default();
+        let tags = if self.tags.is_empty() {
+            String::new()
+        } else {
+            format!("tags: {}\n", self.tags.join(", "))
+        };
+        let content = format!(
+            "{fm}\ntitle: {}\nmodel: {}\ncreated: {}\n{}{}{fm}\n{}",
+            self.title,
+            self.model,
+            self.created,
+            context_trim,
+            tags,
+            conversation,
+            fm = FRONT_MATTER,
+        );
+        fs::write(&self.path, content)?;
+
+        Ok(())
+    }
+
+    /// Picker label: title, model, creation time and the tags
+    pub fn label(&self) -> String {
+        let title = if self.title.is_empty() {
+            "Untitled"
+        } else {
+            self.title.as_str()
+        };
+
+        let label = format!("{} | {} | {}", self.created, title, self.model);
+        if self.tags.is_empty() {
+            label
+        } else {
+            format!("{} | {}", label, self.tag_line())
+        }
+    }
+}
+
+// Words split at blanks and commas, without the `#`, each one once
+fn parse_tags(text: &str) -> Vec<String> {
+    let mut tags: Vec<String> = Vec::new();
+    for word in text.split(|c: char| c.is_whitespace() || c == ',') {
+        let tag = word.trim_start_matches('#');
+        if !tag.is_empty() && !tags.iter().any(|t| t.eq_ignore_ascii_case(tag)) {
+            tags.push(tag.to_string());
+        }
+    }
+    tags
+}
+
+/// Asks for a title of `conversation`, sent with `TITLE_SYSTEM_PROMPT`
+pub fn title_prompt(conversation: &str) -> String {
+    let start: String = conversation.chars().take(TITLE_PROMPT_CHARS).collect();
+    format!("Give this conversation a short title:\n\n{}", start)
+}
+
+// One line without the quotes and period models like to add
+fn clean_title(title: &str) -> String {
+    title
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .unwrap_or("")
+        .trim_start_matches('#')
+        .trim()
+        .trim_matches(|c| c == '"' || c == '\'' || c == '*')
+        .trim_end_matches('.')
+        .chars()
+        .take(MAX_TITLE_LEN)
+        .collect()
+}
+
+/// First line of the conversation that isn't a role header
+pub fn title_from(conversation: &str) -> String {
+    conversation
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && transcript::role(line).is_none())
+        .map(|line| line.trim_start_matches('#').trim())
+        .unwrap_or("")
+        .chars()
+        .take(MAX_TITLE_LEN)
+        .collect()
+}
@@ -0,0 +1,30 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+
+use crate::config;
+use crate::Result;
+
+/// `<history_dir>/system_prompt.md`, so every project keeps its own prompt
+pub fn path() -> PathBuf {
+    PathBuf::from(&config::get().history_dir).join("system_prompt.md")
+}
+
+/// The saved system prompt, or the one from the config when none was saved
+pub fn load() -> Result<String> {
+    match fs::read_to_string(path()) {
+        Ok(prompt) => Ok(prompt),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(config::get().system_prompt.clone()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+pub fn save(prompt: &str) -> Result<()> {
+    let path = path();
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(path, prompt)?;
+
+    Ok(())
+}
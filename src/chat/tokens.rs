@@ -0,0 +1,34 @@
+use crate::config;
+
+// Roughly what OpenAI's tokenizers average on English text and code
+const CHARS_PER_TOKEN: usize = 4;
+
+/// Heuristic token count for `chars` characters of text
+pub fn estimate_tokens(chars: usize) -> usize {
+    chars.div_ceil(CHARS_PER_TOKEN)
+}
+
+/// Characters that fit into `tokens`, the inverse of `estimate_tokens`
+pub fn chars_for_tokens(tokens: usize) -> usize {
+    tokens * CHARS_PER_TOKEN
+}
+
+/// Context window of a model, from `context_windows` in the config or a
+/// table of well known model families.
+pub fn context_window(model_name: &str) -> usize {
+    if let Some(limit) = config::get().context_windows.get(model_name) {
+        return *limit;
+    }
+
+    match model_name {
+        name if name.starts_with("gpt-4.1") => 1_047_576,
+        name if name.starts_with("gpt-4o") => 128_000,
+        name if name.starts_with("o1") || name.starts_with("o3") || name.starts_with("o4") => {
+            200_000
+        }
+        name if name.starts_with("claude") => 200_000,
+        name if name.starts_with("gemma3") => 128_000,
+        // Ollama's default num_ctx
+        _ => 8_192,
+    }
+}
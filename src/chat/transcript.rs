@@ -0,0 +1,48 @@
+use chrono::Local;
+
+const USER: &str = "## User";
+const ASSISTANT: &str = "## Assistant";
+
+/// `## User · 2025-06-01 14:02`, the line above a prompt
+pub fn user_header() -> String {
+    format!("{} · {}", USER, timestamp())
+}
+
+/// `## Assistant (openai/gpt-4.1-mini) · 2025-06-01 14:02`, the line above
+/// an answer
+pub fn assistant_header(model: &str) -> String {
+    format!("{} ({}) · {}", ASSISTANT, model, timestamp())
+}
+
+/// Header of a message without a model or time, for imported conversations
+pub fn plain_header(role: &str) -> &'static str {
+    if role == "user" {
+        USER
+    } else {
+        ASSISTANT
+    }
+}
+
+/// `user` or `assistant` for a message header line. The bare `User` and
+/// `Assistant` lines of older sessions count too.
+pub fn role(line: &str) -> Option<&'static str> {
+    let line = line.trim_end();
+    let header = |prefix: &str| {
+        line == prefix
+            || line
+                .strip_prefix(prefix)
+                .is_some_and(|rest| rest.starts_with(' '))
+    };
+
+    if header(USER) || line == "User" {
+        Some("user")
+    } else if header(ASSISTANT) || line == "Assistant" {
+        Some("assistant")
+    } else {
+        None
+    }
+}
+
+fn timestamp() -> String {
+    Local::now().format("%Y-%m-%d %H:%M").to_string()
+}
@@ -0,0 +1,85 @@
+use super::tokens;
+use super::transcript;
+
+/// Room kept for the summary of dropped messages
+pub const SUMMARY_TOKENS: usize = 400;
+
+/// System prompt of summary requests
+pub const SUMMARY_SYSTEM_PROMPT: &str = "You summarize conversations for a model that can't \
+see them. Keep names, decisions, code identifiers and open questions. Answer in at most 200 \
+words and nothing else.";
+
+/// A conversation cut to fit a budget
+#[derive(Debug, Clone, Default)]
+pub struct Fitted {
+    /// What's sent
+    pub text: String,
+    /// Oldest messages left out, empty when everything fits
+    pub dropped: String,
+}
+
+impl Fitted {
+    /// Estimated tokens left out
+    pub fn dropped_tokens(&self) -> usize {
+        tokens::estimate_tokens(self.dropped.chars().count())
+    }
+}
+
+/// Keeps the newest messages of `conversation` that fit into `max_chars`,
+/// dropping whole messages from the start. A message too long on its own,
+/// usually the question being asked, loses its start.
+pub fn fit(conversation: &str, max_chars: usize) -> Fitted {
+    let len = conversation.chars().count();
+    if len <= max_chars {
+        return Fitted {
+            text: conversation.to_string(),
+            dropped: String::new(),
+        };
+    }
+
+    // Byte offsets of the message header lines
+    let mut offset = 0;
+    let mut starts = Vec::new();
+    for line in conversation.split_inclusive('\n') {
+        if transcript::role(line).is_some() {
+            starts.push(offset);
+        }
+        offset += line.len();
+    }
+
+    let cut = starts
+        .into_iter()
+        .find(|&start| conversation[start..].chars().count() <= max_chars)
+        .unwrap_or_else(|| {
+            let skip = len - max_chars;
+            conversation
+                .char_indices()
+                .nth(skip)
+                .map_or(conversation.len(), |(byte, _)| byte)
+        });
+
+    Fitted {
+        text: conversation[cut..].to_string(),
+        dropped: conversation[..cut].to_string(),
+    }
+}
+
+/// Asks for a summary of `dropped`, on top of the summary of what was
+/// dropped before it
+pub fn summary_prompt(previous: Option<&str>, dropped: &str) -> String {
+    match previous {
+        Some(previous) => format!(
+            "Summary of the conversation so far:\n\n{}\n\nExtend it with these later messages:\n\n{}",
+            previous, dropped
+        ),
+        None => format!("Summarize this conversation:\n\n{}", dropped),
+    }
+}
+
+/// Stands in for the dropped messages at the start of a request
+pub fn summary_note(summary: &str) -> String {
+    format!(
+        "(Earlier messages were left out to fit the context window. Their summary: {})\n\n",
+        summary.trim()
+    )
+}
@@ -0,0 +1,223 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+
+use super::tokens;
+use crate::config;
+use crate::Result;
+
+/// Requests, estimated tokens and cost of one provider
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Totals {
+    pub requests: u64,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    /// USD, from the price of each request's model
+    pub cost: f64,
+    /// Requests refused with HTTP 429
+    pub rate_limited: u64,
+}
+
+impl Totals {
+    fn add(&mut self, input: u64, output: u64, cost: f64) {
+        self.requests += 1;
+        self.input_tokens += input;
+        self.output_tokens += output;
+        self.cost += cost;
+    }
+
+    // `openai  12 requests  40.2k in  8.1k out  $0.0290  (1 rate limited)`
+    fn line(&self, provider: &str) -> String {
+        let limited = match self.rate_limited {
+            0 => String::new(),
+            n => format!("  ({} rate limited)", n),
+        };
+        format!(
+            "{:<10} {:>4} requests {:>8} in {:>8} out  ${:.4}{}",
+            provider,
+            self.requests,
+            short(self.input_tokens),
+            short(self.output_tokens),
+            self.cost,
+            limited
+        )
+    }
+}
+
+// `usage.json`: totals per month (`2025-06`) and provider
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct UsageFile {
+    months: BTreeMap<String, BTreeMap<String, Totals>>,
+}
+
+/// Usage of this run and of the month, the month's persisted to
+/// `<history_dir>/usage.json` after every request
+#[derive(Debug, Clone, Default)]
+pub struct Usage {
+    file: UsageFile,
+    session: BTreeMap<String, Totals>,
+    // The budget warning shows once per run
+    warned: bool,
+}
+
+impl Usage {
+    /// Reads `usage.json`, empty when there's none or it can't be read
+    pub fn load() -> Self {
+        let file = fs::read_to_string(path())
+            .ok()
+            .and_then(|json| serde_json::from_str(&json).ok())
+            .unwrap_or_default();
+        Self {
+            file,
+            ..Self::default()
+        }
+    }
+
+    /// Adds a finished request of `label` (`provider/name`), with `sent` and
+    /// `received` chars. Returns a warning when the month's cost goes over
+    /// `monthly_budget`.
+    pub fn record(&mut self, label: &str, sent: usize, received: usize) -> Option<String> {
+        let (provider, model) = label.split_once('/').unwrap_or((label, label));
+        let input = tokens::estimate_tokens(sent) as u64;
+        let output = tokens::estimate_tokens(received) as u64;
+        let cost = cost(model, input, output);
+
+        self.session_entry(provider).add(input, output, cost);
+        self.month_entry(provider).add(input, output, cost);
+        // Best effort, this run's stats have it either way
+        let _ = self.save();
+
+        let budget = config::get().monthly_budget;
+        let spent = self.month_cost();
+        if budget > 0.0 && spent > budget && !self.warned {
+            self.warned = true;
+            return Some(format!(
+                "Monthly budget exceeded: ${:.2} of ${:.2} spent",
+                spent, budget
+            ));
+        }
+        None
+    }
+
+    /// Counts a request of `label` the provider refused for its rate limit
+    pub fn record_rate_limit(&mut self, label: &str) {
+        let provider = label.split_once('/').map_or(label, |(provider, _)| provider);
+        self.session_entry(provider).rate_limited += 1;
+        self.month_entry(provider).rate_limited += 1;
+        let _ = self.save();
+    }
+
+    /// Estimated cost of the current month, every provider together
+    pub fn month_cost(&self) -> f64 {
+        self.file
+            .months
+            .get(&month())
+            .map_or(0.0, |providers| providers.values().map(|t| t.cost).sum())
+    }
+
+    /// Lines of the stats popup: this run, then the month and its budget
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines = vec!["This session".to_string()];
+        if self.session.is_empty() {
+            lines.push("  No requests yet".to_string());
+        }
+        for (provider, totals) in &self.session {
+            lines.push(format!("  {}", totals.line(provider)));
+        }
+
+        lines.push(String::new());
+        lines.push(format!("This month ({})", month()));
+        match self.file.months.get(&month()) {
+            Some(providers) => {
+                for (provider, totals) in providers {
+                    lines.push(format!("  {}", totals.line(provider)));
+                }
+            }
+            None => lines.push("  No requests yet".to_string()),
+        }
+
+        let budget = config::get().monthly_budget;
+        if budget > 0.0 {
+            lines.push(format!("  Budget: ${:.2} of ${:.2}", self.month_cost(), budget));
+        }
+        lines.push(String::new());
+        lines.push("Tokens are estimated from the text, costs from list prices".to_string());
+        lines
+    }
+
+    fn session_entry(&mut self, provider: &str) -> &mut Totals {
+        self.session.entry(provider.to_string()).or_default()
+    }
+
+    fn month_entry(&mut self, provider: &str) -> &mut Totals {
+        self.file
+            .months
+            .entry(month())
+            .or_default()
+            .entry(provider.to_string())
+            .or_default()
+    }
+
+    fn save(&self) -> Result<()> {
+        let path = path();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let json = serde_json::to_string_pretty(&self.file).map_err(|e| e.to_string())?;
+        fs::write(path, json)?;
+        Ok(())
+    }
+}
+
+/// USD for `input` and `output` tokens of `model`, 0 for local and unknown
+/// models
+pub fn cost(model: &str, input: u64, output: u64) -> f64 {
+    let (input_price, output_price) = price(model).unwrap_or((0.0, 0.0));
+    (input as f64 * input_price + output as f64 * output_price) / 1_000_000.0
+}
+
+// USD per million input and output tokens, from `prices` in the config or
+// the list prices of well known models
+fn price(model: &str) -> Option<(f64, f64)> {
+    if let Some([input, output]) = config::get().prices.get(model) {
+        return Some((*input, *output));
+    }
+
+    let price = match model {
+        name if name.starts_with("gpt-4.1-nano") => (0.10, 0.40),
+        name if name.starts_with("gpt-4.1-mini") => (0.40, 1.60),
+        name if name.starts_with("gpt-4.1") => (2.00, 8.00),
+        name if name.starts_with("gpt-4o-mini") => (0.15, 0.60),
+        name if name.starts_with("gpt-4o") => (2.50, 10.00),
+        name if name.starts_with("o4-mini") => (1.10, 4.40),
+        name if name.starts_with("o3") => (2.00, 8.00),
+        name if name.starts_with("o1") => (15.00, 60.00),
+        name if name.contains("haiku") => (0.80, 4.00),
+        name if name.contains("sonnet") => (3.00, 15.00),
+        name if name.contains("opus") => (15.00, 75.00),
+        _ => return None,
+    };
+    Some(price)
+}
+
+// `<history_dir>/usage.json`
+fn path() -> PathBuf {
+    PathBuf::from(&config::get().history_dir).join("usage.json")
+}
+
+fn month() -> String {
+    Local::now().format("%Y-%m").to_string()
+}
+
+// `812`, `40.2k`, `1.3M`
+fn short(count: u64) -> String {
+    match count {
+        0..=999 => count.to_string(),
+        1_000..=999_999 => format!("{:.1}k", count as f64 / 1_000.0),
+        _ => format!("{:.1}M", count as f64 / 1_000_000.0),
+    }
+}
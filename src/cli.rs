@@ -0,0 +1,163 @@
+use std::io::{self, IsTerminal};
+
+use clap::{Arg, ArgAction, Command};
+
+use crate::chat::Model;
+use crate::config::Config;
+use crate::error::Result;
+
+/// `rusty_ai [PATH] [--view] [--model ..] [--endpoint ..] [--theme ..] [--session ..]
+/// [--history-dir ..]`
+/// or `rusty_ai ask [QUESTION]`
+#[derive(Debug, Clone, Default)]
+pub struct Args {
+    /// File to open instead of a new session
+    pub path: Option<String>,
+    /// Opens `path` read-only, without highlighting
+    pub view: bool,
+    /// Session to resume, a path or a file name in `<history_dir>/sessions`
+    pub session: Option<String>,
+    /// `ask`: print the answer to `question` instead of opening the editor
+    pub ask: bool,
+    pub question: Option<String>,
+    model: Option<String>,
+    endpoint: Option<String>,
+    theme: Option<String>,
+    history_dir: Option<String>,
+}
+
+impl Args {
+    /// Reads the command line. `--help`, `--version` and usage errors print
+    /// and exit here, before the terminal is touched.
+    pub fn parse() -> Self {
+        let matches = Command::new("rusty_ai")
+            .version(env!("CARGO_PKG_VERSION"))
+            .about("Terminal editor for conversations with Ollama, OpenAI and Anthropic models")
+            .arg(
+                Arg::new("path")
+                    .value_name("PATH")
+                    .help("File to open instead of a new session"),
+            )
+            .arg(
+                Arg::new("view")
+                    .long("view")
+                    .short('R')
+                    .action(ArgAction::SetTrue)
+                    .requires("path")
+                    .help("Open PATH read-only without highlighting, for huge logs and generated files"),
+            )
+            .arg(
+                Arg::new("model")
+                    .long("model")
+                    .global(true)
+                    .value_name("PROVIDER/NAME")
+                    .help("Model to start with, e.g. ollama/gemma3:27b"),
+            )
+            .arg(
+                Arg::new("endpoint")
+                    .long("endpoint")
+                    .global(true)
+                    .value_name("URL")
+                    .help("Ollama server, e.g. http://localhost:11434"),
+            )
+            .arg(
+                Arg::new("theme")
+                    .long("theme")
+                    .global(true)
+                    .value_name("NAME")
+                    .help("dark, light or the name of a file in ~/.rusty/themes"),
+            )
+            .arg(
+                Arg::new("session")
+                    .long("session")
+                    .value_name("NAME")
+                    .help("Session to resume, a path or a file name in <history_dir>/sessions")
+                    .conflicts_with("path"),
+            )
+            .arg(
+                Arg::new("history_dir")
+                    .long("history-dir")
+                    .global(true)
+                    .value_name("DIR")
+                    .help("Directory for conversations instead of the project's .rusty or ~/.local/share/rusty_ai"),
+            )
+            .subcommand(
+                Command::new("ask")
+                    .about("Print the answer to a question and exit; piped stdin is added to it")
+                    .arg(
+                        Arg::new("question")
+                            .value_name("QUESTION")
+                            .help("Read from stdin when left out"),
+                    ),
+            )
+            .get_matches();
+
+        let ask = matches.subcommand_matches("ask");
+        let value = |name: &str| matches.get_one::<String>(name).cloned();
+        Self {
+            path: value("path"),
+            view: matches.get_flag("view"),
+            session: value("session"),
+            ask: ask.is_some(),
+            question: ask.and_then(|ask| ask.get_one::<String>("question").cloned()),
+            model: value("model"),
+            endpoint: value("endpoint"),
+            theme: value("theme"),
+            history_dir: value("history_dir"),
+        }
+    }
+
+    /// `ask`, or a prompt piped in with nothing to open
+    pub fn headless(&self) -> bool {
+        self.ask || (self.path.is_none() && self.session.is_none() && !io::stdin().is_terminal())
+    }
+
+    /// Puts the flags over the settings of the config file
+    pub fn apply(&self, config: &mut Config) -> Result<()> {
+        if let Some(model) = &self.model {
+            // Checked here, a bad entry would only fail once the editor is up
+            let provider = model.split_once('/').map(|(provider, _)| provider);
+            if provider.and_then(Model::from_provider).is_none() {
+                return Err(format!(
+                    "Invalid model {}, expected ollama/, openai/ or anthropic/ and a name",
+                    model
+                )
+                .into());
+            }
+            config.default_model = Some(model.clone());
+        }
+
+        if let Some(endpoint) = &self.endpoint {
+            let (host, port) = parse_endpoint(endpoint)?;
+            config.ollama_host = host;
+            config.ollama_port = port;
+        }
+
+        if let Some(theme) = &self.theme {
+            config.color_theme = theme.clone();
+        }
+
+        if let Some(dir) = &self.history_dir {
+            config.history_dir = dir.clone();
+        }
+
+        Ok(())
+    }
+}
+
+// `http://host:11434` into the host with its scheme and the port. Without a
+// scheme it's http, without a port Ollama's default.
+fn parse_endpoint(endpoint: &str) -> Result<(String, u16)> {
+    let trimmed = endpoint.trim_end_matches('/');
+    let (scheme, address) = trimmed.split_once("://").unwrap_or(("http", trimmed));
+
+    match address.rsplit_once(':') {
+        Some((host, port)) => {
+            let port = port
+                .parse()
+                .map_err(|_| format!("Invalid port in endpoint {}", endpoint))?;
+            Ok((format!("{}://{}", scheme, host), port))
+        }
+        None => Ok((format!("{}://{}", scheme, address), 11434)),
+    }
+}
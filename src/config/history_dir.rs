@@ -0,0 +1,95 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::Result;
+
+// Name of a project's history directory, also the config directory in home
+const PROJECT_DIR: &str = ".rusty";
+// Settings of `~/.rusty`, everything else there is history of old versions
+const SETTINGS: [&str; 3] = ["config.toml", "themes", "snippets.toml"];
+
+/// History directory to use: `configured` (from the config or command line,
+/// `~/` meaning home) when set, else the `.rusty` of the working directory
+/// or the nearest one above it, else `~/.local/share/rusty_ai`. Also returns
+/// a note when history was moved there from `~/.rusty`.
+pub(super) fn resolve(configured: &str) -> (String, Option<String>) {
+    if !configured.is_empty() {
+        let dir = match (configured.strip_prefix("~/"), dirs::home_dir()) {
+            (Some(rest), Some(home)) => home.join(rest).to_string_lossy().into_owned(),
+            _ => configured.to_string(),
+        };
+        return (dir, None);
+    }
+    if let Some(dir) = project_dir() {
+        return (dir.to_string_lossy().into_owned(), None);
+    }
+
+    let dir = data_dir();
+    let note = match migrate(&dir) {
+        Ok(note) => note,
+        Err(e) => Some(format!("Can't move old history to {}: {}", dir.display(), e)),
+    };
+    (dir.to_string_lossy().into_owned(), note)
+}
+
+// `.rusty` in the working directory, relative as before, or above it. The
+// one in home holds the config and doesn't count.
+fn project_dir() -> Option<PathBuf> {
+    if Path::new(PROJECT_DIR).is_dir() && !is_config_dir(Path::new(PROJECT_DIR)) {
+        return Some(PathBuf::from(PROJECT_DIR));
+    }
+    let cwd = env::current_dir().ok()?;
+    cwd.ancestors()
+        .skip(1)
+        .map(|dir| dir.join(PROJECT_DIR))
+        .find(|dir| dir.is_dir() && !is_config_dir(dir))
+}
+
+fn is_config_dir(dir: &Path) -> bool {
+    let home = dirs::home_dir().map(|home| home.join(PROJECT_DIR));
+    match (fs::canonicalize(dir), home.map(fs::canonicalize)) {
+        (Ok(dir), Some(Ok(home))) => dir == home,
+        _ => false,
+    }
+}
+
+/// `~/.local/share/rusty_ai`, or where the platform keeps app data
+fn data_dir() -> PathBuf {
+    dirs::data_dir()
+        .map(|dir| dir.join("rusty_ai"))
+        .unwrap_or_else(|| PathBuf::from(PROJECT_DIR))
+}
+
+// Older versions wrote history to `.rusty` of the working directory, which
+// is `~/.rusty` next to the config when started from home. It moves to
+// `dir` the first time, before `dir` exists.
+fn migrate(dir: &Path) -> Result<Option<String>> {
+    let Some(legacy) = dirs::home_dir().map(|home| home.join(PROJECT_DIR)) else {
+        return Ok(None);
+    };
+    if dir.exists() || !legacy.is_dir() {
+        return Ok(None);
+    }
+
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(&legacy)? {
+        let entry = entry?;
+        if !matches!(entry.file_name().to_str(), Some(name) if SETTINGS.contains(&name)) {
+            entries.push(entry.file_name());
+        }
+    }
+    if entries.is_empty() {
+        return Ok(None);
+    }
+
+    fs::create_dir_all(dir)?;
+    for name in &entries {
+        fs::rename(legacy.join(name), dir.join(name))?;
+    }
+    Ok(Some(format!(
+        "Moved history from {} to {}",
+        legacy.display(),
+        dir.display()
+    )))
+}
@@ -0,0 +1,308 @@
+mod history_dir;
+pub mod theme;
+
+use once_cell::sync::OnceCell;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use crate::error::Result;
+
+static CONFIG: OnceCell<Config> = OnceCell::new();
+
+// For providers missing from `request_timeouts`
+const DEFAULT_REQUEST_TIMEOUT_SECS: u64 = 120;
+
+/// Settings read from `~/.rusty/config.toml`. Every field is optional in the
+/// file; missing ones keep the defaults below.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Model picker entry used on startup, e.g. `ollama/gemma3:27b`
+    pub default_model: Option<String>,
+    /// Extra entries for the model picker, same `provider/name` format
+    pub models: Vec<String>,
+    /// Models asked the same question at once from the AI menu, to compare
+    /// their answers
+    pub fan_out: Vec<String>,
+    /// Used until one is saved from the AI menu to `<history_dir>/system_prompt.md`
+    pub system_prompt: String,
+    /// Context window per model name, for models the built-in table gets wrong
+    pub context_windows: HashMap<String, usize>,
+    /// USD per million input and output tokens per model name, for the
+    /// usage stats of models the built-in table doesn't know
+    pub prices: HashMap<String, [f64; 2]>,
+    /// Estimated USD per month after which a warning shows, 0 turns it off
+    pub monthly_budget: f64,
+
+    pub ollama_host: String,
+    pub ollama_port: u16,
+
+    /// Seconds a request may run per provider (`openai`, `anthropic`,
+    /// `ollama`) before it's given up, 0 waits forever. A streamed answer
+    /// runs on as long as tokens keep coming.
+    pub request_timeouts: HashMap<String, u64>,
+
+    /// Directory for conversation files, `--history-dir` on the command line.
+    /// Empty uses the `.rusty` of the project, in the working directory or
+    /// one above it, otherwise `~/.local/share/rusty_ai`.
+    pub history_dir: String,
+    /// Ask the model for a session title after the first answer
+    pub auto_title: bool,
+
+    /// Where answers go: `end` of the buffer, `cursor` or `paragraph`
+    /// (below the paragraph under the cursor). Switched from the AI menu.
+    pub response_insertion: Insertion,
+
+    /// What makes room when the conversation outgrows the context window:
+    /// `drop` the oldest messages or `summarize` them. Sessions can switch
+    /// it from the AI menu.
+    pub context_trim: ContextTrim,
+
+    /// Directory levels of the project layout sent with prompts while it's
+    /// turned on in the AI menu
+    pub project_tree_depth: usize,
+
+    /// Language server for Rust files and code blocks, started by the first
+    /// completion (Ctrl+Space in Insert mode)
+    pub lsp_command: String,
+
+    /// After a pause in typing at the end of a line in a code block, the
+    /// model suggests how it goes on, dim after the cursor; Tab takes it.
+    /// Switched from the AI menu.
+    pub inline_completion: bool,
+    /// Pause in milliseconds before a completion is asked for
+    pub inline_completion_delay_ms: u64,
+
+    /// Insert mode closes `(`, `[`, `{` and `"` as they're typed, and adds
+    /// the closing fence to a ``` typed at the start of a line
+    pub auto_pairs: bool,
+
+    /// Enter keeps the indent of the line, one level deeper after an
+    /// opening bracket in a code block
+    pub auto_indent: bool,
+    /// One indent level per fence tag, e.g. `go = "\t"`; 4 spaces for the
+    /// others. An empty string keeps the indent as it is.
+    pub indent: HashMap<String, String>,
+
+    /// Words replaced as space is typed after them in Insert mode, outside
+    /// of code blocks, e.g. `expl = "Explain the following code step by step:"`
+    pub abbreviations: HashMap<String, String>,
+
+    /// Dim background across the cursor's row
+    pub highlight_cursor_line: bool,
+    /// Column marked with a ruler, e.g. 80; 0 turns it off
+    pub ruler_column: usize,
+    /// Blanks at the end of lines get `trailing_whitespace_bg`
+    pub show_trailing_whitespace: bool,
+    /// Saving strips blanks at the end of lines, outside of read-only
+    /// answers, and ends the file with a newline
+    pub trim_on_save: bool,
+    /// Position in the buffer shown in the rightmost column
+    pub scrollbar: bool,
+
+    /// Milliseconds between two frames
+    pub frame_duration_ms: u64,
+
+    /// Seconds between two swap file writes of unsaved buffers, 0 turns it off
+    pub autosave_interval_secs: u64,
+
+    /// `dark`, `light` or the name of a file in `~/.rusty/themes`
+    pub color_theme: String,
+    /// Colors changed on top of the active theme, e.g. `keyword = "red"`
+    pub theme: toml::Table,
+
+    /// Normal/Select mode key remaps: `"ö" = "/"` makes `ö` act like `/`
+    pub keymap: HashMap<String, String>,
+    /// Commands bound per mode or menu (`normal`, `select`, `goto`, `fold`,
+    /// `file`, `ai`), e.g. `[keys.normal]` `"ctrl+a" = "ai_menu"`. A key
+    /// replaces the default on that key, `"none"` unbinds it. Checked at
+    /// startup; `?` shows the result.
+    pub keys: HashMap<String, HashMap<String, String>>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            default_model: None,
+            models: Vec::new(),
+            fan_out: Vec::new(),
+            system_prompt: "Questions related eather to Rust or Go language".to_string(),
+            context_windows: HashMap::new(),
+            prices: HashMap::new(),
+            monthly_budget: 0.0,
+            ollama_host: "http://localhost".to_string(),
+            ollama_port: 11434,
+            // Local models may take a while to load
+            request_timeouts: HashMap::from([
+                ("openai".to_string(), 120),
+                ("anthropic".to_string(), 120),
+                ("ollama".to_string(), 300),
+            ]),
+            history_dir: String::new(),
+            auto_title: true,
+            response_insertion: Insertion::End,
+            context_trim: ContextTrim::Drop,
+            project_tree_depth: 3,
+            lsp_command: "rust-analyzer".to_string(),
+            inline_completion: false,
+            inline_completion_delay_ms: 600,
+            auto_pairs: true,
+            auto_indent: true,
+            indent: HashMap::from([
+                ("go".to_string(), "\t".to_string()),
+                ("golang".to_string(), "\t".to_string()),
+                ("makefile".to_string(), "\t".to_string()),
+            ]),
+            abbreviations: HashMap::new(),
+            highlight_cursor_line: true,
+            ruler_column: 0,
+            show_trailing_whitespace: true,
+            trim_on_save: false,
+            scrollbar: true,
+            frame_duration_ms: 16, // ~60 FPS
+            autosave_interval_secs: 30,
+            color_theme: "dark".to_string(),
+            theme: toml::Table::new(),
+            keymap: HashMap::new(),
+            keys: HashMap::new(),
+        }
+    }
+}
+
+/// Where an answer is inserted into the buffer
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Insertion {
+    #[default]
+    End,
+    Cursor,
+    Paragraph,
+}
+
+impl Insertion {
+    pub fn next(self) -> Self {
+        match self {
+            Insertion::End => Insertion::Cursor,
+            Insertion::Cursor => Insertion::Paragraph,
+            Insertion::Paragraph => Insertion::End,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Insertion::End => "end of buffer",
+            Insertion::Cursor => "cursor",
+            Insertion::Paragraph => "below paragraph",
+        }
+    }
+}
+
+/// How the oldest messages make room in a full context window
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContextTrim {
+    #[default]
+    Drop,
+    Summarize,
+}
+
+impl ContextTrim {
+    pub fn next(self) -> Self {
+        match self {
+            ContextTrim::Drop => ContextTrim::Summarize,
+            ContextTrim::Summarize => ContextTrim::Drop,
+        }
+    }
+
+    /// As written in the config and session files
+    pub fn name(self) -> &'static str {
+        match self {
+            ContextTrim::Drop => "drop",
+            ContextTrim::Summarize => "summarize",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "drop" => Some(ContextTrim::Drop),
+            "summarize" => Some(ContextTrim::Summarize),
+            _ => None,
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ContextTrim::Drop => "drop oldest messages",
+            ContextTrim::Summarize => "summarize oldest messages",
+        }
+    }
+}
+
+impl Config {
+    /// `~/.rusty/config.toml`
+    pub fn path() -> Option<PathBuf> {
+        dirs::home_dir().map(|home| home.join(".rusty").join("config.toml"))
+    }
+
+    /// Reads the config file, falling back to defaults when it doesn't exist
+    pub fn load() -> Result<Self> {
+        let Some(path) = Self::path() else {
+            return Ok(Self::default());
+        };
+
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(toml::from_str(&content)?)
+    }
+
+    /// How long a request to `provider` may run, `None` for no limit
+    pub fn request_timeout(&self, provider: &str) -> Option<Duration> {
+        let secs = self
+            .request_timeouts
+            .get(provider)
+            .copied()
+            .unwrap_or(DEFAULT_REQUEST_TIMEOUT_SECS);
+        (secs > 0).then(|| Duration::from_secs(secs))
+    }
+
+    /// One indent level in code blocks of `language`
+    pub fn indent_unit(&self, language: &str) -> &str {
+        self.indent.get(language).map_or("    ", String::as_str)
+    }
+
+    /// Resolves a key through the keymap overrides
+    pub fn remap_key(&self, c: char) -> char {
+        let mut buf = [0; 4];
+        self.keymap
+            .get(c.encode_utf8(&mut buf) as &str)
+            .and_then(|target| target.chars().next())
+            .unwrap_or(c)
+    }
+}
+
+/// Loads the config once at startup, with `overrides` from the command line
+/// on top, and settles the history directory. Must run before anything
+/// calls `get`. Returns a note when old history was moved.
+pub fn init(overrides: impl FnOnce(&mut Config) -> Result<()>) -> Result<Option<String>> {
+    let mut config = Config::load()?;
+    overrides(&mut config)?;
+    let (history_dir, note) = history_dir::resolve(&config.history_dir);
+    config.history_dir = history_dir;
+    let name = config.color_theme.clone();
+    let _ = CONFIG.set(config);
+    theme::switch(&name)?;
+    Ok(note)
+}
+
+/// Active config; defaults if `init` was never called
+pub fn get() -> &'static Config {
+    CONFIG.get_or_init(Config::default)
+}
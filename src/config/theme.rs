@@ -0,0 +1,332 @@
+use crossterm::style::Color;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use crate::error::{Error, Result};
+use crate::syntax::Style;
+
+const BUILT_IN: &[&str] = &["dark", "light"];
+
+// Active theme and its name, replaced by `switch`
+static CURRENT: Lazy<RwLock<(String, Arc<Theme>)>> =
+    Lazy::new(|| RwLock::new(("dark".to_string(), Arc::new(Theme::dark()))));
+
+/// Foreground and optional background. In TOML either just a color
+/// (`keyword = "magenta"`) or a table (`selection = { fg = "black", bg = "grey" }`).
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(from = "ColorsDef")]
+pub struct Colors {
+    pub fg: Color,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bg: Option<Color>,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ColorsDef {
+    Fg(Color),
+    Full { fg: Color, bg: Option<Color> },
+}
+
+impl From<ColorsDef> for Colors {
+    fn from(value: ColorsDef) -> Self {
+        match value {
+            ColorsDef::Fg(fg) => Colors::fg(fg),
+            ColorsDef::Full { fg, bg } => Colors { fg, bg },
+        }
+    }
+}
+
+impl Colors {
+    const fn fg(fg: Color) -> Self {
+        Self { fg, bg: None }
+    }
+
+    const fn new(fg: Color, bg: Color) -> Self {
+        Self { fg, bg: Some(bg) }
+    }
+}
+
+/// Colors of every highlight style and of the editor chrome
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Theme {
+    pub normal: Colors,
+    pub keyword: Colors,
+    pub function: Colors,
+    pub r#type: Colors,
+    pub string: Colors,
+    pub number: Colors,
+    pub comment: Colors,
+    pub variable: Colors,
+    pub constant: Colors,
+    pub operator: Colors,
+    pub selection: Colors,
+    pub search_match: Colors,
+    pub error: Colors,
+    pub heading: Colors,
+    pub bold: Colors,
+    pub italic: Colors,
+    pub inline_code: Colors,
+    pub quote: Colors,
+    pub list_bullet: Colors,
+    pub link: Colors,
+    pub diff_add: Colors,
+    pub diff_remove: Colors,
+    /// Gutter marker of lines changed since the last commit, added and
+    /// removed ones take `diff_add` and `diff_remove`
+    pub git_modified: Colors,
+    /// Background behind assistant answers
+    pub answer_bg: Color,
+    /// Background of the row the cursor is on
+    pub cursor_line_bg: Color,
+    /// Background of the `ruler_column` from the config
+    pub ruler_bg: Color,
+    /// Background of blanks at the end of a line
+    pub trailing_whitespace_bg: Color,
+    /// Block where a streamed answer is being typed
+    pub typing_cursor: Color,
+    /// Inline completion after the cursor, not in the buffer yet
+    pub ghost_text: Colors,
+    /// Thumb of the scrollbar, its track takes `line_number`
+    pub scrollbar: Colors,
+
+    pub line_number: Colors,
+    pub status: Colors,
+    /// Key hints on the bottom line
+    pub hint: Colors,
+    /// Request line at the bottom
+    pub message: Colors,
+    /// Messages shown over the request line, by severity
+    pub message_info: Colors,
+    pub message_warning: Colors,
+    pub message_error: Colors,
+    pub tab: Colors,
+    pub tab_active: Colors,
+    pub popup: Colors,
+    pub popup_selected: Colors,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self::dark()
+    }
+}
+
+impl Theme {
+    /// For dark terminal backgrounds
+    pub fn dark() -> Self {
+        Self {
+            normal: Colors::fg(Color::White),
+            keyword: Colors::fg(Color::Magenta),
+            function: Colors::fg(Color::Blue),
+            r#type: Colors::fg(Color::Cyan),
+            string: Colors::fg(Color::Green),
+            number: Colors::fg(Color::Yellow),
+            comment: Colors::fg(Color::DarkGrey),
+            variable: Colors::fg(Color::White),
+            constant: Colors::fg(Color::Yellow),
+            operator: Colors::fg(Color::White),
+            selection: Colors::new(Color::Black, Color::Grey),
+            search_match: Colors::new(Color::Black, Color::Yellow),
+            error: Colors::new(Color::Red, Color::White),
+            heading: Colors::fg(Color::Cyan),
+            bold: Colors::fg(Color::Yellow),
+            italic: Colors::fg(Color::Magenta),
+            inline_code: Colors::fg(Color::Green),
+            quote: Colors::fg(Color::DarkGrey),
+            list_bullet: Colors::fg(Color::Blue),
+            link: Colors::fg(Color::Blue),
+            diff_add: Colors::fg(Color::Green),
+            diff_remove: Colors::fg(Color::Red),
+            git_modified: Colors::fg(Color::Yellow),
+            answer_bg: Color::AnsiValue(235),
+            cursor_line_bg: Color::AnsiValue(236),
+            ruler_bg: Color::AnsiValue(237),
+            trailing_whitespace_bg: Color::AnsiValue(52),
+            typing_cursor: Color::Grey,
+            ghost_text: Colors::fg(Color::AnsiValue(243)),
+            scrollbar: Colors::fg(Color::Grey),
+
+            line_number: Colors::fg(Color::DarkGrey),
+            status: Colors::new(Color::Black, Color::White),
+            hint: Colors::fg(Color::DarkGrey),
+            message: Colors::fg(Color::White),
+            message_info: Colors::fg(Color::Cyan),
+            message_warning: Colors::fg(Color::Yellow),
+            message_error: Colors::new(Color::White, Color::DarkRed),
+            tab: Colors::new(Color::White, Color::DarkGrey),
+            tab_active: Colors::new(Color::Black, Color::White),
+            popup: Colors::new(Color::White, Color::DarkGrey),
+            popup_selected: Colors::new(Color::Black, Color::White),
+        }
+    }
+
+    /// For light terminal backgrounds
+    pub fn light() -> Self {
+        Self {
+            normal: Colors::fg(Color::Black),
+            keyword: Colors::fg(Color::DarkMagenta),
+            function: Colors::fg(Color::DarkBlue),
+            r#type: Colors::fg(Color::DarkCyan),
+            string: Colors::fg(Color::DarkGreen),
+            number: Colors::fg(Color::DarkYellow),
+            comment: Colors::fg(Color::Grey),
+            variable: Colors::fg(Color::Black),
+            constant: Colors::fg(Color::DarkYellow),
+            operator: Colors::fg(Color::Black),
+            selection: Colors::new(Color::White, Color::DarkGrey),
+            search_match: Colors::new(Color::Black, Color::Yellow),
+            error: Colors::new(Color::White, Color::DarkRed),
+            heading: Colors::fg(Color::DarkCyan),
+            bold: Colors::fg(Color::DarkYellow),
+            italic: Colors::fg(Color::DarkMagenta),
+            inline_code: Colors::fg(Color::DarkGreen),
+            quote: Colors::fg(Color::Grey),
+            list_bullet: Colors::fg(Color::DarkBlue),
+            link: Colors::fg(Color::DarkBlue),
+            diff_add: Colors::fg(Color::DarkGreen),
+            diff_remove: Colors::fg(Color::DarkRed),
+            git_modified: Colors::fg(Color::DarkYellow),
+            answer_bg: Color::AnsiValue(255),
+            cursor_line_bg: Color::AnsiValue(254),
+            ruler_bg: Color::AnsiValue(253),
+            trailing_whitespace_bg: Color::AnsiValue(224),
+            typing_cursor: Color::DarkGrey,
+            ghost_text: Colors::fg(Color::AnsiValue(248)),
+            scrollbar: Colors::fg(Color::DarkGrey),
+
+            line_number: Colors::fg(Color::Grey),
+            status: Colors::new(Color::White, Color::DarkGrey),
+            hint: Colors::fg(Color::Grey),
+            message: Colors::fg(Color::Black),
+            message_info: Colors::fg(Color::DarkCyan),
+            message_warning: Colors::fg(Color::DarkYellow),
+            message_error: Colors::new(Color::White, Color::DarkRed),
+            tab: Colors::new(Color::Black, Color::Grey),
+            tab_active: Colors::new(Color::White, Color::DarkGrey),
+            popup: Colors::new(Color::Black, Color::Grey),
+            popup_selected: Colors::new(Color::White, Color::DarkGrey),
+        }
+    }
+
+    /// Foreground and optional background for a highlight style
+    pub fn style(&self, style: Style) -> (Color, Option<Color>) {
+        let colors = match style {
+            Style::Normal => self.normal,
+            Style::Keyword => self.keyword,
+            Style::Function => self.function,
+            Style::Type => self.r#type,
+            Style::String => self.string,
+            Style::Number => self.number,
+            Style::Comment => self.comment,
+            Style::Variable => self.variable,
+            Style::Constant => self.constant,
+            Style::Operator => self.operator,
+            Style::Selection => self.selection,
+            Style::SearchMatch => self.search_match,
+            Style::Error => self.error,
+            Style::Heading => self.heading,
+            Style::Bold => self.bold,
+            Style::Italic => self.italic,
+            Style::InlineCode => self.inline_code,
+            Style::Quote => self.quote,
+            Style::ListBullet => self.list_bullet,
+            Style::Link => self.link,
+            Style::DiffAdd => self.diff_add,
+            Style::DiffRemove => self.diff_remove,
+        };
+
+        (colors.fg, colors.bg)
+    }
+
+    /// A built-in theme or `~/.rusty/themes/<name>.toml`, with the `[theme]`
+    /// overrides of the config on top. Theme files only need the colors they
+    /// change, the rest comes from the dark theme.
+    pub fn load(name: &str) -> Result<Self> {
+        let mut table = match name {
+            "dark" => toml::Table::try_from(Self::dark()).map_err(|e| e.to_string())?,
+            "light" => toml::Table::try_from(Self::light()).map_err(|e| e.to_string())?,
+            _ => {
+                let path = dir()
+                    .map(|dir| dir.join(format!("{}.toml", name)))
+                    .ok_or_else(|| Error::Custom("No home directory".to_string()))?;
+                let content = fs::read_to_string(&path)
+                    .map_err(|e| format!("Can't read theme {}: {}", path.display(), e))?;
+                toml::from_str(&content)?
+            }
+        };
+
+        for (key, value) in &super::get().theme {
+            table.insert(key.clone(), value.clone());
+        }
+
+        Ok(toml::Value::Table(table).try_into()?)
+    }
+}
+
+/// `~/.rusty/themes`
+fn dir() -> Option<PathBuf> {
+    dirs::home_dir().map(|home| home.join(".rusty").join("themes"))
+}
+
+/// Built-in themes followed by the ones in `~/.rusty/themes`
+pub fn names() -> Vec<String> {
+    let mut names: Vec<String> = BUILT_IN.iter().map(|n| n.to_string()).collect();
+
+    let mut files: Vec<String> = dir()
+        .and_then(|dir| fs::read_dir(dir).ok())
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().and_then(|e| e.to_str()) == Some("toml"))
+        .filter_map(|path| path.file_stem().and_then(|s| s.to_str()).map(str::to_string))
+        .filter(|name| !BUILT_IN.contains(&name.as_str()))
+        .collect();
+    files.sort();
+    names.extend(files);
+
+    names
+}
+
+/// Theme used for drawing
+pub fn current() -> Arc<Theme> {
+    match CURRENT.read() {
+        Ok(current) => Arc::clone(&current.1),
+        Err(_) => Arc::new(Theme::dark()),
+    }
+}
+
+pub fn current_name() -> String {
+    match CURRENT.read() {
+        Ok(current) => current.0.clone(),
+        Err(_) => "dark".to_string(),
+    }
+}
+
+/// Loads `name` and makes it the active theme
+pub fn switch(name: &str) -> Result<()> {
+    let theme = Theme::load(name)?;
+    if let Ok(mut current) = CURRENT.write() {
+        *current = (name.to_string(), Arc::new(theme));
+    }
+
+    Ok(())
+}
+
+/// Switches to the theme after the active one, returns its name
+pub fn switch_to_next() -> Result<String> {
+    let names = names();
+    let current = current_name();
+    let next = names
+        .iter()
+        .position(|name| *name == current)
+        .map(|i| (i + 1) % names.len())
+        .unwrap_or(0);
+
+    switch(&names[next])?;
+    Ok(names[next].clone())
+}
@@ -0,0 +1,59 @@
+use super::inline_edit::Placement;
+
+/// Fixed requests about a piece of code, each with where its answer goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Assist {
+    Explain,
+    DocComment,
+    Tests,
+}
+
+impl Assist {
+    /// Where the answer goes in the code, `None` for the conversation
+    pub(super) fn placement(self) -> Option<Placement> {
+        match self {
+            Assist::Explain => None,
+            Assist::DocComment => Some(Placement::Above),
+            Assist::Tests => Some(Placement::Below),
+        }
+    }
+
+    /// Shown while the request runs
+    pub(super) fn progress(self) -> &'static str {
+        match self {
+            Assist::Explain => "Explaining...",
+            Assist::DocComment => "Writing the doc comment...",
+            Assist::Tests => "Writing unit tests...",
+        }
+    }
+
+    /// Request about `code`, written in `language` when it's known
+    pub(super) fn prompt(self, code: &str, language: Option<&str>) -> String {
+        let named = language.map(|language| format!("{} ", language)).unwrap_or_default();
+        let request = match self {
+            Assist::Explain => format!(
+                "Explain what the {}code below does, step by step, and point out anything surprising or wrong.",
+                named
+            ),
+            Assist::DocComment => format!(
+                "Write the doc comment that goes right above the {}code below, in the language's doc comment \
+                 syntax, e.g. /// in Rust. Describe what it does, its parameters, return value and errors where \
+                 they aren't obvious. Answer with the comment only, without the code or code fences.",
+                named
+            ),
+            Assist::Tests => format!(
+                "Write unit tests for the {}code below, in the usual test layout of the language, e.g. a \
+                 #[cfg(test)] module in Rust. Cover the normal cases and the edge cases. Answer with the test \
+                 code only, without the code under test, explanations or code fences.",
+                named
+            ),
+        };
+
+        format!(
+            "{}\n\n```{}\n{}\n```",
+            request,
+            language.unwrap_or(""),
+            code.trim_end_matches('\n')
+        )
+    }
+}
@@ -0,0 +1,102 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use crate::config;
+use crate::error::Result;
+
+/// Writes unsaved buffers to swap files every few seconds, so the work
+/// survives a crash or a killed terminal.
+pub(super) struct Autosave {
+    interval: Option<Duration>,
+    last: Instant,
+}
+
+impl Autosave {
+    pub(super) fn new() -> Self {
+        let secs = config::get().autosave_interval_secs;
+
+        Self {
+            interval: (secs > 0).then(|| Duration::from_secs(secs)),
+            last: Instant::now(),
+        }
+    }
+
+    /// True once per interval; never when auto-save is turned off
+    pub(super) fn is_due(&mut self) -> bool {
+        match self.interval {
+            Some(interval) if self.last.elapsed() >= interval => {
+                self.last = Instant::now();
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// `<history_dir>/autosave`
+fn dir() -> PathBuf {
+    PathBuf::from(&config::get().history_dir).join("autosave")
+}
+
+/// `<history_dir>/autosave/<absolute path, / as %>.swp`, so files of the
+/// same name in different directories don't share one
+fn swap_path(file_path: &str) -> PathBuf {
+    // Not canonicalized: a buffer's swap must keep its name from before the
+    // file exists on disk to after its first save
+    let file = Path::new(file_path);
+    let absolute = std::path::absolute(file).unwrap_or_else(|_| file.to_path_buf());
+    let name: String = absolute
+        .to_string_lossy()
+        .chars()
+        .map(|c| if matches!(c, '/' | '\\' | ':') { '%' } else { c })
+        .collect();
+
+    dir().join(format!("{}.swp", name))
+}
+
+/// The first line remembers which file the content belongs to
+pub(super) fn write(file_path: &str, content: &str) -> Result<()> {
+    fs::create_dir_all(dir())?;
+    fs::write(swap_path(file_path), format!("{}\n{}", file_path, content))?;
+
+    Ok(())
+}
+
+/// Drops the swap file of `file_path` once its content is saved
+pub(super) fn remove(file_path: &str) {
+    let _ = fs::remove_file(swap_path(file_path));
+}
+
+pub(super) fn remove_swap(swap: &Path) {
+    let _ = fs::remove_file(swap);
+}
+
+/// Swap files left behind by a session that didn't exit cleanly
+pub(super) fn leftovers() -> Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(dir()) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut swaps = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().and_then(|ext| ext.to_str()) == Some("swp") {
+            swaps.push(path);
+        }
+    }
+    swaps.sort();
+
+    Ok(swaps)
+}
+
+/// File path and content stored in a swap file
+pub(super) fn read(swap: &Path) -> Result<(String, String)> {
+    let content = fs::read_to_string(swap)?;
+    let (file_path, content) = content.split_once('\n').unwrap_or((content.as_str(), ""));
+
+    Ok((file_path.to_string(), content.to_string()))
+}
@@ -0,0 +1,101 @@
+use ropey::Rope;
+use std::ops::Range;
+use std::path::Path;
+
+use crate::chat::history::History;
+use crate::files::encoding::Encoding;
+use crate::files::line_ending::LineEnding;
+use crate::syntax::cache::SyntaxCache;
+use crate::syntax::Style;
+
+use super::protected::Protected;
+
+/// Everything that belongs to a single open buffer. The active buffer lives
+/// in the `Editor` fields; the others are parked here until switched to.
+pub(super) struct BufferState {
+    // Unique among the buffers of this run, requests are routed by it
+    pub(super) id: u64,
+    pub(super) buffer: Rope,
+    pub(super) cursor_row: usize,
+    pub(super) cursor_col: usize,
+
+    pub(super) history: History,
+    pub(super) modified: bool,
+    pub(super) read_only: bool,
+    pub(super) scratch: bool,
+    pub(super) encoding: Encoding,
+    pub(super) line_ending: LineEnding,
+    pub(super) protected: Protected,
+
+    pub(super) syntax_cache: SyntaxCache,
+    pub(super) syntax_highlights: Vec<(Range<usize>, Style)>,
+
+    pub(super) selection_start: Option<(usize, usize)>,
+    pub(super) selection_active: bool,
+
+    // Where the running answer started and goes on, and the latest
+    // complete one with its prompt
+    pub(super) response_start: Option<usize>,
+    pub(super) response_at: Option<usize>,
+    pub(super) last_answer: Option<(Range<usize>, String)>,
+}
+
+impl BufferState {
+    /// Empty buffer `id` that will be saved to `history`
+    pub(super) fn new(id: u64, history: History) -> Self {
+        let mut buffer = Rope::new();
+        buffer.insert(0, "\n");
+
+        Self {
+            id,
+            buffer,
+            cursor_row: 0,
+            cursor_col: 0,
+            history,
+            modified: false,
+            read_only: false,
+            scratch: false,
+            encoding: Encoding::default(),
+            line_ending: LineEnding::default(),
+            protected: Protected::new(),
+            syntax_cache: SyntaxCache::new(),
+            syntax_highlights: Vec::new(),
+            selection_start: None,
+            selection_active: false,
+            response_start: None,
+            response_at: None,
+            last_answer: None,
+        }
+    }
+
+    /// Adds text of an answer that arrived while the buffer was in the
+    /// background, read-only like in the active one
+    pub(super) fn append_answer(&mut self, text: &str) {
+        let len = self.buffer.len_chars();
+        let at = self.response_at.unwrap_or(len).min(len);
+        let inserted = text.chars().count();
+
+        self.protected.shift(at..at, inserted);
+        self.buffer.insert(at, text);
+        self.protected.add(at..at + inserted);
+        self.response_at = Some(at + inserted);
+        self.modified = true;
+        // Highlighted again when it's switched to
+        self.syntax_cache.mark_all_dirty();
+    }
+
+    /// Tab label: session title or the file name without the history directory
+    pub(super) fn name(history: &History) -> String {
+        if let Some(session) = &history.session {
+            if !session.title.is_empty() {
+                return session.title.clone();
+            }
+        }
+
+        Path::new(&history.file_path)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or(&history.file_path)
+            .to_string()
+    }
+}
@@ -0,0 +1,72 @@
+use crate::syntax::CommentStyle;
+
+// Outside of code blocks lines are quoted instead
+const QUOTE: CommentStyle = CommentStyle::Line(">");
+
+/// `lines` commented out with `style`, or uncommented when every non-blank
+/// one already is. `None` in `style` quotes them as markdown.
+pub(super) fn toggle(lines: &[String], style: Option<CommentStyle>) -> Vec<String> {
+    let style = style.unwrap_or(QUOTE);
+    let filled = || lines.iter().filter(|line| !line.trim().is_empty());
+
+    if filled().all(|line| is_commented(line, style)) {
+        return lines.iter().map(|line| uncomment(line, style)).collect();
+    }
+
+    // Markers line up at the smallest indent
+    let indent = filled()
+        .map(|line| line.len() - line.trim_start().len())
+        .min()
+        .unwrap_or(0);
+    lines
+        .iter()
+        .map(|line| {
+            if line.trim().is_empty() {
+                line.clone()
+            } else {
+                comment(line, indent, style)
+            }
+        })
+        .collect()
+}
+
+fn is_commented(line: &str, style: CommentStyle) -> bool {
+    let line = line.trim();
+    match style {
+        CommentStyle::Line(marker) => line.starts_with(marker),
+        CommentStyle::Block(open, close) => line.starts_with(open) && line.ends_with(close),
+    }
+}
+
+fn comment(line: &str, indent: usize, style: CommentStyle) -> String {
+    // Tabs and spaces are one byte, other blanks may not be
+    let indent = (0..=indent).rev().find(|&i| line.is_char_boundary(i)).unwrap_or(0);
+    let (space, code) = line.split_at(indent);
+    match style {
+        CommentStyle::Line(marker) => format!("{}{} {}", space, marker, code),
+        CommentStyle::Block(open, close) => format!("{}{} {} {}", space, open, code, close),
+    }
+}
+
+// Takes the markers off with the space next to them
+fn uncomment(line: &str, style: CommentStyle) -> String {
+    let code = line.trim_start();
+    let space = &line[..line.len() - code.len()];
+    let code = match style {
+        CommentStyle::Line(marker) => match code.strip_prefix(marker) {
+            Some(rest) => rest.strip_prefix(' ').unwrap_or(rest),
+            None => return line.to_string(),
+        },
+        CommentStyle::Block(open, close) => {
+            let trimmed = code.trim_end();
+            match trimmed.strip_prefix(open).and_then(|rest| rest.strip_suffix(close)) {
+                Some(rest) => {
+                    let rest = rest.strip_prefix(' ').unwrap_or(rest);
+                    rest.strip_suffix(' ').unwrap_or(rest)
+                }
+                None => return line.to_string(),
+            }
+        }
+    };
+    format!("{}{}", space, code)
+}
@@ -0,0 +1,52 @@
+use std::path::PathBuf;
+
+use crate::chat::Model;
+
+/// What happens when a confirm prompt is answered with yes
+#[derive(Debug, Clone)]
+pub(super) enum ConfirmAction {
+    /// Restore the buffer kept in this swap file
+    RestoreSwap(PathBuf),
+    /// Run a shell command, its output goes in at char index `insert_at`
+    RunCommand { command: String, insert_at: usize },
+    /// Write an API key set for this session to the credentials file
+    SaveApiKey { model: Model, key: String },
+    /// Delete a file of the load picker, relative to the history directory
+    DeleteFile(String),
+    /// Read the active file again, it changed on disk. Answered with
+    /// r, k or d instead of yes/no.
+    ReloadFile,
+    /// View the active file read-only, another rusty_ai has it open
+    OpenedElsewhere,
+    /// Quit with unsaved buffers or a running request. Answered with s
+    /// to save first, d to discard or c to stay.
+    Quit,
+    /// Quit while a request runs: w waits for it, c cancels it, d leaves
+    /// it to finish after the screen has closed
+    QuitDuringRequest,
+    /// Close the active buffer with unsaved changes: s saves it first, d
+    /// discards them, c keeps it open
+    CloseBuffer,
+    /// Wipe the buffer once the running request is cancelled with c
+    WipeDuringRequest,
+}
+
+/// Yes/no question shown in a popup until it's answered
+#[derive(Debug, Clone)]
+pub struct Confirm {
+    pub title: String,
+    pub lines: Vec<String>,
+    pub hint: String,
+    pub(super) action: ConfirmAction,
+}
+
+impl Confirm {
+    pub(super) fn new(title: &str, lines: Vec<String>, hint: &str, action: ConfirmAction) -> Self {
+        Self {
+            title: title.to_string(),
+            lines,
+            hint: hint.to_string(),
+            action,
+        }
+    }
+}
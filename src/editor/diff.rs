@@ -0,0 +1,82 @@
+use similar::{ChangeTag, TextDiff};
+
+use crate::syntax::Style;
+
+// Unchanged lines shown around each change
+const CONTEXT_LINES: usize = 3;
+const PAGE_LINES: usize = 10;
+
+/// Read-only line diff between the saved file and the buffer
+#[derive(Debug, Clone)]
+pub struct DiffView {
+    pub title: String,
+    pub lines: Vec<(Style, String)>,
+    scroll: usize,
+}
+
+impl DiffView {
+    pub(super) fn new(title: &str, saved: &str, buffer: &str) -> Self {
+        let diff = TextDiff::from_lines(saved, buffer);
+        let mut lines = Vec::new();
+
+        for group in diff.grouped_ops(CONTEXT_LINES) {
+            let (Some(first), Some(last)) = (group.first(), group.last()) else {
+                continue;
+            };
+            let old = first.old_range().start..last.old_range().end;
+            let new = first.new_range().start..last.new_range().end;
+            lines.push((
+                Style::Heading,
+                format!("@@ -{},{} +{},{} @@", old.start + 1, old.len(), new.start + 1, new.len()),
+            ));
+
+            for op in &group {
+                for change in diff.iter_changes(op) {
+                    let (style, sign) = match change.tag() {
+                        ChangeTag::Delete => (Style::DiffRemove, '-'),
+                        ChangeTag::Insert => (Style::DiffAdd, '+'),
+                        ChangeTag::Equal => (Style::Normal, ' '),
+                    };
+                    let text = change.value().trim_end_matches(['\n', '\r']);
+                    lines.push((style, format!("{} {}", sign, text)));
+                }
+            }
+        }
+
+        if lines.is_empty() {
+            lines.push((Style::Normal, "No changes since the last save".to_string()));
+        }
+
+        Self {
+            title: title.to_string(),
+            lines,
+            scroll: 0,
+        }
+    }
+
+    /// First line shown
+    pub fn scroll(&self) -> usize {
+        self.scroll
+    }
+
+    pub(super) fn scroll_down(&mut self, lines: usize) {
+        self.scroll = self.scroll.saturating_add(lines).min(self.lines.len().saturating_sub(1));
+    }
+
+    pub(super) fn scroll_up(&mut self, lines: usize) {
+        self.scroll = self.scroll.saturating_sub(lines);
+    }
+
+    /// Keeps the last page full once the popup height is known
+    pub fn clamp_scroll(&mut self, max_scroll: usize) {
+        self.scroll = self.scroll.min(max_scroll);
+    }
+
+    pub(super) fn page_down(&mut self) {
+        self.scroll_down(PAGE_LINES);
+    }
+
+    pub(super) fn page_up(&mut self) {
+        self.scroll_up(PAGE_LINES);
+    }
+}
@@ -1,11 +1,34 @@
+use crate::config;
 use crate::error::Result;
-use crate::files::list_files;
+use std::path::{Path, PathBuf};
+
+use super::finder::{self, Entry, EntryKind, Finder};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Action {
     Idle,
     Load,
     Save,
+    PickModel,
+    Attach,
+    ExtractBlock,
+    SaveBlock,
+    PickSession,
+    PickKeyProvider,
+    ApiKey,
+    OllamaModels,
+    PullModel,
+    GotoLine,
+    Completion,
+    RenameFile,
+    Import,
+    RenameSession,
+    TagSession,
+    CommitHistory,
+    RunAttach,
+    PromptHistory,
+    SearchHistory,
+    InlineEdit,
 }
 
 #[derive(Debug, Clone)]
@@ -18,6 +41,22 @@ pub(super) struct FilePicker {
 
     input: String,
     cursor_pos: usize,
+
+    // Load and Attach search the files with a fuzzy finder; `files` holds
+    // the labels of `entries`
+    finder: Option<Finder>,
+    entries: Vec<Entry>,
+    // Finder query while its input is used for a new file name
+    stashed_query: String,
+
+    // Texts the query fuzzy filters when there's no finder, and the ones
+    // listed in `files`
+    choices: Vec<String>,
+    matched: Vec<usize>,
+    // Tags of each choice, `#tag` words of the query must all be among them
+    choice_tags: Vec<Vec<String>>,
+    // Files of the listed sessions, one per choice
+    session_paths: Vec<PathBuf>,
 }
 
 impl FilePicker {
@@ -33,6 +72,15 @@ impl FilePicker {
             // Subject for separation
             cursor_pos: 0,
             input: String::new(),
+
+            finder: None,
+            entries: Vec::new(),
+            stashed_query: String::new(),
+
+            choices: Vec::new(),
+            matched: Vec::new(),
+            choice_tags: Vec::new(),
+            session_paths: Vec::new(),
         }
     }
 
@@ -40,18 +88,372 @@ impl FilePicker {
         (self.active, &self.action)
     }
 
+    /// Finds conversation files below the history directory
     pub(super) fn init_file_picker(&mut self) -> Result<()> {
-        match list_files() {
-            Ok(files) => {
-                self.files = files;
-                self.files_selected_index = 0;
-                self.active = true;
-                self.action = Action::Load;
-
-                Ok(())
+        self.init_finder(Path::new(&config::get().history_dir), Action::Load)
+    }
+
+    /// Reuses the picker list for choosing a model
+    pub(super) fn init_model_picker(&mut self, models: Vec<String>) {
+        self.files = models;
+        self.files_selected_index = 0;
+        self.active = true;
+        self.action = Action::PickModel;
+    }
+
+    /// Adds the models that aren't listed yet to an open model picker
+    pub(super) fn add_models(&mut self, models: Vec<String>) {
+        for model in models {
+            if !self.files.contains(&model) {
+                self.files.push(model);
+            }
+        }
+    }
+
+    /// Finds files of the workspace to attach to the prompt
+    pub(super) fn init_attach_picker(&mut self) -> Result<()> {
+        self.init_finder(Path::new("."), Action::Attach)
+    }
+
+    /// Finds JSON conversations of the workspace to import
+    pub(super) fn init_import_picker(&mut self) -> Result<()> {
+        self.init_finder(Path::new("."), Action::Import)
+    }
+
+    fn init_finder(&mut self, root: &Path, action: Action) -> Result<()> {
+        self.finder = Some(Finder::new(root)?);
+        self.active = true;
+        self.action = action;
+
+        self.input.clear();
+        self.cursor_pos = 0;
+        self.refresh();
+
+        Ok(())
+    }
+
+    /// Lists earlier prompts, newest first, filtered by the typed query
+    pub(super) fn init_prompt_history(&mut self, prompts: Vec<String>) {
+        self.choices = prompts;
+        self.active = true;
+        self.action = Action::PromptHistory;
+
+        self.input.clear();
+        self.cursor_pos = 0;
+        self.refresh();
+    }
+
+    /// Reads a query for the search through all conversations
+    pub(super) fn init_history_search(&mut self) {
+        self.files.clear();
+        self.files_selected_index = 0;
+        self.active = true;
+        self.action = Action::SearchHistory;
+
+        self.input.clear();
+        self.cursor_pos = 0;
+    }
+
+    /// Lists `items` under the query, the first one selected
+    pub(super) fn show_items(&mut self, items: Vec<String>) {
+        self.files = items;
+        self.files_selected_index = 0;
+    }
+
+    /// Recomputes the finder entries after the query or directory changed
+    pub(super) fn refresh(&mut self) {
+        let Some(finder) = &self.finder else {
+            if matches!(self.action, Action::PromptHistory | Action::PickSession) {
+                self.filter_choices();
             }
-            Err(e) => Err(e),
+            return;
+        };
+
+        self.entries = finder.entries(&self.input);
+        self.files = self.entries.iter().map(Entry::label).collect();
+        self.files_selected_index = 0;
+    }
+
+    // Best matches first, the order of `choices` otherwise
+    fn filter_choices(&mut self) {
+        // `#tag` words only filter choices that have tags
+        let (tags, words): (Vec<&str>, Vec<&str>) = self
+            .input
+            .split_whitespace()
+            .partition(|word| self.action == Action::PickSession && word.len() > 1 && word.starts_with('#'));
+        let query = words.join(" ");
+
+        let mut scored: Vec<(i64, usize)> = self
+            .choices
+            .iter()
+            .enumerate()
+            .filter_map(|(index, choice)| {
+                let choice_tags = self.choice_tags.get(index).map(Vec::as_slice).unwrap_or(&[]);
+                let tagged = tags
+                    .iter()
+                    .all(|tag| choice_tags.iter().any(|t| t.eq_ignore_ascii_case(&tag[1..])));
+                if !tagged {
+                    return None;
+                }
+
+                let score = if query.is_empty() {
+                    0
+                } else {
+                    finder::score(&query, choice)?
+                };
+                Some((score, index))
+            })
+            .collect();
+        scored.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+
+        self.matched = scored.into_iter().map(|(_, index)| index).collect();
+        // One line each, the popup isn't a place for whole prompts
+        self.files = self
+            .matched
+            .iter()
+            .map(|&index| self.choices[index].split_whitespace().collect::<Vec<_>>().join(" "))
+            .collect();
+        self.files_selected_index = 0;
+    }
+
+    /// Full text of the choice under the selection
+    pub(super) fn selected_choice(&self) -> Option<&String> {
+        self.choices.get(self.selected_choice_index()?)
+    }
+
+    /// Index in the initial list of the choice under the selection
+    pub(super) fn selected_choice_index(&self) -> Option<usize> {
+        self.matched.get(self.files_selected_index).copied()
+    }
+
+    /// File of the session under the selection
+    pub(super) fn selected_session(&self) -> Option<&PathBuf> {
+        self.session_paths.get(self.selected_choice_index()?)
+    }
+
+    pub(super) fn finder_breadcrumb(&self) -> Option<String> {
+        self.finder.as_ref().map(Finder::breadcrumb)
+    }
+
+    /// Enter in the finder: opens a directory and returns None, or returns
+    /// the picked file relative to the finder root. A `..` query goes up.
+    pub(super) fn open_selected_entry(&mut self) -> Option<String> {
+        if self.finder.is_some() && self.input.trim() == ".." {
+            self.input.clear();
+            self.cursor_pos = 0;
+            self.finder_back();
+            return None;
         }
+
+        let entry = self.entries.get(self.files_selected_index)?.clone();
+        let finder = self.finder.as_mut()?;
+
+        match entry.kind {
+            EntryKind::File => return Some(entry.path),
+            EntryKind::Dir => finder.enter(&entry.path),
+            EntryKind::Parent => finder.up(),
+        }
+
+        self.input.clear();
+        self.cursor_pos = 0;
+        self.refresh();
+        None
+    }
+
+    /// File under the selection, relative to the finder root
+    pub(super) fn selected_file(&self) -> Option<String> {
+        let entry = self.entries.get(self.files_selected_index)?;
+        (entry.kind == EntryKind::File).then(|| entry.path.clone())
+    }
+
+    /// Lists the files again after some were renamed, deleted or copied,
+    /// keeping the directory, query and selection
+    pub(super) fn reload_finder(&mut self) -> Result<()> {
+        let Some(finder) = &mut self.finder else {
+            return Ok(());
+        };
+        finder.reindex(Path::new(&config::get().history_dir))?;
+
+        let selected = self.files_selected_index;
+        self.refresh();
+        self.files_selected_index = selected.min(self.files.len().saturating_sub(1));
+        Ok(())
+    }
+
+    /// Asks for the new name of a file of the load picker. The finder is
+    /// kept for `back_to_finder`.
+    pub(super) fn init_rename(&mut self, name: &str) {
+        self.stashed_query = std::mem::take(&mut self.input);
+        self.action = Action::RenameFile;
+
+        self.input = name.to_string();
+        self.cursor_pos = self.input.len();
+    }
+
+    /// Returns from the rename prompt to the load picker
+    pub(super) fn back_to_finder(&mut self) -> Result<()> {
+        self.action = Action::Load;
+        self.input = std::mem::take(&mut self.stashed_query);
+        self.cursor_pos = self.input.len();
+        self.reload_finder()
+    }
+
+    /// Backspace on an empty query leaves the directory
+    pub(super) fn finder_back(&mut self) {
+        if let Some(finder) = &mut self.finder {
+            finder.up();
+        }
+        self.refresh();
+    }
+
+    /// Lists saved sessions in the given order, filtered by the typed
+    /// query and its `#tag` words
+    pub(super) fn init_session_picker(
+        &mut self,
+        labels: Vec<String>,
+        tags: Vec<Vec<String>>,
+        paths: Vec<PathBuf>,
+    ) {
+        self.choices = labels;
+        self.choice_tags = tags;
+        self.session_paths = paths;
+        self.active = true;
+        self.action = Action::PickSession;
+
+        self.input.clear();
+        self.cursor_pos = 0;
+        self.refresh();
+    }
+
+    /// Asks for the tags of the current session
+    pub(super) fn init_session_tags(&mut self, tags: &str) {
+        self.files.clear();
+        self.files_selected_index = 0;
+        self.active = true;
+        self.action = Action::TagSession;
+
+        self.input = tags.to_string();
+        self.cursor_pos = self.input.len();
+    }
+
+    /// Lists the providers that need an API key
+    pub(super) fn init_key_provider_picker(&mut self, labels: Vec<String>) {
+        self.files = labels;
+        self.files_selected_index = 0;
+        self.active = true;
+        self.action = Action::PickKeyProvider;
+    }
+
+    /// Reads an API key; the popup only shows it masked
+    pub(super) fn init_api_key_input(&mut self) {
+        self.files.clear();
+        self.files_selected_index = 0;
+        self.active = true;
+        self.action = Action::ApiKey;
+
+        self.input.clear();
+        self.cursor_pos = 0;
+    }
+
+    /// Lists the models installed on the Ollama server
+    pub(super) fn init_ollama_picker(&mut self, labels: Vec<String>) {
+        self.files = labels;
+        self.files_selected_index = 0;
+        self.active = true;
+        self.action = Action::OllamaModels;
+    }
+
+    /// Asks for the name of an Ollama model to download
+    pub(super) fn init_pull_input(&mut self) {
+        self.files.clear();
+        self.files_selected_index = 0;
+        self.active = true;
+        self.action = Action::PullModel;
+
+        self.input.clear();
+        self.cursor_pos = 0;
+    }
+
+    /// Asks for a line number, starting with what was typed after `g`
+    pub(super) fn init_goto_line(&mut self, initial: &str) {
+        self.files.clear();
+        self.files_selected_index = 0;
+        self.active = true;
+        self.action = Action::GotoLine;
+
+        self.input = initial.to_string();
+        self.cursor_pos = self.input.len();
+    }
+
+    /// Asks for a new title of the current session
+    pub(super) fn init_session_title(&mut self, title: &str) {
+        self.files.clear();
+        self.files_selected_index = 0;
+        self.active = true;
+        self.action = Action::RenameSession;
+
+        self.input = title.to_string();
+        self.cursor_pos = self.input.len();
+    }
+
+    /// Asks for the message of a commit of the history folder
+    pub(super) fn init_commit_message(&mut self, message: &str) {
+        self.files.clear();
+        self.files_selected_index = 0;
+        self.active = true;
+        self.action = Action::CommitHistory;
+
+        self.input = message.to_string();
+        self.cursor_pos = self.input.len();
+    }
+
+    /// Asks for a shell command whose output goes with the next prompt
+    pub(super) fn init_shell_command(&mut self, command: &str) {
+        self.files.clear();
+        self.files_selected_index = 0;
+        self.active = true;
+        self.action = Action::RunAttach;
+
+        self.input = command.to_string();
+        self.cursor_pos = self.input.len();
+    }
+
+    /// Asks for the instruction to rewrite the selected text by
+    pub(super) fn init_inline_edit(&mut self) {
+        self.files.clear();
+        self.files_selected_index = 0;
+        self.active = true;
+        self.action = Action::InlineEdit;
+
+        self.input.clear();
+        self.cursor_pos = 0;
+    }
+
+    /// Lists the language server's completions at the cursor
+    pub(super) fn init_completion_picker(&mut self, labels: Vec<String>) {
+        self.files = labels;
+        self.files_selected_index = 0;
+        self.active = true;
+        self.action = Action::Completion;
+    }
+
+    /// Lists the code blocks of the conversation, one label each
+    pub(super) fn init_block_picker(&mut self, labels: Vec<String>) {
+        self.files = labels;
+        self.files_selected_index = 0;
+        self.active = true;
+        self.action = Action::ExtractBlock;
+    }
+
+    /// Asks where to write the picked block, starting from `default`
+    pub(super) fn init_block_save_as(&mut self, default: &str) {
+        self.files.clear();
+        self.files_selected_index = 0;
+        self.active = true;
+        self.action = Action::SaveBlock;
+
+        self.input = default.to_string();
+        self.cursor_pos = self.input.len();
     }
 
     /// Activate FileSaveAs popup
@@ -104,6 +506,15 @@ impl FilePicker {
         self.input.clear();
         self.cursor_pos = 0;
 
+        self.finder = None;
+        self.entries.clear();
+        self.stashed_query.clear();
+
+        self.choices.clear();
+        self.matched.clear();
+        self.choice_tags.clear();
+        self.session_paths.clear();
+
         self.action = Action::Idle;
     }
 
@@ -0,0 +1,219 @@
+use std::path::Path;
+
+use ignore::WalkBuilder;
+
+use crate::error::Result;
+
+// Keeps huge trees from freezing the popup
+const MAX_INDEXED: usize = 50_000;
+const MAX_MATCHES: usize = 200;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum EntryKind {
+    File,
+    Dir,
+    Parent,
+}
+
+/// One line of the finder; `path` is relative to the finder root
+#[derive(Debug, Clone)]
+pub(super) struct Entry {
+    pub(super) path: String,
+    pub(super) kind: EntryKind,
+}
+
+impl Entry {
+    pub(super) fn label(&self) -> String {
+        match self.kind {
+            EntryKind::File => self.path.clone(),
+            EntryKind::Dir => format!("{}/", self.path),
+            EntryKind::Parent => "../".to_string(),
+        }
+    }
+}
+
+/// Every file and directory below a root, honouring `.gitignore`. An empty
+/// query browses the current directory, anything else fuzzy matches the
+/// files below it.
+#[derive(Debug, Clone)]
+pub(super) struct Finder {
+    // (path relative to the root, is_dir)
+    index: Vec<(String, bool)>,
+    // Current directory relative to the root, "" for the root itself
+    dir: String,
+    // Root as given, for the breadcrumb
+    root: String,
+}
+
+impl Finder {
+    pub(super) fn new(root: &Path) -> Result<Self> {
+        let mut index = Vec::new();
+
+        let walker = WalkBuilder::new(root)
+            .require_git(false)
+            .parents(false)
+            .sort_by_file_name(|a, b| a.cmp(b))
+            .build();
+
+        // A directory that can't be read is left out, not the whole listing
+        for entry in walker.filter_map(|entry| entry.ok()).take(MAX_INDEXED) {
+            let Ok(path) = entry.path().strip_prefix(root) else {
+                continue;
+            };
+            if path.as_os_str().is_empty() {
+                continue;
+            }
+
+            let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+            index.push((to_slash(path), is_dir));
+        }
+
+        Ok(Self {
+            index,
+            dir: String::new(),
+            root: to_slash(root),
+        })
+    }
+
+    /// Entries to show for `query`, best match first
+    pub(super) fn entries(&self, query: &str) -> Vec<Entry> {
+        if query.is_empty() {
+            return self.browse();
+        }
+
+        let mut scored: Vec<(i64, &str)> = self
+            .index
+            .iter()
+            .filter(|(path, is_dir)| !is_dir && self.contains(path))
+            .filter_map(|(path, _)| score(query, path).map(|s| (s, path.as_str())))
+            .collect();
+        scored.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(b.1)));
+
+        scored
+            .into_iter()
+            .take(MAX_MATCHES)
+            .map(|(_, path)| Entry {
+                path: path.to_string(),
+                kind: EntryKind::File,
+            })
+            .collect()
+    }
+
+    pub(super) fn enter(&mut self, dir: &str) {
+        self.dir = dir.to_string();
+    }
+
+    /// Goes to the parent directory, stays put at the root
+    pub(super) fn up(&mut self) {
+        self.dir = match self.dir.rsplit_once('/') {
+            Some((parent, _)) => parent.to_string(),
+            None => String::new(),
+        };
+    }
+
+    /// Root and current directory, e.g. `.rusty › notes › rust`
+    pub(super) fn breadcrumb(&self) -> String {
+        let root = self.root.trim_end_matches('/');
+        let root = if root.is_empty() { "." } else { root };
+
+        std::iter::once(root)
+            .chain(self.dir.split('/').filter(|part| !part.is_empty()))
+            .collect::<Vec<_>>()
+            .join(" › ")
+    }
+
+    /// Indexes the root again after files changed, staying in the same
+    /// directory when it's still there
+    pub(super) fn reindex(&mut self, root: &Path) -> Result<()> {
+        let mut finder = Finder::new(root)?;
+        if finder.index.iter().any(|(path, is_dir)| *is_dir && *path == self.dir) {
+            finder.dir = std::mem::take(&mut self.dir);
+        }
+        *self = finder;
+        Ok(())
+    }
+
+    /// Direct children of the current directory, directories first
+    fn browse(&self) -> Vec<Entry> {
+        let mut entries = Vec::new();
+        if !self.dir.is_empty() {
+            entries.push(Entry {
+                path: self.dir.clone(),
+                kind: EntryKind::Parent,
+            });
+        }
+
+        let children = self
+            .index
+            .iter()
+            .filter(|(path, _)| parent_of(path) == self.dir);
+        let (dirs, files): (Vec<_>, Vec<_>) = children.partition(|(_, is_dir)| *is_dir);
+
+        for (path, is_dir) in dirs.into_iter().chain(files) {
+            entries.push(Entry {
+                path: path.clone(),
+                kind: if *is_dir {
+                    EntryKind::Dir
+                } else {
+                    EntryKind::File
+                },
+            });
+        }
+
+        entries
+    }
+
+    fn contains(&self, path: &str) -> bool {
+        self.dir.is_empty()
+            || path
+                .strip_prefix(&self.dir)
+                .map(|rest| rest.starts_with('/'))
+                .unwrap_or(false)
+    }
+}
+
+fn parent_of(path: &str) -> &str {
+    path.rsplit_once('/').map(|(parent, _)| parent).unwrap_or("")
+}
+
+fn to_slash(path: &Path) -> String {
+    path.components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Case-insensitive subsequence match. Consecutive characters and matches
+/// at the start of a path segment or word score higher, long paths lower.
+/// `None` when `query` isn't a subsequence of `candidate`.
+pub(super) fn score(query: &str, candidate: &str) -> Option<i64> {
+    let candidate: Vec<char> = candidate.chars().collect();
+    let mut score = 0;
+    let mut pos = 0;
+    let mut previous: Option<usize> = None;
+
+    for q in query.chars().flat_map(char::to_lowercase) {
+        let found = (pos..candidate.len())
+            .find(|&i| candidate[i].to_lowercase().any(|c| c == q))?;
+
+        score += 1;
+        if previous.map(|p| p + 1 == found).unwrap_or(false) {
+            score += 5;
+        }
+        if found == 0 || matches!(candidate[found - 1], '/' | '_' | '-' | '.' | ' ') {
+            score += 8;
+        }
+
+        previous = Some(found);
+        pos = found + 1;
+    }
+
+    // Prefer matches in the file name over ones in the directories
+    let name_start = candidate.iter().rposition(|&c| c == '/').map(|i| i + 1).unwrap_or(0);
+    if previous.map(|p| p >= name_start).unwrap_or(false) {
+        score += 10;
+    }
+
+    Some(score * 100 - candidate.len() as i64)
+}
+
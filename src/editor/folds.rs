@@ -0,0 +1,192 @@
+use std::ops::Range;
+
+use ropey::Rope;
+
+use super::shift_position;
+use crate::chat::transcript;
+
+/// Rows `header..=last` of a fold, drawn as the one row `summary`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Folded {
+    pub header: usize,
+    pub last: usize,
+    pub summary: String,
+    /// A function or block in a code block rather than a whole message
+    pub code: bool,
+}
+
+/// Folded messages and code of a buffer, as char positions that move with
+/// edits like the protected answers do
+#[derive(Debug, Clone, Default)]
+pub struct Folds {
+    // Start of the header line of each folded message
+    messages: Vec<usize>,
+    // Chars of each folded function or block
+    code: Vec<Range<usize>>,
+}
+
+impl Folds {
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty() && self.code.is_empty()
+    }
+
+    /// Moves the folds along with an edit that replaced `range` with
+    /// `inserted` chars
+    pub(super) fn shift(&mut self, range: &Range<usize>, inserted: usize) {
+        for fold in &mut self.messages {
+            *fold = shift_position(*fold, range, inserted);
+        }
+        for fold in &mut self.code {
+            *fold = shift_position(fold.start, range, inserted)
+                ..shift_position(fold.end, range, inserted);
+        }
+    }
+
+    /// Folds the message whose header line starts at `start`, or unfolds it
+    pub(super) fn toggle_message(&mut self, start: usize) {
+        match self.messages.iter().position(|&fold| fold == start) {
+            Some(i) => {
+                self.messages.remove(i);
+            }
+            None => self.messages.push(start),
+        }
+    }
+
+    pub(super) fn fold_messages(&mut self, starts: Vec<usize>) {
+        self.messages = starts;
+    }
+
+    pub(super) fn add_code(&mut self, range: Range<usize>) {
+        self.code.push(range);
+    }
+
+    /// Unfolds the code folded on `row`. Returns whether there was any.
+    pub(super) fn remove_code_on(&mut self, buffer: &Rope, row: usize) -> bool {
+        let len = self.code.len();
+        self.code
+            .retain(|fold| fold.start > buffer.len_chars() || buffer.char_to_line(fold.start) != row);
+        self.code.len() != len
+    }
+
+    pub(super) fn clear(&mut self) {
+        self.messages.clear();
+        self.code.clear();
+    }
+
+    /// The folds still valid in `buffer`, in order, outer ones before the
+    /// ones inside them
+    pub fn rows(&self, buffer: &Rope) -> Vec<Folded> {
+        if self.is_empty() {
+            return Vec::new();
+        }
+
+        let headers = headers(buffer);
+        let mut folded: Vec<Folded> = self
+            .messages
+            .iter()
+            .filter_map(|&fold| message_fold(buffer, &headers, fold))
+            .chain(self.code.iter().filter_map(|fold| code_fold(buffer, fold)))
+            .collect();
+        folded.sort_by(|a, b| a.header.cmp(&b.header).then(b.last.cmp(&a.last)));
+        folded.dedup_by(|a, b| a.header == b.header && a.last == b.last);
+        folded
+    }
+
+    /// Drops the folds an edit broke, e.g. by removing a message header, so
+    /// they don't come back with a later one
+    pub(super) fn prune(&mut self, buffer: &Rope) {
+        if self.is_empty() {
+            return;
+        }
+
+        let headers = headers(buffer);
+        self.messages
+            .retain(|&fold| message_fold(buffer, &headers, fold).is_some());
+        self.code.retain(|fold| code_fold(buffer, fold).is_some());
+    }
+}
+
+fn line(buffer: &Rope, row: usize) -> String {
+    let line = buffer.line(row).to_string();
+    line.trim_end_matches(['\n', '\r']).to_string()
+}
+
+// Rows of the message header lines
+fn headers(buffer: &Rope) -> Vec<usize> {
+    (0..buffer.len_lines())
+        .filter(|&row| transcript::role(&line(buffer, row)).is_some())
+        .collect()
+}
+
+// A message runs from its header to the next one
+fn message_fold(buffer: &Rope, headers: &[usize], fold: usize) -> Option<Folded> {
+    if fold > buffer.len_chars() {
+        return None;
+    }
+    // Edits may have moved the line start or removed the header
+    let header = buffer.char_to_line(fold);
+    if buffer.line_to_char(header) != fold || !headers.contains(&header) {
+        return None;
+    }
+
+    let next = headers
+        .iter()
+        .find(|&&row| row > header)
+        .copied()
+        .unwrap_or(buffer.len_lines());
+    // Blank lines before the next header stay visible
+    let mut last = next - 1;
+    while last > header && line(buffer, last).trim().is_empty() {
+        last -= 1;
+    }
+
+    Some(Folded {
+        header,
+        last,
+        summary: message_summary(buffer, header, last),
+        code: false,
+    })
+}
+
+fn code_fold(buffer: &Rope, fold: &Range<usize>) -> Option<Folded> {
+    if fold.end > buffer.len_chars() || fold.start >= fold.end {
+        return None;
+    }
+    let header = buffer.char_to_line(fold.start);
+    let last = buffer.char_to_line(fold.end - 1);
+    if last <= header {
+        return None;
+    }
+
+    Some(Folded {
+        header,
+        last,
+        summary: code_summary(&line(buffer, header), &line(buffer, last)),
+        code: true,
+    })
+}
+
+// `▸ ## Assistant (...) · 14:02 — 12 lines: First line of the answer`
+fn message_summary(buffer: &Rope, header: usize, last: usize) -> String {
+    let first = (header + 1..=last)
+        .map(|row| line(buffer, row))
+        .find(|text| !text.trim().is_empty())
+        .unwrap_or_default();
+    format!(
+        "▸ {} — {} lines: {}",
+        line(buffer, header).trim_end(),
+        last - header,
+        first.trim()
+    )
+}
+
+// `fn main() {…}` and what follows the closing brace, e.g. `{…});`. Blocks
+// without braces, like Python's, end in `…`.
+fn code_summary(header_line: &str, last_line: &str) -> String {
+    let head = header_line.trim_end();
+    if head.ends_with(['{', '(', '[']) {
+        format!("{}…{}", head, last_line.trim())
+    } else {
+        format!("{} …", head)
+    }
+}
@@ -0,0 +1,124 @@
+use std::time::{Duration, Instant};
+
+/// System prompt of inline completions
+pub(super) const SYSTEM_PROMPT: &str = "You complete code. Answer with the text that goes at <CURSOR> only: \
+no explanations, no code fences, nothing that's already there.";
+
+// Longest suggestion, in lines
+const MAX_LINES: usize = 8;
+
+/// Completion suggested after a pause in typing at the end of a line in a
+/// code block. It's drawn dim after the cursor without being in the buffer
+/// until Tab takes it; any other key drops it.
+#[derive(Debug)]
+pub(super) struct Ghost {
+    enabled: bool,
+    // Last edit in Insert mode, a request goes out once it's a pause ago
+    typed_at: Option<Instant>,
+    // Request on its way and the buffer position it was asked for
+    asked: Option<(u64, usize)>,
+    next_id: u64,
+    // Suggestion and the buffer position it goes at
+    shown: Option<(usize, String)>,
+}
+
+impl Ghost {
+    pub(super) fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            typed_at: None,
+            asked: None,
+            next_id: 0,
+            shown: None,
+        }
+    }
+
+    /// Turns completions on or off, returns whether they're on now
+    pub(super) fn toggle(&mut self) -> bool {
+        self.enabled = !self.enabled;
+        self.typed(false);
+        self.enabled
+    }
+
+    /// A key in Insert mode: drops the suggestion and any answer still on
+    /// its way. An `edited` buffer starts the pause again.
+    pub(super) fn typed(&mut self, edited: bool) {
+        self.shown = None;
+        self.asked = None;
+        self.typed_at = (edited && self.enabled).then(Instant::now);
+    }
+
+    /// Whether typing paused for `delay`, once per pause
+    pub(super) fn due(&mut self, delay: Duration) -> bool {
+        match self.typed_at {
+            Some(at) if at.elapsed() >= delay => {
+                self.typed_at = None;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Id of a new request for a completion at `pos`
+    pub(super) fn ask(&mut self, pos: usize) -> u64 {
+        self.next_id += 1;
+        self.asked = Some((self.next_id, pos));
+        self.next_id
+    }
+
+    /// Shows the answer of request `id` when it's the latest one and the
+    /// cursor is still at `pos`. Returns whether it's shown.
+    pub(super) fn receive(&mut self, id: u64, pos: usize, answer: &str) -> bool {
+        if self.asked != Some((id, pos)) {
+            return false;
+        }
+        self.asked = None;
+        self.shown = Some((pos, clean(answer))).filter(|(_, text)| !text.is_empty());
+        self.shown.is_some()
+    }
+
+    /// The suggestion at `pos`, taken out
+    pub(super) fn take(&mut self, pos: usize) -> Option<String> {
+        match self.shown.take() {
+            Some((at, text)) if at == pos => Some(text),
+            _ => None,
+        }
+    }
+
+    /// What's drawn after the cursor at `pos`: the first line of the
+    /// suggestion and how many more there are
+    pub(super) fn label(&self, pos: usize) -> Option<String> {
+        let (at, text) = self.shown.as_ref()?;
+        if *at != pos {
+            return None;
+        }
+        let mut lines = text.lines();
+        let first = lines.next().unwrap_or("").replace('\t', "    ");
+        Some(match lines.count() {
+            0 => first,
+            more => format!("{}  (+{} lines)", first, more),
+        })
+    }
+}
+
+/// Request for what goes between `before` and `after` in a code block
+pub(super) fn prompt(language: &str, before: &str, after: &str) -> String {
+    format!(
+        "Complete the code at <CURSOR>, up to the end of the statement or a few lines at most.\n\n```{}\n{}<CURSOR>{}\n```",
+        language, before, after
+    )
+}
+
+// The answer without a code fence the model put around it anyway, cut to
+// `MAX_LINES`
+fn clean(answer: &str) -> String {
+    let mut lines: Vec<&str> = answer.trim_end().lines().collect();
+    if lines.first().is_some_and(|line| line.starts_with("```")) {
+        lines.remove(0);
+        if lines.last().is_some_and(|line| line.trim() == "```") {
+            lines.pop();
+        }
+    }
+    lines.truncate(MAX_LINES);
+    lines.join("\n")
+}
@@ -0,0 +1,49 @@
+use ropey::Rope;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Char columns where the grapheme clusters of a line start, followed by the
+/// end of its text. Clusters never span lines, the line break is left out.
+fn boundaries(buffer: &Rope, row: usize) -> Vec<usize> {
+    if row >= buffer.len_lines() {
+        return vec![0];
+    }
+    let line = buffer.line(row).to_string();
+    let text = line.trim_end_matches(['\n', '\r']);
+
+    let mut columns = Vec::new();
+    let mut col = 0;
+    for grapheme in text.graphemes(true) {
+        columns.push(col);
+        col += grapheme.chars().count();
+    }
+    columns.push(col);
+    columns
+}
+
+/// Start of the cluster before `col`, 0 at the start of the line
+pub(super) fn previous(buffer: &Rope, row: usize, col: usize) -> usize {
+    boundaries(buffer, row)
+        .into_iter()
+        .rev()
+        .find(|&b| b < col)
+        .unwrap_or(0)
+}
+
+/// Start of the cluster after the one at `col`. Past the end of the text
+/// it moves one char, onto the line break.
+pub(super) fn next(buffer: &Rope, row: usize, col: usize) -> usize {
+    boundaries(buffer, row)
+        .into_iter()
+        .find(|&b| b > col)
+        .unwrap_or(col + 1)
+}
+
+/// `col` moved back to the start of the cluster it's in
+pub(super) fn snap(buffer: &Rope, row: usize, col: usize) -> usize {
+    let columns = boundaries(buffer, row);
+    match columns.last() {
+        // Beyond the text, e.g. on the line break
+        Some(&end) if col >= end => col,
+        _ => columns.into_iter().rev().find(|&b| b <= col).unwrap_or(0),
+    }
+}
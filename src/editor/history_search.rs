@@ -0,0 +1,117 @@
+use std::fs;
+use std::path::Path;
+
+use ignore::WalkBuilder;
+
+use crate::error::Result;
+
+// Enough to pick from, a common word shouldn't list every line ever written
+const MAX_HITS: usize = 500;
+// Lines shown above and below the selected hit
+const CONTEXT_LINES: usize = 2;
+
+/// Every conversation below the history directory, read once when the
+/// search opens and searched again on each typed key
+#[derive(Debug, Clone)]
+pub(super) struct HistorySearch {
+    // (path relative to the root, lines)
+    files: Vec<(String, Vec<String>)>,
+    // (file, line) of each match, newest file first
+    hits: Vec<(usize, usize)>,
+}
+
+impl HistorySearch {
+    pub(super) fn new(root: &Path) -> Result<Self> {
+        let walker = WalkBuilder::new(root)
+            .require_git(false)
+            .parents(false)
+            .build();
+
+        let mut files = Vec::new();
+        // A directory that can't be read is left out, not the whole search
+        for entry in walker.filter_map(|entry| entry.ok()) {
+            let path = entry.path();
+            // Sessions and daily files, not swap files or the prompt history
+            if path.extension().and_then(|e| e.to_str()) != Some("md") {
+                continue;
+            }
+            let Ok(text) = fs::read_to_string(path) else {
+                continue;
+            };
+            let modified = entry.metadata().ok().and_then(|m| m.modified().ok());
+            let name = path.strip_prefix(root).unwrap_or(path).to_string_lossy().into_owned();
+            files.push((modified, name, text.lines().map(str::to_string).collect()));
+        }
+        files.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+
+        Ok(Self {
+            files: files.into_iter().map(|(_, name, lines)| (name, lines)).collect(),
+            hits: Vec::new(),
+        })
+    }
+
+    /// Finds the lines holding `query`, ignoring case unless it has capitals
+    pub(super) fn search(&mut self, query: &str) {
+        self.hits.clear();
+        if query.is_empty() {
+            return;
+        }
+
+        let ignore_case = !query.chars().any(char::is_uppercase);
+        let query = if ignore_case {
+            query.to_lowercase()
+        } else {
+            query.to_string()
+        };
+
+        'files: for (file, (_, lines)) in self.files.iter().enumerate() {
+            for (line, text) in lines.iter().enumerate() {
+                let found = if ignore_case {
+                    text.to_lowercase().contains(&query)
+                } else {
+                    text.contains(&query)
+                };
+                if found {
+                    self.hits.push((file, line));
+                    if self.hits.len() >= MAX_HITS {
+                        break 'files;
+                    }
+                }
+            }
+        }
+    }
+
+    /// `file:line: text` of every hit
+    pub(super) fn labels(&self) -> Vec<String> {
+        self.hits
+            .iter()
+            .map(|&(file, line)| {
+                let (name, lines) = &self.files[file];
+                format!("{}:{}: {}", name, line + 1, lines[line].trim())
+            })
+            .collect()
+    }
+
+    /// Numbered lines around hit `index`, the hit itself marked
+    pub(super) fn context(&self, index: usize) -> Vec<String> {
+        let Some(&(file, line)) = self.hits.get(index) else {
+            return Vec::new();
+        };
+        let lines = &self.files[file].1;
+        let first = line.saturating_sub(CONTEXT_LINES);
+        let last = (line + CONTEXT_LINES).min(lines.len() - 1);
+
+        (first..=last)
+            .map(|row| {
+                let mark = if row == line { '>' } else { ' ' };
+                format!("{} {:>4} {}", mark, row + 1, lines[row])
+            })
+            .collect()
+    }
+
+    /// File of hit `index`, relative to the root, and its 1-based line
+    pub(super) fn target(&self, index: usize) -> Option<(String, usize)> {
+        let &(file, line) = self.hits.get(index)?;
+        Some((self.files[file].0.clone(), line + 1))
+    }
+}
@@ -0,0 +1,116 @@
+use std::ops::Range;
+
+use crate::async_handler::Origin;
+
+/// Where the answer of an inline edit goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Placement {
+    /// In place of the text
+    Replace,
+    /// Lines above it, indented like its first line
+    Above,
+    /// Below its last line after a blank one, indented like its first line
+    Below,
+}
+
+/// Text sent to be rewritten by an instruction, or to get code written
+/// above or below it. The answer goes in once it's complete, unless the
+/// text changed meanwhile.
+#[derive(Debug, Clone)]
+pub(super) struct InlineEdit {
+    // Buffer the text is in
+    pub(super) origin: Origin,
+    pub(super) range: Range<usize>,
+    pub(super) original: String,
+    pub(super) answer: String,
+    pub(super) placement: Placement,
+}
+
+impl InlineEdit {
+    /// Range of the buffer the answer replaces and the text it's replaced by
+    pub(super) fn change(&self) -> (Range<usize>, String) {
+        match self.placement {
+            Placement::Replace => (self.range.clone(), rewritten(&self.answer, &self.original)),
+            Placement::Above => {
+                let code = indented(&rewritten(&self.answer, ""), indent_of(&self.original));
+                (self.range.start..self.range.start, format!("{}\n", code))
+            }
+            Placement::Below => {
+                let code = indented(&rewritten(&self.answer, ""), indent_of(&self.original));
+                // The text may end without a line break at the end of the buffer
+                let text = if self.original.ends_with('\n') {
+                    format!("\n{}\n", code)
+                } else {
+                    format!("\n\n{}", code)
+                };
+                (self.range.end..self.range.end, text)
+            }
+        }
+    }
+}
+
+/// A rewrite in the buffer, for putting the original text back
+#[derive(Debug, Clone)]
+pub(super) struct Rewrite {
+    pub(super) range: Range<usize>,
+    pub(super) original: String,
+    pub(super) rewritten: String,
+}
+
+/// Request for rewriting `text`, written in `language` when it's code
+pub(super) fn prompt(instruction: &str, text: &str, language: Option<&str>) -> String {
+    let kind = if language.is_some() { "code" } else { "text" };
+    format!(
+        "{}\n\nApply this to the {} below. Answer with the rewritten {} only, without explanations or code fences.\n\n```{}\n{}\n```",
+        instruction.trim(),
+        kind,
+        kind,
+        language.unwrap_or(""),
+        text.trim_end_matches('\n'),
+    )
+}
+
+/// Rewritten text of an answer: inside its code fence when the model put
+/// one around it anyway, and ending in a line break like `original`
+fn rewritten(answer: &str, original: &str) -> String {
+    let answer = answer.trim_matches('\n');
+    let mut lines: Vec<&str> = answer.lines().collect();
+    if lines.len() >= 2 && lines[0].starts_with("```") && lines[lines.len() - 1].trim() == "```" {
+        lines.remove(0);
+        lines.pop();
+    }
+
+    let mut text = lines.join("\n");
+    if original.ends_with('\n') {
+        text.push('\n');
+    }
+    text
+}
+
+// Leading spaces and tabs of the first line of `text`
+fn indent_of(text: &str) -> &str {
+    let line = text.lines().next().unwrap_or("");
+    &line[..line.len() - line.trim_start_matches([' ', '\t']).len()]
+}
+
+// `code` moved to `indent`, keeping how its lines are indented among
+// themselves. Only spaces and tabs count as indent, so the cut is always
+// on a char boundary.
+fn indented(code: &str, indent: &str) -> String {
+    let common = code
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| line.len() - line.trim_start_matches([' ', '\t']).len())
+        .min()
+        .unwrap_or(0);
+    code.lines()
+        .map(|line| {
+            if line.trim().is_empty() {
+                String::new()
+            } else {
+                format!("{}{}", indent, &line[common..])
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
@@ -0,0 +1,765 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crossterm::event::{KeyCode, KeyModifiers};
+use once_cell::sync::OnceCell;
+
+use crate::config;
+use crate::Result;
+
+// Defaults with the `[keys.*]` tables of the config applied
+static KEYMAP: OnceCell<HashMap<Context, Vec<Binding>>> = OnceCell::new();
+
+/// Where a key binding applies: a mode, or a menu opened by a key
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Context {
+    Normal,
+    Select,
+    GoTo,
+    Fold,
+    Table,
+    File,
+    AI,
+}
+
+impl Context {
+    pub const ALL: [Context; 7] = [
+        Context::Normal,
+        Context::Select,
+        Context::GoTo,
+        Context::Fold,
+        Context::Table,
+        Context::File,
+        Context::AI,
+    ];
+
+    /// Name of its `[keys.*]` table in the config
+    pub fn name(self) -> &'static str {
+        match self {
+            Context::Normal => "normal",
+            Context::Select => "select",
+            Context::GoTo => "goto",
+            Context::Fold => "fold",
+            Context::Table => "table",
+            Context::File => "file",
+            Context::AI => "ai",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|context| context.name() == name)
+    }
+
+    pub fn title(self) -> &'static str {
+        match self {
+            Context::Normal => "Normal mode",
+            Context::Select => "Select mode",
+            Context::GoTo => "Go to menu",
+            Context::Fold => "Fold menu",
+            Context::Table => "Table menu",
+            Context::File => "File menu",
+            Context::AI => "AI menu",
+        }
+    }
+}
+
+/// What a key does. Dispatch matches on these, so the help always says
+/// what a key really runs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    // Modes and menus
+    InsertMode,
+    SelectMode,
+    GoToMenu,
+    FoldMenu,
+    TableMenu,
+    FileMenu,
+    AIMenu,
+    CloseMenu,
+    Help,
+    Preview,
+
+    // Motions
+    Up,
+    Down,
+    Left,
+    Right,
+    WordStart,
+    WordBack,
+    WordEnd,
+    LineStart,
+    LineEnd,
+    BufferStart,
+    BufferEnd,
+    GotoLine,
+    OpenFileUnderCursor,
+    MatchingPair,
+
+    // Search
+    Search,
+    NextMatch,
+    PreviousMatch,
+    Replace,
+
+    // Editing
+    SelectLine,
+    SelectRegister,
+    Yank,
+    YankBlock,
+    Paste,
+    DeleteChar,
+    DeleteSelection,
+    RecordMacro,
+    PlayMacro,
+    RunInShell,
+    ApplySelectedDiff,
+    InnerObject,
+    AroundObject,
+    ToggleComment,
+    DuplicateLines,
+    MoveLinesUp,
+    MoveLinesDown,
+
+    // Folds
+    ToggleFold,
+    FoldAll,
+    UnfoldAll,
+
+    // Markdown tables
+    FormatTable,
+    InsertTableRow,
+    InsertTableColumn,
+
+    // Files and buffers
+    Save,
+    SaveAs,
+    WipeBuffer,
+    LoadFile,
+    DiffSaved,
+    NewBuffer,
+    NewScratch,
+    NewSession,
+    OpenSession,
+    CloseBuffer,
+    NextBuffer,
+    PreviousBuffer,
+    SplitView,
+    ChatView,
+    ToggleWrap,
+    ToggleReadOnly,
+    ToggleLineEnding,
+    SwitchTheme,
+    ExtractBlock,
+    Export,
+    Import,
+    MessageHistory,
+    RenameSession,
+    TagSession,
+    StageHistory,
+    CommitHistory,
+    SearchHistory,
+    Quit,
+
+    // Requests
+    SendOllama,
+    SendOpenAI,
+    SendAnthropic,
+    SendFanOut,
+    ToggleStreaming,
+    CancelRequest,
+    PickModel,
+    OllamaModels,
+    AttachFiles,
+    RunAndAttach,
+    FixErrors,
+    ClearAttachments,
+    ToggleProject,
+    RefreshProject,
+    EditSystemPrompt,
+    EditParams,
+    RequestPanel,
+    RequestLog,
+    Usage,
+    ApiKey,
+    UnlockAnswers,
+    CycleInsertion,
+    CycleContextTrim,
+    ApplyAnswerDiff,
+    Regenerate,
+    InlineEdit,
+    UndoRewrite,
+    ToggleInlineCompletion,
+    Explain,
+    DocComment,
+    UnitTests,
+    TogglePin,
+    PinsPanel,
+    PromptHistory,
+}
+
+impl Command {
+    /// Name of the command in the `[keys.*]` tables of the config
+    pub fn name(self) -> &'static str {
+        match self {
+            Command::InsertMode => "insert_mode",
+            Command::SelectMode => "select_mode",
+            Command::GoToMenu => "goto_menu",
+            Command::FoldMenu => "fold_menu",
+            Command::TableMenu => "table_menu",
+            Command::FileMenu => "file_menu",
+            Command::AIMenu => "ai_menu",
+            Command::CloseMenu => "close_menu",
+            Command::Help => "help",
+            Command::Preview => "preview",
+            Command::Up => "up",
+            Command::Down => "down",
+            Command::Left => "left",
+            Command::Right => "right",
+            Command::WordStart => "word_start",
+            Command::WordBack => "word_back",
+            Command::WordEnd => "word_end",
+            Command::LineStart => "line_start",
+            Command::LineEnd => "line_end",
+            Command::BufferStart => "buffer_start",
+            Command::BufferEnd => "buffer_end",
+            Command::GotoLine => "goto_line",
+            Command::OpenFileUnderCursor => "open_file_under_cursor",
+            Command::MatchingPair => "matching_pair",
+            Command::Search => "search",
+            Command::NextMatch => "next_match",
+            Command::PreviousMatch => "previous_match",
+            Command::Replace => "replace",
+            Command::SelectLine => "select_line",
+            Command::SelectRegister => "select_register",
+            Command::Yank => "yank",
+            Command::YankBlock => "yank_block",
+            Command::Paste => "paste",
+            Command::DeleteChar => "delete_char",
+            Command::DeleteSelection => "delete_selection",
+            Command::RecordMacro => "record_macro",
+            Command::PlayMacro => "play_macro",
+            Command::RunInShell => "run_in_shell",
+            Command::ApplySelectedDiff => "apply_selected_diff",
+            Command::InnerObject => "inner_object",
+            Command::AroundObject => "around_object",
+            Command::ToggleComment => "toggle_comment",
+            Command::DuplicateLines => "duplicate_lines",
+            Command::MoveLinesUp => "move_lines_up",
+            Command::MoveLinesDown => "move_lines_down",
+            Command::ToggleFold => "toggle_fold",
+            Command::FoldAll => "fold_all",
+            Command::UnfoldAll => "unfold_all",
+            Command::FormatTable => "format_table",
+            Command::InsertTableRow => "insert_table_row",
+            Command::InsertTableColumn => "insert_table_column",
+            Command::Save => "save",
+            Command::SaveAs => "save_as",
+            Command::WipeBuffer => "wipe_buffer",
+            Command::LoadFile => "load_file",
+            Command::DiffSaved => "diff_saved",
+            Command::NewBuffer => "new_buffer",
+            Command::NewScratch => "new_scratch",
+            Command::NewSession => "new_session",
+            Command::OpenSession => "open_session",
+            Command::CloseBuffer => "close_buffer",
+            Command::NextBuffer => "next_buffer",
+            Command::PreviousBuffer => "previous_buffer",
+            Command::SplitView => "split_view",
+            Command::ChatView => "chat_view",
+            Command::ToggleWrap => "toggle_wrap",
+            Command::ToggleReadOnly => "toggle_read_only",
+            Command::ToggleLineEnding => "toggle_line_ending",
+            Command::SwitchTheme => "switch_theme",
+            Command::ExtractBlock => "extract_block",
+            Command::Export => "export",
+            Command::Import => "import",
+            Command::MessageHistory => "message_history",
+            Command::RenameSession => "rename_session",
+            Command::TagSession => "tag_session",
+            Command::StageHistory => "stage_history",
+            Command::CommitHistory => "commit_history",
+            Command::SearchHistory => "search_history",
+            Command::Quit => "quit",
+            Command::SendOllama => "send_ollama",
+            Command::SendOpenAI => "send_openai",
+            Command::SendAnthropic => "send_anthropic",
+            Command::SendFanOut => "send_fan_out",
+            Command::ToggleStreaming => "toggle_streaming",
+            Command::CancelRequest => "cancel_request",
+            Command::PickModel => "pick_model",
+            Command::OllamaModels => "ollama_models",
+            Command::AttachFiles => "attach_files",
+            Command::RunAndAttach => "run_and_attach",
+            Command::FixErrors => "fix_errors",
+            Command::ClearAttachments => "clear_attachments",
+            Command::ToggleProject => "toggle_project",
+            Command::RefreshProject => "refresh_project",
+            Command::EditSystemPrompt => "edit_system_prompt",
+            Command::EditParams => "edit_params",
+            Command::RequestPanel => "request_panel",
+            Command::RequestLog => "request_log",
+            Command::Usage => "usage",
+            Command::ApiKey => "api_key",
+            Command::UnlockAnswers => "unlock_answers",
+            Command::CycleInsertion => "cycle_insertion",
+            Command::CycleContextTrim => "cycle_context_trim",
+            Command::ApplyAnswerDiff => "apply_answer_diff",
+            Command::Regenerate => "regenerate",
+            Command::InlineEdit => "inline_edit",
+            Command::UndoRewrite => "undo_rewrite",
+            Command::ToggleInlineCompletion => "toggle_inline_completion",
+            Command::Explain => "explain",
+            Command::DocComment => "doc_comment",
+            Command::UnitTests => "unit_tests",
+            Command::TogglePin => "toggle_pin",
+            Command::PinsPanel => "pins_panel",
+            Command::PromptHistory => "prompt_history",
+        }
+    }
+}
+
+/// A character with Ctrl and Alt. Shift is part of the character.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Key {
+    pub code: char,
+    pub modifiers: KeyModifiers,
+}
+
+impl Key {
+    pub fn new(code: char, modifiers: KeyModifiers) -> Self {
+        Self {
+            code,
+            modifiers: modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT),
+        }
+    }
+
+    /// `a`, `A`, `space`, `ctrl+a`, `alt+shift+x`, `ctrl++`
+    pub fn parse(spec: &str) -> std::result::Result<Self, String> {
+        let (prefix, name) = match spec.strip_suffix("++") {
+            Some(prefix) => (Some(prefix), "+"),
+            None => match spec.rsplit_once('+') {
+                Some((prefix, name)) if !name.is_empty() => (Some(prefix), name),
+                _ => (None, spec),
+            },
+        };
+
+        let mut code = match name {
+            _ if name.eq_ignore_ascii_case("space") => ' ',
+            _ => {
+                let mut chars = name.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => c,
+                    _ => return Err(format!("\"{}\" isn't a single character or `space`", name)),
+                }
+            }
+        };
+
+        let mut modifiers = KeyModifiers::NONE;
+        for modifier in prefix.into_iter().flat_map(|prefix| prefix.split('+')) {
+            match modifier.to_ascii_lowercase().as_str() {
+                "ctrl" | "control" => modifiers |= KeyModifiers::CONTROL,
+                "alt" => modifiers |= KeyModifiers::ALT,
+                "shift" => code = code.to_ascii_uppercase(),
+                _ => return Err(format!("unknown modifier \"{}\", use ctrl, alt or shift", modifier)),
+            }
+        }
+        Ok(Self::new(code, modifiers))
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.modifiers.contains(KeyModifiers::CONTROL) {
+            write!(f, "Ctrl+")?;
+        }
+        if self.modifiers.contains(KeyModifiers::ALT) {
+            write!(f, "Alt+")?;
+        }
+        match self.code {
+            ' ' => write!(f, "Space"),
+            c => write!(f, "{}", c),
+        }
+    }
+}
+
+/// A key of a context, the command it runs and its help line
+#[derive(Debug, Clone, Copy)]
+pub struct Binding {
+    pub key: Key,
+    pub command: Command,
+    pub description: &'static str,
+}
+
+const fn bind(key: char, command: Command, description: &'static str) -> Binding {
+    Binding {
+        key: Key {
+            code: key,
+            modifiers: KeyModifiers::NONE,
+        },
+        command,
+        description,
+    }
+}
+
+// `bind` with Alt held
+const fn alt(key: char, command: Command, description: &'static str) -> Binding {
+    Binding {
+        key: Key {
+            code: key,
+            modifiers: KeyModifiers::ALT,
+        },
+        command,
+        description,
+    }
+}
+
+const NORMAL: &[Binding] = &[
+    bind('i', Command::InsertMode, "Insert mode"),
+    bind('v', Command::SelectMode, "Select mode"),
+    bind('x', Command::SelectLine, "Select the line"),
+    bind('k', Command::Up, "Up"),
+    bind('j', Command::Down, "Down"),
+    bind('h', Command::Left, "Left"),
+    bind('l', Command::Right, "Right"),
+    bind('w', Command::WordStart, "Next word"),
+    bind('b', Command::WordBack, "Previous word"),
+    bind('e', Command::WordEnd, "End of word"),
+    bind('%', Command::MatchingPair, "Jump to the matching bracket"),
+    bind('/', Command::Search, "Search"),
+    bind('n', Command::NextMatch, "Next match"),
+    bind('N', Command::PreviousMatch, "Previous match"),
+    bind('R', Command::Replace, "Search and replace"),
+    bind('"', Command::SelectRegister, "Pick the register of the next yank, delete or paste"),
+    bind('y', Command::Yank, "Copy the selection, or a text object: yiw, yi(, yip, yic ..."),
+    bind('Y', Command::YankBlock, "Copy the code block under the cursor"),
+    bind('p', Command::Paste, "Paste"),
+    bind('d', Command::DeleteChar, "Delete the character under the cursor, or a text object: diw, da\", dap ..."),
+    bind('D', Command::DuplicateLines, "Duplicate the line"),
+    alt('k', Command::MoveLinesUp, "Move the line up (Alt+Up too)"),
+    alt('j', Command::MoveLinesDown, "Move the line down (Alt+Down too)"),
+    bind('q', Command::RecordMacro, "Record a macro, or stop recording"),
+    bind('@', Command::PlayMacro, "Play a macro, a count in front repeats it"),
+    bind('!', Command::RunInShell, "Run the code block under the cursor in the shell"),
+    bind('s', Command::Save, "Save"),
+    bind(':', Command::GotoLine, "Go to line number"),
+    bind('g', Command::GoToMenu, "Go to menu"),
+    bind('z', Command::FoldMenu, "Fold menu"),
+    bind('t', Command::TableMenu, "Table menu, Tab and Shift+Tab move between cells"),
+    bind(' ', Command::FileMenu, "File menu"),
+    bind('a', Command::AIMenu, "AI menu"),
+    bind('P', Command::Preview, "Read the buffer as rendered markdown"),
+    bind('?', Command::Help, "Show all key bindings"),
+];
+
+const SELECT: &[Binding] = &[
+    bind('x', Command::SelectLine, "Extend to the whole line"),
+    bind('k', Command::Up, "Up"),
+    bind('j', Command::Down, "Down"),
+    bind('h', Command::Left, "Left"),
+    bind('l', Command::Right, "Right"),
+    bind('w', Command::WordStart, "Next word"),
+    bind('b', Command::WordBack, "Previous word"),
+    bind('e', Command::WordEnd, "End of word"),
+    bind('%', Command::MatchingPair, "Jump to the matching bracket"),
+    bind('/', Command::Search, "Search, extending the selection to the match"),
+    bind('n', Command::NextMatch, "Next match"),
+    bind('N', Command::PreviousMatch, "Previous match"),
+    bind('"', Command::SelectRegister, "Pick the register of the next yank or delete"),
+    bind('y', Command::Yank, "Copy the selection"),
+    bind('d', Command::DeleteSelection, "Delete the selection"),
+    bind('D', Command::DuplicateLines, "Duplicate the selected lines"),
+    alt('k', Command::MoveLinesUp, "Move the selected lines up (Alt+Up too)"),
+    alt('j', Command::MoveLinesDown, "Move the selected lines down (Alt+Down too)"),
+    bind('!', Command::RunInShell, "Run the selection in the shell"),
+    bind('A', Command::ApplySelectedDiff, "Apply the selected unified diff to its files"),
+    bind('R', Command::InlineEdit, "Rewrite the selection by an instruction"),
+    bind('X', Command::Explain, "Explain the selected code"),
+    bind('C', Command::DocComment, "Write the doc comment of the selected code above it"),
+    bind('G', Command::UnitTests, "Write unit tests of the selected code below it"),
+    bind('i', Command::InnerObject, "Select inside a text object: w ( [ { < \" ' ` p(aragraph) c(ode block)"),
+    bind('a', Command::AroundObject, "Select a text object with its brackets, quotes, fences or blanks"),
+    bind('g', Command::GoToMenu, "Go to menu"),
+];
+
+const GOTO: &[Binding] = &[
+    bind('g', Command::BufferStart, "Goto first line"),
+    bind('e', Command::BufferEnd, "Goto end last line"),
+    bind('l', Command::LineEnd, "Goto end of line"),
+    bind('h', Command::LineStart, "Goto start of line"),
+    bind('n', Command::NextBuffer, "Next buffer"),
+    bind('p', Command::PreviousBuffer, "Previous buffer"),
+    bind(':', Command::GotoLine, "Goto line number, or type its digits"),
+    bind('c', Command::ToggleComment, "Comment or uncomment the line, or the selected lines"),
+    bind('f', Command::OpenFileUnderCursor, "Open the file under the cursor, at its line"),
+];
+
+const FOLD: &[Binding] = &[
+    bind('a', Command::ToggleFold, "Fold or unfold the function, block or message"),
+    bind('M', Command::FoldAll, "Fold all messages"),
+    bind('R', Command::UnfoldAll, "Unfold all messages"),
+];
+
+const TABLE: &[Binding] = &[
+    bind('f', Command::FormatTable, "Align the columns of the table under the cursor"),
+    bind('r', Command::InsertTableRow, "Add a row below the cursor's"),
+    bind('c', Command::InsertTableColumn, "Add a column after the cursor's"),
+];
+
+const FILE: &[Binding] = &[
+    bind('w', Command::WipeBuffer, "Wipe buffer"),
+    bind('l', Command::LoadFile, "Load file"),
+    bind('s', Command::Save, "Save"),
+    bind('S', Command::SaveAs, "Save as"),
+    bind('d', Command::DiffSaved, "Diff with saved file"),
+    bind('n', Command::NewBuffer, "New buffer"),
+    bind('b', Command::NewScratch, "New scratch buffer, never saved"),
+    bind('N', Command::NewSession, "New session"),
+    bind('o', Command::OpenSession, "Open session"),
+    bind('x', Command::CloseBuffer, "Close buffer"),
+    bind('v', Command::SplitView, "Toggle split view"),
+    bind('c', Command::ChatView, "Toggle chat view: input box below the conversation"),
+    bind('t', Command::ToggleWrap, "Toggle line wrap"),
+    bind('L', Command::ToggleLineEnding, "Convert line endings between LF and CRLF"),
+    bind('R', Command::ToggleReadOnly, "Toggle read-only view: no edits or highlighting, for huge files"),
+    bind('T', Command::SwitchTheme, "Switch theme"),
+    bind('e', Command::ExtractBlock, "Extract code block to file"),
+    bind('E', Command::Export, "Export conversation to JSON and HTML"),
+    bind('I', Command::Import, "Import ChatGPT or exported conversation"),
+    bind('m', Command::MessageHistory, "Message history"),
+    bind('r', Command::RenameSession, "Rename session"),
+    bind('#', Command::TagSession, "Tag session"),
+    bind('g', Command::StageHistory, "Stage the history folder in git"),
+    bind('G', Command::CommitHistory, "Commit the history folder"),
+    bind('/', Command::SearchHistory, "Search all conversations"),
+    bind('q', Command::Quit, "Exit editor"),
+];
+
+const AI: &[Binding] = &[
+    bind('l', Command::SendOllama, "Send request to Ollama"),
+    bind('o', Command::SendOpenAI, "Send request to OpenAI"),
+    bind('a', Command::SendAnthropic, "Send request to Anthropic"),
+    bind('M', Command::SendFanOut, "Send request to the fan_out models"),
+    bind('s', Command::ToggleStreaming, "Toggle streaming"),
+    bind('c', Command::CancelRequest, "Cancel request and discard answer"),
+    bind('m', Command::PickModel, "Pick model"),
+    bind('O', Command::OllamaModels, "Manage Ollama models"),
+    bind('f', Command::AttachFiles, "Attach files"),
+    bind('!', Command::RunAndAttach, "Run a shell command and attach its output to the next prompt"),
+    bind('E', Command::FixErrors, "Send cargo check errors to be fixed, then preview the diff"),
+    bind('F', Command::ClearAttachments, "Clear attached files"),
+    bind('t', Command::ToggleProject, "Toggle project layout in prompts"),
+    bind('T', Command::RefreshProject, "Refresh project layout"),
+    bind('p', Command::EditSystemPrompt, "Edit system prompt"),
+    bind('g', Command::EditParams, "Set temperature, top p, max tokens and stop"),
+    bind('r', Command::RequestPanel, "Toggle request panel"),
+    bind('L', Command::RequestLog, "Show request log"),
+    bind('U', Command::Usage, "Show token usage and cost per provider"),
+    bind('k', Command::ApiKey, "Set API key"),
+    bind('u', Command::UnlockAnswers, "Unlock answers for editing"),
+    bind('i', Command::CycleInsertion, "Cycle where answers go"),
+    bind('x', Command::CycleContextTrim, "Drop or summarize old messages when the context is full"),
+    bind('d', Command::ApplyAnswerDiff, "Apply diff from the last answer"),
+    bind(
+        'R',
+        Command::Regenerate,
+        "Regenerate the last answer with the current model and parameters, or retry a timed out request",
+    ),
+    bind(
+        'w',
+        Command::InlineEdit,
+        "Rewrite the selection, or the code block or paragraph under the cursor, by an instruction",
+    ),
+    bind('W', Command::UndoRewrite, "Undo the last rewrite, or take out the last doc comment or tests"),
+    bind('X', Command::Explain, "Explain the code block or paragraph under the cursor"),
+    bind('D', Command::DocComment, "Write the doc comment of the definition or paragraph under the cursor"),
+    bind('G', Command::UnitTests, "Write unit tests of the definition or paragraph under the cursor"),
+    bind('C', Command::ToggleInlineCompletion, "Toggle inline completion of code after a pause in typing"),
+    bind('P', Command::TogglePin, "Pin or unpin the code block or paragraph under the cursor"),
+    bind('V', Command::PinsPanel, "Toggle pinned panel (Alt+1..9 inserts a pin)"),
+    bind('h', Command::PromptHistory, "Recall an earlier prompt to edit and send again"),
+    bind('e', Command::CloseMenu, "Exit"),
+];
+
+// Built-in bindings of `context`
+fn defaults(context: Context) -> &'static [Binding] {
+    match context {
+        Context::Normal => NORMAL,
+        Context::Select => SELECT,
+        Context::GoTo => GOTO,
+        Context::Fold => FOLD,
+        Context::Table => TABLE,
+        Context::File => FILE,
+        Context::AI => AI,
+    }
+}
+
+/// Applies the `[keys.*]` tables of the config on top of the defaults.
+/// Fails with every invalid or conflicting entry; returns warnings about
+/// commands that lost their only key.
+pub fn init() -> Result<Vec<String>> {
+    let (keymap, errors) = build(&config::get().keys);
+    if !errors.is_empty() {
+        let path = config::Config::path()
+            .map(|path| path.display().to_string())
+            .unwrap_or_else(|| "config.toml".to_string());
+        return Err(format!("Invalid key bindings in {}:\n  {}", path, errors.join("\n  ")).into());
+    }
+
+    let mut warnings = Vec::new();
+    for context in Context::ALL {
+        for binding in defaults(context) {
+            if !keymap[&context].iter().any(|b| b.command == binding.command) {
+                warnings.push(format!("No key left in [keys.{}] for {}", context.name(), binding.command.name()));
+            }
+        }
+    }
+
+    let _ = KEYMAP.set(keymap);
+    Ok(warnings)
+}
+
+// The bindings of every context and the errors found on the way. A user
+// key replaces the default on the same key; a new key joins the other keys
+// of its command.
+fn build(tables: &HashMap<String, HashMap<String, String>>) -> (HashMap<Context, Vec<Binding>>, Vec<String>) {
+    let mut keymap: HashMap<Context, Vec<Binding>> = Context::ALL
+        .into_iter()
+        .map(|context| (context, defaults(context).to_vec()))
+        .collect();
+    let mut errors = Vec::new();
+
+    let mut tables: Vec<_> = tables.iter().collect();
+    tables.sort_by_key(|(table, _)| *table);
+    for (table, entries) in tables {
+        let Some(context) = Context::from_name(table) else {
+            let names: Vec<_> = Context::ALL.iter().map(|c| c.name()).collect();
+            errors.push(format!("[keys.{}]: no such mode or menu, use one of {}", table, names.join(", ")));
+            continue;
+        };
+
+        let mut seen: HashMap<Key, &str> = HashMap::new();
+        let mut entries: Vec<_> = entries.iter().collect();
+        entries.sort();
+        for (spec, name) in entries {
+            let at = format!("[keys.{}] \"{}\"", table, spec);
+            let key = match Key::parse(spec) {
+                Ok(key) => key,
+                Err(e) => {
+                    errors.push(format!("{}: {}", at, e));
+                    continue;
+                }
+            };
+            if let Some(other) = seen.insert(key, spec) {
+                errors.push(format!("{}: same key as \"{}\" ({})", at, other, key));
+                continue;
+            }
+            if let Some(reason) = reserved(context, key) {
+                errors.push(format!("{}: {} is taken, {}", at, key, reason));
+                continue;
+            }
+
+            let bindings = keymap.get_mut(&context).expect("every context has bindings");
+            let position = bindings.iter().position(|b| b.key == key);
+            if name == "none" {
+                if let Some(position) = position {
+                    bindings.remove(position);
+                }
+                continue;
+            }
+            let binding = match lookup(context, name) {
+                Ok(binding) => Binding { key, ..binding },
+                Err(e) => {
+                    errors.push(format!("{}: {}", at, e));
+                    continue;
+                }
+            };
+            match position {
+                Some(position) => bindings[position] = binding,
+                // Next to the command's other keys in the help
+                None => match bindings.iter().rposition(|b| b.command == binding.command) {
+                    Some(position) => bindings.insert(position + 1, binding),
+                    None => bindings.push(binding),
+                },
+            }
+        }
+    }
+
+    (keymap, errors)
+}
+
+// Default binding of the command called `name`, for its description
+fn lookup(context: Context, name: &str) -> std::result::Result<Binding, String> {
+    if let Some(binding) = defaults(context).iter().find(|b| b.command.name() == name) {
+        return Ok(*binding);
+    }
+
+    let elsewhere: Vec<_> = Context::ALL
+        .into_iter()
+        .filter(|c| defaults(*c).iter().any(|b| b.command.name() == name))
+        .map(|c| format!("[keys.{}]", c.name()))
+        .collect();
+    if elsewhere.is_empty() {
+        Err(format!("unknown command \"{}\", the `?` help lists them", name))
+    } else {
+        Err(format!("\"{}\" only works in {}", name, elsewhere.join(", ")))
+    }
+}
+
+// Keys the editor handles before the keymap, so a binding would never run
+fn reserved(context: Context, key: Key) -> Option<&'static str> {
+    let plain = key.modifiers.is_empty();
+    let alt = key.modifiers == KeyModifiers::ALT;
+    match (context, key.code) {
+        (_, 'q') if key.modifiers.contains(KeyModifiers::CONTROL) => Some("Ctrl+Q quits"),
+        (Context::Normal, '1'..='9') if plain => Some("digits are the count of the next command"),
+        (Context::GoTo, '0'..='9') if plain => Some("digits start the line number"),
+        (Context::Normal, '1'..='9') if alt => Some("Alt+1..9 insert pinned snippets"),
+        (Context::Normal, 'v') if alt => Some("Alt+v pastes from the clipboard"),
+        _ => None,
+    }
+}
+
+/// Every binding of `context`, in help order
+pub fn bindings(context: Context) -> &'static [Binding] {
+    KEYMAP
+        .get_or_init(|| build(&HashMap::new()).0)
+        .get(&context)
+        .map_or(&[], Vec::as_slice)
+}
+
+/// Command bound to `key` with `modifiers` in `context`
+pub fn command(context: Context, key: KeyCode, modifiers: KeyModifiers) -> Option<Command> {
+    let KeyCode::Char(code) = key else {
+        return None;
+    };
+    let key = Key::new(code, modifiers);
+    bindings(context)
+        .iter()
+        .find(|binding| binding.key == key)
+        .map(|binding| binding.command)
+}
+
+/// `k - Description` lines of a menu popup
+pub fn help(context: Context) -> Vec<String> {
+    bindings(context)
+        .iter()
+        .map(|binding| format!("{} - {}", binding.key, binding.description))
+        .collect()
+}
+
+/// Help of every context under its title, for the `?` overlay
+pub fn overview() -> Vec<String> {
+    let mut lines = Vec::new();
+    for context in Context::ALL {
+        if !lines.is_empty() {
+            lines.push(String::new());
+        }
+        lines.push(format!("{} [keys.{}]", context.title(), context.name()));
+        lines.extend(
+            bindings(context)
+                .iter()
+                .map(|b| format!("  {:<8} {:<20} {}", b.key.to_string(), b.command.name(), b.description)),
+        );
+    }
+    lines
+}
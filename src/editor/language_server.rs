@@ -0,0 +1,204 @@
+use ropey::Rope;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config;
+use crate::error::Result;
+use crate::lsp::{self, CompletionItem, LspClient};
+
+// Buffer changes reach the server at most this often
+const SYNC_INTERVAL: Duration = Duration::from_millis(300);
+
+/// File the code block under the cursor is synced as. It's passed to the
+/// server as a detached file, so it gets analyzed outside a Cargo project.
+pub(super) fn block_path() -> PathBuf {
+    Path::new(&config::get().history_dir).join("lsp").join("block.rs")
+}
+
+/// Editor side of the language server: what's synced and the diagnostics
+/// mapped back into the buffer. The server starts on its own thread on the
+/// first completion and again on the next one after it exited.
+pub struct LanguageServer {
+    client: Option<LspClient>,
+    // Answer of the thread starting the server
+    starting: Option<mpsc::Receiver<Result<LspClient>>>,
+    // Completion asked for while the server started, buffer row and column
+    queued_completion: Option<(usize, usize)>,
+    // URI of the synced document and the buffer line it starts on
+    document: Option<(String, usize)>,
+    last_sync: Instant,
+
+    // Buffer char ranges with the line they start on and their message
+    diagnostics: Vec<(Range<usize>, usize, String)>,
+}
+
+impl LanguageServer {
+    pub fn new() -> Self {
+        Self {
+            client: None,
+            starting: None,
+            queued_completion: None,
+            document: None,
+            last_sync: Instant::now(),
+            diagnostics: Vec::new(),
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.client.is_some() || self.starting.is_some()
+    }
+
+    pub(super) fn is_due(&mut self) -> bool {
+        if self.last_sync.elapsed() < SYNC_INTERVAL {
+            return false;
+        }
+        self.last_sync = Instant::now();
+        true
+    }
+
+    /// The client once the server answered `initialize`, `None` while it
+    /// starts. An exited server is dropped with an error.
+    fn start(&mut self) -> Result<Option<&mut LspClient>> {
+        if self.client.as_ref().is_some_and(LspClient::has_exited) {
+            self.client = None;
+            self.queued_completion = None;
+            self.diagnostics.clear();
+            return Err("lsp: the server exited".into());
+        }
+
+        if self.client.is_none() {
+            let starting = match self.starting.take() {
+                Some(starting) => starting,
+                None => Self::spawn()?,
+            };
+            match starting.try_recv() {
+                Ok(client) => self.client = Some(client?),
+                Err(mpsc::TryRecvError::Empty) => self.starting = Some(starting),
+                Err(mpsc::TryRecvError::Disconnected) => return Err("lsp: start failed".into()),
+            }
+        }
+
+        Ok(self.client.as_mut())
+    }
+
+    // Starts the server on its own thread, `initialize` can take seconds
+    fn spawn() -> Result<mpsc::Receiver<Result<LspClient>>> {
+        let block = block_path();
+        if let Some(dir) = block.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        if !block.exists() {
+            fs::write(&block, "")?;
+        }
+
+        let detached = [std::path::absolute(&block)?];
+        let command = config::get().lsp_command.clone();
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = tx.send(LspClient::start(&command, Path::new("."), &detached));
+        });
+        Ok(rx)
+    }
+
+    /// Sends the document's text, starting the server if needed
+    pub(super) fn sync(&mut self, uri: &str, text: &str, first_line: usize) -> Result<()> {
+        // Another document or a moved block, the positions are mapped again
+        let document = Some((uri.to_string(), first_line));
+        if self.document != document {
+            self.document = document;
+            self.diagnostics.clear();
+            if let Some(client) = &mut self.client {
+                client.reread_diagnostics();
+            }
+        }
+
+        let Some(client) = self.start()? else {
+            return Ok(());
+        };
+        client.sync(uri, text)?;
+
+        // The server is up now, the completion asked for meanwhile goes out
+        match self.queued_completion.take() {
+            Some((row, col)) => self.request_completion(row, col),
+            None => Ok(()),
+        }
+    }
+
+    /// Nothing to analyze under the cursor
+    pub(super) fn forget_document(&mut self) {
+        self.document = None;
+        self.diagnostics.clear();
+    }
+
+    /// Asks for completions at a buffer position of the synced document
+    pub(super) fn request_completion(&mut self, row: usize, col: usize) -> Result<()> {
+        let Some((uri, first_line)) = self.document.clone() else {
+            return Ok(());
+        };
+        let line = row.saturating_sub(first_line);
+
+        match self.start()? {
+            Some(client) => client.request_completion(&uri, line, col),
+            None => {
+                self.queued_completion = Some((row, col));
+                Ok(())
+            }
+        }
+    }
+
+    pub(super) fn take_completions(&mut self) -> Option<Vec<CompletionItem>> {
+        self.client.as_mut()?.take_completions()
+    }
+
+    /// Picks up new diagnostics and maps them to buffer chars. Returns
+    /// whether there were any.
+    pub(super) fn update_diagnostics(&mut self, buffer: &Rope) -> bool {
+        let Some((uri, first_line)) = &self.document else {
+            return false;
+        };
+        let Some(client) = &mut self.client else {
+            return false;
+        };
+        let Some(diagnostics) = client.take_diagnostics(uri) else {
+            return false;
+        };
+
+        let char_idx = |(line, col): (usize, usize)| -> Option<usize> {
+            let line = first_line + line;
+            if line >= buffer.len_lines() {
+                return None;
+            }
+            let len = buffer.line(line).len_chars();
+            Some(buffer.line_to_char(line) + col.min(len))
+        };
+
+        self.diagnostics = diagnostics
+            .into_iter()
+            .filter_map(|d| {
+                let start = char_idx(d.start)?;
+                // Zero-width ones still mark a char
+                let end = char_idx(d.end)?.max(start + 1);
+                Some((start..end, first_line + d.start.0, d.message))
+            })
+            .collect();
+        true
+    }
+
+    pub fn is_diagnostic(&self, char_idx: usize) -> bool {
+        self.diagnostics
+            .iter()
+            .any(|(range, _, _)| range.contains(&char_idx))
+    }
+
+    /// Message of the first diagnostic starting on a buffer line
+    pub fn diagnostic_on_line(&self, line: usize) -> Option<&str> {
+        self.diagnostics
+            .iter()
+            .find(|(_, start_line, _)| *start_line == line)
+            .map(|(_, _, message)| message.as_str())
+    }
+}
@@ -0,0 +1,125 @@
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::config;
+use crate::error::Result;
+
+/// `<history_dir>/locks`
+fn dir() -> PathBuf {
+    PathBuf::from(&config::get().history_dir).join("locks")
+}
+
+/// `<history_dir>/locks/<absolute path, / as %>.lock`, so files of the
+/// same name in different directories don't share one
+fn lock_path(file: &Path) -> PathBuf {
+    let absolute = fs::canonicalize(file).unwrap_or_else(|_| {
+        env::current_dir()
+            .map(|cwd| cwd.join(file))
+            .unwrap_or_else(|_| file.to_path_buf())
+    });
+    let name: String = absolute
+        .to_string_lossy()
+        .chars()
+        .map(|c| if matches!(c, '/' | '\\' | ':') { '%' } else { c })
+        .collect();
+
+    dir().join(format!("{}.lock", name))
+}
+
+// Whether process `pid` still runs. Without /proc to look at, every lock
+// counts as live and a crashed run's lock is taken over by hand.
+fn alive(pid: u32) -> bool {
+    if cfg!(target_os = "linux") {
+        Path::new("/proc").join(pid.to_string()).exists()
+    } else {
+        true
+    }
+}
+
+/// Process id in a lock file, `None` when it's unreadable
+fn holder(lock: &Path) -> Option<u32> {
+    fs::read_to_string(lock).ok()?.trim().parse().ok()
+}
+
+/// Process id of another running instance that has `file` locked
+pub(super) fn held_elsewhere(file: &Path) -> Option<u32> {
+    holder(&lock_path(file)).filter(|&pid| pid != process::id() && alive(pid))
+}
+
+/// Lock files of the files this instance has open, so a second rusty_ai
+/// opening one of them is warned before saves of both overwrite each
+/// other. They're removed when the files close and on exit.
+pub(super) struct Locks {
+    // (file, its lock file)
+    held: Vec<(PathBuf, PathBuf)>,
+}
+
+impl Locks {
+    pub(super) fn new() -> Self {
+        Self { held: Vec::new() }
+    }
+
+    /// Locks `file` for this instance. When another running instance has
+    /// it, returns that one's process id and leaves the lock alone.
+    pub(super) fn acquire(&mut self, file: &Path) -> Result<Option<u32>> {
+        if self.held.iter().any(|(held, _)| held == file) {
+            return Ok(None);
+        }
+        fs::create_dir_all(dir())?;
+        let lock = lock_path(file);
+
+        match OpenOptions::new().write(true).create_new(true).open(&lock) {
+            Ok(mut out) => writeln!(out, "{}", process::id())?,
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => match holder(&lock) {
+                Some(pid) if pid != process::id() && alive(pid) => return Ok(Some(pid)),
+                // Left behind by a run that didn't exit cleanly
+                _ => fs::write(&lock, format!("{}\n", process::id()))?,
+            },
+            Err(e) => return Err(e.into()),
+        }
+
+        self.held.push((file.to_path_buf(), lock));
+        Ok(None)
+    }
+
+    /// Takes the lock of `file` from the instance holding it, which
+    /// isn't told
+    pub(super) fn take_over(&mut self, file: &Path) -> Result<()> {
+        let lock = lock_path(file);
+        fs::write(&lock, format!("{}\n", process::id()))?;
+        if !self.held.iter().any(|(held, _)| held == file) {
+            self.held.push((file.to_path_buf(), lock));
+        }
+
+        Ok(())
+    }
+
+    /// Releases the locks of files that aren't in `open` anymore
+    pub(super) fn retain(&mut self, open: &[PathBuf]) {
+        self.held.retain(|(file, lock)| {
+            let keep = open.contains(file);
+            if !keep {
+                release(lock);
+            }
+            keep
+        });
+    }
+}
+
+impl Drop for Locks {
+    fn drop(&mut self) {
+        for (_, lock) in &self.held {
+            release(lock);
+        }
+    }
+}
+
+// Only while it's still ours, another instance may have taken it over
+fn release(lock: &Path) {
+    if holder(lock) == Some(process::id()) {
+        let _ = fs::remove_file(lock);
+    }
+}
@@ -0,0 +1,50 @@
+// Lines moved by PgUp/PgDn
+const PAGE_LINES: usize = 20;
+
+/// Scroll position of the request log popup. It sticks to the newest entry
+/// until scrolled up, like a terminal's scrollback.
+#[derive(Debug, Clone, Default)]
+pub struct LogView {
+    scroll: usize,
+    follow: bool,
+}
+
+impl LogView {
+    pub(super) fn new() -> Self {
+        Self {
+            scroll: 0,
+            follow: true,
+        }
+    }
+
+    /// First line shown, once the number of lines and rows is known
+    pub fn scroll(&mut self, lines: usize, rows: usize) -> usize {
+        let max_scroll = lines.saturating_sub(rows);
+        if self.follow || self.scroll >= max_scroll {
+            self.scroll = max_scroll;
+            self.follow = true;
+        }
+        self.scroll
+    }
+
+    pub(super) fn scroll_down(&mut self, lines: usize) {
+        self.scroll = self.scroll.saturating_add(lines);
+    }
+
+    pub(super) fn scroll_up(&mut self, lines: usize) {
+        self.scroll = self.scroll.saturating_sub(lines);
+        self.follow = false;
+    }
+
+    pub(super) fn page_down(&mut self) {
+        self.scroll_down(PAGE_LINES);
+    }
+
+    pub(super) fn page_up(&mut self) {
+        self.scroll_up(PAGE_LINES);
+    }
+
+    pub(super) fn scroll_to_end(&mut self) {
+        self.follow = true;
+    }
+}
@@ -0,0 +1,113 @@
+use crossterm::event::{KeyCode, KeyModifiers};
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pending {
+    // `q` was pressed, the next key names the register to record into
+    Record,
+    // `@` was pressed, the next key names the register to play `count` times
+    Play(usize),
+}
+
+/// Keyboard macros: `q<reg>` records every key until the next `q` in Normal
+/// mode, `@<reg>` plays them back, `@@` repeats the last one played.
+#[derive(Debug, Clone, Default)]
+pub struct Macros {
+    saved: HashMap<char, Vec<(KeyCode, KeyModifiers)>>,
+    recording: Option<(char, Vec<(KeyCode, KeyModifiers)>)>,
+    pending: Option<Pending>,
+
+    // Count prefix typed in Normal mode
+    count: Option<usize>,
+    last_played: Option<char>,
+    // Played keys aren't recorded again, and macros don't call macros
+    playing: bool,
+}
+
+impl Macros {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(super) fn push_count_digit(&mut self, digit: char) {
+        let digit = digit.to_digit(10).unwrap_or(0) as usize;
+        let count = self.count.unwrap_or(0).saturating_mul(10).saturating_add(digit);
+        self.count = Some(count);
+    }
+
+    pub(super) fn has_count(&self) -> bool {
+        self.count.is_some()
+    }
+
+    pub(super) fn take_count(&mut self) -> Option<usize> {
+        self.count.take()
+    }
+
+    pub(super) fn begin_record(&mut self) {
+        self.pending = Some(Pending::Record);
+    }
+
+    pub(super) fn begin_play(&mut self, count: usize) {
+        self.pending = Some(Pending::Play(count));
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.is_some()
+    }
+
+    /// Handles the register name after `q` or `@`. Returns the keys to play
+    /// and how often; anything but `a`-`z` (or `@` after `@`) cancels.
+    pub(super) fn name_register(
+        &mut self,
+        name: char,
+    ) -> Option<(Vec<(KeyCode, KeyModifiers)>, usize)> {
+        match self.pending.take()? {
+            Pending::Record if name.is_ascii_lowercase() => {
+                self.recording = Some((name, Vec::new()));
+                None
+            }
+            Pending::Record => None,
+            Pending::Play(count) => {
+                let name = if name == '@' { self.last_played? } else { name };
+                let keys = self.saved.get(&name)?.clone();
+                self.last_played = Some(name);
+                Some((keys, count))
+            }
+        }
+    }
+
+    pub(super) fn cancel_pending(&mut self) {
+        self.pending = None;
+    }
+
+    /// Register being recorded into, for the status line
+    pub fn recording(&self) -> Option<char> {
+        self.recording.as_ref().map(|(name, _)| *name)
+    }
+
+    /// Captures a key while recording
+    pub(super) fn record(&mut self, key: KeyCode, modifiers: KeyModifiers) {
+        if self.playing {
+            return;
+        }
+        if let Some((_, keys)) = &mut self.recording {
+            keys.push((key, modifiers));
+        }
+    }
+
+    /// Saves the recording, without the `q` that stopped it
+    pub(super) fn stop_recording(&mut self) {
+        if let Some((name, mut keys)) = self.recording.take() {
+            keys.pop();
+            self.saved.insert(name, keys);
+        }
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    pub(super) fn set_playing(&mut self, playing: bool) {
+        self.playing = playing;
+    }
+}
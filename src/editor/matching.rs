@@ -0,0 +1,63 @@
+use ropey::Rope;
+
+const PAIRS: &[(char, char)] = &[('(', ')'), ('[', ']'), ('{', '}')];
+
+/// First bracket at or after `char_idx` on its line, like Vim's `%`
+pub(super) fn bracket_on_line(buffer: &Rope, char_idx: usize) -> Option<usize> {
+    let line = buffer.char_to_line(char_idx);
+    let line_end = buffer.line_to_char(line) + buffer.line(line).len_chars();
+
+    (char_idx..line_end).find(|&idx| is_bracket(buffer.char(idx)))
+}
+
+fn is_bracket(c: char) -> bool {
+    PAIRS.iter().any(|&(open, close)| c == open || c == close)
+}
+
+/// Bracket matching the one at `char_idx`, counting nested pairs of the same
+/// kind. Brackets in strings or comments aren't told apart here.
+pub(super) fn matching_bracket(buffer: &Rope, char_idx: usize) -> Option<usize> {
+    let c = buffer.char(char_idx);
+    let mut depth = 0usize;
+
+    if let Some(&(open, close)) = PAIRS.iter().find(|(open, _)| *open == c) {
+        for idx in char_idx + 1..buffer.len_chars() {
+            match buffer.char(idx) {
+                ch if ch == open => depth += 1,
+                ch if ch == close && depth == 0 => return Some(idx),
+                ch if ch == close => depth -= 1,
+                _ => {}
+            }
+        }
+    } else if let Some(&(open, close)) = PAIRS.iter().find(|(_, close)| *close == c) {
+        for idx in (0..char_idx).rev() {
+            match buffer.char(idx) {
+                ch if ch == close => depth += 1,
+                ch if ch == open && depth == 0 => return Some(idx),
+                ch if ch == open => depth -= 1,
+                _ => {}
+            }
+        }
+    }
+
+    None
+}
+
+/// Line of the fence that closes or opens the fence on `line`. Fences pair up
+/// in order, like the highlighter reads them.
+pub(super) fn matching_fence(buffer: &Rope, line: usize) -> Option<usize> {
+    let is_fence = |l: usize| buffer.line(l).chars().take(3).eq("```".chars());
+    if !is_fence(line) {
+        return None;
+    }
+
+    let fences: Vec<usize> = (0..buffer.len_lines()).filter(|&l| is_fence(l)).collect();
+    let i = fences.iter().position(|&l| l == line)?;
+
+    // Even positions open a block, odd ones close it
+    if i % 2 == 0 {
+        fences.get(i + 1).copied()
+    } else {
+        fences.get(i - 1).copied()
+    }
+}
@@ -1,34 +1,16 @@
+use crate::chat::credentials;
 use crate::error::Result;
 use crate::files::list_files;
 
 use super::filepicker::{self, FilePicker};
-
-const HELP_GOTO_COMMANDS: &'static [&'static str] = &[
-    "g - Goto first line",
-    "e - Goto end last line",
-    "l - Goto end of line",
-    "h - Goto start of line",
-];
-
-const HELP_AI_COMMANDS: &'static [&'static str] = &[
-    "l - Send request to Ollama",
-    "o - Send request to OpenAI",
-    "a - Send request to Anthropic",
-    "e - Exit",
-];
-
-const HELP_FILE_COMMANDS: &'static [&'static str] = &[
-    "w - Wipe buffer",
-    "l - Load file",
-    "s - Save",
-    "S - Save as",
-    "q - Exit editor",
-];
+use super::keymap::{self, Context};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MenuType {
     InActive,
     GoTo,
+    Fold,
+    Table,
     Main,
     File,
     AI,
@@ -47,6 +29,8 @@ impl From<MenuType> for String {
         match value {
             MenuType::InActive => "In Active".to_string(),
             MenuType::GoTo => "Go to".to_string(),
+            MenuType::Fold => "Fold".to_string(),
+            MenuType::Table => "Table".to_string(),
             MenuType::Main => "Main".to_string(),
             MenuType::File => "File".to_string(),
             MenuType::AI => "AI".to_string(),
@@ -96,17 +80,29 @@ impl CommandsMenu {
                 (Some("Main".to_string()), Some(s))
             }
             MenuType::File => {
-                let s = self.vec_string_from_slice(HELP_FILE_COMMANDS);
+                let s = keymap::help(Context::File);
 
                 (Some(self.menu_type.into()), Some(s))
             }
             MenuType::GoTo => {
-                let s = self.vec_string_from_slice(HELP_GOTO_COMMANDS);
+                let s = keymap::help(Context::GoTo);
+
+                (Some(self.menu_type.into()), Some(s))
+            }
+            MenuType::Fold => {
+                let s = keymap::help(Context::Fold);
+
+                (Some(self.menu_type.into()), Some(s))
+            }
+            MenuType::Table => {
+                let s = keymap::help(Context::Table);
 
                 (Some(self.menu_type.into()), Some(s))
             }
             MenuType::AI => {
-                let s = self.vec_string_from_slice(HELP_AI_COMMANDS);
+                let mut s = keymap::help(Context::AI);
+                s.push(String::new());
+                s.extend(credentials::status());
 
                 (Some(self.menu_type.into()), Some(s))
             }
@@ -144,6 +140,12 @@ impl CommandsMenu {
         self.file_picker.get_files()
     }
 
+    /// Directory the fuzzy finder is in, relative to its root
+    /// Where the finder is, from its root down
+    pub fn get_file_picker_breadcrumb(&self) -> Option<String> {
+        self.file_picker.finder_breadcrumb()
+    }
+
     pub fn file_picker_selected_index(&self) -> usize {
         self.file_picker.get_selected_file_index()
     }
@@ -0,0 +1,111 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local};
+
+// Older messages are dropped from the history
+const HISTORY_LEN: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Level {
+    Info,
+    Warning,
+    Error,
+}
+
+impl Level {
+    // Errors stay longer, they usually need reading
+    fn duration(self) -> Duration {
+        match self {
+            Level::Info => Duration::from_secs(4),
+            Level::Warning => Duration::from_secs(6),
+            Level::Error => Duration::from_secs(10),
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Level::Info => "info",
+            Level::Warning => "warning",
+            Level::Error => "error",
+        }
+    }
+}
+
+/// One line for the message line and its history
+#[derive(Debug, Clone)]
+pub struct Message {
+    pub level: Level,
+    pub text: String,
+    pub time: DateTime<Local>,
+    shown: Instant,
+    dismissed: bool,
+    id: u64,
+}
+
+impl Message {
+    /// e.g. `14:02:11 error Can't export: permission denied`
+    pub fn line(&self) -> String {
+        format!(
+            "{} {} {}",
+            self.time.format("%H:%M:%S"),
+            self.level.label(),
+            self.text
+        )
+    }
+}
+
+/// Messages for the bottom line, kept in `EditorState` so request threads
+/// can report too. The newest one shows until it expires.
+#[derive(Debug, Clone, Default)]
+pub struct Messages {
+    history: VecDeque<Message>,
+    next_id: u64,
+    // Message on screen at the last `changed`
+    drawn: Option<u64>,
+}
+
+impl Messages {
+    pub fn push(&mut self, level: Level, text: impl Into<String>) {
+        if self.history.len() >= HISTORY_LEN {
+            self.history.pop_front();
+        }
+        self.next_id += 1;
+        self.history.push_back(Message {
+            level,
+            // One row, a multi-line error would bleed into the next
+            text: text.into().replace('\n', " "),
+            time: Local::now(),
+            shown: Instant::now(),
+            dismissed: false,
+            id: self.next_id,
+        });
+    }
+
+    /// The newest message, until it expires
+    pub fn current(&self) -> Option<&Message> {
+        self.history
+            .back()
+            .filter(|message| !message.dismissed && message.shown.elapsed() < message.level.duration())
+    }
+
+    /// Hides the current message, it stays in the history
+    pub fn dismiss(&mut self) {
+        if let Some(message) = self.history.back_mut() {
+            message.dismissed = true;
+        }
+    }
+
+    /// Whether a message appeared or went away since the last call
+    pub fn changed(&mut self) -> bool {
+        let current = self.current().map(|message| message.id);
+        let changed = current != self.drawn;
+        self.drawn = current;
+        changed
+    }
+
+    /// Oldest first
+    pub fn history(&self) -> Vec<Message> {
+        self.history.iter().cloned().collect()
+    }
+}
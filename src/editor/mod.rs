@@ -1,28 +1,90 @@
 use crate::error::{Error, Result};
 
+mod assist;
+mod autosave;
+mod buffer;
+mod comment;
+pub mod confirm;
+pub mod diff;
 pub mod filepicker;
+mod finder;
+mod ghost;
+pub mod folds;
+mod graphemes;
+mod history_search;
+mod inline_edit;
+pub mod keymap;
+pub mod language_server;
+mod lock;
+pub mod log_view;
+pub mod macros;
+mod matching;
 pub mod menu;
+pub mod messages;
+pub mod params_form;
+mod pairs;
+pub mod patch;
+pub mod preview;
+pub mod prompt_editor;
+pub mod protected;
+mod reference;
+pub mod register;
+pub mod replace;
+pub mod search;
+mod snippets;
+pub mod split;
+mod table;
+mod textobject;
+mod watcher;
+
+use buffer::BufferState;
+use confirm::{Confirm, ConfirmAction};
+use diff::DiffView;
+use folds::{Folded, Folds};
+use keymap::{Command, Context};
 
 use menu::MenuType;
+use messages::{Level, Message};
 
 use once_cell::sync::Lazy;
 use ropey::Rope;
 
 use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
 
-use crate::chat::{history::History, ChatContext, Model};
-
-use crate::syntax::{Style, SyntaxHighlighter};
+use crate::chat::attachment::{self, Attachment, Kind};
+use crate::chat::project::ProjectContext;
+use crate::chat::prompt_history;
+use crate::chat::credentials;
+use crate::chat::export::{self, Export};
+use crate::chat::fix;
+use crate::chat::import;
+use crate::chat::session::{self, Session};
+use crate::chat::transcript;
+use crate::chat::trim;
+use crate::chat::{history::History, models, system_prompt, tokens, ChatContext, Model};
+use crate::config::{self, theme, ContextTrim, Insertion};
+use crate::files;
+use crate::files::encoding::{self, Encoding};
+use crate::files::line_ending::{self, LineEnding};
+use crate::git;
+use crate::lsp::{self, CompletionItem};
+
+use crate::syntax::{CodeBlock, Style, SyntaxHighlighter};
 use clipboard::{ClipboardContext, ClipboardProvider};
 
-use crate::async_handler::{AsyncCommandHandler, EditorState};
+use crate::async_handler::log::LogEntry;
+use crate::async_handler::{
+    AsyncCommandHandler, EditorState, Origin, RequestInfo, ShellPurpose, ShellRun,
+};
 use std::num::IntErrorKind;
 use std::sync::{Arc, Mutex};
 
+use std::collections::HashMap;
 use std::fs;
-use std::io::{stdout, Write};
+use std::io::{stdout, ErrorKind, Write};
 use std::ops::Range;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::process;
 use std::sync::mpsc;
 use std::thread;
 use std::time::Duration;
@@ -39,6 +101,14 @@ pub struct Editor {
 
     history: History,
     modified: bool,
+    // Viewer for big files: no edits, highlighting or git markers
+    read_only: bool,
+    // Throwaway buffer: never saved or swapped, quitting with text in it
+    // asks first
+    scratch: bool,
+    // How the file is stored on disk, it's saved the same way
+    encoding: Encoding,
+    line_ending: LineEnding,
     chat_context: ChatContext,
 
     syntax_cache: SyntaxCache,
@@ -48,22 +118,194 @@ pub struct Editor {
     selection_start: Option<(usize, usize)>,
     selection_active: bool,
 
+    // Open buffers in tab order. `None` marks the active one, whose state
+    // lives in the fields above.
+    buffers: Vec<Option<BufferState>>,
+    active_buffer: usize,
+    // Id of the active buffer, which requests carry so their answers find
+    // it in any tab, and the id of the next new one
+    buffer_id: u64,
+    next_buffer_id: u64,
+
     // New fields for async support
     shared_state: Arc<Mutex<EditorState>>,
     async_handler: AsyncCommandHandler,
 
     // Track if we need to check for responses
     needs_response_check: bool,
+    // Append tokens as they arrive instead of waiting for the full answer
+    stream_responses: bool,
+    // Files prepended to every prompt as fenced code blocks, and command
+    // output prepended to the next one
+    attachments: Vec<Attachment>,
+    // Last command run for its output, offered again
+    shell_command: String,
+    // The running request asks to fix compiler errors, its diff is
+    // previewed when it's done
+    fix_pending: bool,
+    // File tree of the workspace sent with prompts while enabled
+    pub project: ProjectContext,
+    // Where the running answer started, used to drop a partial answer
+    response_start: Option<usize>,
+    // Region of the latest complete answer in the conversation and the
+    // prompt it answered, for regenerating it
+    last_answer: Option<(Range<usize>, String)>,
+    // Text being rewritten by an instruction, its answer replaces it
+    inline_edit: Option<inline_edit::InlineEdit>,
+    // Latest rewrite in this buffer, to put the original back
+    last_rewrite: Option<inline_edit::Rewrite>,
+    // Inline completion suggested after a pause in typing
+    ghost: ghost::Ghost,
+    // The running answer got its header
+    answer_started: bool,
+    // Answer of a buffer that's no longer open or only viewed
+    file_answer: Option<FileAnswer>,
+    // Folded messages and code, shown as one row each
+    folds: Folds,
+    // The open file as committed, loaded again when another file opens
+    git_head: Option<(String, Option<String>)>,
+    // Rows changed since HEAD, marked in the gutter
+    git_changes: HashMap<usize, git::LineChange>,
+    // How much of the conversation the last request left out, and how
+    context_trimmed: Option<(ContextTrim, usize)>,
+    // Where answers go, and where the running one continues in the buffer
+    insertion: Insertion,
+    response_at: Option<usize>,
 
     show_help_menu: bool,
+    // Request status panel, also shown while requests are pending
+    show_request_panel: bool,
+    // Pinned snippets of the session, Alt+1..9 inserts one
+    show_pins_panel: bool,
+    // Asks the renderer to switch between soft wrap and horizontal scrolling
+    soft_wrap_toggle: bool,
+    // Code of the block picked for extraction, until its path is entered
+    extracted_block: Option<String>,
+    // File of the load picker being renamed, until its new name is entered
+    renaming: Option<String>,
+    // Provider picked for a new API key, until the key is entered
+    key_provider: Option<Model>,
+    // Names behind the entries of the Ollama model popup
+    ollama_models: Vec<String>,
+    // Popup waiting for the Ollama models being looked up
+    ollama_listing: Option<OllamaListing>,
+
+    autosave: autosave::Autosave,
+    // Yes/no popup, takes every key until answered
+    confirm: Option<Confirm>,
+    // Diff against the saved file, takes every key until closed
+    diff: Option<DiffView>,
+    // Hunks of a diff from an answer, waiting to be accepted and written
+    patch: Option<patch::PatchPreview>,
+    // Request log popup, takes every key until closed
+    log_view: Option<log_view::LogView>,
+    // Message history popup, scrolls like the request log
+    message_view: Option<log_view::LogView>,
+    // Usage and cost popup, scrolls like the request log
+    usage_view: Option<log_view::LogView>,
+    // Every key binding, opened with `?`
+    help_view: Option<log_view::LogView>,
+    // Rendered markdown over the whole content area, opened with `P`
+    preview: Option<preview::Preview>,
+    // `d`, `y` or select mode waiting for the rest of a text object
+    text_object: Option<textobject::Pending>,
+    // Conversations searched by the File menu's `/`, while its popup is open
+    history_search: Option<history_search::HistorySearch>,
+    // Line and column of a `gf` reference, applied once its file has loaded
+    pending_position: Option<(usize, Option<usize>)>,
+    // Stops of the snippet expanded last, until the final one or Esc
+    tab_stops: Option<snippets::TabStops>,
+    // Swap files of a previous run still to ask about
+    swaps: Vec<PathBuf>,
+    // Changes to the open file from outside, asked about when no popup is up
+    watcher: watcher::FileWatcher,
+    // Lock files telling other instances which files are open here
+    locks: lock::Locks,
+    // Quit once the running requests are done, then `quit` lets the main
+    // loop end
+    quit_after_request: bool,
+    quit: bool,
+    // Quit with requests running, their answers are saved after the
+    // screen has closed
+    detached: bool,
     pub menu_status: menu::CommandsMenu,
+    pub search: search::Search,
+    pub replace: replace::Replace,
+    // Answer text, read-only until unlocked
+    pub protected: protected::Protected,
+    pub registers: register::Registers,
+    pub macros: macros::Macros,
+    pub language_server: language_server::LanguageServer,
+    // Items of the open completion popup
+    completions: Vec<CompletionItem>,
+    pub prompt_editor: prompt_editor::PromptEditor,
+    pub params_form: params_form::ParamsForm,
+    // Prompt/transcript split, `None` shows the buffer alone
+    split: Option<split::SplitView>,
 }
 
+// Rows moved by Ctrl+d/Ctrl+u and PageDown/PageUp in the transcript pane
+const TRANSCRIPT_PAGE: isize = 10;
+// Files above this many bytes stream in instead of being read at once
+const STREAM_LOAD_BYTES: u64 = 1024 * 1024;
+// Wait between two looks for the answer of a detached request
+const DETACH_POLL: Duration = Duration::from_millis(50);
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum RequestState {
     Idle,
     Proccessing,
     Error(String),
+    /// Gave up after this many seconds, AI menu: R retries
+    TimedOut(u64),
+}
+
+// What the Ollama models being looked up are for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OllamaListing {
+    // Added to the model picker
+    Picker,
+    // Listed with size and quantization
+    Manager,
+}
+
+// Answer written to a file once complete rather than into a buffer
+struct FileAnswer {
+    // File of the buffer that asked
+    origin: String,
+    text: String,
+    // The buffer is a read-only view, the answer goes to the inbox
+    inbox: bool,
+}
+
+// Where the text of the running answer goes
+enum AnswerTarget {
+    Active,
+    // A buffer in the background, by index
+    Parked(usize),
+    // The file of a buffer no longer open
+    File(String),
+}
+
+// Word motions stop where the class changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CharClass {
+    Whitespace,
+    Word,
+    Punctuation,
+}
+
+impl CharClass {
+    // `is_alphanumeric` is Unicode-aware, so accented and CJK letters count as word chars
+    fn of(c: char) -> Self {
+        if c.is_whitespace() {
+            CharClass::Whitespace
+        } else if c.is_alphanumeric() || c == '_' {
+            CharClass::Word
+        } else {
+            CharClass::Punctuation
+        }
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -75,9 +317,9 @@ pub enum Mode {
 
 impl Editor {
     pub fn new() -> Result<Self> {
-        let current_file = History::new()?;
-
         let chat_context = ChatContext::new().unwrap();
+        let current_file = History::new(Session::new(chat_context.model_entry()))?;
+
         let syntax_highlighter = SyntaxHighlighter::new().ok();
 
         // Create shared state
@@ -96,6 +338,10 @@ impl Editor {
             mode: Mode::Normal,
             history: current_file,
             modified: false,
+            read_only: false,
+            scratch: false,
+            encoding: Encoding::default(),
+            line_ending: LineEnding::default(),
 
             syntax_cache: SyntaxCache::new(),
             syntax_highlighter,
@@ -105,15 +351,75 @@ impl Editor {
             selection_start: None,
             selection_active: false,
 
+            buffers: vec![None],
+            active_buffer: 0,
+            buffer_id: 0,
+            next_buffer_id: 1,
+
             // New fields for async support
             shared_state,
             async_handler,
 
             // Track if we need to check for responses
             needs_response_check: false,
+            stream_responses: true,
+            attachments: Vec::new(),
+            shell_command: String::new(),
+            fix_pending: false,
+            project: ProjectContext::new(),
+            response_start: None,
+            last_answer: None,
+            inline_edit: None,
+            last_rewrite: None,
+            ghost: ghost::Ghost::new(config::get().inline_completion),
+            answer_started: false,
+            file_answer: None,
+            folds: Folds::default(),
+            git_head: None,
+            git_changes: HashMap::new(),
+            context_trimmed: None,
+            insertion: config::get().response_insertion,
+            response_at: None,
 
             show_help_menu: false,
+            show_request_panel: false,
+            show_pins_panel: false,
+            soft_wrap_toggle: false,
+            extracted_block: None,
+            renaming: None,
+            key_provider: None,
+            ollama_models: Vec::new(),
+            ollama_listing: None,
+            autosave: autosave::Autosave::new(),
+            confirm: None,
+            diff: None,
+            patch: None,
+            log_view: None,
+            message_view: None,
+            usage_view: None,
+            help_view: None,
+            preview: None,
+            text_object: None,
+            history_search: None,
+            pending_position: None,
+            tab_stops: None,
+            swaps: Vec::new(),
+            watcher: watcher::FileWatcher::new(),
+            locks: lock::Locks::new(),
+            quit_after_request: false,
+            quit: false,
+            detached: false,
             menu_status: menu::CommandsMenu::default(),
+            search: search::Search::new(),
+            replace: replace::Replace::new(),
+            protected: protected::Protected::new(),
+            registers: register::Registers::new(),
+            macros: macros::Macros::new(),
+            language_server: language_server::LanguageServer::new(),
+            completions: Vec::new(),
+            prompt_editor: prompt_editor::PromptEditor::new(),
+            params_form: params_form::ParamsForm::new(),
+            split: None,
         })
     }
 
@@ -159,9 +465,78 @@ impl Editor {
         // Mark this line and subsequent lines as dirty
         let total_lines = self.buffer.len_lines();
         self.syntax_cache.mark_range_dirty(line, total_lines);
+
+        // Match positions shift with the edit
+        self.search.mark_stale();
+    }
+
+    /// Tells the highlighter about an edit before it's applied to the buffer,
+    /// so code blocks are parsed incrementally
+    fn record_edit(&mut self, range: Range<usize>, text: &str) {
+        let inserted = text.chars().count();
+        self.protected.shift(range.clone(), inserted);
+        self.folds.shift(&range, inserted);
+        // The split's transcript has its own positions, the buffer is the prompt
+        if self.split.is_none() {
+            self.response_start = self
+                .response_start
+                .map(|p| shift_position(p, &range, inserted));
+            self.response_at = self
+                .response_at
+                .map(|p| shift_position(p, &range, inserted));
+            self.last_answer = self
+                .last_answer
+                .take()
+                .map(|(answer, prompt)| (shift_range(&answer, &range, inserted), prompt));
+        }
+        if let Some(edit) = &mut self.inline_edit {
+            edit.range = shift_range(&edit.range, &range, inserted);
+        }
+        if let Some(rewrite) = &mut self.last_rewrite {
+            rewrite.range = shift_range(&rewrite.range, &range, inserted);
+        }
+        if let Some(highlighter) = &self.syntax_highlighter {
+            highlighter.edit(
+                self.buffer.char_to_byte(range.start),
+                self.buffer.char_to_byte(range.end),
+                text,
+            );
+        }
+    }
+
+    /// Refuses an edit of answer text while answers are locked, and any
+    /// edit while the file loads: its chunks go in at the end unrecorded
+    fn edit_blocked(&self, range: Range<usize>) -> bool {
+        if self.load_blocks() {
+            return true;
+        }
+        if self.read_only {
+            self.set_message(Level::Warning, "Read-only view (File menu: R to edit)");
+            return true;
+        }
+        if !self.protected.blocks(&range) {
+            return false;
+        }
+
+        self.set_message(Level::Warning, "Answers are read-only (AI menu: u to unlock)");
+        true
+    }
+
+    /// Finds the search matches again if the buffer changed, before they're
+    /// drawn
+    pub fn refresh_search(&mut self) {
+        self.search.refresh(&self.buffer);
     }
 
     pub fn update_syntax_highlighting(&mut self) {
+        // Nothing to parse in a viewer, lines are styled as they're drawn
+        if self.read_only {
+            self.syntax_highlights.clear();
+            self.git_changes.clear();
+            self.search.mark_stale();
+            return;
+        }
+
         // Check if we need a full update
         let current_len = self.buffer.len_chars();
         let need_full_update = current_len != self.syntax_cache.last_content_length;
@@ -182,6 +557,32 @@ impl Editor {
             self.syntax_highlights =
                 highlighter.convert_highlights_to_char_ranges(&self.buffer, highlights);
         }
+
+        self.search.mark_stale();
+        self.update_git_changes();
+    }
+
+    // Diffs the buffer against the open file in HEAD. The prompt pane of
+    // the split view isn't a file.
+    fn update_git_changes(&mut self) {
+        let path = &self.history.file_path;
+        if self.split.is_some() || path.is_empty() || self.is_loading() {
+            self.git_changes.clear();
+            return;
+        }
+
+        if self.git_head.as_ref().is_none_or(|(head_path, _)| head_path != path) {
+            self.git_head = Some((path.clone(), git::head_text(Path::new(path))));
+        }
+        self.git_changes = match &self.git_head {
+            Some((_, Some(head))) => git::line_changes(head, &self.buffer.to_string()),
+            _ => HashMap::new(),
+        };
+    }
+
+    /// How `row` differs from the open file in HEAD
+    pub fn git_change(&self, row: usize) -> Option<git::LineChange> {
+        self.git_changes.get(&row).copied()
     }
 
     pub fn open_file(&mut self) -> Result<()> {
@@ -190,12 +591,35 @@ impl Editor {
         //     None => return Err(Error::Custom("editor: can't find file".to_string())),
         // };
 
-        let content = fs::read_to_string(&self.history.file_path)?;
+        // Sessions are parsed for their metadata, big plain files stream in
+        // through poll_file_load
+        let size = fs::metadata(&self.history.file_path).map(|m| m.len()).unwrap_or(0);
+        let stream = self.history.session.is_none() && size > STREAM_LOAD_BYTES;
+
+        let (content, encoding) = if stream {
+            (String::new(), Encoding::default())
+        } else {
+            self.history.content()?
+        };
+        let (content, line_ending) = line_ending::normalize(&content);
         self.buffer = Rope::from_str(&content);
+        self.encoding = encoding;
+        self.line_ending = line_ending;
+        self.protected.clear();
+        self.folds.clear();
         // self.file_path = Some(file.to_string());
         self.cursor_row = 0;
         self.cursor_col = 0;
         self.modified = false;
+        self.read_only = false;
+        self.scratch = false;
+        self.track_file();
+
+        if stream {
+            self.syntax_highlights.clear();
+            self.async_handler.load_file(self.history.file_path.clone());
+            return Ok(());
+        }
 
         // Update syntax highlighting for the newly loaded file
         self.update_syntax_highlighting();
@@ -250,15 +674,11 @@ impl Editor {
         line_styles
     }
 
-    fn copy_selection_to_clipboard(&mut self) -> Result<()> {
+    /// Yanks the selection into the registers, and the system clipboard when
+    /// one is available
+    fn yank_selection(&mut self) -> Result<()> {
         if let Some(text) = self.get_selected_text() {
-            // Create a clipboard context
-            let mut ctx: ClipboardContext = ClipboardProvider::new()
-                .map_err(|e| format!("Failed to create clipboard context: {}", e))?;
-
-            // Set the clipboard content
-            ctx.set_contents(text.to_owned())
-                .map_err(|e| format!("Failed to set clipboard contents: {}", e))?;
+            self.copy_text(text);
 
             // If in Select mode, exit to Normal mode
             if self.mode == Mode::Select {
@@ -273,543 +693,5286 @@ impl Editor {
         }
     }
 
-    fn get_selected_text(&self) -> Option<String> {
-        if !self.selection_active || self.selection_start.is_none() {
-            return None;
+    /// Stores `text` in the selected register and the system clipboard
+    fn copy_text(&mut self, text: String) {
+        self.registers.store(text.clone());
+
+        // No clipboard (e.g. over ssh) is fine, the register still has it
+        if let Ok(mut ctx) = ClipboardContext::new() {
+            let _ = ctx.set_contents(text);
         }
+    }
 
-        let selection_range = self.get_selection_range()?;
-        let start_idx = selection_range.start;
-        let end_idx = selection_range.end;
+    /// Fenced block whose lines, fences included, contain the cursor
+    fn code_block_at_cursor(&self) -> Option<CodeBlock> {
+        self.code_block_at(self.cursor_row)
+    }
 
-        // Extract the text from the buffer
-        Some(self.buffer.slice(start_idx..end_idx).to_string())
+    /// Fenced block whose lines, fences included, contain `row`
+    fn code_block_at(&self, row: usize) -> Option<CodeBlock> {
+        let highlighter = self.syntax_highlighter.as_ref()?;
+
+        highlighter
+            .extract_code_blocks(&self.buffer.to_string())
+            .into_iter()
+            .find(|block| {
+                let first = self.buffer.byte_to_line(block.start).saturating_sub(1);
+                let last = self.buffer.byte_to_line(block.end);
+                (first..=last).contains(&row)
+            })
     }
 
-    pub fn get_style_at(&mut self, char_idx: usize) -> Style {
-        // Convert char_idx to line and column
-        let position = self.position_from_char_idx(char_idx);
-        let (line, col) = position;
+    // First and last row of the selection, or the cursor's row
+    fn selected_rows(&self) -> (usize, usize) {
+        match self.get_selection_range() {
+            Some(range) => {
+                let first = self.buffer.char_to_line(range.start);
+                let mut last = self.buffer.char_to_line(range.end);
+                // A selection up to the start of a line leaves that line out
+                if last > first && range.end == self.buffer.line_to_char(last) {
+                    last -= 1;
+                }
+                (first, last)
+            }
+            None => (self.cursor_row, self.cursor_row),
+        }
+    }
 
-        // Check for selection first (direct check)
-        if self.is_position_selected(line, col, &self.get_selection_range()) {
-            return Style::Selection;
+    // Replaces `first..=last` with `lines`, line breaks of the rows around
+    // it stay. Only those rows are highlighted again.
+    fn splice_lines(&mut self, first: usize, last: usize, lines: &[String]) -> bool {
+        let start = self.buffer.line_to_char(first);
+        let end = self.buffer.line_to_char(last) + self.line_text(last).chars().count();
+        if self.edit_blocked(start..end) {
+            return false;
         }
 
-        // Try to get from cache first
-        if let Some(style) = self.syntax_cache.get_cached_style(line, col) {
-            return style;
+        let text = lines.join("\n");
+        self.record_edit(start..end, &text);
+        self.buffer.remove(start..end);
+        self.buffer.insert(start, &text);
+        self.modified = true;
+        self.syntax_cache.mark_range_dirty(first, first + lines.len());
+        self.search.mark_stale();
+        true
+    }
+
+    /// First and last row of the markdown table around `row`, outside of
+    /// code blocks
+    fn table_around(&self, row: usize) -> Option<(usize, usize)> {
+        if !table::is_row(&self.line_text(row)) || self.code_block_at(row).is_some() {
+            return None;
         }
 
-        // If not in cache, highlight the whole line and get the style
-        let line_styles = self.highlight_line(line);
-        if col < line_styles.len() {
-            return line_styles[col];
+        let mut first = row;
+        while first > 0 && table::is_row(&self.line_text(first - 1)) {
+            first -= 1;
         }
+        let mut last = row;
+        while last + 1 < self.buffer.len_lines() && table::is_row(&self.line_text(last + 1)) {
+            last += 1;
+        }
+        Some((first, last))
+    }
 
-        // Fallback
-        Style::Normal
+    /// Lets `edit` change the table under the cursor, given the cursor's row
+    /// and cell in it, and writes it back aligned. The cursor goes to the
+    /// start of the cell `edit` returns.
+    fn edit_table(
+        &mut self,
+        edit: impl FnOnce(&mut table::Table, usize, usize) -> (usize, usize),
+    ) -> Result<bool> {
+        let table = self.table_around(self.cursor_row).and_then(|(first, last)| {
+            let lines: Vec<String> = (first..=last).map(|row| self.line_text(row)).collect();
+            Some((first, last, table::Table::parse(&lines)?, lines))
+        });
+        let Some((first, last, mut table, lines)) = table else {
+            self.set_message(Level::Warning, "No table with a |---| row under the cursor");
+            return Ok(false);
+        };
+
+        let row = self.cursor_row - first;
+        let cell = table.cell_at(&lines[row], self.cursor_col);
+        let (row, cell) = edit(&mut table, row, cell);
+
+        let formatted = table.format();
+        let text: Vec<String> = formatted.iter().map(|(line, _)| line.clone()).collect();
+        if text != lines && !self.splice_lines(first, last, &text) {
+            return Ok(false);
+        }
+        self.cursor_row = first + row;
+        self.cursor_col = formatted[row].1[cell];
+        Ok(false)
     }
 
-    pub fn save_file(&mut self) -> Result<()> {
-        self.history.save_file(self.buffer.to_string())?;
-        self.modified = false;
+    /// Tab and Shift+Tab in a table: the next or previous cell, skipping
+    /// the separator row. Tab in the last cell adds a row.
+    fn next_table_cell(&mut self, forward: bool) -> Result<bool> {
+        self.edit_table(|table, row, cell| {
+            let row = if row == 1 { 0 } else { row };
+            let last_column = table.columns().saturating_sub(1);
+            if forward {
+                let next = if row == 0 { 2 } else { row + 1 };
+                if cell < last_column {
+                    (row, cell + 1)
+                } else if next < table.rows() {
+                    (next, 0)
+                } else {
+                    (table.insert_row(row), 0)
+                }
+            } else if cell > 0 {
+                (row, cell - 1)
+            } else if row == 0 {
+                (0, 0)
+            } else {
+                (if row == 2 { 0 } else { row - 1 }, last_column)
+            }
+        })
+    }
 
-        Ok(())
+    /// Copies the selected lines, or the cursor's, below themselves. The
+    /// cursor and selection move along to the copy.
+    fn duplicate_lines(&mut self) -> Result<bool> {
+        let (first, last) = self.selected_rows();
+        let mut lines: Vec<String> = (first..=last).map(|row| self.line_text(row)).collect();
+        lines.extend_from_within(..);
+
+        if self.splice_lines(first, last, &lines) {
+            self.shift_rows(last - first + 1, true);
+        }
+        Ok(false)
     }
 
-    // Get the current request state
-    pub fn get_request_state(&self) -> RequestState {
-        match self.shared_state.lock() {
-            Ok(state) => state.request_state.clone(),
-            Err(_) => RequestState::Error("Failed to lock state".to_string()),
+    /// Alt+Up/Down: swaps the selected lines, or the cursor's, with the line
+    /// above or below. The selection goes with them.
+    fn move_lines(&mut self, up: bool) -> Result<bool> {
+        let (first, last) = self.selected_rows();
+        let mut rows = self.buffer.len_lines();
+        // The empty row after a final line break stays last
+        if rows > 1 && self.line_text(rows - 1).is_empty() {
+            rows -= 1;
+        }
+        if (up && first == 0) || (!up && last + 1 >= rows) {
+            return Ok(false);
+        }
+
+        let (from, to) = if up { (first - 1, last) } else { (first, last + 1) };
+        let mut lines: Vec<String> = (from..=to).map(|row| self.line_text(row)).collect();
+        if up {
+            lines.rotate_left(1);
+        } else {
+            lines.rotate_right(1);
+        }
+
+        if self.splice_lines(from, to, &lines) {
+            self.shift_rows(1, !up);
         }
+        Ok(false)
     }
 
-    // New method to check and process any API responses
-    pub fn check_api_responses(&mut self) {
-        // Only check if we need to
-        if !self.needs_response_check {
-            return;
+    // Moves the cursor and the selection's start `by` rows
+    fn shift_rows(&mut self, by: usize, down: bool) {
+        let shift = |row: usize| if down { row + by } else { row - by };
+        self.cursor_row = shift(self.cursor_row);
+        if let Some((row, col)) = self.selection_start {
+            self.selection_start = Some((shift(row), col));
         }
+        self.clamp_cursor();
+    }
 
-        // Create a variable to store the response we'll process
-        let response_to_process = {
-            // Scope the lock to this block only
-            if let Ok(mut state) = self.shared_state.lock() {
-                // Take the response if available
-                state.api_response.take()
-            } else {
-                None
+    /// `gc`: comments out the selected lines, or the cursor's, in the syntax
+    /// of the code block they're in. Outside of code they're quoted. Lines
+    /// that all are commented get uncommented.
+    fn toggle_comment(&mut self) -> Result<bool> {
+        let (first, last) = self.selected_rows();
+
+        // The block of the first line decides, fences stay as they are
+        let (first, last, style) = match self.code_block_at(first) {
+            Some(block) => {
+                let Some(style) = block.comment_style() else {
+                    self.set_message(
+                        Level::Warning,
+                        format!("No comment syntax known for ```{} blocks", block.language),
+                    );
+                    return Ok(false);
+                };
+                let code_first = self.buffer.byte_to_line(block.start);
+                let code_last = self.buffer.byte_to_line(block.end).saturating_sub(1);
+                (first.max(code_first), last.min(code_last), Some(style))
             }
-        }; // Lock is released here when the block ends
+            None => (first, last, None),
+        };
+        if first > last {
+            self.set_message(Level::Warning, "Nothing to comment here");
+            return Ok(false);
+        }
 
-        // Try to lock the shared state
-        if let Some(response) = response_to_process {
-            // If there was an error, we've already set the request state
-            if response.error.is_none() && !response.content.is_empty() {
-                // Add the response to the end of the buffer
-                let char_idx = self.buffer.len_chars();
-                self.buffer.insert(char_idx, &response.content);
+        let lines: Vec<String> = (first..=last).map(|row| self.line_text(row)).collect();
+        let text = comment::toggle(&lines, style).join("\n");
+        let start = self.buffer.line_to_char(first);
+        let end = self.buffer.line_to_char(last) + lines[lines.len() - 1].chars().count();
+        if self.edit_blocked(start..end) {
+            return Ok(false);
+        }
 
-                // Now we can safely call this method since the lock is dropped
-                self.update_syntax_highlighting();
+        self.record_edit(start..end, &text);
+        self.buffer.remove(start..end);
+        self.buffer.insert(start, &text);
+        self.modified = true;
+        self.invalidate_syntax_at_line(first);
 
-                // Update cursor position to the end
-                let new_lines = self.buffer.len_lines() - 1;
-                self.cursor_row = new_lines;
-                let last_line = self.buffer.line(new_lines);
-                self.cursor_col = last_line.len_chars().saturating_sub(1);
+        if self.mode == Mode::Select {
+            self.mode = Mode::Normal;
+            self.selection_active = false;
+            self.selection_start = None;
+        }
+        self.clamp_cursor();
+        Ok(false)
+    }
 
-                self.modified = true;
-            }
+    /// What the language server sees: the whole buffer of a Rust file, or
+    /// the Rust code block under the cursor. URI, text and first buffer line.
+    fn lsp_document(&self) -> Option<(String, String, usize)> {
+        if self.history.file_path.ends_with(".rs") {
+            let uri = lsp::uri(Path::new(&self.history.file_path));
+            return Some((uri, self.buffer.to_string(), 0));
+        }
 
-            // We've processed the response, no need to check again
-            self.needs_response_check = false;
+        let block = self.code_block_at_cursor()?;
+        if !matches!(block.language.as_str(), "rust" | "rs") {
+            return None;
         }
+        let uri = lsp::uri(&language_server::block_path());
+        Some((uri, block.code, self.buffer.byte_to_line(block.start)))
     }
 
-    pub fn is_waiting_for_command(&self) -> bool {
-        self.menu_status.is_active_menu()
-            // && !self.menu_status.is_active(MenuType::FilePicker)
-            // && !self.menu_status.is_active(MenuType::FileSaveAs)
-            && !self.menu_status.is_file_picker_active()
+    /// Ctrl+Space: completions for the Rust code under the cursor. The
+    /// answer opens a popup once it arrives.
+    fn request_completion(&mut self) {
+        let Some((uri, text, first_line)) = self.lsp_document() else {
+            self.set_message(Level::Warning, "Completion needs a Rust file or a rust code block");
+            return;
+        };
+
+        let result = self
+            .language_server
+            .sync(&uri, &text, first_line)
+            .and_then(|_| {
+                self.language_server
+                    .request_completion(self.cursor_row, self.cursor_col)
+            });
+        if let Err(e) = result {
+            self.set_message(Level::Error, format!("Language server: {}", e));
+        }
     }
 
-    fn move_to_end_of_line(&mut self) -> Result<bool> {
-        // Move the cursor to the end of the line
-        let line = self.buffer.line(self.cursor_row);
-        let line_len = line.len_chars().saturating_sub(1); // Account for newline
-        self.cursor_col = line_len;
+    /// Keeps the server's copy of the document current and picks up its
+    /// completions and diagnostics. Does nothing until the server runs.
+    /// Returns whether completions or diagnostics came in
+    pub fn poll_language_server(&mut self) -> bool {
+        if !self.language_server.is_running() {
+            return false;
+        }
 
-        self.clamp_cursor();
+        let mut changed = false;
+        if let Some(items) = self.language_server.take_completions() {
+            changed = true;
+            if self.mode == Mode::Insert && !items.is_empty() {
+                let labels = items
+                    .iter()
+                    .map(|item| match &item.detail {
+                        Some(detail) => format!("{}  {}", item.label, detail),
+                        None => item.label.clone(),
+                    })
+                    .collect();
+                self.completions = items;
+                self.menu_status.file_picker.init_completion_picker(labels);
+            }
+        }
 
-        Ok(false)
+        if self.language_server.is_due() {
+            let synced = match self.lsp_document() {
+                Some((uri, text, first_line)) => self.language_server.sync(&uri, &text, first_line),
+                None => {
+                    self.language_server.forget_document();
+                    Ok(())
+                }
+            };
+            if let Err(e) = synced {
+                self.set_message(Level::Error, format!("Language server: {}", e));
+            }
+        }
+
+        changed | self.language_server.update_diagnostics(&self.buffer)
     }
 
-    fn move_to_start_of_line(&mut self) -> Result<bool> {
-        // Move the cursor to the beginning of the line
-        self.cursor_col = 0;
+    /// Keys of the completion popup. `None` closes it and lets the key
+    /// through, so typing isn't interrupted.
+    fn handle_completion(&mut self, key: KeyCode) -> Option<Result<bool>> {
+        match key {
+            KeyCode::Up => self.menu_status.file_picker.move_file_picker_up(),
+            KeyCode::Down => self.menu_status.file_picker.move_file_picker_down(),
+            KeyCode::Enter | KeyCode::Tab => {
+                let index = self.menu_status.file_picker.get_selected_file_index();
+                if let Some(item) = self.completions.get(index).cloned() {
+                    self.insert_completion(&item.text);
+                }
+                self.menu_status.reset();
+            }
+            KeyCode::Esc => self.menu_status.reset(),
+            _ => return None,
+        }
+        Some(Ok(false))
+    }
 
-        Ok(false)
+    /// Replaces the word before the cursor with a completion
+    fn insert_completion(&mut self, text: &str) {
+        let end = self.get_char_idx();
+        let mut start = end;
+        while start > 0 {
+            let c = self.buffer.char(start - 1);
+            if !(c.is_alphanumeric() || c == '_') {
+                break;
+            }
+            start -= 1;
+        }
+        if self.edit_blocked(start..end) {
+            return;
+        }
+
+        self.record_edit(start..end, text);
+        self.buffer.remove(start..end);
+        self.buffer.insert(start, text);
+        self.set_cursor_to_char_idx(start + text.chars().count());
+        self.modified = true;
+        self.invalidate_syntax_at_line(self.buffer.char_to_line(start));
     }
 
-    fn move_to_start_of_buffer(&mut self) -> Result<bool> {
-        // Move cursor to the first position in the buffer
-        self.cursor_row = 0;
-        self.cursor_col = 0;
+    /// Copies the code of the fenced block under the cursor, without its
+    /// fences. Returns false when the cursor isn't in a block.
+    fn yank_code_block(&mut self) -> bool {
+        match self.code_block_at_cursor() {
+            Some(block) => {
+                self.copy_text(block.code);
+                true
+            }
+            None => false,
+        }
+    }
 
-        Ok(false)
+    /// Pins the code block under the cursor, or else the paragraph, to the
+    /// session. Pinning a pinned one unpins it.
+    fn toggle_pin_at_cursor(&mut self) {
+        let text = match self.code_block_at_cursor() {
+            Some(block) => format!("```{}\n{}\n```", block.language, block.code.trim_end()),
+            None => self.paragraph_at_cursor(),
+        };
+        if text.trim().is_empty() {
+            self.set_message(Level::Warning, "Nothing to pin here");
+            return;
+        }
+        let Some(session) = &mut self.history.session else {
+            self.set_message(Level::Warning, "Only sessions keep pins");
+            return;
+        };
+
+        match session.toggle_pin(&text) {
+            Ok(true) => self.set_message(Level::Info, "Pinned"),
+            Ok(false) => self.set_message(Level::Info, "Unpinned"),
+            Err(e) => self.set_message(Level::Error, format!("Can't save pins: {}", e)),
+        }
     }
 
-    fn move_to_end_of_buffer(&mut self) -> Result<bool> {
-        let total_lines = self.buffer.len_lines();
+    /// Folded messages and code, in order
+    pub fn folded(&self) -> Vec<Folded> {
+        self.folds.rows(&self.buffer)
+    }
 
-        if total_lines == 0 {
-            // Buffer is empty: place cursor at 0,0
-            self.cursor_row = 0;
-            self.cursor_col = 0;
+    /// Folds or unfolds the function or block under the cursor in a code
+    /// block, elsewhere the message the cursor is in
+    fn toggle_fold(&mut self) -> Result<bool> {
+        if self.folds.remove_code_on(&self.buffer, self.cursor_row) {
             return Ok(false);
         }
 
-        let last_line_idx = total_lines - 1;
+        let byte = self.buffer.char_to_byte(self.get_char_idx());
+        let code = self
+            .syntax_highlighter
+            .as_ref()
+            .and_then(|highlighter| highlighter.foldable_range(byte));
+        if let Some(range) = code {
+            let start = self.buffer.byte_to_char(range.start);
+            let end = self.buffer.byte_to_char(range.end);
+            self.folds.add_code(start..end);
+            return Ok(false);
+        }
 
-        self.cursor_row = last_line_idx;
+        let header = (0..=self.cursor_row)
+            .rev()
+            .find(|&row| transcript::role(&self.line_text(row)).is_some());
+        let Some(header) = header else {
+            self.set_message(Level::Warning, "Nothing to fold here");
+            return Ok(false);
+        };
+        self.folds.toggle_message(self.buffer.line_to_char(header));
+        Ok(false)
+    }
 
-        let line = self.buffer.line(last_line_idx);
+    fn fold_all(&mut self) -> Result<bool> {
+        let headers = (0..self.buffer.len_lines())
+            .filter(|&row| transcript::role(&self.line_text(row)).is_some())
+            .map(|row| self.buffer.line_to_char(row))
+            .collect();
+        self.folds.fold_messages(headers);
+        Ok(false)
+    }
 
-        // line.len_chars() is always at least 1 (newline at end)
-        // saturate to 0 if len_chars() == 0 (shouldn't happen)
-        let line_len = line.len_chars().saturating_sub(1);
+    /// Keeps the cursor out of folds: past one it moved down into, on the
+    /// header otherwise
+    fn skip_folded(&mut self, from_row: usize) {
+        if self.folds.is_empty() {
+            return;
+        }
 
-        self.cursor_col = line_len;
+        self.folds.prune(&self.buffer);
+        let row = self.cursor_row;
+        let folded = self.folded();
+        // Outermost first, a fold inside another one is hidden with it
+        let Some(fold) = folded.iter().find(|fold| fold.header < row && row <= fold.last) else {
+            return;
+        };
 
+        self.cursor_row = if row > from_row && fold.last + 1 < self.buffer.len_lines() {
+            fold.last + 1
+        } else {
+            fold.header
+        };
         self.clamp_cursor();
-
-        Ok(false)
     }
 
-    // Add a new method to select the current line or expand selection
-    fn select_current_line(&mut self) -> Result<bool> {
-        // Check if we're already in select mode with an active selection
-        if self.mode == Mode::Select && self.selection_active && self.selection_start.is_some() {
-            // Get the current selection range to determine if it already covers full lines
-            let selection_range = self.get_selection_range().unwrap_or(0..0);
-            let start_row = self.buffer.char_to_line(selection_range.start);
-            let end_row = self.buffer.char_to_line(selection_range.end);
+    // Lines around the cursor up to the blank ones
+    fn paragraph_at_cursor(&self) -> String {
+        let blank = |row: usize| self.buffer.line(row).to_string().trim().is_empty();
+        if self.cursor_row >= self.buffer.len_lines() || blank(self.cursor_row) {
+            return String::new();
+        }
 
-            // If the selection already covers complete lines, extend to include one more line
-            if end_row < self.buffer.len_lines() - 1 {
-                // Move cursor to beginning of the next line
-                self.cursor_row = end_row + 1;
+        let mut first = self.cursor_row;
+        while first > 0 && !blank(first - 1) {
+            first -= 1;
+        }
+        let mut last = self.cursor_row;
+        while last + 1 < self.buffer.len_lines() && !blank(last + 1) {
+            last += 1;
+        }
 
-                // If this is the last line, move to the end of it
-                if self.cursor_row >= self.buffer.len_lines() - 1 {
-                    let line = self.buffer.line(self.cursor_row);
-                    self.cursor_col = line.len_chars().saturating_sub(1);
-                } else {
-                    // Otherwise, move to the end of this line
-                    let line = self.buffer.line(self.cursor_row);
-                    self.cursor_col = line.len_chars().saturating_sub(1);
-                }
+        let start = self.buffer.line_to_char(first);
+        let end = self.buffer.line_to_char(last) + self.buffer.line(last).len_chars();
+        self.buffer.slice(start..end).to_string().trim_end().to_string()
+    }
+
+    /// Pinned snippets of the active session, oldest first
+    pub fn pins(&self) -> Vec<String> {
+        self.history
+            .session
+            .as_ref()
+            .map(|session| session.pins.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn pins_panel_visible(&self) -> bool {
+        self.show_pins_panel
+    }
+
+    /// Alt+1..9: pin `number` as a new prompt at the end of the buffer
+    fn insert_pin(&mut self, number: usize) -> Result<()> {
+        let Some(pin) = self.pins().into_iter().nth(number.wrapping_sub(1)) else {
+            self.set_message(Level::Warning, format!("No pin {}", number));
+            return Ok(());
+        };
+
+        // Below the last answer, after a blank line
+        let text = self.buffer.to_string();
+        let separator = if text.trim().is_empty() || text.ends_with("\n\n") {
+            ""
+        } else if text.ends_with('\n') {
+            "\n"
+        } else {
+            "\n\n"
+        };
+
+        self.set_cursor_to_char_idx(self.buffer.len_chars());
+        self.insert_at_cursor(&format!("{}{}\n", separator, pin))
+    }
+
+    /// Asks to run the selection, or else the code block under the cursor,
+    /// as a shell command. The output goes below it.
+    fn confirm_run_command(&mut self) {
+        let target = match self.get_selection_range() {
+            Some(range) => {
+                let command = self.buffer.slice(range.clone()).to_string();
+                Some((command, self.buffer.char_to_line(range.end)))
             }
+            None => self
+                .code_block_at_cursor()
+                .map(|block| (block.code, self.buffer.byte_to_line(block.end))),
+        };
+
+        let Some((command, last_line)) = target.filter(|(c, _)| !c.trim().is_empty()) else {
+            self.set_message(Level::Warning, "Nothing to run, select a command first");
+            return;
+        };
+
+        // Start of the line after the selection or closing fence
+        let insert_at = if last_line + 1 < self.buffer.len_lines() {
+            self.buffer.line_to_char(last_line + 1)
         } else {
-            // Start a new line selection
-            // Move cursor to the beginning of the current line
-            self.cursor_col = 0;
+            self.buffer.len_chars()
+        };
 
-            // Set selection start
-            self.selection_start = Some((self.cursor_row, 0));
+        let mut lines: Vec<String> = command.lines().take(8).map(str::to_string).collect();
+        if command.lines().count() > lines.len() {
+            lines.push("...".to_string());
+        }
 
-            // Move cursor to the end of the line
-            let line = self.buffer.line(self.cursor_row);
-            let line_end = line.len_chars().saturating_sub(1);
-            self.cursor_col = line_end;
+        self.confirm = Some(Confirm::new(
+            "Run this command?",
+            lines,
+            "y: Run | n: Cancel",
+            ConfirmAction::RunCommand { command, insert_at },
+        ));
+    }
 
-            // Activate selection and enter select mode
-            self.selection_active = true;
-            self.mode = Mode::Select;
+    /// Runs `command` through the shell in the background, its output
+    /// goes in as a fenced block at `insert_at` once it's done
+    fn run_command(&mut self, command: String, insert_at: usize) {
+        if self.edit_blocked(insert_at..insert_at) {
+            return;
         }
 
-        Ok(false)
+        if self.mode == Mode::Select {
+            self.mode = Mode::Normal;
+            self.selection_active = false;
+            self.selection_start = None;
+        }
+
+        let purpose = ShellPurpose::Insert(self.origin(), insert_at);
+        self.async_handler.run_shell(command, purpose);
     }
 
-    pub fn get_selection_range(&self) -> Option<std::ops::Range<usize>> {
+    // Inserts the output of a command run from the buffer below it. If that
+    // buffer can't take it now, the output is copied instead.
+    fn insert_command_output(&mut self, origin: &Origin, insert_at: usize, output: &str) {
+        let insert_at = insert_at.min(self.buffer.len_chars());
+        let mut block = format!("```output\n{}```\n", output);
+        // Appended after a last line without newline
+        if insert_at > 0 && self.buffer.char(insert_at - 1) != '\n' {
+            block.insert(0, '\n');
+        }
+
+        if *origin != self.origin() || self.edit_blocked(insert_at..insert_at) {
+            self.copy_text(block);
+            self.set_message(
+                Level::Warning,
+                "Can't insert the command output here, it's copied (p pastes it)",
+            );
+            return;
+        }
+
+        self.record_edit(insert_at..insert_at, &block);
+        self.buffer.insert(insert_at, &block);
+        self.modified = true;
+        self.update_syntax_highlighting();
+    }
+
+    fn get_selected_text(&self) -> Option<String> {
         if !self.selection_active || self.selection_start.is_none() {
             return None;
         }
 
-        let (start_row, start_col) = self.selection_start.unwrap();
-        let (end_row, end_col) = (self.cursor_row, self.cursor_col);
+        let selection_range = self.get_selection_range()?;
+        let start_idx = selection_range.start;
+        let end_idx = selection_range.end;
 
-        let start_idx = self.char_idx_from_position(start_row, start_col);
-        let end_idx = self.char_idx_from_position(end_row, end_col);
+        // Extract the text from the buffer
+        Some(self.buffer.slice(start_idx..end_idx).to_string())
+    }
 
-        // Make sure start_idx <= end_idx
-        if start_idx <= end_idx {
-            Some(start_idx..end_idx)
-        } else {
-            Some(end_idx..start_idx)
+    pub fn get_style_at(&mut self, char_idx: usize) -> Style {
+        // Convert char_idx to line and column
+        let position = self.position_from_char_idx(char_idx);
+        let (line, col) = position;
+
+        // Check for selection first (direct check)
+        if self.is_position_selected(line, col, &self.get_selection_range()) {
+            return Style::Selection;
+        }
+
+        // A viewer has no highlights, and no cache to fill
+        if self.read_only {
+            return Style::Normal;
+        }
+
+        // Try to get from cache first
+        if let Some(style) = self.syntax_cache.get_cached_style(line, col) {
+            return style;
+        }
+
+        // If not in cache, highlight the whole line and get the style
+        let line_styles = self.highlight_line(line);
+        if col < line_styles.len() {
+            return line_styles[col];
+        }
+
+        // Fallback
+        Style::Normal
+    }
+
+    /// Opens the split with the buffer's content as transcript, or folds the
+    /// transcript back above the prompt when it's open. The chat layout
+    /// starts typing right away.
+    fn toggle_split(&mut self, layout: split::Layout) {
+        if self.split.is_some() {
+            self.close_split();
+            return;
+        }
+
+        let transcript = std::mem::replace(&mut self.buffer, Rope::new());
+        let protected = std::mem::take(&mut self.protected);
+        self.folds.clear();
+        self.split = Some(split::SplitView::new(transcript, protected, layout));
+        self.cursor_row = 0;
+        self.cursor_col = 0;
+        if layout == split::Layout::Chat {
+            self.mode = Mode::Insert;
         }
+        self.update_syntax_highlighting();
     }
 
-    pub fn is_position_selected(
-        &self,
-        row: usize,
-        col: usize,
-        selection_range: &Option<std::ops::Range<usize>>,
-    ) -> bool {
-        if let Some(range) = selection_range {
-            let pos_idx = self.char_idx_from_position(row, col);
-            range.contains(&pos_idx)
+    /// Up on the first line of the chat input box shows the prompt sent
+    /// before, Down on its last line the one after. Returns whether the key
+    /// was taken.
+    fn recall_prompt(&mut self, key: KeyCode) -> bool {
+        let Some(split) = &mut self.split else {
+            return false;
+        };
+        if split.layout() != split::Layout::Chat || split.focus() != split::Pane::Prompt {
+            return false;
+        }
+
+        let last_row = self.buffer.len_lines().saturating_sub(1);
+        let prompt = match key {
+            KeyCode::Up if self.cursor_row == 0 => split.previous_prompt(&self.buffer.to_string()),
+            KeyCode::Down if self.cursor_row >= last_row => split.next_prompt(),
+            _ => return false,
+        };
+        let Some(prompt) = prompt else {
+            return false;
+        };
+
+        self.record_edit(0..self.buffer.len_chars(), &prompt);
+        self.buffer = Rope::from_str(&prompt);
+        self.cursor_row = self.buffer.len_lines().saturating_sub(1);
+        self.cursor_col = self.buffer.line(self.cursor_row).len_chars();
+        self.clamp_cursor();
+        self.update_syntax_highlighting();
+        true
+    }
+
+    /// Replaces the buffer with a file of the history directory, given
+    /// relative to it. Sessions open with their metadata, other files
+    /// stream in through poll_file_load.
+    fn load_history_file(&mut self, selected_file: &str) -> Result<()> {
+        let path = Path::new(&self.history.root).join(selected_file);
+        if path.starts_with(session::dir()) {
+            return self.open_session(Session::open(&path)?);
+        }
+
+        self.buffer = Rope::new();
+        self.protected.clear();
+        self.folds.clear();
+        self.syntax_highlights.clear();
+        self.cursor_row = 0;
+        self.cursor_col = 0;
+        self.modified = false;
+        self.read_only = false;
+        self.scratch = false;
+        self.encoding = Encoding::default();
+        self.line_ending = LineEnding::default();
+
+        // Update file path in history or state if relevant
+        self.history.file_path = selected_file.to_string();
+        self.history.session = None;
+
+        let full_path = format!("{}/{}", self.history.root, selected_file);
+        self.async_handler.load_file(full_path);
+        self.track_file();
+        Ok(())
+    }
+
+    /// Lines around the selected match of the conversation search
+    pub fn history_search_context(&self) -> Vec<String> {
+        let index = self.menu_status.file_picker.get_selected_file_index();
+        self.history_search
+            .as_ref()
+            .map(|search| search.context(index))
+            .unwrap_or_default()
+    }
+
+    /// Lists the prompts sent so far, newest first
+    fn open_prompt_history(&mut self) {
+        match prompt_history::load() {
+            Ok(prompts) if prompts.is_empty() => self.set_message(Level::Info, "No prompts sent yet"),
+            Ok(mut prompts) => {
+                prompts.reverse();
+                self.menu_status.file_picker.init_prompt_history(prompts);
+            }
+            Err(e) => self.set_message(Level::Error, format!("Can't read prompt history: {}", e)),
+        }
+    }
+
+    /// Puts a prompt picked from the history up for editing: it replaces the
+    /// input of the split view, otherwise it goes in at the cursor
+    fn recall_into_prompt(&mut self, prompt: &str) {
+        if self.split.is_some() {
+            self.record_edit(0..self.buffer.len_chars(), prompt);
+            self.buffer = Rope::from_str(prompt);
+            self.cursor_row = self.buffer.len_lines().saturating_sub(1);
+            self.cursor_col = self.buffer.line(self.cursor_row).len_chars();
         } else {
-            false
+            let char_idx = self.get_char_idx();
+            if self.edit_blocked(char_idx..char_idx) {
+                return;
+            }
+            self.record_edit(char_idx..char_idx, prompt);
+            self.buffer.insert(char_idx, prompt);
+            self.modified = true;
+            (self.cursor_row, self.cursor_col) =
+                self.position_from_char_idx(char_idx + prompt.chars().count());
         }
+        self.clamp_cursor();
+        self.mode = Mode::Insert;
+        self.update_syntax_highlighting();
     }
 
-    pub fn get_style_for_position(&mut self, row: usize, col: usize) -> Style {
-        // Check if the position is selected first
-        if self.is_position_selected(row, col, &self.get_selection_range()) {
-            return Style::Selection;
+    fn close_split(&mut self) {
+        let Some(split) = self.split.take() else {
+            return;
+        };
+
+        let prompt = self.buffer.to_string();
+        (self.buffer, self.protected) = split.into_transcript();
+        if !prompt.trim().is_empty() {
+            let end = self.buffer.len_chars();
+            self.buffer.insert(end, &format!("\n\n{}", prompt));
         }
+        self.clamp_cursor();
+        self.update_syntax_highlighting();
+    }
 
-        // Otherwise, get the syntax highlighting style
-        let char_idx = self.char_idx_from_position(row, col);
-        self.get_style_at(char_idx)
+    pub fn split_view(&self) -> Option<&split::SplitView> {
+        self.split.as_ref()
     }
 
-    pub fn char_idx_from_position(&self, row: usize, col: usize) -> usize {
-        if row >= self.buffer.len_lines() {
-            return self.buffer.len_chars();
+    pub fn split_view_mut(&mut self) -> Option<&mut split::SplitView> {
+        self.split.as_mut()
+    }
+
+    /// Where the answer being streamed grows, as a char index into the
+    /// buffer or, with the split open, into the transcript
+    pub fn stream_point(&self) -> Option<usize> {
+        if !self.async_handler.is_streaming() {
+            return None;
+        }
+        match &self.split {
+            Some(split) => Some(split.transcript().len_chars()),
+            None => self.response_at.map(|at| at.min(self.buffer.len_chars())),
         }
+    }
 
-        // Get the char index of the start of the line
-        let line_start_idx = self.buffer.line_to_char(row);
+    fn transcript_focused(&self) -> bool {
+        self.split
+            .as_ref()
+            .is_some_and(|split| split.focus() == split::Pane::Transcript)
+    }
 
-        // Add the column, clamping to line length
-        let line_len = self.buffer.line(row).len_chars();
-        let clamped_col = col.min(line_len);
+    /// Scrolling keys of the read-only transcript pane. `None` lets the key
+    /// through to the Normal mode handler (menus, cancel).
+    fn handle_transcript_key(&mut self, key: KeyCode, modifiers: KeyModifiers) -> Option<bool> {
+        let processing = self.get_request_state() == RequestState::Proccessing;
+        let split = self.split.as_mut()?;
+        let ctrl = modifiers.contains(KeyModifiers::CONTROL);
 
-        line_start_idx + clamped_col
+        match key {
+            KeyCode::Tab => split.toggle_focus(),
+            KeyCode::Esc if !processing => split.toggle_focus(),
+            KeyCode::Char('j') | KeyCode::Down => split.scroll_by(1),
+            KeyCode::Char('k') | KeyCode::Up => split.scroll_by(-1),
+            KeyCode::Char('d') if ctrl => split.scroll_by(TRANSCRIPT_PAGE),
+            KeyCode::Char('u') if ctrl => split.scroll_by(-TRANSCRIPT_PAGE),
+            KeyCode::PageDown => split.scroll_by(TRANSCRIPT_PAGE),
+            KeyCode::PageUp => split.scroll_by(-TRANSCRIPT_PAGE),
+            KeyCode::Home => split.scroll_to_top(),
+            KeyCode::End | KeyCode::Char('G') => split.scroll_to_bottom(),
+            KeyCode::Esc => return None,
+            // The menus, wherever they're bound
+            _ if matches!(
+                keymap::command(Context::Normal, key, modifiers),
+                Some(Command::GoToMenu | Command::FileMenu | Command::AIMenu)
+            ) =>
+            {
+                return None
+            }
+            // Read-only: everything else is ignored
+            _ => {}
+        }
+
+        Some(false)
     }
 
-    pub fn handle_key(&mut self, key: KeyCode, modifiers: KeyModifiers) -> Result<bool> {
-        // Handle regular keys based on mode
-        match self.mode {
-            Mode::Normal => self.handle_normal_mode(key, modifiers),
-            Mode::Insert => self.handle_insert_mode(key, modifiers),
-            Mode::Select => self.handle_select_mode(key, modifiers),
+    /// Everything that gets saved: transcript and prompt in split view
+    fn document_text(&self) -> String {
+        match &self.split {
+            Some(split) if self.buffer.len_chars() > 0 => {
+                format!("{}\n\n{}", split.transcript(), self.buffer)
+            }
+            Some(split) => split.transcript().to_string(),
+            None => self.buffer.to_string(),
         }
     }
 
-    fn send_to_anthropic(&mut self) -> Result<()> {
-        self.send_to_api(Model::ANTROPIC)
+    pub fn save_file(&mut self) -> Result<()> {
+        // Saving half a file would cut it off on disk
+        if self.is_loading() {
+            return Err("The file is still loading".into());
+        }
+        if self.scratch {
+            return Err("Scratch buffers aren't saved, File menu: S saves it under a name".into());
+        }
+        // Another instance may have the file open, the viewer's copy would
+        // overwrite its saves
+        if self.read_only {
+            return Err("Read-only view, File menu: R to edit".into());
+        }
+        if config::get().trim_on_save {
+            self.trim_whitespace();
+        }
+        if let Some(session) = &mut self.history.session {
+            session.model = self.chat_context.model_entry();
+        }
+        let text = self.line_ending.apply(&self.document_text());
+        self.history.save_file(text, self.encoding)?;
+        autosave::remove(&self.history.file_path);
+        self.modified = false;
+        self.track_file();
+
+        Ok(())
     }
 
-    fn send_to_ollama(&mut self) -> Result<()> {
-        // self.async_handler.request_ollama();
-        // self.needs_response_check = true;
-        self.send_to_api(Model::OLLAMA);
+    // `trim_on_save`: strips blanks at the end of lines, leaving read-only
+    // answers alone, and ends the buffer with a newline
+    fn trim_whitespace(&mut self) {
+        if self.read_only {
+            return;
+        }
+        let mut first_changed = None;
+        for row in (0..self.buffer.len_lines()).rev() {
+            let line = self.line_text(row);
+            let kept = line.trim_end().chars().count();
+            let len = line.chars().count();
+            if kept == len {
+                continue;
+            }
+
+            let start = self.buffer.line_to_char(row);
+            let range = start + kept..start + len;
+            if self.protected.blocks(&range) {
+                continue;
+            }
+            self.record_edit(range.clone(), "");
+            self.buffer.remove(range);
+            first_changed = Some(row);
+        }
+
+        let end = self.buffer.len_chars();
+        if end > 0 && self.buffer.char(end - 1) != '\n' && !self.protected.blocks(&(end..end)) {
+            self.record_edit(end..end, "\n");
+            self.buffer.insert_char(end, '\n');
+            first_changed = first_changed.or(Some(self.buffer.len_lines() - 2));
+        }
+
+        if let Some(row) = first_changed {
+            self.invalidate_syntax_at_line(row);
+            self.clamp_cursor();
+        }
+    }
+
+    // Suggested message of a history commit, naming the open session
+    fn commit_message(&self) -> String {
+        match &self.history.session {
+            Some(session) if !session.title.is_empty() => format!("Update {}", session.title),
+            _ => "Update conversations".to_string(),
+        }
+    }
+
+    /// Commits the history folder, with the open session saved first so
+    /// the commit has its latest messages
+    fn commit_history(&mut self, message: &str) {
+        if self.history.session.is_some() {
+            if let Err(e) = self.save_file() {
+                self.set_message(Level::Error, format!("Can't save session: {}", e));
+                return;
+            }
+        }
+
+        let dir = PathBuf::from(&config::get().history_dir);
+        match git::commit_all(&dir, message) {
+            Ok(summary) => {
+                // The open file may be one of the committed ones
+                self.git_head = None;
+                self.update_git_changes();
+                self.set_message(Level::Info, format!("Committed {}", summary));
+            }
+            Err(e) => self.set_message(Level::Error, format!("Can't commit: {}", e)),
+        }
+    }
+
+    /// Writes the conversation to `<history_dir>/exports` as JSON and HTML,
+    /// named after the session or file
+    fn export_conversation(&self) -> Result<PathBuf> {
+        let path = Path::new(&self.history.file_path);
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "conversation".to_string());
+        let (title, created) = match &self.history.session {
+            Some(session) => (session.title.clone(), session.created.clone()),
+            None => (name.clone(), String::new()),
+        };
+
+        let model = self.chat_context.model_entry();
+        Export::new(&title, &model, &created, &self.document_text()).write(&name)
+    }
+
+    /// Opens a conversation exported from ChatGPT, or by
+    /// `export_conversation`, as a new unsaved session
+    fn import_conversation(&mut self, path: &str) -> Result<()> {
+        let imported = import::parse(&fs::read_to_string(path)?)?;
+
+        self.new_session()?;
+        if let Some(session) = &mut self.history.session {
+            session.title = imported.title;
+        }
+        self.buffer = Rope::from_str(&imported.transcript);
+        self.modified = true;
+        self.update_syntax_highlighting();
+
         Ok(())
     }
 
-    fn send_to_openai(&mut self) -> Result<()> {
-        // self.request_state = RequestState::Proccessing;
-        self.send_to_api(Model::OPENAI)
+    /// Moves the active buffer's state out of the editor fields
+    fn take_active_state(&mut self) -> BufferState {
+        // The split shows the active buffer's conversation, fold it back first
+        self.close_split();
+
+        BufferState {
+            id: self.buffer_id,
+            buffer: std::mem::replace(&mut self.buffer, Rope::new()),
+            cursor_row: self.cursor_row,
+            cursor_col: self.cursor_col,
+            history: self.history.clone(),
+            modified: self.modified,
+            read_only: self.read_only,
+            scratch: self.scratch,
+            encoding: self.encoding,
+            line_ending: self.line_ending,
+            protected: std::mem::take(&mut self.protected),
+            syntax_cache: std::mem::replace(&mut self.syntax_cache, SyntaxCache::new()),
+            syntax_highlights: std::mem::take(&mut self.syntax_highlights),
+            selection_start: self.selection_start.take(),
+            selection_active: self.selection_active,
+            response_start: self.response_start.take(),
+            response_at: self.response_at.take(),
+            last_answer: self.last_answer.take(),
+        }
     }
 
-    fn send_to_api(&mut self, ai_model: Model) -> Result<()> {
-        let content = self.buffer.to_string();
+    fn restore_state(&mut self, state: BufferState) {
+        self.folds.clear();
+        self.buffer_id = state.id;
+        self.buffer = state.buffer;
+        self.cursor_row = state.cursor_row;
+        self.cursor_col = state.cursor_col;
+        self.history = state.history;
+        self.modified = state.modified;
+        self.read_only = state.read_only;
+        self.scratch = state.scratch;
+        self.encoding = state.encoding;
+        self.line_ending = state.line_ending;
+        self.protected = state.protected;
+        self.syntax_cache = state.syntax_cache;
+        self.syntax_highlights = state.syntax_highlights;
+        self.selection_start = state.selection_start;
+        self.selection_active = state.selection_active;
+        self.response_start = state.response_start;
+        self.response_at = state.response_at;
+        self.last_answer = state.last_answer;
+        // Its positions were in the buffer we left
+        self.last_rewrite = None;
+        self.watcher.watch(Path::new(&self.history.file_path));
+        self.lock_file();
+
+        // Selection belongs to the buffer we left
+        self.mode = if self.selection_active {
+            Mode::Select
+        } else {
+            Mode::Normal
+        };
 
-        // Delegate to the async handler
-        self.async_handler.send_to_api(content, ai_model);
+        self.update_syntax_highlighting();
+    }
 
-        // Set flag to check for responses
-        self.needs_response_check = true;
+    /// Opens an empty buffer right after the active one and switches to it
+    fn new_buffer(&mut self) {
+        if self.load_blocks() {
+            return;
+        }
+        let history = self.history.sibling(&self.unused_name("untitled"));
+
+        let current = self.take_active_state();
+        self.buffers[self.active_buffer] = Some(current);
+
+        self.active_buffer += 1;
+        self.buffers.insert(self.active_buffer, None);
+        let id = self.next_buffer_id;
+        self.next_buffer_id += 1;
+        self.restore_state(BufferState::new(id, history));
+    }
+
+    /// Empties the buffer and saves the empty file, unless it's scratch
+    fn wipe_buffer(&mut self) -> Result<()> {
+        if let Some(split) = &mut self.split {
+            split.clear();
+        }
+        self.buffer = Rope::new();
+        self.protected.clear();
+        self.folds.clear();
+        self.cursor_row = 0;
+        self.cursor_col = 0;
+        self.modified = false;
+
+        // if self.file_path.is_some() {
+        if !self.scratch {
+            self.save_file()?;
+        }
+        // }
+
+        // Update syntax highlighting for the empty buffer
+        self.update_syntax_highlighting();
 
         Ok(())
     }
 
-    fn handle_normal_mode(&mut self, key: KeyCode, modifiers: KeyModifiers) -> Result<bool> {
-        if (modifiers.contains(KeyModifiers::ALT) && key == KeyCode::Char('v'))
-            || (modifiers.is_empty() && key == KeyCode::Char('p'))
-        {
-            match self.paste_from_clipboard() {
-                Ok(_) => return Ok(false),
-                Err(e) => eprintln!("Paste error: {}", e),
+    /// Opens a throwaway buffer right after the active one, for prompts
+    /// not worth keeping. Save As turns it into a file.
+    fn new_scratch(&mut self) {
+        if self.load_blocks() {
+            return;
+        }
+        self.new_buffer();
+        self.history = self.history.sibling(&self.unused_name("scratch"));
+        self.scratch = true;
+    }
+
+    // `<prefix>_<n>.md` with the lowest n that's neither open in a buffer
+    // nor a file already, so a new buffer never saves over another one
+    fn unused_name(&self, prefix: &str) -> String {
+        let open: Vec<&str> = self
+            .buffers
+            .iter()
+            .flatten()
+            .map(|state| state.history.file_path.as_str())
+            .chain([self.history.file_path.as_str()])
+            .collect();
+
+        let mut n = 1;
+        loop {
+            let name = format!("{}_{}.md", prefix, n);
+            let history = self.history.sibling(&name);
+            if !open.contains(&history.file_path.as_str()) && Self::disk_path(&history).is_none() {
+                return name;
+            }
+            n += 1;
+        }
+    }
+
+    /// Whether quitting can go ahead. Unsaved buffers, text in scratch
+    /// buffers and running requests would be lost, so that's asked first.
+    pub fn request_quit(&mut self) -> bool {
+        self.quit_after_request = false;
+        self.detached = false;
+        if self.request_running() {
+            self.confirm = Some(Confirm::new(
+                "A request is still running",
+                vec!["Quitting now loses its answer".to_string()],
+                "w: Wait, then quit | c: Cancel it | d: Detach, the answer is saved after quitting | Esc: Stay",
+                ConfirmAction::QuitDuringRequest,
+            ));
+            return false;
+        }
+
+        self.confirm_unsaved(None)
+    }
+
+    // Asks about the buffers quitting would lose, but `saved`, which a
+    // detached quit saves with its answer. Returns whether there are none.
+    fn confirm_unsaved(&mut self, saved: Option<usize>) -> bool {
+        let has_text = |buffer: &Rope| buffer.chars().any(|c| !c.is_whitespace());
+        let lines: Vec<String> = self
+            .buffers
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| Some(*index) != saved)
+            .map(|(_, slot)| match slot {
+                Some(state) => (&state.history, &state.buffer, state.modified, state.scratch),
+                None => (&self.history, &self.buffer, self.modified, self.scratch),
+            })
+            .filter_map(|(history, buffer, modified, scratch)| {
+                let name = BufferState::name(history);
+                if scratch {
+                    has_text(buffer).then(|| format!("{} (scratch, not saved)", name))
+                } else {
+                    modified.then_some(name)
+                }
+            })
+            .collect();
+        if lines.is_empty() {
+            return true;
+        }
+
+        self.confirm = Some(Confirm::new(
+            "Quit with unsaved changes?",
+            lines,
+            "s: Save and quit | d: Discard | c: Cancel",
+            ConfirmAction::Quit,
+        ));
+        false
+    }
+
+    fn request_running(&self) -> bool {
+        self.async_handler.has_pending() || self.get_request_state() == RequestState::Proccessing
+    }
+
+    /// Set once a quit that waited for the running requests can go ahead
+    pub fn should_quit(&self) -> bool {
+        self.quit
+    }
+
+    /// Whether the editor quit with requests left running
+    pub fn is_detached(&self) -> bool {
+        self.detached
+    }
+
+    /// Waits for the requests left running by a detached quit, puts their
+    /// answers in their buffers as usual and saves those. Runs after the
+    /// screen has closed, so it reports on stderr.
+    pub fn finish_detached(&mut self) {
+        eprintln!("Waiting for the answer to {}, Ctrl+C drops it", BufferState::name(&self.history));
+        let mut answered = Vec::new();
+        while self.needs_response_check {
+            if let Some(index) = self.answer_buffer() {
+                if !answered.contains(&index) {
+                    answered.push(index);
+                }
+            }
+            self.check_api_responses();
+            thread::sleep(DETACH_POLL);
+        }
+
+        match self.get_request_state() {
+            RequestState::Error(e) => eprintln!("Request failed: {}", e),
+            RequestState::TimedOut(secs) => eprintln!("Request gave up after {}s", secs),
+            _ => {}
+        }
+        // Other buffers were asked about before quitting
+        for index in answered {
+            self.switch_buffer(index);
+            if self.scratch || self.read_only || !self.modified {
+                continue;
+            }
+            match self.save_file() {
+                Ok(()) => eprintln!("Saved {}", self.history.file_path),
+                Err(e) => eprintln!("Can't save {}: {}", self.history.file_path, e),
+            }
+        }
+    }
+
+    /// Saves every modified buffer but scratch and read-only ones, the last
+    /// one saved ends up active
+    fn save_all(&mut self) -> Result<()> {
+        for index in 0..self.buffers.len() {
+            let unsaved = match &self.buffers[index] {
+                Some(state) => state.modified && !state.scratch && !state.read_only,
+                None => self.modified && !self.scratch && !self.read_only,
+            };
+            if unsaved {
+                self.switch_buffer(index);
+                self.save_file()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Starts a new session in its own buffer
+    fn new_session(&mut self) -> Result<()> {
+        self.new_buffer();
+        self.history = History::new(Session::new(self.chat_context.model_entry()))?;
+
+        Ok(())
+    }
+
+    /// Lists the saved sessions to resume one, the last modified first.
+    /// Nothing opens when there are none.
+    pub fn open_session_picker(&mut self) -> Result<()> {
+        let sessions = session::list()?;
+        if !sessions.is_empty() {
+            let labels = sessions.iter().map(Session::label).collect();
+            let paths = sessions.iter().map(|session| session.path.clone()).collect();
+            let tags = sessions.into_iter().map(|session| session.tags).collect();
+            self.menu_status
+                .file_picker
+                .init_session_picker(labels, tags, paths);
+        }
+
+        Ok(())
+    }
+
+    /// Makes a buffer for `path` active: switches to its tab when it's open
+    /// already, reuses the active buffer when that one is empty, otherwise
+    /// opens a new one. Returns whether the file was open.
+    fn focus_buffer(&mut self, path: &str) -> bool {
+        let open = self.buffers.iter().position(|slot| {
+            slot.as_ref().map(|state| state.history.file_path == path).unwrap_or(false)
+        });
+        if let Some(index) = open {
+            self.switch_buffer(index);
+            return true;
+        }
+        if self.history.file_path == path {
+            return true;
+        }
+
+        if self.modified || !self.document_text().trim().is_empty() {
+            self.new_buffer();
+        }
+        self.close_split();
+
+        false
+    }
+
+    /// Resumes a session in its own buffer
+    fn open_session(&mut self, session: Session) -> Result<()> {
+        if self.focus_buffer(&session.path.to_string_lossy()) {
+            return Ok(());
+        }
+
+        self.history = History::new(session)?;
+        self.open_file()
+    }
+
+    /// Opens a file given on the command line instead of the new session.
+    /// One that doesn't exist yet starts empty and is created on save.
+    pub fn open_path(&mut self, path: &str) -> Result<()> {
+        self.history = self.history.with_path(path);
+        self.buffer = Rope::new();
+        self.protected.clear();
+        self.folds.clear();
+        self.cursor_row = 0;
+        self.cursor_col = 0;
+        self.modified = false;
+        self.read_only = false;
+        self.scratch = false;
+        self.encoding = Encoding::default();
+        self.line_ending = LineEnding::default();
+
+        if Path::new(path).exists() {
+            // Streams in through poll_file_load, like a file from the picker
+            self.async_handler.load_file(path.to_string());
+        }
+        self.track_file();
+
+        Ok(())
+    }
+
+    /// Resumes the session `name` from the command line: a path, or a file
+    /// name in the sessions directory with or without `.md`
+    pub fn resume_session(&mut self, name: &str) -> Result<()> {
+        let mut path = PathBuf::from(name);
+        if !path.exists() {
+            path = session::dir().join(name);
+            if path.extension().is_none() {
+                path.set_extension("md");
+            }
+        }
+        if !path.exists() {
+            return Err(format!("No session {}", name).into());
+        }
+
+        self.open_session(Session::open(&path)?)
+    }
+
+    /// Writes swap files of the unsaved buffers when the interval has passed
+    pub fn autosave(&mut self) {
+        if !self.autosave.is_due() {
+            return;
+        }
+
+        // Best effort, a failed write must not interrupt typing
+        if self.modified && !self.scratch {
+            let _ = autosave::write(&self.history.file_path, &self.document_text());
+        }
+        for state in self.buffers.iter().flatten() {
+            if state.modified && !state.scratch {
+                let _ = autosave::write(&state.history.file_path, &state.buffer.to_string());
+            }
+        }
+    }
+
+    /// Removes the swap files on a clean exit
+    pub fn discard_swaps(&self) {
+        autosave::remove(&self.history.file_path);
+        for state in self.buffers.iter().flatten() {
+            autosave::remove(&state.history.file_path);
+        }
+    }
+
+    /// Looks for swap files of a previous run and asks to restore them
+    pub fn check_swap_files(&mut self) -> Result<()> {
+        self.swaps = autosave::leftovers()?;
+        self.ask_next_swap();
+
+        Ok(())
+    }
+
+    fn ask_next_swap(&mut self) {
+        while !self.swaps.is_empty() {
+            let swap = self.swaps.remove(0);
+            let Ok((path, content)) = autosave::read(&swap) else {
+                autosave::remove_swap(&swap);
+                continue;
+            };
+
+            self.confirm = Some(Confirm::new(
+                "Restore unsaved work?",
+                vec![path, format!("{} lines", content.lines().count())],
+                "y: Restore | n: Discard | Esc: Ask next time",
+                ConfirmAction::RestoreSwap(swap),
+            ));
+            return;
+        }
+    }
+
+    /// Takes the active file as it's on disk now and watches it for
+    /// changes from outside
+    fn track_file(&mut self) {
+        let path = PathBuf::from(&self.history.file_path);
+        self.watcher.remember(&path);
+        self.watcher.watch(&path);
+        self.lock_file();
+    }
+
+    // The file behind a buffer on disk, if there is one yet. Files of the
+    // load picker are named relative to the history directory.
+    fn disk_path(history: &History) -> Option<PathBuf> {
+        let path = PathBuf::from(&history.file_path);
+        [Path::new(&history.root).join(&path), path]
+            .into_iter()
+            .find(|path| path.is_file())
+    }
+
+    /// Locks the active file against other rusty_ai instances, and lets go
+    /// of the files no longer open. When another one has the file, asks
+    /// whether to view it read-only.
+    fn lock_file(&mut self) {
+        let open: Vec<PathBuf> = self
+            .buffers
+            .iter()
+            .flatten()
+            .map(|state| (&state.history, state.read_only))
+            .chain([(&self.history, self.read_only)])
+            .filter(|(_, read_only)| !read_only)
+            .filter_map(|(history, _)| Self::disk_path(history))
+            .collect();
+        self.locks.retain(&open);
+        let Some(path) = Self::disk_path(&self.history).filter(|_| !self.read_only) else {
+            return;
+        };
+
+        match self.locks.acquire(&path) {
+            Ok(None) => {}
+            Ok(Some(pid)) if self.confirm.is_none() => {
+                self.confirm = Some(Confirm::new(
+                    "Open in another rusty_ai",
+                    vec![
+                        path.display().to_string(),
+                        format!("Process {} has it, saves of both overwrite each other", pid),
+                    ],
+                    "y: View read-only | n: Edit anyway",
+                    ConfirmAction::OpenedElsewhere,
+                ));
+            }
+            Ok(Some(pid)) => self.set_message(
+                Level::Warning,
+                format!("{} is open in process {}, File menu: R views it read-only", path.display(), pid),
+            ),
+            Err(e) => self.set_message(Level::Warning, format!("Can't lock {}: {}", path.display(), e)),
+        }
+    }
+
+    /// Asks what to do when the open file changed on disk. Waits while
+    /// another popup or a load is in the way.
+    pub fn poll_file_watcher(&mut self) -> bool {
+        if !self.watcher.poll() || self.confirm.is_some() || self.diff.is_some() || self.is_loading() {
+            return false;
+        }
+        self.watcher.dismiss();
+
+        let path = self.history.file_path.clone();
+        if !Path::new(&path).exists() {
+            self.set_message(Level::Warning, format!("{} was deleted, saving writes it again", path));
+            return true;
+        }
+        let mut lines = vec![path];
+        if self.modified {
+            lines.push("Reloading drops the unsaved changes".to_string());
+        }
+        self.confirm = Some(Confirm::new(
+            "File changed on disk",
+            lines,
+            "r: Reload | k: Keep buffer | d: Diff | Esc: Ask next time",
+            ConfirmAction::ReloadFile,
+        ));
+        true
+    }
+
+    /// Reads the active file again, the cursor stays on its line
+    fn reload_file(&mut self) -> Result<()> {
+        let (line, column) = (self.cursor_row + 1, Some(self.cursor_col + 1));
+        let read_only = self.read_only;
+        autosave::remove(&self.history.file_path);
+        // A checkout may have moved HEAD too
+        self.git_head = None;
+        self.open_file()?;
+        self.read_only = read_only;
+
+        if self.is_loading() {
+            self.pending_position = Some((line, column));
+        } else {
+            self.goto_position(line, column);
+        }
+        self.set_message(Level::Info, format!("Reloaded {}", self.history.file_path));
+        Ok(())
+    }
+
+    /// Opens the content of a swap file as an unsaved buffer of its file
+    fn restore_swap(&mut self, swap: &Path) -> Result<()> {
+        let (path, content) = autosave::read(swap)?;
+
+        if !self.focus_buffer(&path) {
+            self.history = if Path::new(&path).starts_with(session::dir()) {
+                History::new(Session::open(Path::new(&path))?)?
+            } else {
+                self.history.with_path(&path)
+            };
+            // Saving goes back to the file as it is on disk, a new one as
+            // UTF-8 with LF
+            let (encoding, line_ending) = match encoding::read(Path::new(&path)) {
+                Ok((text, encoding)) => (encoding, line_ending::normalize(&text).1),
+                Err(_) => (Encoding::default(), LineEnding::default()),
+            };
+            self.encoding = encoding;
+            self.line_ending = line_ending;
+            self.read_only = false;
+            self.scratch = false;
+        }
+
+        self.buffer = Rope::from_str(&content);
+        self.protected.clear();
+        self.folds.clear();
+        self.cursor_row = 0;
+        self.cursor_col = 0;
+        self.modified = true;
+        self.update_syntax_highlighting();
+
+        autosave::remove_swap(swap);
+        Ok(())
+    }
+
+    /// Renames a file of the load picker; the open buffer follows it
+    fn rename_file(&mut self, from: &str, to: &str) {
+        let root = Path::new(&self.history.root);
+        match files::rename_file(&root.join(from), &root.join(to)) {
+            Ok(()) if self.history.file_path == from => {
+                self.history.file_path = to.to_string();
+                self.track_file();
+            }
+            Ok(()) => {}
+            Err(e) => {
+                self.set_message(Level::Error, format!("Can't rename {}: {}", from, e));
+            }
+        }
+    }
+
+    /// Deletes a file of the load picker. A buffer showing it keeps the text
+    /// as unsaved changes.
+    fn delete_file(&mut self, name: &str) {
+        let path = Path::new(&self.history.root).join(name);
+        let deleted = files::delete_file(&path)
+            .and_then(|_| self.menu_status.file_picker.reload_finder());
+
+        match deleted {
+            Ok(()) if self.history.file_path == name => self.modified = true,
+            Ok(()) => {}
+            Err(e) => {
+                self.set_message(Level::Error, format!("Can't delete {}: {}", name, e));
+            }
+        }
+    }
+
+    fn back_to_load_picker(&mut self) {
+        if let Err(e) = self.menu_status.file_picker.back_to_finder() {
+            self.set_message(Level::Error, format!("Can't list files: {}", e));
+        }
+    }
+
+    /// Typing in the fuzzy finder of the Load and Attach pickers
+    fn handle_finder_query(&mut self, key: KeyCode) {
+        let picker = &mut self.menu_status.file_picker;
+        match key {
+            KeyCode::Char(c) => picker.insert_char(c),
+            KeyCode::Backspace if picker.is_input_empty() => return picker.finder_back(),
+            KeyCode::Backspace => picker.delete_previous_char(),
+            KeyCode::Delete => picker.delete_current_char(),
+            KeyCode::Left => return picker.move_cursor_pos_left(),
+            KeyCode::Right => return picker.move_cursor_pos_right(),
+            _ => return,
+        }
+
+        picker.refresh();
+    }
+
+    /// Keys of a yes/no popup. Esc leaves the question for later.
+    fn handle_confirm(&mut self, key: KeyCode) -> Result<bool> {
+        let Some(confirm) = self.confirm.take() else {
+            return Ok(false);
+        };
+
+        match (key, confirm.action) {
+            (KeyCode::Char('y'), ConfirmAction::RestoreSwap(swap)) => self.restore_swap(&swap)?,
+            (KeyCode::Char('n'), ConfirmAction::RestoreSwap(swap)) => autosave::remove_swap(&swap),
+            (KeyCode::Char('y'), ConfirmAction::RunCommand { command, insert_at }) => {
+                self.run_command(command, insert_at)
+            }
+            (KeyCode::Char('n'), ConfirmAction::RunCommand { .. }) => {}
+            (KeyCode::Char('y'), ConfirmAction::SaveApiKey { model, key }) => {
+                if let Err(e) = credentials::save(&model, &key) {
+                    self.set_message(Level::Error, format!("Can't save API key: {}", e));
+                }
+            }
+            (KeyCode::Char('n'), ConfirmAction::SaveApiKey { .. }) => {}
+            (KeyCode::Char('y'), ConfirmAction::DeleteFile(name)) => self.delete_file(&name),
+            (KeyCode::Char('n'), ConfirmAction::DeleteFile(_)) => {}
+            (KeyCode::Char('r'), ConfirmAction::ReloadFile) => self.reload_file()?,
+            (KeyCode::Char('k'), ConfirmAction::ReloadFile) => {
+                // The buffer no longer matches the file, a viewer can't save it
+                self.watcher.remember(Path::new(&self.history.file_path));
+                self.modified |= !self.read_only;
+            }
+            (KeyCode::Char('s'), ConfirmAction::Quit) => {
+                self.save_all()?;
+                return Ok(true);
+            }
+            (KeyCode::Char('d'), ConfirmAction::Quit) => return Ok(true),
+            (KeyCode::Char('w'), ConfirmAction::QuitDuringRequest) => {
+                self.quit_after_request = true;
+                self.set_message(Level::Info, "Quitting once the answer is in, Ctrl+Q asks again");
+            }
+            (KeyCode::Char('c'), ConfirmAction::QuitDuringRequest) => {
+                self.cancel_request(true);
+                return Ok(self.request_quit());
+            }
+            (KeyCode::Char('d'), ConfirmAction::QuitDuringRequest) => {
+                self.detached = true;
+                return Ok(self.confirm_unsaved(self.answer_buffer()));
+            }
+            (KeyCode::Char('c'), ConfirmAction::WipeDuringRequest) => {
+                self.cancel_request(true);
+                self.wipe_buffer()?;
+            }
+            (KeyCode::Char('c'), ConfirmAction::Quit) => {}
+            (KeyCode::Char('s'), ConfirmAction::CloseBuffer) => {
+                self.save_file()?;
+                self.discard_buffer();
+            }
+            (KeyCode::Char('d'), ConfirmAction::CloseBuffer) => self.discard_buffer(),
+            (KeyCode::Char('c'), ConfirmAction::CloseBuffer) => {}
+            (KeyCode::Char('y'), ConfirmAction::OpenedElsewhere) => self.set_read_only(true),
+            (KeyCode::Char('n'), ConfirmAction::OpenedElsewhere) => {
+                if let Some(path) = Self::disk_path(&self.history) {
+                    self.locks.take_over(&path)?;
+                }
+            }
+            (KeyCode::Char('d'), ConfirmAction::ReloadFile) => {
+                self.open_diff()?;
+                // Asked again once the diff is closed
+                self.watcher.ask_again();
+            }
+            (KeyCode::Esc, _) => {}
+            (_, action) => {
+                // Not an answer, keep asking
+                self.confirm = Some(Confirm { action, ..confirm });
+                return Ok(false);
+            }
+        }
+
+        self.ask_next_swap();
+        Ok(false)
+    }
+
+    pub fn confirm_prompt(&self) -> Option<&Confirm> {
+        self.confirm.as_ref()
+    }
+
+    /// Diffs the buffer against the file a save would overwrite
+    fn open_diff(&mut self) -> Result<()> {
+        // Never saved, so everything is new
+        let saved = match self.history.content() {
+            Ok((content, _)) => line_ending::normalize(&content).0,
+            Err(Error::Io(e)) if e.kind() == ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+
+        let title = format!("Diff {} (saved -> buffer)", BufferState::name(&self.history));
+        self.diff = Some(DiffView::new(&title, &saved, &self.buffer.to_string()));
+
+        Ok(())
+    }
+
+    /// Keys of the diff popup, which only scrolls
+    fn handle_diff(&mut self, key: KeyCode) -> Result<bool> {
+        let Some(diff) = &mut self.diff else {
+            return Ok(false);
+        };
+
+        match key {
+            KeyCode::Char('j') | KeyCode::Down => diff.scroll_down(1),
+            KeyCode::Char('k') | KeyCode::Up => diff.scroll_up(1),
+            KeyCode::PageDown | KeyCode::Char(' ') => diff.page_down(),
+            KeyCode::PageUp => diff.page_up(),
+            KeyCode::Char('g') | KeyCode::Home => diff.scroll_up(usize::MAX),
+            KeyCode::Char('G') | KeyCode::End => diff.scroll_down(usize::MAX),
+            KeyCode::Esc | KeyCode::Char('q') => self.diff = None,
+            _ => {}
+        }
+
+        Ok(false)
+    }
+
+    pub fn diff_view_mut(&mut self) -> Option<&mut DiffView> {
+        self.diff.as_mut()
+    }
+
+    /// Text of the last answer, from below its `## Assistant` header to the
+    /// next header
+    fn last_answer(&self) -> Option<String> {
+        let conversation = self.conversation().to_string();
+        let lines: Vec<&str> = conversation.lines().collect();
+        let header = lines
+            .iter()
+            .rposition(|line| transcript::role(line) == Some("assistant"))?;
+        let answer: Vec<&str> = lines[header + 1..]
+            .iter()
+            .take_while(|line| transcript::role(line).is_none())
+            .copied()
+            .collect();
+        Some(answer.join("\n"))
+    }
+
+    /// Previews the unified diff in `text`: its ```diff blocks, or the text
+    /// itself when it has none
+    fn open_patch(&mut self, text: &str) {
+        let blocks: Vec<String> = match &self.syntax_highlighter {
+            Some(highlighter) => highlighter
+                .extract_code_blocks(text)
+                .into_iter()
+                .filter(|block| matches!(block.language.as_str(), "diff" | "patch"))
+                .map(|block| block.code)
+                .collect(),
+            None => Vec::new(),
+        };
+        let diff = if blocks.is_empty() {
+            text.to_string()
+        } else {
+            blocks.join("\n")
+        };
+
+        match patch::parse(&diff) {
+            Ok(hunks) => self.patch = Some(patch::PatchPreview::new(hunks)),
+            Err(e) => {
+                self.set_message(Level::Error, e.to_string());
+            }
+        }
+    }
+
+    /// Keys of the patch preview: pick hunks, then write them or cancel
+    fn handle_patch(&mut self, key: KeyCode) -> Result<bool> {
+        let Some(patch) = &mut self.patch else {
+            return Ok(false);
+        };
+
+        match key {
+            KeyCode::Char('j') | KeyCode::Down => patch.select_next(),
+            KeyCode::Char('k') | KeyCode::Up => patch.select_previous(),
+            KeyCode::Char('y') => patch.decide(true),
+            KeyCode::Char('n') => patch.decide(false),
+            KeyCode::Char('a') => patch.set_all(true),
+            KeyCode::Char('r') => patch.set_all(false),
+            KeyCode::Enter => {
+                let unsaved: Vec<PathBuf> = self
+                    .buffers
+                    .iter()
+                    .map(|slot| match slot {
+                        Some(state) => (&state.history, state.modified),
+                        None => (&self.history, self.modified),
+                    })
+                    .filter(|(_, modified)| *modified)
+                    .filter_map(|(history, _)| Self::disk_path(history))
+                    .filter_map(|path| fs::canonicalize(path).ok())
+                    .collect();
+                let written = patch.write(&unsaved);
+                self.patch = None;
+                match written {
+                    // The open file shows the change unless it has edits of its own
+                    Ok(paths) if paths.contains(&self.history.file_path) && !self.modified => {
+                        self.open_file()?;
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        self.set_message(Level::Error, format!("Diff not applied: {}", e));
+                    }
+                }
+            }
+            KeyCode::Esc | KeyCode::Char('q') => self.patch = None,
+            _ => {}
+        }
+
+        Ok(false)
+    }
+
+    pub fn patch_preview_mut(&mut self) -> Option<&mut patch::PatchPreview> {
+        self.patch.as_mut()
+    }
+
+    /// Keys of the request log popup, which only scrolls
+    fn handle_log_view(&mut self, key: KeyCode) -> Result<bool> {
+        let Some(view) = &mut self.log_view else {
+            return Ok(false);
+        };
+
+        match key {
+            KeyCode::Char('j') | KeyCode::Down => view.scroll_down(1),
+            KeyCode::Char('k') | KeyCode::Up => view.scroll_up(1),
+            KeyCode::PageDown | KeyCode::Char(' ') => view.page_down(),
+            KeyCode::PageUp => view.page_up(),
+            KeyCode::Char('g') | KeyCode::Home => view.scroll_up(usize::MAX),
+            KeyCode::Char('G') | KeyCode::End => view.scroll_to_end(),
+            KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('L') => self.log_view = None,
+            _ => {}
+        }
+
+        Ok(false)
+    }
+
+    /// Keys of the message history popup
+    fn handle_message_view(&mut self, key: KeyCode) -> Result<bool> {
+        let Some(view) = &mut self.message_view else {
+            return Ok(false);
+        };
+
+        match key {
+            KeyCode::Char('j') | KeyCode::Down => view.scroll_down(1),
+            KeyCode::Char('k') | KeyCode::Up => view.scroll_up(1),
+            KeyCode::PageDown | KeyCode::Char(' ') => view.page_down(),
+            KeyCode::PageUp => view.page_up(),
+            KeyCode::Char('g') | KeyCode::Home => view.scroll_up(usize::MAX),
+            KeyCode::Char('G') | KeyCode::End => view.scroll_to_end(),
+            KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('m') => self.message_view = None,
+            _ => {}
+        }
+
+        Ok(false)
+    }
+
+    /// Keys of the usage popup
+    fn handle_usage_view(&mut self, key: KeyCode) -> Result<bool> {
+        let Some(view) = &mut self.usage_view else {
+            return Ok(false);
+        };
+
+        match key {
+            KeyCode::Char('j') | KeyCode::Down => view.scroll_down(1),
+            KeyCode::Char('k') | KeyCode::Up => view.scroll_up(1),
+            KeyCode::PageDown | KeyCode::Char(' ') => view.page_down(),
+            KeyCode::PageUp => view.page_up(),
+            KeyCode::Char('g') | KeyCode::Home => view.scroll_up(usize::MAX),
+            KeyCode::Char('G') | KeyCode::End => view.scroll_to_end(),
+            KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('U') => self.usage_view = None,
+            _ => {}
+        }
+
+        Ok(false)
+    }
+
+    /// Keys of the key binding popup
+    fn handle_help_view(&mut self, key: KeyCode) -> Result<bool> {
+        let Some(view) = &mut self.help_view else {
+            return Ok(false);
+        };
+
+        match key {
+            KeyCode::Char('j') | KeyCode::Down => view.scroll_down(1),
+            KeyCode::Char('k') | KeyCode::Up => view.scroll_up(1),
+            KeyCode::PageDown | KeyCode::Char(' ') => view.page_down(),
+            KeyCode::PageUp => view.page_up(),
+            KeyCode::Char('g') | KeyCode::Home => view.scroll_up(usize::MAX),
+            KeyCode::Char('G') | KeyCode::End => view.scroll_to_end(),
+            KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('?') => self.help_view = None,
+            _ => {}
+        }
+
+        Ok(false)
+    }
+
+    /// Keys of the markdown preview. Closing it keeps the cursor on what
+    /// was on screen.
+    fn handle_preview(&mut self, key: KeyCode, modifiers: KeyModifiers) -> Result<bool> {
+        let Some(preview) = &mut self.preview else {
+            return Ok(false);
+        };
+
+        match key {
+            KeyCode::Char('j') | KeyCode::Down => preview.scroll_down(1),
+            KeyCode::Char('k') | KeyCode::Up => preview.scroll_up(1),
+            KeyCode::PageDown | KeyCode::Char(' ') => preview.page_down(),
+            KeyCode::PageUp => preview.page_up(),
+            KeyCode::Char('g') | KeyCode::Home => preview.scroll_up(usize::MAX),
+            KeyCode::Char('G') | KeyCode::End => preview.scroll_down(usize::MAX),
+            KeyCode::Esc | KeyCode::Char('q') => self.close_preview(),
+            _ if keymap::command(Context::Normal, key, modifiers) == Some(Command::Preview) => {
+                self.close_preview()
+            }
+            _ => {}
+        }
+
+        Ok(false)
+    }
+
+    fn close_preview(&mut self) {
+        let Some(preview) = self.preview.take() else {
+            return;
+        };
+        let (first, last) = preview.shown();
+        if !(first..=last).contains(&self.cursor_row) {
+            self.cursor_row = first;
+            self.cursor_col = 0;
+            self.clamp_cursor();
+        }
+    }
+
+    /// Scroll state of the markdown preview while it's open
+    pub fn preview_mut(&mut self) -> Option<&mut preview::Preview> {
+        self.preview.as_mut()
+    }
+
+    pub fn is_previewing(&self) -> bool {
+        self.preview.is_some()
+    }
+
+    /// Highlighter styles of a buffer line, without the selection or
+    /// search matches on top
+    pub fn syntax_styles(&self, row: usize) -> Vec<Style> {
+        let start = self.buffer.line_to_char(row);
+        let len = self.buffer.line(row).len_chars();
+        let mut styles = vec![Style::Normal; len];
+
+        for (range, style) in &self.syntax_highlights {
+            if range.end <= start || range.start >= start + len {
+                continue;
+            }
+            for i in range.start.max(start)..range.end.min(start + len) {
+                if styles[i - start] == Style::Normal {
+                    styles[i - start] = *style;
+                }
+            }
+        }
+
+        styles
+    }
+
+    /// Shows `text` on the message line until it expires. It's kept in the
+    /// message history (File menu: m).
+    pub fn set_message(&self, level: Level, text: impl Into<String>) {
+        if let Ok(mut state) = self.shared_state.lock() {
+            state.messages.push(level, text);
+        }
+    }
+
+    /// Message for the bottom line, if one is showing
+    pub fn current_message(&self) -> Option<Message> {
+        let state = self.shared_state.lock().ok()?;
+        state.messages.current().cloned()
+    }
+
+    /// Whether a message appeared or expired since the last call, so the
+    /// idle loop knows to draw
+    pub fn poll_messages(&mut self) -> bool {
+        match self.shared_state.lock() {
+            Ok(mut state) => state.messages.changed(),
+            Err(_) => false,
+        }
+    }
+
+    /// Scroll state of the message history popup while it's open
+    pub fn message_view_mut(&mut self) -> Option<&mut log_view::LogView> {
+        self.message_view.as_mut()
+    }
+
+    /// All messages, oldest first
+    pub fn message_history(&self) -> Vec<Message> {
+        match self.shared_state.lock() {
+            Ok(state) => state.messages.history(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    fn open_message_view(&mut self) {
+        // Already on screen in the popup
+        if let Ok(mut state) = self.shared_state.lock() {
+            state.messages.dismiss();
+        }
+        self.message_view = Some(log_view::LogView::new());
+    }
+
+    /// Scroll state of the key binding popup while it's open
+    pub fn help_view_mut(&mut self) -> Option<&mut log_view::LogView> {
+        self.help_view.as_mut()
+    }
+
+    /// Scroll state of the usage popup while it's open
+    pub fn usage_view_mut(&mut self) -> Option<&mut log_view::LogView> {
+        self.usage_view.as_mut()
+    }
+
+    /// Lines of the usage popup: estimated tokens and cost per provider
+    pub fn usage_lines(&self) -> Vec<String> {
+        match self.shared_state.lock() {
+            Ok(state) => state.usage.lines(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// Scroll state of the request log popup while it's open
+    pub fn log_view_mut(&mut self) -> Option<&mut log_view::LogView> {
+        self.log_view.as_mut()
+    }
+
+    /// Request log, oldest first
+    pub fn log_entries(&self) -> Vec<LogEntry> {
+        self.async_handler.log().entries()
+    }
+
+    fn switch_buffer(&mut self, index: usize) {
+        if index == self.active_buffer || self.load_blocks() {
+            return;
+        }
+
+        let Some(next) = self.buffers.get_mut(index).and_then(Option::take) else {
+            return;
+        };
+
+        let current = self.take_active_state();
+        self.buffers[self.active_buffer] = Some(current);
+
+        self.active_buffer = index;
+        self.restore_state(next);
+    }
+
+    fn cycle_buffer(&mut self, forward: bool) -> Result<bool> {
+        let count = self.buffers.len();
+        let index = if forward {
+            (self.active_buffer + 1) % count
+        } else {
+            (self.active_buffer + count - 1) % count
+        };
+
+        self.switch_buffer(index);
+
+        Ok(false)
+    }
+
+    /// Closes the active buffer, asking first when that loses changes or
+    /// the text of a scratch buffer. The last buffer stays open.
+    fn close_buffer(&mut self) {
+        if self.buffers.len() <= 1 {
+            return;
+        }
+
+        let name = BufferState::name(&self.history);
+        let (line, hint) = if self.scratch {
+            let has_text = self.buffer.chars().any(|c| !c.is_whitespace());
+            (has_text.then(|| format!("{} (scratch, not saved)", name)), "d: Discard | c: Cancel")
+        } else {
+            (self.modified.then_some(name), "s: Save and close | d: Discard | c: Cancel")
+        };
+        match line {
+            Some(line) => {
+                self.confirm = Some(Confirm::new(
+                    "Close with unsaved changes?",
+                    vec![line],
+                    hint,
+                    ConfirmAction::CloseBuffer,
+                ))
+            }
+            None => self.discard_buffer(),
+        }
+    }
+
+    // Closes the active buffer without saving
+    fn discard_buffer(&mut self) {
+        // Its changes are dropped on purpose, nothing to restore later
+        autosave::remove(&self.history.file_path);
+        self.split = None;
+        self.buffers.remove(self.active_buffer);
+        let index = self.active_buffer.min(self.buffers.len() - 1);
+
+        if let Some(next) = self.buffers[index].take() {
+            self.active_buffer = index;
+            self.restore_state(next);
+        }
+    }
+
+    /// Name and modified flag of every open buffer, in tab order
+    pub fn buffer_tabs(&self) -> Vec<(String, bool)> {
+        self.buffers
+            .iter()
+            .map(|slot| match slot {
+                Some(state) => (BufferState::name(&state.history), state.modified),
+                None => (BufferState::name(&self.history), self.modified),
+            })
+            .collect()
+    }
+
+    pub fn active_buffer_index(&self) -> usize {
+        self.active_buffer
+    }
+
+    // Get the current request state
+    pub fn get_request_state(&self) -> RequestState {
+        match self.shared_state.lock() {
+            Ok(state) => state.request_state.clone(),
+            Err(_) => RequestState::Error("Failed to lock state".to_string()),
+        }
+    }
+
+    // New method to check and process any API responses
+    /// Applies what arrived from the request in flight. Returns whether
+    /// anything did, so the screen needs drawing.
+    pub fn check_api_responses(&mut self) -> bool {
+        // Only check if we need to
+        if !self.needs_response_check {
+            return false;
+        }
+
+        // Create a variable to store the response we'll process
+        let (response_to_process, finished) = {
+            // Scope the lock to this block only
+            if let Ok(mut state) = self.shared_state.lock() {
+                // Take the response if available
+                (
+                    state.api_response.take(),
+                    state.request_state != RequestState::Proccessing,
+                )
+            } else {
+                (None, false)
+            }
+        }; // Lock is released here when the block ends
+
+        // Read after the state, a finished stream has sent its last batch
+        let streamed = self.async_handler.stream_batches();
+        if !streamed.is_empty() {
+            self.append_answer(&streamed);
+        }
+
+        // Try to lock the shared state
+        if let Some(response) = response_to_process {
+            // If there was an error, we've already set the request state
+            if response.error.is_none() && !response.content.is_empty() {
+                // Add the response to the end of the buffer
+                self.append_answer(&response.content);
+            }
+
+            // We've processed the response, no need to check again
+            self.needs_response_check = false;
+        } else if finished {
+            // Stream ended and every token has been appended
+            self.needs_response_check = false;
+        }
+
+        // Answer is complete, the next queued request may run
+        let done = !self.needs_response_check;
+        if done {
+            // Positions, fixes and titles are about the active buffer
+            let active = self.file_answer.is_none() && matches!(self.answer_target(), AnswerTarget::Active);
+            // A failed request has nothing to write
+            if let Some(answer) = self.file_answer.take().filter(|answer| !answer.text.is_empty()) {
+                self.append_to_file(answer);
+            }
+            if let Some(edit) = self.inline_edit.take() {
+                self.finish_inline_edit(edit);
+            } else if active {
+                self.remember_answer();
+            }
+            if std::mem::take(&mut self.fix_pending) && active {
+                self.offer_fix();
+            }
+            if active {
+                self.request_title();
+            }
+            self.async_handler.finish_current();
+            self.start_next_request();
+            self.needs_response_check = self.async_handler.has_pending();
+            if !self.needs_response_check && std::mem::take(&mut self.quit_after_request) {
+                self.quit = self.request_quit();
+            }
+        }
+
+        done || !streamed.is_empty()
+    }
+
+    /// Keeps where the answer that just finished is, with its prompt
+    fn remember_answer(&mut self) {
+        let (Some(start), Some(prompt)) = (self.response_start, self.async_handler.running_prompt()) else {
+            return;
+        };
+        let end = match &self.split {
+            Some(split) => split.transcript().len_chars(),
+            None => self.response_at.unwrap_or(start),
+        };
+
+        // Failed requests leave nothing to regenerate
+        if start < end {
+            self.last_answer = Some((start..end, prompt.to_string()));
+        }
+    }
+
+    /// Removes the latest answer and sends its prompt again, to the model
+    /// and with the parameters picked now. The new answer takes its place.
+    fn regenerate_answer(&mut self) {
+        if self.async_handler.has_pending() {
+            self.set_message(Level::Warning, "Wait for the running requests to finish");
+            return;
+        }
+        // Nothing came back in time, ask again
+        if matches!(self.get_request_state(), RequestState::TimedOut(_)) {
+            self.retry_request();
+            return;
+        }
+        let Some((range, prompt)) = self.last_answer.take() else {
+            self.set_message(Level::Warning, "No answer to regenerate");
+            return;
+        };
+
+        // Still the answer it was: inside the conversation, with its header
+        // and read-only marking
+        let conversation = self.conversation();
+        let protected = match &self.split {
+            Some(split) => split.protected(),
+            None => &self.protected,
+        };
+        let intact = range.end <= conversation.len_chars() && {
+            let answer = conversation.slice(range.clone()).to_string();
+            let header = answer.trim_start_matches('\n');
+            let blank = answer.len() - header.len();
+            header.lines().next().and_then(transcript::role) == Some("assistant")
+                && protected.contains(range.start + blank)
+        };
+        if !intact {
+            self.set_message(Level::Warning, "The last answer was edited, can't regenerate it");
+            return;
+        }
+
+        match &mut self.split {
+            Some(split) if range.end == split.transcript().len_chars() => split.truncate(range.start),
+            Some(_) => {
+                self.set_message(Level::Warning, "The answer isn't the last one anymore");
+                return;
+            }
+            None => {
+                self.record_edit(range.clone(), "");
+                self.buffer.remove(range.clone());
+                self.clamp_cursor();
+                self.update_syntax_highlighting();
+                self.modified = true;
+            }
+        }
+
+        let model = self.chat_context.model.clone();
+        self.async_handler
+            .enqueue(prompt, model, self.stream_responses, None, self.origin());
+        self.start_next_request();
+        if self.split.is_none() {
+            self.response_start = Some(range.start);
+            self.response_at = Some(range.start);
+        }
+        self.needs_response_check = true;
+    }
+
+    /// Asks for an instruction to rewrite the selection with, or the code
+    /// block or paragraph under the cursor without one
+    fn start_inline_edit(&mut self) {
+        if self.async_handler.has_pending() {
+            self.set_message(Level::Warning, "Wait for the running requests to finish");
+            return;
+        }
+        let Some(range) = self.edit_target(false) else {
+            self.set_message(Level::Warning, "Nothing to rewrite here");
+            return;
+        };
+        if self.edit_blocked(range.clone()) {
+            return;
+        }
+
+        self.inline_edit = Some(inline_edit::InlineEdit {
+            origin: self.origin(),
+            original: self.buffer.slice(range.clone()).to_string(),
+            range,
+            answer: String::new(),
+            placement: inline_edit::Placement::Replace,
+        });
+        self.menu_status.file_picker.init_inline_edit();
+    }
+
+    // The selection, else the code block or paragraph under the cursor,
+    // unless it's blank. With `item` the function, struct or similar
+    // definition under the cursor is taken instead of the code block.
+    fn edit_target(&self, item: bool) -> Option<Range<usize>> {
+        let cursor = self.get_char_idx();
+        let code = || {
+            if item {
+                self.item_at(cursor)
+            } else {
+                textobject::range(&self.buffer, cursor, textobject::Scope::Inner, 'c')
+            }
+        };
+        self.get_selection_range()
+            .filter(|range| !range.is_empty())
+            .or_else(code)
+            .or_else(|| textobject::range(&self.buffer, cursor, textobject::Scope::Inner, 'p'))
+            .filter(|range| self.buffer.slice(range.clone()).chars().any(|c| !c.is_whitespace()))
+    }
+
+    // Chars of the innermost definition around `char_idx`, from the parse
+    // tree of its code block
+    fn item_at(&self, char_idx: usize) -> Option<Range<usize>> {
+        let byte = self.buffer.char_to_byte(char_idx);
+        let range = self.syntax_highlighter.as_ref()?.item_range(byte)?;
+        Some(self.buffer.byte_to_char(range.start)..self.buffer.byte_to_char(range.end))
+    }
+
+    // Language of the code block `char_idx` is in, else of a source file
+    // by its extension
+    fn language_at(&self, char_idx: usize) -> Option<String> {
+        let row = self.buffer.char_to_line(char_idx);
+        if let Some(block) = self.code_block_at(row) {
+            return Some(block.language).filter(|language| !language.is_empty());
+        }
+        Path::new(&self.history.file_path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .filter(|extension| !matches!(*extension, "md" | "markdown" | "txt"))
+            .map(str::to_string)
+    }
+
+    /// Explains the selection, or the code block or paragraph under the
+    /// cursor, or writes the doc comment above or unit tests below the
+    /// selection, or the definition or paragraph under the cursor
+    fn start_assist(&mut self, assist: assist::Assist) {
+        if self.async_handler.has_pending() {
+            self.set_message(Level::Warning, "Wait for the running requests to finish");
+            return;
+        }
+        // A doc comment or tests are about one definition, not a whole block
+        let Some(mut range) = self.edit_target(assist.placement().is_some()) else {
+            self.set_message(Level::Warning, "No code here");
+            return;
+        };
+        let language = self.language_at(range.start);
+        let content = assist.prompt(&self.buffer.slice(range.clone()).to_string(), language.as_deref());
+        let model = self.chat_context.model.clone();
+        let origin = self.origin();
+
+        match assist.placement() {
+            // The answer comes into the conversation like any other
+            None => self
+                .async_handler
+                .enqueue(content, model, self.stream_responses, None, origin),
+            Some(placement) => {
+                // Whole lines, the answer goes above or below them
+                let first = self.buffer.char_to_line(range.start);
+                let last = self.buffer.char_to_line(range.end.saturating_sub(1).max(range.start));
+                range = self.buffer.line_to_char(first)..self.buffer.line_to_char(last + 1);
+                let at = match placement {
+                    inline_edit::Placement::Below => range.end..range.end,
+                    _ => range.start..range.start,
+                };
+                if self.edit_blocked(at) {
+                    return;
+                }
+                self.inline_edit = Some(inline_edit::InlineEdit {
+                    origin: origin.clone(),
+                    original: self.buffer.slice(range.clone()).to_string(),
+                    range,
+                    answer: String::new(),
+                    placement,
+                });
+                self.async_handler.enqueue(content, model, false, None, origin);
+            }
+        }
+        self.start_next_request();
+        self.needs_response_check = true;
+
+        self.mode = Mode::Normal;
+        self.selection_active = false;
+        self.selection_start = None;
+        self.set_message(Level::Info, assist.progress());
+    }
+
+    /// Sends the text picked by `start_inline_edit` with `instruction`
+    fn send_inline_edit(&mut self, instruction: &str) {
+        let Some(edit) = &self.inline_edit else {
+            return;
+        };
+        // Code is asked for in its language
+        let language = self.language_at(edit.range.start);
+        let content = inline_edit::prompt(instruction, &edit.original, language.as_deref());
+        let origin = edit.origin.clone();
+
+        let model = self.chat_context.model.clone();
+        self.async_handler.enqueue(content, model, false, None, origin);
+        self.start_next_request();
+        self.needs_response_check = true;
+
+        self.mode = Mode::Normal;
+        self.selection_active = false;
+        self.selection_start = None;
+        self.set_message(Level::Info, "Rewriting...");
+    }
+
+    // Puts the answer of a finished inline edit in place of its text, or
+    // above or below it. When the text changed meanwhile, the answer goes
+    // to the default register.
+    fn finish_inline_edit(&mut self, edit: inline_edit::InlineEdit) {
+        // A failed request already said why
+        if edit.answer.trim().is_empty() {
+            return;
+        }
+        let (range, text) = edit.change();
+        let intact = edit.origin == self.origin()
+            && !self.read_only
+            && edit.range.end <= self.buffer.len_chars()
+            && self.buffer.slice(edit.range.clone()) == edit.original.as_str();
+        if !intact {
+            self.registers.store(text);
+            self.set_message(
+                Level::Warning,
+                "The text changed meanwhile, the answer is in the register (p pastes it)",
+            );
+            return;
+        }
+
+        let start = range.start;
+        let replaced = self.buffer.slice(range.clone()).to_string();
+        self.replace_range(range, &text);
+        self.last_rewrite = Some(inline_edit::Rewrite {
+            range: start..start + text.chars().count(),
+            original: replaced,
+            rewritten: text,
+        });
+        let message = match edit.placement {
+            inline_edit::Placement::Replace => "Rewritten (AI menu: W puts the original back)",
+            _ => "Inserted (AI menu: W takes it out again)",
+        };
+        self.set_message(Level::Info, message);
+    }
+
+    /// Puts back the text the last rewrite replaced, unless the rewritten
+    /// text was edited since
+    fn undo_rewrite(&mut self) {
+        let Some(rewrite) = self.last_rewrite.take() else {
+            self.set_message(Level::Warning, "No rewrite to undo");
+            return;
+        };
+        let intact = rewrite.range.end <= self.buffer.len_chars()
+            && self.buffer.slice(rewrite.range.clone()) == rewrite.rewritten.as_str();
+        if !intact {
+            self.set_message(Level::Warning, "The rewrite was edited, can't undo it");
+            return;
+        }
+        if self.edit_blocked(rewrite.range.clone()) {
+            self.last_rewrite = Some(rewrite);
+            return;
+        }
+
+        self.replace_range(rewrite.range, &rewrite.original);
+        self.set_message(Level::Info, "Rewrite undone");
+    }
+
+    // Puts `text` in place of `range` with the cursor at its start
+    fn replace_range(&mut self, range: Range<usize>, text: &str) {
+        let start = range.start;
+        self.record_edit(range.clone(), text);
+        self.buffer.remove(range);
+        self.buffer.insert(start, text);
+        self.set_cursor_to_char_idx(start);
+        self.modified = true;
+        self.update_syntax_highlighting();
+    }
+
+    /// Asks the model to name the session once its first answer is in,
+    /// unless it was renamed already
+    fn request_title(&mut self) {
+        if !config::get().auto_title {
+            return;
+        }
+        let Some(session) = &self.history.session else {
+            return;
+        };
+
+        let conversation = self.document_text();
+        let answers = export::messages(&conversation)
+            .iter()
+            .filter(|message| message.role == "assistant")
+            .count();
+        // Saving names a session after its first question until then
+        let untitled = session.title.is_empty() || session.title == session::title_from(&conversation);
+
+        if answers == 1 && untitled {
+            self.async_handler
+                .generate_title(session.path.clone(), &conversation);
+        }
+    }
+
+    /// Asks for an inline completion once typing paused at the end of a
+    /// line in a code block, and shows the one that came back while the
+    /// cursor is still there. Returns whether there's a new one to draw.
+    pub fn poll_ghost(&mut self) -> bool {
+        let pos = self.get_char_idx();
+        let answer = match self.shared_state.lock() {
+            Ok(mut state) => state.completion.take(),
+            Err(_) => None,
+        };
+        let shown = match answer {
+            Some((id, text)) => self.ghost.receive(id, pos, &text),
+            None => false,
+        };
+
+        let delay = Duration::from_millis(config::get().inline_completion_delay_ms);
+        if self.mode != Mode::Insert || !self.ghost.due(delay) {
+            return shown;
+        }
+        let line = self.line_text(self.cursor_row);
+        if line.chars().skip(self.cursor_col).any(|c| !c.is_whitespace()) {
+            return shown;
+        }
+        let Some(code) = textobject::range(&self.buffer, pos, textobject::Scope::Inner, 'c') else {
+            return shown;
+        };
+
+        let language = self
+            .code_block_at(self.cursor_row)
+            .map(|block| block.language)
+            .unwrap_or_default();
+        let before = self.buffer.slice(code.start..pos).to_string();
+        let after = self.buffer.slice(pos..code.end).to_string();
+        let id = self.ghost.ask(pos);
+        self.async_handler
+            .complete(id, ghost::SYSTEM_PROMPT, ghost::prompt(&language, &before, &after));
+        shown
+    }
+
+    /// Inline completion to draw after the cursor
+    pub fn ghost_text(&self) -> Option<String> {
+        if self.mode != Mode::Insert {
+            return None;
+        }
+        self.ghost.label(self.get_char_idx())
+    }
+
+    /// Gives generated titles to their sessions, open in any buffer.
+    /// Returns whether there were any, for the tab bar.
+    pub fn poll_titles(&mut self) -> bool {
+        let titles = match self.shared_state.lock() {
+            Ok(mut state) => std::mem::take(&mut state.titles),
+            Err(_) => return false,
+        };
+
+        for (path, title) in &titles {
+            let active = self.history.session.as_mut();
+            let others = self
+                .buffers
+                .iter_mut()
+                .flatten()
+                .filter_map(|state| state.history.session.as_mut());
+            // Closed meanwhile: the title is dropped
+            if let Some(session) = active.into_iter().chain(others).find(|s| &s.path == path) {
+                if let Err(e) = session.set_title(title) {
+                    self.set_message(Level::Error, format!("Can't save the title: {}", e));
+                }
+            }
+        }
+
+        !titles.is_empty()
+    }
+
+    /// Title of the active buffer's session, if it has one yet
+    pub fn session_title(&self) -> Option<&str> {
+        self.history
+            .session
+            .as_ref()
+            .map(|session| session.title.as_str())
+            .filter(|title| !title.is_empty())
+    }
+
+    fn append_answer(&mut self, text: &str) {
+        // A rewrite is collected and replaces its text once complete
+        if let Some(edit) = &mut self.inline_edit {
+            edit.answer.push_str(text);
+            return;
+        }
+        // The first text of an answer comes under its header
+        let first = !self.answer_started;
+        let text = if self.answer_started {
+            text.to_string()
+        } else {
+            self.answer_started = true;
+            format!("{}{}", self.answer_header(), text)
+        };
+
+        // Its target was decided when it started, it's written once complete
+        if let Some(answer) = &mut self.file_answer {
+            answer.text.push_str(&text);
+            return;
+        }
+
+        match self.answer_target() {
+            AnswerTarget::Active => match &mut self.split {
+                Some(split) => split.append_answer(&text),
+                None => self.append_to_buffer(&text),
+            },
+            AnswerTarget::Parked(index) => {
+                if let Some(state) = &mut self.buffers[index] {
+                    state.append_answer(&text);
+                    if first {
+                        let name = BufferState::name(&state.history);
+                        self.set_message(Level::Info, format!("The answer goes to the {} tab", name));
+                    }
+                }
+            }
+            // Closed while the answer came in, the rest goes to its file
+            // under the header again
+            AnswerTarget::File(file) => {
+                let text = if first { text } else { format!("{}{}", self.answer_header(), text) };
+                self.file_answer = Some(FileAnswer {
+                    origin: file,
+                    text,
+                    inbox: false,
+                });
+            }
+        }
+    }
+
+    // Blank line and `## Assistant` header above the running answer
+    fn answer_header(&self) -> String {
+        let model = self.async_handler.running_label().unwrap_or("unknown");
+        format!("\n\n{}\n", transcript::assistant_header(model))
+    }
+
+    /// Where the running request's answer goes: the buffer that asked,
+    /// active or not, or its file when it's closed or holds another file
+    fn answer_target(&self) -> AnswerTarget {
+        let Some(origin) = self.async_handler.running_origin() else {
+            return AnswerTarget::Active;
+        };
+        if *origin == self.origin() {
+            return AnswerTarget::Active;
+        }
+        let parked = self.buffers.iter().position(|slot| {
+            matches!(slot, Some(state) if state.id == origin.buffer && state.history.file_path == origin.file)
+        });
+        match parked {
+            Some(index) => AnswerTarget::Parked(index),
+            None => AnswerTarget::File(origin.file.clone()),
+        }
+    }
+
+    // What a request sent from the active buffer carries, so its answer
+    // finds its way back
+    fn origin(&self) -> Origin {
+        Origin {
+            buffer: self.buffer_id,
+            file: self.history.file_path.clone(),
+        }
+    }
+
+    // Index of the open buffer the running answer goes to
+    fn answer_buffer(&self) -> Option<usize> {
+        match self.answer_target() {
+            AnswerTarget::Active => Some(self.active_buffer),
+            AnswerTarget::Parked(index) => Some(index),
+            AnswerTarget::File(_) => None,
+        }
+    }
+
+    // Answers of a buffer that was closed or replaced go to the end of its
+    // file, or `<history_dir>/inbox.md` when it was never saved, is only
+    // viewed or another rusty_ai has it open
+    fn append_to_file(&mut self, answer: FileAnswer) {
+        let origin = answer.origin;
+        let file = Self::disk_path(&self.history.with_path(&origin)).filter(|_| !answer.inbox);
+        let elsewhere = file.as_deref().and_then(lock::held_elsewhere);
+        let (path, text) = match file.filter(|_| elsewhere.is_none()) {
+            Some(path) => (path, answer.text),
+            None => {
+                let path = Path::new(&config::get().history_dir).join("inbox.md");
+                (path, format!("\n\n> For {}{}", origin, answer.text))
+            }
+        };
+
+        match Self::append_encoded(&path, &text) {
+            Ok(()) => {
+                let reason = match elsewhere {
+                    Some(pid) => format!("is open in rusty_ai (pid {})", pid),
+                    None if answer.inbox => "is read-only".to_string(),
+                    None => "isn't open".to_string(),
+                };
+                let message = format!("{} {}, the answer went to {}", origin, reason, path.display());
+                self.set_message(Level::Info, message);
+            }
+            Err(e) => {
+                let message = format!("Can't write the answer to {}: {}", path.display(), e);
+                self.set_message(Level::Error, message);
+            }
+        }
+    }
+
+    // Adds `text` to the end of a file the way a save writes it, in the
+    // file's encoding and line breaks. A new file is UTF-8.
+    fn append_encoded(path: &Path, text: &str) -> Result<()> {
+        let (content, encoding) = match encoding::read(path) {
+            Ok(read) => read,
+            Err(Error::Io(e)) if e.kind() == ErrorKind::NotFound => (String::new(), Encoding::Utf8),
+            Err(e) => return Err(e),
+        };
+        let (mut content, line_ending) = line_ending::normalize(&content);
+        content.push_str(text);
+
+        encoding::write(path, &line_ending.apply(&content), encoding)
+    }
+
+    fn append_to_buffer(&mut self, text: &str) {
+        // `start_next_request` sends the answers of a viewer to the inbox
+        if self.read_only {
+            return;
+        }
+        let len = self.buffer.len_chars();
+        let char_idx = self.response_at.unwrap_or(len).min(len);
+        let inserted = text.chars().count();
+        let cursor = self.get_char_idx();
+
+        self.record_edit(char_idx..char_idx, text);
+        self.buffer.insert(char_idx, text);
+        self.protected.add(char_idx..char_idx + inserted);
+        self.response_at = Some(char_idx + inserted);
+
+        // Now we can safely call this method since the lock is dropped
+        self.update_syntax_highlighting();
+
+        match self.insertion {
+            Insertion::End => {
+                // Update cursor position to the end
+                let new_lines = self.buffer.len_lines() - 1;
+                self.cursor_row = new_lines;
+                let last_line = self.buffer.line(new_lines);
+                self.cursor_col = last_line.len_chars().saturating_sub(1);
+            }
+            // Follow the answer like typed text
+            Insertion::Cursor => self.set_cursor_to_char_idx(char_idx + inserted),
+            // Stay on the text the cursor was on
+            Insertion::Paragraph => {
+                let cursor = if cursor > char_idx {
+                    cursor + inserted
+                } else {
+                    cursor
+                };
+                self.set_cursor_to_char_idx(cursor);
+            }
+        }
+
+        self.modified = true;
+    }
+
+    /// A popup is reading typed text, so keys must reach it unchanged
+    fn is_text_input_active(&self) -> bool {
+        self.search.is_active()
+            || self.replace.is_entering_replacement()
+            || self.prompt_editor.is_active()
+            || self.params_form.is_active()
+            || self.menu_status.file_picker_state(filepicker::Action::Save)
+            || self.menu_status.file_picker_state(filepicker::Action::SaveBlock)
+            || self.menu_status.file_picker_state(filepicker::Action::Load)
+            || self.menu_status.file_picker_state(filepicker::Action::Attach)
+            || self.menu_status.file_picker_state(filepicker::Action::PromptHistory)
+            || self.menu_status.file_picker_state(filepicker::Action::SearchHistory)
+            || self.menu_status.file_picker_state(filepicker::Action::Import)
+            || self.menu_status.file_picker_state(filepicker::Action::ApiKey)
+            || self.menu_status.file_picker_state(filepicker::Action::PullModel)
+            || self.menu_status.file_picker_state(filepicker::Action::GotoLine)
+            || self.menu_status.file_picker_state(filepicker::Action::RenameFile)
+            || self.menu_status.file_picker_state(filepicker::Action::RenameSession)
+            || self.menu_status.file_picker_state(filepicker::Action::TagSession)
+            || self.menu_status.file_picker_state(filepicker::Action::PickSession)
+            || self.menu_status.file_picker_state(filepicker::Action::CommitHistory)
+            || self.menu_status.file_picker_state(filepicker::Action::RunAttach)
+            || self.menu_status.file_picker_state(filepicker::Action::InlineEdit)
+    }
+
+    /// Feeds recorded keys back through `handle_key`, `count` times
+    fn play_macro(&mut self, keys: Vec<(KeyCode, KeyModifiers)>, count: usize) -> Result<bool> {
+        self.macros.set_playing(true);
+
+        let mut result = Ok(false);
+        'outer: for _ in 0..count {
+            for (key, modifiers) in &keys {
+                result = self.handle_key(*key, *modifiers);
+                // Stop on quit or error
+                if !matches!(result, Ok(false)) {
+                    break 'outer;
+                }
+            }
+        }
+
+        self.macros.set_playing(false);
+        result
+    }
+
+    /// Keys of the system prompt popup. Ctrl+s saves, Esc drops the changes.
+    fn handle_prompt_editor(&mut self, key: KeyCode, modifiers: KeyModifiers) -> Result<bool> {
+        match key {
+            KeyCode::Char('s') if modifiers.contains(KeyModifiers::CONTROL) => {
+                let prompt = self.prompt_editor.get_text().to_string();
+                if let Err(e) = system_prompt::save(&prompt) {
+                    self.set_message(Level::Error, format!("Can't save system prompt: {}", e));
+                }
+
+                // Used from the next request on, even if saving failed
+                self.chat_context.system_prompt = prompt;
+                self.async_handler
+                    .set_chat_context(self.chat_context.clone());
+                self.prompt_editor.close();
+            }
+            KeyCode::Esc => self.prompt_editor.close(),
+            KeyCode::Enter => self.prompt_editor.insert_char('\n'),
+            KeyCode::Char(c) => self.prompt_editor.insert_char(c),
+            KeyCode::Backspace => self.prompt_editor.delete_previous_char(),
+            KeyCode::Delete => self.prompt_editor.delete_current_char(),
+            KeyCode::Left => self.prompt_editor.move_cursor_left(),
+            KeyCode::Right => self.prompt_editor.move_cursor_right(),
+            KeyCode::Up => self.prompt_editor.move_cursor_vertically(true),
+            KeyCode::Down => self.prompt_editor.move_cursor_vertically(false),
+            _ => {}
+        }
+
+        Ok(false)
+    }
+
+    /// Keys of the generation parameters form. Enter applies the values,
+    /// or keeps the form open to fix them; Esc drops the changes.
+    fn handle_params_form(&mut self, key: KeyCode) -> Result<bool> {
+        match key {
+            KeyCode::Enter => match self.params_form.params() {
+                Ok(params) => {
+                    self.chat_context.params = params;
+                    self.async_handler
+                        .set_chat_context(self.chat_context.clone());
+                    self.params_form.close();
+                }
+                Err(e) => {
+                    self.set_message(Level::Error, e.to_string());
+                }
+            },
+            KeyCode::Esc => self.params_form.close(),
+            KeyCode::Tab | KeyCode::Down => self.params_form.next_field(false),
+            KeyCode::BackTab | KeyCode::Up => self.params_form.next_field(true),
+            KeyCode::Char(c) => self.params_form.insert_char(c),
+            KeyCode::Backspace => self.params_form.delete_previous_char(),
+            KeyCode::Delete => self.params_form.delete_current_char(),
+            KeyCode::Left => self.params_form.move_cursor_left(),
+            KeyCode::Right => self.params_form.move_cursor_right(),
+            _ => {}
+        }
+
+        Ok(false)
+    }
+
+    pub fn is_waiting_for_command(&self) -> bool {
+        self.menu_status.is_active_menu()
+            // && !self.menu_status.is_active(MenuType::FilePicker)
+            // && !self.menu_status.is_active(MenuType::FileSaveAs)
+            && !self.menu_status.is_file_picker_active()
+    }
+
+    fn move_to_end_of_line(&mut self) -> Result<bool> {
+        // Move the cursor to the end of the line
+        let line = self.buffer.line(self.cursor_row);
+        let line_len = line.len_chars().saturating_sub(1); // Account for newline
+        self.cursor_col = line_len;
+
+        self.clamp_cursor();
+
+        Ok(false)
+    }
+
+    fn move_to_start_of_line(&mut self) -> Result<bool> {
+        // Move the cursor to the beginning of the line
+        self.cursor_col = 0;
+
+        Ok(false)
+    }
+
+    /// Moves to the start of a 1-based line, clamped to the buffer. The
+    /// renderer scrolls it into view.
+    fn goto_line(&mut self, line: usize) {
+        let last_line = self.buffer.len_lines().saturating_sub(1);
+
+        self.cursor_row = line.saturating_sub(1).min(last_line);
+        self.cursor_col = 0;
+    }
+
+    // Line and optional column as `gf` reads them, both 1-based
+    fn goto_position(&mut self, line: usize, column: Option<usize>) {
+        self.goto_line(line);
+        if let Some(column) = column {
+            self.cursor_col = column.saturating_sub(1);
+            self.clamp_cursor();
+        }
+    }
+
+    /// Opens the file named under the cursor, e.g. `src/editor/mod.rs:123`,
+    /// at its line. An open file gets its tab back, anything else a new
+    /// buffer unless the active one is empty.
+    fn open_file_under_cursor(&mut self) -> Result<bool> {
+        let line = self.line_text(self.cursor_row);
+        let Some(reference) = reference::at(&line, self.cursor_col) else {
+            self.set_message(Level::Warning, "No file name under the cursor");
+            return Ok(false);
+        };
+        let Some(path) = reference::resolve(&reference.path) else {
+            self.set_message(Level::Warning, format!("No file {}", reference.path));
+            return Ok(false);
+        };
+        if self.load_blocks() {
+            return Ok(false);
+        }
+
+        let path = path.to_string_lossy().into_owned();
+        let line = reference.line.unwrap_or(1);
+        if self.focus_buffer(&path) {
+            self.goto_position(line, reference.column);
+        } else {
+            self.open_path(&path)?;
+            self.pending_position = Some((line, reference.column));
+        }
+
+        Ok(false)
+    }
+
+    fn move_to_start_of_buffer(&mut self) -> Result<bool> {
+        // Move cursor to the first position in the buffer
+        self.cursor_row = 0;
+        self.cursor_col = 0;
+
+        Ok(false)
+    }
+
+    fn move_to_end_of_buffer(&mut self) -> Result<bool> {
+        let total_lines = self.buffer.len_lines();
+
+        if total_lines == 0 {
+            // Buffer is empty: place cursor at 0,0
+            self.cursor_row = 0;
+            self.cursor_col = 0;
+            return Ok(false);
+        }
+
+        let last_line_idx = total_lines - 1;
+
+        self.cursor_row = last_line_idx;
+
+        let line = self.buffer.line(last_line_idx);
+
+        // line.len_chars() is always at least 1 (newline at end)
+        // saturate to 0 if len_chars() == 0 (shouldn't happen)
+        let line_len = line.len_chars().saturating_sub(1);
+
+        self.cursor_col = line_len;
+
+        self.clamp_cursor();
+
+        Ok(false)
+    }
+
+    /// Key after `d`, `y`, `di`, `ya`, select mode's `i` ... `None` lets
+    /// the key through: after a lone `d` that's anything but `i`/`a`, once
+    /// the character under the cursor is deleted.
+    fn handle_text_object(&mut self, pending: textobject::Pending, key: KeyCode) -> Option<Result<bool>> {
+        if key == KeyCode::Esc {
+            return Some(Ok(false));
+        }
+
+        let Some(scope) = pending.scope else {
+            let scope = match key {
+                KeyCode::Char('i') => textobject::Scope::Inner,
+                KeyCode::Char('a') => textobject::Scope::Around,
+                _ => {
+                    if pending.operator == textobject::Operator::Delete {
+                        if let Err(e) = self.delete_char_to_register() {
+                            return Some(Err(e));
+                        }
+                    }
+                    return None;
+                }
+            };
+            self.text_object = Some(textobject::Pending {
+                scope: Some(scope),
+                ..pending
+            });
+            return Some(Ok(false));
+        };
+
+        let KeyCode::Char(c) = key else {
+            return Some(Ok(false));
+        };
+        match textobject::range(&self.buffer, self.get_char_idx(), scope, c) {
+            Some(range) => self.apply_text_object(pending.operator, range),
+            None => {
+                let label = textobject::Pending { scope: Some(scope), ..pending }.label();
+                self.set_message(Level::Warning, format!("No {}{} here", label, c));
+            }
+        }
+        Some(Ok(false))
+    }
+
+    // Selects `range`, then deletes or yanks it
+    fn apply_text_object(&mut self, operator: textobject::Operator, range: Range<usize>) {
+        let start = self.position_from_char_idx(range.start);
+        let end = if range.end >= self.buffer.len_chars() {
+            let last = self.buffer.len_lines() - 1;
+            (last, self.buffer.len_chars() - self.buffer.line_to_char(last))
+        } else {
+            self.position_from_char_idx(range.end)
+        };
+        self.selection_start = Some(start);
+        (self.cursor_row, self.cursor_col) = end;
+        self.selection_active = true;
+
+        match operator {
+            textobject::Operator::Select => self.mode = Mode::Select,
+            textobject::Operator::Delete => {
+                if range.is_empty() {
+                    self.selection_active = false;
+                    self.selection_start = None;
+                    (self.cursor_row, self.cursor_col) = start;
+                } else if let Err(e) = self.delete_selection() {
+                    self.set_message(Level::Error, format!("Delete error: {}", e));
+                }
+                self.mode = Mode::Normal;
+            }
+            textobject::Operator::Yank => {
+                if let Some(text) = self.get_selected_text() {
+                    self.copy_text(text);
+                }
+                // The cursor goes back to where the object starts
+                self.selection_active = false;
+                self.selection_start = None;
+                (self.cursor_row, self.cursor_col) = start;
+                self.mode = Mode::Normal;
+            }
+        }
+    }
+
+    // `d` on its own
+    fn delete_char_to_register(&mut self) -> Result<()> {
+        let char_idx = self.get_char_idx();
+        if char_idx < self.buffer.len_chars() {
+            self.registers.store(self.buffer.char(char_idx).to_string());
+        }
+        self.delete_char_at_cursor()?;
+        Ok(())
+    }
+
+    /// Keys of a text object typed so far, e.g. `di`
+    pub fn pending_text_object(&self) -> Option<String> {
+        self.text_object.map(|pending| pending.label())
+    }
+
+    // Add a new method to select the current line or expand selection
+    fn select_current_line(&mut self) -> Result<bool> {
+        // Check if we're already in select mode with an active selection
+        if self.mode == Mode::Select && self.selection_active && self.selection_start.is_some() {
+            // Get the current selection range to determine if it already covers full lines
+            let selection_range = self.get_selection_range().unwrap_or(0..0);
+            let start_row = self.buffer.char_to_line(selection_range.start);
+            let end_row = self.buffer.char_to_line(selection_range.end);
+
+            // If the selection already covers complete lines, extend to include one more line
+            if end_row < self.buffer.len_lines() - 1 {
+                // Move cursor to beginning of the next line
+                self.cursor_row = end_row + 1;
+
+                // If this is the last line, move to the end of it
+                if self.cursor_row >= self.buffer.len_lines() - 1 {
+                    let line = self.buffer.line(self.cursor_row);
+                    self.cursor_col = line.len_chars().saturating_sub(1);
+                } else {
+                    // Otherwise, move to the end of this line
+                    let line = self.buffer.line(self.cursor_row);
+                    self.cursor_col = line.len_chars().saturating_sub(1);
+                }
+            }
+        } else {
+            // Start a new line selection
+            // Move cursor to the beginning of the current line
+            self.cursor_col = 0;
+
+            // Set selection start
+            self.selection_start = Some((self.cursor_row, 0));
+
+            // Move cursor to the end of the line
+            let line = self.buffer.line(self.cursor_row);
+            let line_end = line.len_chars().saturating_sub(1);
+            self.cursor_col = line_end;
+
+            // Activate selection and enter select mode
+            self.selection_active = true;
+            self.mode = Mode::Select;
+        }
+
+        Ok(false)
+    }
+
+    pub fn get_selection_range(&self) -> Option<std::ops::Range<usize>> {
+        if !self.selection_active || self.selection_start.is_none() {
+            return None;
+        }
+
+        let (start_row, start_col) = self.selection_start.unwrap();
+        let (end_row, end_col) = (self.cursor_row, self.cursor_col);
+
+        let start_idx = self.char_idx_from_position(start_row, start_col);
+        let end_idx = self.char_idx_from_position(end_row, end_col);
+
+        // Make sure start_idx <= end_idx
+        if start_idx <= end_idx {
+            Some(start_idx..end_idx)
+        } else {
+            Some(end_idx..start_idx)
+        }
+    }
+
+    pub fn is_position_selected(
+        &self,
+        row: usize,
+        col: usize,
+        selection_range: &Option<std::ops::Range<usize>>,
+    ) -> bool {
+        if let Some(range) = selection_range {
+            let pos_idx = self.char_idx_from_position(row, col);
+            range.contains(&pos_idx)
+        } else {
+            false
+        }
+    }
+
+    pub fn get_style_for_position(&mut self, row: usize, col: usize) -> Style {
+        // Check if the position is selected first
+        if self.is_position_selected(row, col, &self.get_selection_range()) {
+            return Style::Selection;
+        }
+
+        // Otherwise, get the syntax highlighting style
+        let char_idx = self.char_idx_from_position(row, col);
+        self.get_style_at(char_idx)
+    }
+
+    pub fn char_idx_from_position(&self, row: usize, col: usize) -> usize {
+        if row >= self.buffer.len_lines() {
+            return self.buffer.len_chars();
+        }
+
+        // Get the char index of the start of the line
+        let line_start_idx = self.buffer.line_to_char(row);
+
+        // Add the column, clamping to line length
+        let line_len = self.buffer.line(row).len_chars();
+        let clamped_col = col.min(line_len);
+
+        line_start_idx + clamped_col
+    }
+
+    pub fn handle_key(&mut self, key: KeyCode, modifiers: KeyModifiers) -> Result<bool> {
+        // Raw keys, playback goes through the keymap again
+        self.macros.record(key, modifiers);
+
+        if self.confirm.is_some() {
+            return self.handle_confirm(key);
+        }
+
+        if self.diff.is_some() {
+            return self.handle_diff(key);
+        }
+
+        if self.patch.is_some() {
+            return self.handle_patch(key);
+        }
+
+        if self.log_view.is_some() {
+            return self.handle_log_view(key);
+        }
+
+        if self.message_view.is_some() {
+            return self.handle_message_view(key);
+        }
+        if self.usage_view.is_some() {
+            return self.handle_usage_view(key);
+        }
+        if self.help_view.is_some() {
+            return self.handle_help_view(key);
+        }
+        if self.preview.is_some() {
+            return self.handle_preview(key, modifiers);
+        }
+
+        // The pattern goes through the search prompt, the rest is handled here
+        if self.replace.is_active() && !self.search.is_active() {
+            return self.handle_replace(key);
+        }
+
+        // Apply keymap overrides from the config outside of text input
+        let key = match key {
+            KeyCode::Char(c) if self.mode != Mode::Insert && !self.is_text_input_active() => {
+                KeyCode::Char(config::get().remap_key(c))
+            }
+            _ => key,
+        };
+
+        if self.prompt_editor.is_active() {
+            return self.handle_prompt_editor(key, modifiers);
+        }
+
+        if self.params_form.is_active() {
+            return self.handle_params_form(key);
+        }
+
+        if self.menu_status.file_picker_state(filepicker::Action::Completion) {
+            match self.handle_completion(key) {
+                Some(result) => return result,
+                // Typing goes on, the list is stale now
+                None => self.menu_status.reset(),
+            }
+        }
+
+        if matches!(key, KeyCode::Up | KeyCode::Down)
+            && self.mode != Mode::Select
+            && !modifiers.contains(KeyModifiers::ALT)
+            && self.recall_prompt(key)
+        {
+            return Ok(false);
+        }
+
+        // Handle regular keys based on mode
+        let row = self.cursor_row;
+        let result = match self.mode {
+            Mode::Normal => self.handle_normal_mode(key, modifiers),
+            Mode::Insert => self.handle_insert_mode(key, modifiers),
+            Mode::Select => self.handle_select_mode(key, modifiers),
+        };
+        self.skip_folded(row);
+        result
+    }
+
+    fn send_to_anthropic(&mut self) -> Result<()> {
+        self.send_to_api(Model::ANTROPIC)
+    }
+
+    fn send_to_ollama(&mut self) -> Result<()> {
+        // self.async_handler.request_ollama();
+        // self.needs_response_check = true;
+        self.send_to_api(Model::OLLAMA);
+        Ok(())
+    }
+
+    fn send_to_openai(&mut self) -> Result<()> {
+        // self.request_state = RequestState::Proccessing;
+        self.send_to_api(Model::OPENAI)
+    }
+
+    /// Estimated tokens in the buffer and the selected model's context window
+    pub fn token_usage(&self) -> (usize, usize) {
+        let attached: usize = self.attachments.iter().map(|a| a.content.len()).sum();
+        let project = self.project.prompt().len();
+
+        let transcript = self.split.as_ref().map_or(0, |s| s.transcript().len_chars());
+
+        (
+            tokens::estimate_tokens(self.buffer.len_chars() + transcript + attached + project),
+            self.chat_context.context_window(&self.chat_context.model),
+        )
+    }
+
+    /// Lines, words, chars and estimated tokens of the selection
+    pub fn selection_stats(&self) -> Option<(usize, usize, usize, usize)> {
+        let range = self.get_selection_range()?;
+        let selected = self.buffer.slice(range.clone());
+        let chars = range.len();
+
+        let end_line = self.buffer.char_to_line(range.end.saturating_sub(1).max(range.start));
+        let lines = end_line - self.buffer.char_to_line(range.start) + 1;
+        let words = selected.to_string().split_whitespace().count();
+
+        Some((lines, words, chars, tokens::estimate_tokens(chars)))
+    }
+
+    /// Where answers go: the transcript in split view, otherwise the buffer
+    fn conversation(&self) -> &Rope {
+        match &self.split {
+            Some(split) => split.transcript(),
+            None => &self.buffer,
+        }
+    }
+
+    /// Fenced code blocks of the conversation
+    fn code_blocks(&self) -> Vec<CodeBlock> {
+        match &self.syntax_highlighter {
+            Some(highlighter) => highlighter.extract_code_blocks(&self.conversation().to_string()),
+            None => Vec::new(),
+        }
+    }
+
+    /// Opens a picker over the code blocks, labelled by language and first line
+    fn open_block_picker(&mut self) {
+        let labels: Vec<String> = self
+            .code_blocks()
+            .iter()
+            .enumerate()
+            .map(|(i, block)| {
+                let language = if block.language.is_empty() {
+                    "text"
+                } else {
+                    block.language.as_str()
+                };
+                let first_line: String = block
+                    .code
+                    .lines()
+                    .find(|line| !line.trim().is_empty())
+                    .unwrap_or("")
+                    .trim()
+                    .chars()
+                    .take(40)
+                    .collect();
+                let lines = block.code.lines().count();
+                format!("{}. {} ({} lines) {}", i + 1, language, lines, first_line)
+            })
+            .collect();
+
+        if labels.is_empty() {
+            self.set_message(Level::Warning, "No code blocks found");
+            return;
+        }
+
+        self.menu_status.file_picker.init_block_picker(labels);
+    }
+
+    /// Names of the files attached to the prompt
+    pub fn attached_files(&self) -> Vec<&str> {
+        self.attachments.iter().map(|a| a.name.as_str()).collect()
+    }
+
+    /// Attaches `name`, or detaches it when it's already attached
+    fn toggle_attachment(&mut self, name: &str) {
+        if let Some(i) = self.attachments.iter().position(|a| a.name == name) {
+            self.attachments.remove(i);
+            return;
+        }
+
+        match Attachment::load(name) {
+            Ok(attachment) => self.attachments.push(attachment),
+            Err(e) => {
+                self.set_message(Level::Error, format!("Can't attach {}: {}", name, e));
+            }
+        }
+    }
+
+    fn send_to_api(&mut self, ai_model: Model) -> Result<()> {
+        let limit = self.chat_context.context_window(&ai_model);
+        let Some((content, earlier)) = self.request_content(limit) else {
+            return Ok(());
+        };
+
+        // Delegate to the async handler, it runs queued requests one by one
+        self.async_handler
+            .enqueue(content, ai_model, self.stream_responses, earlier, self.origin());
+        self.start_next_request();
+
+        // Set flag to check for responses
+        self.needs_response_check = true;
+
+        Ok(())
+    }
+
+    /// Sends the request to every `fan_out` model of the config at once
+    fn send_fan_out(&mut self) -> Result<()> {
+        let entries = &config::get().fan_out;
+        if entries.is_empty() {
+            return Err("No fan_out models in the config".into());
+        }
+
+        // The smallest context window decides how much of the conversation fits
+        let mut limit = usize::MAX;
+        for entry in entries {
+            let mut chat_context = self.chat_context.clone();
+            chat_context.select_model(entry)?;
+            limit = limit.min(chat_context.context_window(&chat_context.model));
+        }
+        let Some((content, _)) = self.request_content(limit) else {
+            return Ok(());
+        };
+        // Several models would each need their own summary, the oldest
+        // messages are dropped instead
+        if let Some((_, dropped)) = self.context_trimmed {
+            self.context_trimmed = Some((ContextTrim::Drop, dropped));
+        }
+
+        self.async_handler
+            .enqueue_fan_out(content, entries, self.origin())?;
+        self.start_next_request();
+        self.needs_response_check = true;
+
+        Ok(())
+    }
+
+    /// Conversation and attachments sent with a request, cut to fit `limit`
+    /// tokens, and the messages left out when they're to be summarized.
+    /// `None` when there's nothing to send in split view.
+    fn request_content(&mut self, limit: usize) -> Option<(String, Option<String>)> {
+        // In split view the prompt pane moves into the transcript
+        if let Some(split) = &mut self.split {
+            let prompt = self.buffer.to_string();
+            if prompt.trim().is_empty() {
+                return None;
+            }
+
+            split.push_prompt(&prompt);
+            self.remember_prompt(&prompt);
+            self.record_edit(0..self.buffer.len_chars(), "");
+            self.buffer = Rope::new();
+            self.cursor_row = 0;
+            self.cursor_col = 0;
+            self.update_syntax_highlighting();
+        } else {
+            if self.insertion == Insertion::End {
+                self.mark_prompt();
+            }
+            let conversation = self.conversation().to_string();
+            if let Some(prompt) = export::messages(&conversation)
+                .into_iter()
+                .rev()
+                .find(|message| message.role == "user")
+            {
+                self.remember_prompt(&prompt.content);
+            }
+        }
+
+        let attached =
+            self.project.prompt() + &attachment::attachments_prompt(&self.attachments);
+        // Command output is only for the prompt it was run for
+        self.attachments.retain(|a| a.kind == Kind::File);
+
+        // Drop the oldest messages when they can't fit, leaving room for
+        // their summary
+        let strategy = self.context_trim();
+        let mut max_chars =
+            tokens::chars_for_tokens(limit).saturating_sub(attached.chars().count());
+        let conversation = self.conversation().to_string();
+        if strategy == ContextTrim::Summarize && conversation.chars().count() > max_chars {
+            max_chars = max_chars.saturating_sub(tokens::chars_for_tokens(trim::SUMMARY_TOKENS));
+        }
+        let fitted = trim::fit(&conversation, max_chars);
+
+        self.context_trimmed = if fitted.dropped.is_empty() {
+            None
+        } else {
+            Some((strategy, fitted.dropped_tokens()))
+        };
+        let earlier = match strategy {
+            ContextTrim::Summarize if !fitted.dropped.is_empty() => Some(fitted.dropped),
+            _ => None,
+        };
+        Some((attached + &fitted.text, earlier))
+    }
+
+    /// Drop or summarize, as the session says or else the config
+    fn context_trim(&self) -> ContextTrim {
+        self.history
+            .session
+            .as_ref()
+            .and_then(|session| session.context_trim)
+            .unwrap_or(config::get().context_trim)
+    }
+
+    /// Switches how the session makes room in a full context window
+    fn cycle_context_trim(&mut self) {
+        let next = self.context_trim().next();
+        let Some(session) = &mut self.history.session else {
+            self.set_message(Level::Warning, "Only sessions keep a context strategy");
+            return;
+        };
+
+        match session.set_context_trim(next) {
+            Ok(()) => self.set_message(Level::Info, format!("Full context: {}", next.label())),
+            Err(e) => self.set_message(Level::Error, format!("Can't save the session: {}", e)),
+        }
+    }
+
+    /// How the last request fit the context window: the strategy and the
+    /// estimated tokens it left out
+    pub fn context_trimmed(&self) -> Option<(ContextTrim, usize)> {
+        self.context_trimmed
+    }
+
+    // Best effort, a failed write mustn't hold up the request
+    fn remember_prompt(&self, prompt: &str) {
+        if let Err(e) = prompt_history::push(prompt) {
+            self.set_message(Level::Warning, format!("Can't save prompt history: {}", e));
+        }
+    }
+
+    /// Puts a `## User` header above the text typed since the last answer,
+    /// unless it has one
+    fn mark_prompt(&mut self) {
+        let start = self.protected.last_end().min(self.buffer.len_chars());
+        let prompt = self.buffer.slice(start..).to_string();
+        let typed = prompt.trim_start();
+        if typed.is_empty() || typed.lines().next().and_then(transcript::role).is_some() {
+            return;
+        }
+
+        let at = start + prompt[..prompt.len() - typed.len()].chars().count();
+        let header = format!("{}\n", transcript::user_header());
+        self.record_edit(at..at, &header);
+        self.buffer.insert(at, &header);
+        self.update_syntax_highlighting();
+        self.modified = true;
+    }
+
+    /// Starts the next queued request once the previous answer is applied
+    fn start_next_request(&mut self) {
+        if !self.async_handler.start_next() {
+            return;
+        }
+        self.answer_started = false;
+        self.file_answer = None;
+
+        let target = self.answer_target();
+        // Nothing goes into a read-only view
+        let viewed = match &target {
+            AnswerTarget::Active => self.read_only,
+            AnswerTarget::Parked(index) => self.buffers[*index].as_ref().is_some_and(|state| state.read_only),
+            AnswerTarget::File(_) => false,
+        };
+        if viewed {
+            self.file_answer = Some(FileAnswer {
+                origin: self.async_handler.running_origin().map(|origin| origin.file.clone()).unwrap_or_default(),
+                text: String::new(),
+                inbox: true,
+            });
+            return;
+        }
+
+        match target {
+            AnswerTarget::Active => {
+                let start = match self.split {
+                    Some(_) => self.conversation().len_chars(),
+                    None => self.insertion_point(),
+                };
+                self.response_start = Some(start);
+                self.response_at = Some(start);
+            }
+            // Asked before switching away, answered at the end
+            AnswerTarget::Parked(index) => {
+                if let Some(state) = &mut self.buffers[index] {
+                    let end = state.buffer.len_chars();
+                    state.response_start = Some(end);
+                    state.response_at = Some(end);
+                }
+            }
+            // Collected and written once complete, not a chunk at a time
+            AnswerTarget::File(file) => {
+                self.file_answer = Some(FileAnswer {
+                    origin: file,
+                    text: String::new(),
+                    inbox: false,
+                })
+            }
+        }
+    }
+
+    /// Where the next answer goes in the buffer
+    fn insertion_point(&self) -> usize {
+        match self.insertion {
+            Insertion::End => self.buffer.len_chars(),
+            Insertion::Cursor => self.get_char_idx(),
+            Insertion::Paragraph => {
+                // Last line before the next blank one, answer goes after its text
+                let mut row = self.cursor_row;
+                while row + 1 < self.buffer.len_lines()
+                    && !self.buffer.line(row + 1).to_string().trim().is_empty()
+                {
+                    row += 1;
+                }
+                let line = self.buffer.line(row).to_string();
+                self.buffer.line_to_char(row) + line.trim_end_matches(['\n', '\r']).chars().count()
+            }
+        }
+    }
+
+    /// Queued, running and recently finished requests for the status panel
+    pub fn requests(&self) -> Vec<RequestInfo> {
+        match self.shared_state.lock() {
+            Ok(state) => state.requests.clone(),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// Moves text read by the background file load into the buffer.
+    /// Highlighting waits until the whole file is in. Returns whether a
+    /// load is going on.
+    pub fn poll_file_load(&mut self) -> bool {
+        let load = match self.shared_state.lock() {
+            Ok(mut state) => match &mut state.load {
+                Some(load) => {
+                    self.encoding = load.encoding;
+                    self.line_ending = load.line_ending;
+                    let restarted = std::mem::take(&mut load.restarted);
+                    let text = std::mem::take(&mut load.text);
+                    let done = load.finished.then(|| load.error.clone());
+                    if done.is_some() {
+                        state.load = None;
+                    }
+                    Some((restarted, text, done))
+                }
+                None => None,
+            },
+            Err(_) => None,
+        };
+        let Some((restarted, text, done)) = load else {
+            return false;
+        };
+
+        // Read again in another encoding, from the start
+        if restarted {
+            self.buffer = Rope::new();
+        }
+        if !text.is_empty() {
+            let end = self.buffer.len_chars();
+            self.buffer.insert(end, &text);
+        }
+
+        match done {
+            Some(Some(error)) => {
+                // Saving the part that loaded would cut the file off on disk
+                self.set_read_only(true);
+                self.set_message(
+                    Level::Error,
+                    format!("Can't load {}: {}, it's shown read-only", self.history.file_path, error),
+                );
+            }
+            Some(None) => {
+                self.update_syntax_highlighting();
+                if let Some((line, column)) = self.pending_position.take() {
+                    self.goto_position(line, column);
+                }
+            }
+            None => {}
+        }
+        true
+    }
+
+    pub fn is_loading(&self) -> bool {
+        match self.shared_state.lock() {
+            Ok(state) => state.load.is_some(),
+            Err(_) => false,
+        }
+    }
+
+    // The rest of the file would land in another buffer
+    fn load_blocks(&self) -> bool {
+        if !self.is_loading() {
+            return false;
+        }
+        self.set_message(Level::Warning, "Wait until the file has loaded");
+        true
+    }
+
+    /// File load in progress, for the status line
+    pub fn load_progress(&self) -> Option<String> {
+        match self.shared_state.lock() {
+            Ok(state) => state.load.as_ref().map(|load| load.label()),
+            Err(_) => None,
+        }
+    }
+
+    /// Whether a request, file load, model download or shell command is
+    /// running, whose results the main loop should pick up at frame rate
+    pub fn is_busy(&self) -> bool {
+        self.needs_response_check
+            || self.is_loading()
+            || self.pull_progress().is_some()
+            || self.shell_progress().is_some()
+    }
+
+    /// Runs `cargo check` in the background, its errors are sent to the
+    /// current model once it's done
+    fn start_fix(&mut self) {
+        if self.async_handler.has_pending() {
+            self.set_message(Level::Warning, "Wait for the running requests to finish");
+            return;
+        }
+        self.async_handler
+            .run_shell(fix::CHECK_COMMAND.to_string(), ShellPurpose::Fix);
+    }
+
+    /// Asks the current model to fix the errors in `output` of `cargo
+    /// check`, the prompt going below the conversation like a typed one.
+    /// The diff in its answer is previewed to be applied.
+    fn send_fix_prompt(&mut self, output: &str) {
+        let errors = fix::errors(output);
+        if errors.is_empty() {
+            self.set_message(Level::Info, "cargo check found no errors");
+            return;
+        }
+        // A request sent meanwhile would take the answer's place
+        if self.async_handler.has_pending() {
+            self.set_message(Level::Warning, "Requests are running, not sending the errors");
+            return;
+        }
+
+        let text = self.buffer.to_string();
+        let separator = if text.trim().is_empty() || text.ends_with("\n\n") {
+            ""
+        } else if text.ends_with('\n') {
+            "\n"
+        } else {
+            "\n\n"
+        };
+        self.set_cursor_to_char_idx(self.buffer.len_chars());
+        if let Err(e) = self.insert_at_cursor(&format!("{}{}", separator, fix::prompt(&errors))) {
+            self.set_message(Level::Error, e.to_string());
+            return;
+        }
+
+        let model = self.chat_context.model.clone();
+        match self.send_to_api(model) {
+            Ok(()) => self.fix_pending = true,
+            Err(e) => self.set_message(Level::Error, format!("Can't send the errors: {}", e)),
+        }
+    }
+
+    // The fix answer is done: preview its diff
+    fn offer_fix(&mut self) {
+        // A failed request left no answer
+        if !self.answer_started {
+            return;
+        }
+        match self.last_answer() {
+            Some(answer) if answer.contains("@@") => self.open_patch(&answer),
+            _ => self.set_message(Level::Warning, "The answer has no diff to apply"),
+        }
+    }
+
+    /// Shell command running for its output, for the status line
+    pub fn shell_progress(&self) -> Option<String> {
+        match self.shared_state.lock() {
+            Ok(state) => state.shell.as_ref().map(|run| format!("running `{}`", run.command)),
+            Err(_) => None,
+        }
+    }
+
+    /// Attaches the output of a finished shell command to the next prompt,
+    /// inserts it in the buffer that ran it, or sends the errors of a `cargo
+    /// check` run to be fixed. Running the same command again replaces its
+    /// attached output.
+    pub fn poll_shell(&mut self) -> bool {
+        let run = match self.shared_state.lock() {
+            Ok(mut state) if state.shell.as_ref().is_some_and(|run| run.output.is_some()) => {
+                state.shell.take()
+            }
+            _ => None,
+        };
+        let Some(ShellRun {
+            command,
+            output: Some(output),
+            purpose,
+        }) = run
+        else {
+            return false;
+        };
+        match purpose {
+            ShellPurpose::Fix => {
+                self.send_fix_prompt(&output);
+                return true;
+            }
+            ShellPurpose::Insert(origin, insert_at) => {
+                self.insert_command_output(&origin, insert_at, &output);
+                return true;
+            }
+            ShellPurpose::Attach => {}
+        }
+
+        let lines = output.lines().count();
+        self.attachments
+            .retain(|a| a.kind != Kind::Command || a.name != command);
+        self.attachments.push(Attachment::command(&command, output));
+        self.set_message(
+            Level::Info,
+            format!("Attached the output of `{}` ({} lines) to the next prompt", command, lines),
+        );
+        true
+    }
+
+    /// Download of an Ollama model in progress, for the status line
+    pub fn pull_progress(&self) -> Option<String> {
+        match self.shared_state.lock() {
+            Ok(state) => state.pull.as_ref().map(|pull| pull.label()),
+            Err(_) => None,
+        }
+    }
+
+    /// Lists the installed Ollama models with size and quantization, once
+    /// the server answers
+    fn open_ollama_manager(&mut self) {
+        self.ollama_listing = Some(OllamaListing::Manager);
+        self.async_handler.fetch_ollama_models();
+        self.set_message(Level::Info, "Listing Ollama models...");
+    }
+
+    /// Fills the popup waiting for the Ollama models once they're in.
+    /// Returns whether they came, to draw it.
+    pub fn poll_ollama_models(&mut self) -> bool {
+        let result = match self.shared_state.lock() {
+            Ok(mut state) => state.ollama_models.take(),
+            Err(_) => None,
+        };
+        let (Some(result), Some(listing)) = (result, self.ollama_listing.take()) else {
+            return false;
+        };
+
+        match (listing, result) {
+            (OllamaListing::Manager, Ok(models)) => {
+                let labels = models.iter().map(|model| model.label()).collect();
+                self.ollama_models = models.into_iter().map(|model| model.name).collect();
+                self.menu_status.file_picker.init_ollama_picker(labels);
+            }
+            (OllamaListing::Manager, Err(e)) => {
+                self.set_message(Level::Error, format!("Can't list Ollama models: {}", e));
+            }
+            (OllamaListing::Picker, result) => {
+                // The picker shows the configured models without a server
+                let names = result
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|model| format!("{}/{}", Model::OLLAMA.provider(), model.name))
+                    .collect::<Vec<_>>();
+                if self.menu_status.file_picker_state(filepicker::Action::PickModel) {
+                    self.menu_status.file_picker.add_models(names);
+                } else if names.is_empty() {
+                    self.set_message(Level::Warning, "No models found in the config or Ollama");
+                } else if !self.is_text_input_active() && !self.menu_status.file_picker.state().0 {
+                    self.menu_status.file_picker.init_model_picker(names);
+                }
+            }
+        }
+        true
+    }
+
+    /// True once after the wrap toggle command
+    pub fn take_soft_wrap_toggle(&mut self) -> bool {
+        std::mem::take(&mut self.soft_wrap_toggle)
+    }
+
+    /// Moves on to the next built-in or user theme
+    fn switch_theme(&mut self) {
+        if let Err(e) = theme::switch_to_next() {
+            self.set_message(Level::Error, format!("Can't switch theme: {}", e));
+        }
+    }
+
+    pub fn toggle_request_panel(&mut self) {
+        self.show_request_panel = !self.show_request_panel;
+    }
+
+    /// The panel is shown on demand and whenever requests are waiting
+    pub fn request_panel_visible(&self) -> bool {
+        self.show_request_panel || self.async_handler.has_pending()
+    }
+
+    fn handle_search_prompt(&mut self, key: KeyCode, modifiers: KeyModifiers) -> Result<bool> {
+        match key {
+            KeyCode::Char('c') if modifiers.contains(KeyModifiers::ALT) => {
+                self.search.toggle_case_insensitive()
+            }
+            KeyCode::Char('r') if modifiers.contains(KeyModifiers::ALT) => {
+                self.search.toggle_regex()
+            }
+            KeyCode::Char(c) => self.search.insert_char(c),
+            KeyCode::Backspace => self.search.delete_previous_char(),
+            KeyCode::Delete => self.search.delete_current_char(),
+            KeyCode::Left => self.search.move_cursor_pos_left(),
+            KeyCode::Right => self.search.move_cursor_pos_right(),
+            KeyCode::Enter => {
+                self.search.close();
+                if self.replace.is_entering_pattern() {
+                    self.replace.enter_replacement();
+                    return Ok(false);
+                }
+                return self.jump_to_match(true);
+            }
+            KeyCode::Esc => {
+                self.search.clear();
+                self.replace.cancel();
+            }
+            _ => {}
+        }
+
+        // Incremental highlighting while typing
+        self.search.refresh(&self.buffer);
+
+        Ok(false)
+    }
+
+    /// Keys of the replacement prompt and of the per-match question
+    fn handle_replace(&mut self, key: KeyCode) -> Result<bool> {
+        if self.replace.is_entering_replacement() {
+            match key {
+                KeyCode::Char(c) => self.replace.insert_char(c),
+                KeyCode::Backspace => self.replace.delete_previous_char(),
+                KeyCode::Delete => self.replace.delete_current_char(),
+                KeyCode::Left => self.replace.move_cursor_pos_left(),
+                KeyCode::Right => self.replace.move_cursor_pos_right(),
+                KeyCode::Enter => {
+                    // Goes through the whole buffer from the top
+                    self.search.refresh(&self.buffer);
+                    let first = self.search.match_from(0);
+                    if first.is_none() {
+                        self.set_message(Level::Warning, "Pattern not found");
+                    }
+                    self.replace.confirm(first);
+                }
+                KeyCode::Esc => self.replace.cancel(),
+                _ => {}
+            }
+            self.show_replace_candidate();
+            return Ok(false);
+        }
+
+        let Some(current) = self.replace.current() else {
+            self.replace.cancel();
+            return Ok(false);
+        };
+        let replacement = self.replace.get_input().to_string();
+
+        match key {
+            KeyCode::Char('y') => {
+                let found = self
+                    .search
+                    .replacements(&self.buffer, current.start, &replacement)
+                    .into_iter()
+                    .next();
+                // Continue after the new text, so it's never matched again
+                let next_from = match found {
+                    Some((range, text)) if !self.edit_blocked(range.clone()) => {
+                        let end = range.start + text.chars().count();
+                        self.apply_replacements(vec![(range, text)]);
+                        end
+                    }
+                    _ => current.end,
+                };
+                self.search.refresh(&self.buffer);
+                let next = self.search.match_from(next_from);
+                self.replace.confirm(next);
+            }
+            KeyCode::Char('n') => {
+                let next = self.search.match_from(current.end);
+                self.replace.confirm(next);
+            }
+            KeyCode::Char('a') => {
+                let rest = self
+                    .search
+                    .replacements(&self.buffer, current.start, &replacement);
+                self.apply_replacements(rest);
+                self.replace.confirm(None);
+            }
+            KeyCode::Char('q') | KeyCode::Esc => self.replace.cancel(),
+            _ => {}
+        }
+
+        self.show_replace_candidate();
+        Ok(false)
+    }
+
+    /// Applies replacements, last first so the earlier ranges stay valid
+    fn apply_replacements(&mut self, mut replacements: Vec<(Range<usize>, String)>) {
+        // Matches inside locked answers are left alone
+        let total = replacements.len();
+        replacements.retain(|(range, _)| !self.protected.blocks(range));
+        if replacements.len() < total {
+            self.set_message(
+                Level::Warning,
+                format!("Skipped {} matches in read-only answers", total - replacements.len()),
+            );
+        }
+
+        let Some(first) = replacements.first().map(|(range, _)| range.start) else {
+            return;
+        };
+
+        for (range, text) in replacements.iter().rev() {
+            self.record_edit(range.clone(), text);
+            self.buffer.remove(range.clone());
+            self.buffer.insert(range.start, text);
+        }
+
+        self.replace.count_replaced(replacements.len());
+        self.modified = true;
+        self.invalidate_syntax_at_line(self.buffer.char_to_line(first));
+    }
+
+    /// Puts the cursor on the match in question so it scrolls into view
+    fn show_replace_candidate(&mut self) {
+        if let Some(current) = self.replace.current() {
+            let (row, col) = self.position_from_char_idx(current.start);
+            self.cursor_row = row;
+            self.cursor_col = col;
+        }
+    }
+
+    /// Moves the cursor to the next (or previous) search match
+    fn jump_to_match(&mut self, forward: bool) -> Result<bool> {
+        self.search.refresh(&self.buffer);
+
+        let char_idx = self.get_char_idx();
+        let target = if forward {
+            self.search.next_match(char_idx)
+        } else {
+            self.search.previous_match(char_idx)
+        };
+
+        if let Some(target) = target {
+            let (row, col) = self.position_from_char_idx(target);
+            self.cursor_row = row;
+            self.cursor_col = col;
+        }
+
+        Ok(false)
+    }
+
+    /// Opens the model picker with the configured models. Whatever the
+    /// local Ollama server reports is added when it answers, the picker
+    /// opens then when no models are configured.
+    fn open_model_picker(&mut self) -> Result<()> {
+        let configured = models::configured_models()?;
+        if configured.is_empty() {
+            self.set_message(Level::Info, "Looking for Ollama models...");
+        } else {
+            self.menu_status.file_picker.init_model_picker(configured);
+        }
+
+        self.ollama_listing = Some(OllamaListing::Picker);
+        self.async_handler.fetch_ollama_models();
+        Ok(())
+    }
+
+    /// Lists the providers that need an API key and whether they have one
+    fn open_key_provider_picker(&mut self) {
+        let labels = credentials::PROVIDERS
+            .iter()
+            .map(|model| {
+                let state = if credentials::is_configured(model) {
+                    "set"
+                } else {
+                    "missing"
+                };
+                let var = model.api_key_env().unwrap_or_default();
+                format!("{} ({}) - {}", credentials::name(model), var, state)
+            })
+            .collect();
+
+        self.menu_status.file_picker.init_key_provider_picker(labels);
+    }
+
+    /// Shows right away when the current model has no API key
+    pub fn check_api_key(&mut self) {
+        if let Err(e) = self.chat_context.model.check_api_key() {
+            self.set_message(Level::Error, e.to_string());
+        }
+    }
+
+    /// Current provider and model, e.g. `openai/gpt-4.1-mini`
+    pub fn model_label(&self) -> String {
+        let model = &self.chat_context.model;
+        format!("{}/{}", model.provider(), self.chat_context.model_name(model))
+    }
+
+    /// Aborts the running request. With `discard` set, whatever part of the
+    /// answer was already appended is removed from the buffer as well.
+    fn cancel_request(&mut self, discard: bool) {
+        if self.get_request_state() != RequestState::Proccessing
+            && !self.async_handler.has_pending()
+        {
+            return;
+        }
+
+        self.async_handler.cancel_request(discard);
+        self.fix_pending = false;
+        self.inline_edit = None;
+
+        // Without discard the queue goes on with the next request
+        if discard {
+            self.drop_partial_answer();
+            self.async_handler.finish_current();
+            self.needs_response_check = false;
+        }
+    }
+
+    // Removes what the last request put in the buffer. One that went to
+    // another buffer or a file stays there.
+    fn drop_partial_answer(&mut self) {
+        self.file_answer = None;
+        if !matches!(self.answer_target(), AnswerTarget::Active) {
+            return;
+        }
+        let Some(start) = self.response_start.take() else {
+            return;
+        };
+        match &mut self.split {
+            Some(split) => split.truncate(start),
+            None => {
+                let len = self.buffer.len_chars();
+                let end = self.response_at.take().unwrap_or(len).min(len);
+                if start < end {
+                    self.record_edit(start..end, "");
+                    self.buffer.remove(start..end);
+                    self.clamp_cursor();
+                    self.update_syntax_highlighting();
+                }
+            }
+        }
+    }
+
+    /// Sends the request that timed out again. What it streamed before it
+    /// stalled makes room for the new answer.
+    fn retry_request(&mut self) {
+        self.drop_partial_answer();
+        self.last_answer = None;
+        if !self.async_handler.retry() {
+            self.set_message(Level::Warning, "No request to retry");
+            return;
+        }
+        self.start_next_request();
+        self.needs_response_check = true;
+    }
+
+    /// Seconds the running request has waited for its answer and its
+    /// timeout, once it's past half of it
+    pub fn slow_request(&self) -> Option<(u64, u64)> {
+        if self.answer_started {
+            return None;
+        }
+        let (elapsed, timeout) = self.async_handler.running_since()?;
+        let timeout = timeout?;
+        (elapsed * 2 >= timeout).then_some((elapsed.as_secs(), timeout.as_secs()))
+    }
+
+    fn handle_normal_mode(&mut self, key: KeyCode, modifiers: KeyModifiers) -> Result<bool> {
+        if self.registers.is_selecting() {
+            if let KeyCode::Char(c) = key {
+                self.registers.select(c);
+            } else {
+                self.registers.select(' ');
+            }
+            return Ok(false);
+        }
+
+        if self.macros.is_pending() {
+            let name = match key {
+                KeyCode::Char(c) => c,
+                _ => ' ',
+            };
+            if let Some((keys, count)) = self.macros.name_register(name) {
+                return self.play_macro(keys, count);
+            }
+            return Ok(false);
+        }
+
+        if let Some(pending) = self.text_object.take() {
+            if let Some(result) = self.handle_text_object(pending, key) {
+                return result;
+            }
+        }
+
+        if let KeyCode::Char(c @ '1'..='9') = key {
+            if modifiers.contains(KeyModifiers::ALT) && !self.is_text_input_active() {
+                self.insert_pin(c as usize - '0' as usize)?;
+                return Ok(false);
+            }
+        }
+
+        if modifiers.contains(KeyModifiers::ALT) && key == KeyCode::Char('v') {
+            match self.paste_from_clipboard() {
+                Ok(_) => return Ok(false),
+                Err(e) => self.set_message(Level::Error, format!("Paste error: {}", e)),
+            }
+        }
+
+        if self.menu_status.file_picker_state(filepicker::Action::Save) {
+            match key {
+                KeyCode::Char(c) => {
+                    // Insert character at cursor position
+                    self.menu_status.file_picker.insert_char(c);
+
+                    return Ok(false);
+                }
+                KeyCode::Backspace => {
+                    self.menu_status.file_picker.delete_previous_char();
+
+                    return Ok(false);
+                }
+                KeyCode::Delete => {
+                    self.menu_status.file_picker.delete_current_char();
+
+                    return Ok(false);
+                }
+                KeyCode::Left => {
+                    self.menu_status.file_picker.move_cursor_pos_left();
+
+                    return Ok(false);
+                }
+                KeyCode::Right => {
+                    self.menu_status.file_picker.move_cursor_pos_right();
+
+                    return Ok(false);
+                }
+                KeyCode::Enter => {
+                    let filename = self.menu_status.file_picker.get_input();
+                    if !filename.is_empty() {
+                        // Save to file
+                        let content = self.line_ending.apply(&self.buffer.to_string());
+                        autosave::remove(&self.history.file_path);
+                        self.history.save_to_file(filename.to_string(), content, self.encoding)?;
+                        self.scratch = false;
+                        self.track_file();
+
+                        let (content, _) = line_ending::normalize(&self.history.current_file_content()?);
+
+                        self.buffer = Rope::from_str(&content);
+                        self.cursor_row = 0;
+                        self.cursor_col = 0;
+                        self.modified = false;
+
+                        // Update syntax highlighting
+                        self.update_syntax_highlighting();
+
+                        self.modified = false;
+                    }
+
+                    self.menu_status.reset();
+                    return Ok(false);
+                }
+                KeyCode::Esc => {
+                    self.menu_status.reset();
+                    return Ok(false);
+                }
+                _ => return Ok(false),
+            }
+        }
+
+        if self.menu_status.file_picker_state(filepicker::Action::Load) {
+            match key {
+                KeyCode::Up => {
+                    self.menu_status.file_picker.move_file_picker_up();
+                    return Ok(false);
+                }
+                KeyCode::Down => {
+                    self.menu_status.file_picker.move_file_picker_down();
+                    return Ok(false);
+                }
+                KeyCode::Enter => {
+                    if let Some(selected_file) = self.menu_status.file_picker.open_selected_entry() {
+                        self.menu_status.reset(); // close popup
+                        self.load_history_file(&selected_file)?;
+                    }
+                    return Ok(false);
+                }
+                KeyCode::Esc => {
+                    self.menu_status.reset(); // close popup
+                    return Ok(false);
+                }
+                KeyCode::Char('r') if modifiers.contains(KeyModifiers::CONTROL) => {
+                    if let Some(name) = self.menu_status.file_picker.selected_file() {
+                        self.menu_status.file_picker.init_rename(&name);
+                        self.renaming = Some(name);
+                    }
+                    return Ok(false);
+                }
+                KeyCode::Char('x') if modifiers.contains(KeyModifiers::CONTROL) => {
+                    if let Some(name) = self.menu_status.file_picker.selected_file() {
+                        self.confirm = Some(Confirm::new(
+                            "Delete this file?",
+                            vec![name.clone()],
+                            "y: Delete | n: Keep",
+                            ConfirmAction::DeleteFile(name),
+                        ));
+                    }
+                    return Ok(false);
+                }
+                KeyCode::Char('c') if modifiers.contains(KeyModifiers::CONTROL) => {
+                    if let Some(name) = self.menu_status.file_picker.selected_file() {
+                        let path = Path::new(&self.history.root).join(&name);
+                        let copied = files::duplicate_file(&path)
+                            .and_then(|_| self.menu_status.file_picker.reload_finder());
+                        if let Err(e) = copied {
+                            self.set_message(Level::Error, format!("Can't duplicate {}: {}", name, e));
+                        }
+                    }
+                    return Ok(false);
+                }
+                _ => {
+                    self.handle_finder_query(key);
+                    return Ok(false);
+                }
+            }
+        }
+
+        if self.search.is_active() {
+            return self.handle_search_prompt(key, modifiers);
+        }
+
+        if self.menu_status.file_picker_state(filepicker::Action::SearchHistory) {
+            match key {
+                KeyCode::Up => self.menu_status.file_picker.move_file_picker_up(),
+                KeyCode::Down => self.menu_status.file_picker.move_file_picker_down(),
+                KeyCode::Enter => {
+                    let index = self.menu_status.file_picker.get_selected_file_index();
+                    let target = self.history_search.as_ref().and_then(|search| search.target(index));
+                    if let Some((name, line)) = target {
+                        self.menu_status.reset();
+                        self.history_search = None;
+                        self.pending_position = Some((line, None));
+                        self.load_history_file(&name)?;
+                        // Sessions open right away, other files once loaded
+                        if !self.is_loading() {
+                            if let Some((line, column)) = self.pending_position.take() {
+                                self.goto_position(line, column);
+                            }
+                        }
+                    }
+                }
+                KeyCode::Esc => {
+                    self.menu_status.reset();
+                    self.history_search = None;
+                }
+                _ => {
+                    self.handle_finder_query(key);
+                    if let Some(search) = &mut self.history_search {
+                        search.search(&self.menu_status.file_picker.get_input());
+                        self.menu_status.file_picker.show_items(search.labels());
+                    }
+                }
+            }
+            return Ok(false);
+        }
+
+        if self.menu_status.file_picker_state(filepicker::Action::PromptHistory) {
+            match key {
+                KeyCode::Up => self.menu_status.file_picker.move_file_picker_up(),
+                KeyCode::Down => self.menu_status.file_picker.move_file_picker_down(),
+                KeyCode::Enter => {
+                    let prompt = self.menu_status.file_picker.selected_choice().cloned();
+                    self.menu_status.reset();
+                    if let Some(prompt) = prompt {
+                        self.recall_into_prompt(&prompt);
+                    }
+                }
+                KeyCode::Esc => self.menu_status.reset(),
+                _ => self.handle_finder_query(key),
+            }
+            return Ok(false);
+        }
+
+        // Enter toggles a file and keeps the picker open for more
+        if self.menu_status.file_picker_state(filepicker::Action::Attach) {
+            match key {
+                KeyCode::Up => self.menu_status.file_picker.move_file_picker_up(),
+                KeyCode::Down => self.menu_status.file_picker.move_file_picker_down(),
+                KeyCode::Enter => {
+                    if let Some(name) = self.menu_status.file_picker.open_selected_entry() {
+                        self.toggle_attachment(&name);
+                    }
+                }
+                KeyCode::Esc => self.menu_status.reset(),
+                _ => self.handle_finder_query(key),
+            }
+            return Ok(false);
+        }
+
+        if self.menu_status.file_picker_state(filepicker::Action::Import) {
+            match key {
+                KeyCode::Up => self.menu_status.file_picker.move_file_picker_up(),
+                KeyCode::Down => self.menu_status.file_picker.move_file_picker_down(),
+                KeyCode::Enter => {
+                    if let Some(name) = self.menu_status.file_picker.open_selected_entry() {
+                        self.menu_status.reset();
+                        if let Err(e) = self.import_conversation(&name) {
+                            self.set_message(Level::Error, format!("Can't import {}: {}", name, e));
+                        }
+                    }
+                }
+                KeyCode::Esc => self.menu_status.reset(),
+                _ => self.handle_finder_query(key),
+            }
+            return Ok(false);
+        }
+
+        if self.menu_status.file_picker_state(filepicker::Action::ExtractBlock) {
+            match key {
+                KeyCode::Up => self.menu_status.file_picker.move_file_picker_up(),
+                KeyCode::Down => self.menu_status.file_picker.move_file_picker_down(),
+                KeyCode::Enter => {
+                    let index = self.menu_status.file_picker.get_selected_file_index();
+                    let block = self.code_blocks().into_iter().nth(index);
+                    self.menu_status.reset();
+
+                    if let Some(block) = block {
+                        let default = format!("block_{}.{}", index + 1, block.extension());
+                        self.menu_status.file_picker.init_block_save_as(&default);
+                        self.extracted_block = Some(block.code);
+                    }
+                }
+                KeyCode::Esc => self.menu_status.reset(),
+                _ => {}
+            }
+            return Ok(false);
+        }
+
+        if self.menu_status.file_picker_state(filepicker::Action::RenameFile) {
+            match key {
+                KeyCode::Char(c) => self.menu_status.file_picker.insert_char(c),
+                KeyCode::Backspace => self.menu_status.file_picker.delete_previous_char(),
+                KeyCode::Delete => self.menu_status.file_picker.delete_current_char(),
+                KeyCode::Left => self.menu_status.file_picker.move_cursor_pos_left(),
+                KeyCode::Right => self.menu_status.file_picker.move_cursor_pos_right(),
+                KeyCode::Enter => {
+                    let to = self.menu_status.file_picker.get_input();
+                    if let Some(from) = self.renaming.take() {
+                        if !to.is_empty() && to != from {
+                            self.rename_file(&from, &to);
+                        }
+                    }
+                    self.back_to_load_picker();
+                }
+                KeyCode::Esc => {
+                    self.renaming = None;
+                    self.back_to_load_picker();
+                }
+                _ => {}
+            }
+            return Ok(false);
+        }
+
+        if self.menu_status.file_picker_state(filepicker::Action::SaveBlock) {
+            match key {
+                KeyCode::Char(c) => self.menu_status.file_picker.insert_char(c),
+                KeyCode::Backspace => self.menu_status.file_picker.delete_previous_char(),
+                KeyCode::Delete => self.menu_status.file_picker.delete_current_char(),
+                KeyCode::Left => self.menu_status.file_picker.move_cursor_pos_left(),
+                KeyCode::Right => self.menu_status.file_picker.move_cursor_pos_right(),
+                KeyCode::Enter => {
+                    let path = self.menu_status.file_picker.get_input();
+                    if let Some(code) = self.extracted_block.take() {
+                        if !path.is_empty() {
+                            if let Err(e) = files::create_file(&path, &(code + "\n")) {
+                                self.set_message(Level::Error, format!("Can't extract block: {}", e));
+                            }
+                        }
+                    }
+                    self.menu_status.reset();
+                }
+                KeyCode::Esc => {
+                    self.extracted_block = None;
+                    self.menu_status.reset();
+                }
+                _ => {}
+            }
+            return Ok(false);
+        }
+
+        if self.menu_status.file_picker_state(filepicker::Action::PickSession) {
+            match key {
+                KeyCode::Up => self.menu_status.file_picker.move_file_picker_up(),
+                KeyCode::Down => self.menu_status.file_picker.move_file_picker_down(),
+                KeyCode::Enter => {
+                    let path = self.menu_status.file_picker.selected_session().cloned();
+                    self.menu_status.reset();
+
+                    if let Some(path) = path {
+                        self.open_session(Session::open(&path)?)?;
+                    }
+                }
+                KeyCode::Esc => self.menu_status.reset(),
+                _ => self.handle_finder_query(key),
+            }
+            return Ok(false);
+        }
+
+        if self.menu_status.file_picker_state(filepicker::Action::PickKeyProvider) {
+            match key {
+                KeyCode::Up => self.menu_status.file_picker.move_file_picker_up(),
+                KeyCode::Down => self.menu_status.file_picker.move_file_picker_down(),
+                KeyCode::Enter => {
+                    let index = self.menu_status.file_picker.get_selected_file_index();
+                    self.menu_status.reset();
+
+                    if let Some(model) = credentials::PROVIDERS.get(index) {
+                        self.key_provider = Some(model.clone());
+                        self.menu_status.file_picker.init_api_key_input();
+                    }
+                }
+                KeyCode::Esc => self.menu_status.reset(),
+                _ => {}
+            }
+            return Ok(false);
+        }
+
+        // The key is used for this session right away, saving it is asked after
+        if self.menu_status.file_picker_state(filepicker::Action::ApiKey) {
+            match key {
+                KeyCode::Char(c) => self.menu_status.file_picker.insert_char(c),
+                KeyCode::Backspace => self.menu_status.file_picker.delete_previous_char(),
+                KeyCode::Delete => self.menu_status.file_picker.delete_current_char(),
+                KeyCode::Left => self.menu_status.file_picker.move_cursor_pos_left(),
+                KeyCode::Right => self.menu_status.file_picker.move_cursor_pos_right(),
+                KeyCode::Enter => {
+                    let key = self.menu_status.file_picker.get_input();
+                    self.menu_status.reset();
+
+                    if let Some(model) = self.key_provider.take() {
+                        if !key.is_empty() {
+                            credentials::set(&model, &key);
+                            self.confirm = Some(Confirm::new(
+                                &format!("{} key set for this session", credentials::name(&model)),
+                                vec![format!(
+                                    "Save it to {}?",
+                                    credentials::path().display()
+                                )],
+                                "y: Save | n: Only this session",
+                                ConfirmAction::SaveApiKey { model, key },
+                            ));
+                        }
+                    }
+                }
+                KeyCode::Esc => {
+                    self.key_provider = None;
+                    self.menu_status.reset();
+                }
+                _ => {}
+            }
+            return Ok(false);
+        }
+
+        if self.menu_status.file_picker_state(filepicker::Action::OllamaModels) {
+            match key {
+                KeyCode::Up => self.menu_status.file_picker.move_file_picker_up(),
+                KeyCode::Down => self.menu_status.file_picker.move_file_picker_down(),
+                KeyCode::Enter => {
+                    let index = self.menu_status.file_picker.get_selected_file_index();
+                    self.menu_status.reset();
+
+                    if let Some(name) = self.ollama_models.get(index) {
+                        let entry = format!("{}/{}", Model::OLLAMA.provider(), name);
+                        self.chat_context.select_model(&entry)?;
+                        self.async_handler
+                            .set_chat_context(self.chat_context.clone());
+                    }
+                }
+                KeyCode::Char('p') => {
+                    self.menu_status.reset();
+                    self.menu_status.file_picker.init_pull_input();
+                }
+                KeyCode::Esc => self.menu_status.reset(),
+                _ => {}
+            }
+            return Ok(false);
+        }
+
+        if self.menu_status.file_picker_state(filepicker::Action::PullModel) {
+            match key {
+                KeyCode::Char(c) => self.menu_status.file_picker.insert_char(c),
+                KeyCode::Backspace => self.menu_status.file_picker.delete_previous_char(),
+                KeyCode::Delete => self.menu_status.file_picker.delete_current_char(),
+                KeyCode::Left => self.menu_status.file_picker.move_cursor_pos_left(),
+                KeyCode::Right => self.menu_status.file_picker.move_cursor_pos_right(),
+                KeyCode::Enter => {
+                    let name = self.menu_status.file_picker.get_input();
+                    if !name.is_empty() {
+                        self.async_handler.pull_model(name);
+                    }
+                    self.menu_status.reset();
+                }
+                KeyCode::Esc => self.menu_status.reset(),
+                _ => {}
+            }
+            return Ok(false);
+        }
+
+        if self.menu_status.file_picker_state(filepicker::Action::InlineEdit) {
+            match key {
+                KeyCode::Char(c) => self.menu_status.file_picker.insert_char(c),
+                KeyCode::Backspace => self.menu_status.file_picker.delete_previous_char(),
+                KeyCode::Delete => self.menu_status.file_picker.delete_current_char(),
+                KeyCode::Left => self.menu_status.file_picker.move_cursor_pos_left(),
+                KeyCode::Right => self.menu_status.file_picker.move_cursor_pos_right(),
+                KeyCode::Enter => {
+                    let instruction = self.menu_status.file_picker.get_input();
+                    self.menu_status.reset();
+                    if instruction.trim().is_empty() {
+                        self.inline_edit = None;
+                    } else {
+                        self.send_inline_edit(&instruction);
+                    }
+                }
+                KeyCode::Esc => {
+                    self.menu_status.reset();
+                    self.inline_edit = None;
+                }
+                _ => {}
+            }
+            return Ok(false);
+        }
+
+        if self.menu_status.file_picker_state(filepicker::Action::RenameSession) {
+            match key {
+                KeyCode::Char(c) => self.menu_status.file_picker.insert_char(c),
+                KeyCode::Backspace => self.menu_status.file_picker.delete_previous_char(),
+                KeyCode::Delete => self.menu_status.file_picker.delete_current_char(),
+                KeyCode::Left => self.menu_status.file_picker.move_cursor_pos_left(),
+                KeyCode::Right => self.menu_status.file_picker.move_cursor_pos_right(),
+                KeyCode::Enter => {
+                    let title = self.menu_status.file_picker.get_input();
+                    self.menu_status.reset();
+                    if let Some(session) = &mut self.history.session {
+                        if !title.trim().is_empty() {
+                            if let Err(e) = session.set_title(&title) {
+                                self.set_message(Level::Error, format!("Can't rename session: {}", e));
+                            }
+                        }
+                    }
+                }
+                KeyCode::Esc => self.menu_status.reset(),
+                _ => {}
+            }
+            return Ok(false);
+        }
+
+        if self.menu_status.file_picker_state(filepicker::Action::TagSession) {
+            match key {
+                KeyCode::Char(c) => self.menu_status.file_picker.insert_char(c),
+                KeyCode::Backspace => self.menu_status.file_picker.delete_previous_char(),
+                KeyCode::Delete => self.menu_status.file_picker.delete_current_char(),
+                KeyCode::Left => self.menu_status.file_picker.move_cursor_pos_left(),
+                KeyCode::Right => self.menu_status.file_picker.move_cursor_pos_right(),
+                KeyCode::Enter => {
+                    let tags = self.menu_status.file_picker.get_input();
+                    self.menu_status.reset();
+                    if let Some(session) = &mut self.history.session {
+                        if let Err(e) = session.set_tags(&tags) {
+                            self.set_message(Level::Error, format!("Can't tag session: {}", e));
+                        }
+                    }
+                }
+                KeyCode::Esc => self.menu_status.reset(),
+                _ => {}
+            }
+            return Ok(false);
+        }
+
+        if self.menu_status.file_picker_state(filepicker::Action::RunAttach) {
+            match key {
+                KeyCode::Char(c) => self.menu_status.file_picker.insert_char(c),
+                KeyCode::Backspace => self.menu_status.file_picker.delete_previous_char(),
+                KeyCode::Delete => self.menu_status.file_picker.delete_current_char(),
+                KeyCode::Left => self.menu_status.file_picker.move_cursor_pos_left(),
+                KeyCode::Right => self.menu_status.file_picker.move_cursor_pos_right(),
+                KeyCode::Enter => {
+                    let command = self.menu_status.file_picker.get_input();
+                    self.menu_status.reset();
+                    if !command.trim().is_empty() {
+                        self.shell_command = command.clone();
+                        self.async_handler.run_shell(command, ShellPurpose::Attach);
+                    }
+                }
+                KeyCode::Esc => self.menu_status.reset(),
+                _ => {}
+            }
+            return Ok(false);
+        }
+
+        if self.menu_status.file_picker_state(filepicker::Action::CommitHistory) {
+            match key {
+                KeyCode::Char(c) => self.menu_status.file_picker.insert_char(c),
+                KeyCode::Backspace => self.menu_status.file_picker.delete_previous_char(),
+                KeyCode::Delete => self.menu_status.file_picker.delete_current_char(),
+                KeyCode::Left => self.menu_status.file_picker.move_cursor_pos_left(),
+                KeyCode::Right => self.menu_status.file_picker.move_cursor_pos_right(),
+                KeyCode::Enter => {
+                    let message = self.menu_status.file_picker.get_input();
+                    self.menu_status.reset();
+                    if !message.trim().is_empty() {
+                        self.commit_history(&message);
+                    }
+                }
+                KeyCode::Esc => self.menu_status.reset(),
+                _ => {}
+            }
+            return Ok(false);
+        }
+
+        if self.menu_status.file_picker_state(filepicker::Action::GotoLine) {
+            match key {
+                KeyCode::Char(c) if c.is_ascii_digit() => {
+                    self.menu_status.file_picker.insert_char(c)
+                }
+                KeyCode::Backspace => self.menu_status.file_picker.delete_previous_char(),
+                KeyCode::Delete => self.menu_status.file_picker.delete_current_char(),
+                KeyCode::Left => self.menu_status.file_picker.move_cursor_pos_left(),
+                KeyCode::Right => self.menu_status.file_picker.move_cursor_pos_right(),
+                KeyCode::Enter => {
+                    let input = self.menu_status.file_picker.get_input();
+                    self.menu_status.reset();
+                    if let Ok(line) = input.parse::<usize>() {
+                        self.goto_line(line);
+                    }
+                }
+                KeyCode::Esc => self.menu_status.reset(),
+                _ => {}
+            }
+            return Ok(false);
+        }
+
+        if self.menu_status.file_picker_state(filepicker::Action::PickModel) {
+            match key {
+                KeyCode::Up => self.menu_status.file_picker.move_file_picker_up(),
+                KeyCode::Down => self.menu_status.file_picker.move_file_picker_down(),
+                KeyCode::Enter => {
+                    if let Some(entry) = self.menu_status.file_picker.get_selected_file() {
+                        let entry = entry.to_string();
+                        self.chat_context.select_model(&entry)?;
+                        self.async_handler
+                            .set_chat_context(self.chat_context.clone());
+                    }
+                    self.menu_status.reset();
+                }
+                KeyCode::Esc => self.menu_status.reset(),
+                _ => {}
+            }
+            return Ok(false);
+        }
+
+        // Handle the key after 'z'
+        if self.menu_status.is_active(MenuType::Fold) {
+            self.menu_status.reset();
+
+            match keymap::command(Context::Fold, key, modifiers) {
+                Some(Command::ToggleFold) => return self.toggle_fold(),
+                Some(Command::FoldAll) => return self.fold_all(),
+                Some(Command::UnfoldAll) => {
+                    self.folds.clear();
+                    return Ok(false);
+                }
+                _ => return Ok(false),
+            }
+        }
+
+        // Handle the key after 't'
+        if self.menu_status.is_active(MenuType::Table) {
+            self.menu_status.reset();
+
+            match keymap::command(Context::Table, key, modifiers) {
+                Some(Command::FormatTable) => return self.edit_table(|_, row, cell| (row, cell)),
+                Some(Command::InsertTableRow) => {
+                    return self.edit_table(|table, row, cell| (table.insert_row(row), cell))
+                }
+                Some(Command::InsertTableColumn) => {
+                    return self.edit_table(|table, row, cell| (row, table.insert_column(cell)))
+                }
+                _ => return Ok(false),
+            }
+        }
+
+        // Handle the key 'Go To (g)' menu
+        if self.menu_status.is_active(MenuType::GoTo) {
+            self.menu_status.reset(); // Reset the flag
+
+            // `g12` starts the line number prompt with the digits typed
+            if let KeyCode::Char(c) = key {
+                if c.is_ascii_digit() {
+                    self.menu_status.file_picker.init_goto_line(&c.to_string());
+                    return Ok(false);
+                }
+            }
+
+            match keymap::command(Context::GoTo, key, modifiers) {
+                Some(Command::LineEnd) => return self.move_to_end_of_line(),
+                Some(Command::LineStart) => return self.move_to_start_of_line(),
+                Some(Command::BufferStart) => return self.move_to_start_of_buffer(),
+                Some(Command::BufferEnd) => return self.move_to_end_of_buffer(),
+                Some(Command::NextBuffer) => return self.cycle_buffer(true),
+                Some(Command::PreviousBuffer) => return self.cycle_buffer(false),
+                Some(Command::ToggleComment) => return self.toggle_comment(),
+                Some(Command::OpenFileUnderCursor) => return self.open_file_under_cursor(),
+                Some(Command::GotoLine) => {
+                    self.menu_status.file_picker.init_goto_line("");
+                    return Ok(false);
+                }
+                _ => return Ok(false),
+            }
+        }
+
+        if self.menu_status.is_active(MenuType::AI) {
+            self.menu_status.reset(); // Reset the flag
+
+            match keymap::command(Context::AI, key, modifiers) {
+                Some(Command::SendAnthropic) => {
+                    self.send_to_anthropic()?;
+                    return Ok(false);
+                }
+                Some(Command::SendOpenAI) => {
+                    self.send_to_openai()?;
+                    return Ok(false);
+                }
+                Some(Command::SendFanOut) => {
+                    if let Err(e) = self.send_fan_out() {
+                        self.set_message(Level::Error, format!("Fan-out: {}", e));
+                    }
+                    return Ok(false);
+                }
+                Some(Command::SendOllama) => {
+                    self.send_to_ollama()?;
+                    return Ok(false);
+                }
+                Some(Command::ToggleStreaming) => {
+                    self.stream_responses = !self.stream_responses;
+                    return Ok(false);
+                }
+                Some(Command::CancelRequest) => {
+                    self.cancel_request(true);
+                    return Ok(false);
+                }
+                Some(Command::PickModel) => {
+                    self.open_model_picker()?;
+                    return Ok(false);
+                }
+                Some(Command::OllamaModels) => {
+                    self.open_ollama_manager();
+                    return Ok(false);
+                }
+                Some(Command::AttachFiles) => {
+                    self.menu_status.file_picker.init_attach_picker()?;
+                    return Ok(false);
+                }
+                Some(Command::ClearAttachments) => {
+                    self.attachments.clear();
+                    return Ok(false);
+                }
+                Some(Command::ToggleProject) => {
+                    if let Err(e) = self.project.toggle() {
+                        self.set_message(Level::Error, format!("Can't list the project: {}", e));
+                    }
+                    return Ok(false);
+                }
+                Some(Command::RefreshProject) => {
+                    if let Err(e) = self.project.refresh() {
+                        self.set_message(Level::Error, format!("Can't list the project: {}", e));
+                    }
+                    return Ok(false);
+                }
+                Some(Command::EditSystemPrompt) => {
+                    self.prompt_editor.open(&self.chat_context.system_prompt);
+                    return Ok(false);
+                }
+                Some(Command::EditParams) => {
+                    self.params_form.open(&self.chat_context.params);
+                    return Ok(false);
+                }
+                Some(Command::RequestPanel) => {
+                    self.toggle_request_panel();
+                    return Ok(false);
+                }
+                Some(Command::RequestLog) => {
+                    self.log_view = Some(log_view::LogView::new());
+                    return Ok(false);
+                }
+                Some(Command::Usage) => {
+                    self.usage_view = Some(log_view::LogView::new());
+                    return Ok(false);
+                }
+                Some(Command::ApiKey) => {
+                    self.open_key_provider_picker();
+                    return Ok(false);
+                }
+                Some(Command::UnlockAnswers) => {
+                    self.protected.toggle_unlocked();
+                    return Ok(false);
+                }
+                Some(Command::TogglePin) => {
+                    self.toggle_pin_at_cursor();
+                    return Ok(false);
+                }
+                Some(Command::Regenerate) => {
+                    self.regenerate_answer();
+                    return Ok(false);
+                }
+                Some(Command::InlineEdit) => {
+                    self.start_inline_edit();
+                    return Ok(false);
+                }
+                Some(Command::UndoRewrite) => {
+                    self.undo_rewrite();
+                    return Ok(false);
+                }
+                Some(Command::Explain) => {
+                    self.start_assist(assist::Assist::Explain);
+                    return Ok(false);
+                }
+                Some(Command::DocComment) => {
+                    self.start_assist(assist::Assist::DocComment);
+                    return Ok(false);
+                }
+                Some(Command::UnitTests) => {
+                    self.start_assist(assist::Assist::Tests);
+                    return Ok(false);
+                }
+                Some(Command::ToggleInlineCompletion) => {
+                    let message = if self.ghost.toggle() {
+                        "Inline completion on (Tab takes a suggestion)"
+                    } else {
+                        "Inline completion off"
+                    };
+                    self.set_message(Level::Info, message);
+                    return Ok(false);
+                }
+                Some(Command::PinsPanel) => {
+                    self.show_pins_panel = !self.show_pins_panel;
+                    return Ok(false);
+                }
+                Some(Command::PromptHistory) => {
+                    self.open_prompt_history();
+                    return Ok(false);
+                }
+                Some(Command::FixErrors) => {
+                    self.start_fix();
+                    return Ok(false);
+                }
+                Some(Command::RunAndAttach) => {
+                    self.menu_status.file_picker.init_shell_command(&self.shell_command);
+                    return Ok(false);
+                }
+                Some(Command::CycleInsertion) => {
+                    self.insertion = self.insertion.next();
+                    return Ok(false);
+                }
+                Some(Command::CycleContextTrim) => {
+                    self.cycle_context_trim();
+                    return Ok(false);
+                }
+                Some(Command::ApplyAnswerDiff) => {
+                    match self.last_answer() {
+                        Some(answer) => self.open_patch(&answer),
+                        None => {
+                            self.set_message(Level::Warning, "No answer to take a diff from");
+                        }
+                    }
+                    return Ok(false);
+                }
+                _ => return Ok(false),
             }
         }
 
-        if self.menu_status.file_picker_state(filepicker::Action::Save) {
-            match key {
-                KeyCode::Char(c) => {
-                    // Insert character at cursor position
-                    self.menu_status.file_picker.insert_char(c);
-
+        // Handle the key 'File (:)' menu
+        if self.menu_status.is_active(MenuType::File) {
+            self.menu_status.reset();
+            match keymap::command(Context::File, key, modifiers) {
+                Some(Command::WipeBuffer) => {
+                    // The answer would land in the emptied buffer
+                    if self.request_running() {
+                        self.confirm = Some(Confirm::new(
+                            "A request is still running",
+                            vec!["Wiping the buffer loses its answer".to_string()],
+                            "c: Cancel it and wipe | Esc: Wait",
+                            ConfirmAction::WipeDuringRequest,
+                        ));
+                        return Ok(false);
+                    }
+                    self.wipe_buffer()?;
                     return Ok(false);
                 }
-                KeyCode::Backspace => {
-                    self.menu_status.file_picker.delete_previous_char();
 
+                Some(Command::Save) => {
+                    self.save_file()?;
                     return Ok(false);
                 }
-                KeyCode::Delete => {
-                    self.menu_status.file_picker.delete_current_char();
 
+                Some(Command::SaveAs) => {
+                    self.close_split();
+                    self.menu_status.file_picker.init_file_save_as();
                     return Ok(false);
                 }
-                KeyCode::Left => {
-                    self.menu_status.file_picker.move_cursor_pos_left();
 
+                Some(Command::LoadFile) => {
+                    self.close_split();
+                    // self.menu_status.set_active_menu(MenuType::FilePicker);
+                    self.menu_status.file_picker.init_file_picker()?;
                     return Ok(false);
                 }
-                KeyCode::Right => {
-                    self.menu_status.file_picker.move_cursor_pos_right();
 
+                Some(Command::NewBuffer) => {
+                    self.new_buffer();
                     return Ok(false);
                 }
-                KeyCode::Enter => {
-                    let filename = self.menu_status.file_picker.get_input();
-                    if !filename.is_empty() {
-                        // Save to file
-                        let content = self.buffer.to_string();
-                        self.history.save_to_file(filename.to_string(), content)?;
 
-                        let content = self.history.current_file_content()?;
-
-                        self.buffer = Rope::from_str(&content);
-                        self.cursor_row = 0;
-                        self.cursor_col = 0;
-                        self.modified = false;
+                Some(Command::NewScratch) => {
+                    self.new_scratch();
+                    return Ok(false);
+                }
 
-                        // Update syntax highlighting
-                        self.update_syntax_highlighting();
+                Some(Command::NewSession) => {
+                    self.new_session()?;
+                    return Ok(false);
+                }
 
-                        self.modified = false;
-                    }
+                Some(Command::OpenSession) => {
+                    self.open_session_picker()?;
+                    return Ok(false);
+                }
 
-                    self.menu_status.reset();
+                Some(Command::CloseBuffer) => {
+                    self.close_buffer();
                     return Ok(false);
                 }
-                KeyCode::Esc => {
-                    self.menu_status.reset();
+
+                Some(Command::SplitView) => {
+                    self.toggle_split(split::Layout::Stacked);
                     return Ok(false);
                 }
-                _ => return Ok(false),
-            }
-        }
 
-        if self.menu_status.file_picker_state(filepicker::Action::Load) {
-            match key {
-                KeyCode::Up => {
-                    self.menu_status.file_picker.move_file_picker_up();
+                Some(Command::ChatView) => {
+                    self.toggle_split(split::Layout::Chat);
                     return Ok(false);
                 }
-                KeyCode::Down => {
-                    self.menu_status.file_picker.move_file_picker_down();
+
+                Some(Command::ExtractBlock) => {
+                    self.open_block_picker();
                     return Ok(false);
                 }
-                KeyCode::Enter => {
-                    if let Some(selected_file) = self.menu_status.file_picker.get_selected_file() {
-                        // load the selected file into editor's buffer
-                        let content = self.history.load_file(selected_file.to_string())?;
 
-                        self.buffer = Rope::from_str(&content);
-                        self.cursor_row = 0;
-                        self.cursor_col = 0;
-                        self.modified = false;
+                Some(Command::Import) => {
+                    self.menu_status.file_picker.init_import_picker()?;
+                    return Ok(false);
+                }
 
-                        // Update file path in history or state if relevant
-                        self.history.file_path = selected_file.to_string();
+                Some(Command::MessageHistory) => {
+                    self.open_message_view();
+                    return Ok(false);
+                }
 
-                        // Update syntax highlighting
-                        self.update_syntax_highlighting();
-                        // self.history.load_file(selected_file.to_string())?;
-                        self.menu_status.reset(); // close popup
+                Some(Command::RenameSession) => {
+                    match &self.history.session {
+                        Some(session) => {
+                            let title = session.title.clone();
+                            self.menu_status.file_picker.init_session_title(&title);
+                        }
+                        None => self.set_message(Level::Warning, "Only sessions have a title"),
                     }
                     return Ok(false);
                 }
-                KeyCode::Esc => {
-                    self.menu_status.reset(); // close popup
+
+                Some(Command::TagSession) => {
+                    match &self.history.session {
+                        Some(session) => {
+                            let tags = session.tag_line();
+                            self.menu_status.file_picker.init_session_tags(&tags);
+                        }
+                        None => self.set_message(Level::Warning, "Only sessions have tags"),
+                    }
                     return Ok(false);
                 }
-                _ => {
-                    // Ignore other keys when file picker active
+
+                Some(Command::StageHistory) => {
+                    let dir = PathBuf::from(&config::get().history_dir);
+                    match git::stage_all(&dir) {
+                        Ok(()) => self.set_message(Level::Info, format!("Staged {}", dir.display())),
+                        Err(e) => self.set_message(Level::Error, format!("Can't stage: {}", e)),
+                    }
                     return Ok(false);
                 }
-            }
-        }
-
-        // Handle the key 'Go To (g)' menu
-        if self.menu_status.is_active(MenuType::GoTo) {
-            self.menu_status.reset(); // Reset the flag
-
-            match key {
-                KeyCode::Char('l') => return self.move_to_end_of_line(),
-                KeyCode::Char('h') => return self.move_to_start_of_line(),
-                KeyCode::Char('g') => return self.move_to_start_of_buffer(),
-                KeyCode::Char('e') => return self.move_to_end_of_buffer(),
-                _ => return Ok(false),
-            }
-        }
-
-        if self.menu_status.is_active(MenuType::AI) {
-            self.menu_status.reset(); // Reset the flag
 
-            match key {
-                KeyCode::Char('a') => {
-                    self.send_to_anthropic()?;
+                Some(Command::CommitHistory) => {
+                    self.menu_status.file_picker.init_commit_message(&self.commit_message());
                     return Ok(false);
                 }
-                KeyCode::Char('o') => {
-                    self.send_to_openai()?;
+                Some(Command::SearchHistory) => {
+                    let root = PathBuf::from(&config::get().history_dir);
+                    self.history_search = Some(history_search::HistorySearch::new(&root)?);
+                    self.close_split();
+                    self.menu_status.file_picker.init_history_search();
                     return Ok(false);
                 }
-                KeyCode::Char('l') => {
-                    self.send_to_ollama()?;
+
+                Some(Command::Export) => {
+                    match self.export_conversation() {
+                        Ok(path) => self.set_message(Level::Info, format!("Exported to {}", path.display())),
+                        Err(e) => self.set_message(Level::Error, format!("Can't export: {}", e)),
+                    }
                     return Ok(false);
                 }
-                _ => return Ok(false),
-            }
-        }
-
-        // Handle the key 'File (:)' menu
-        if self.menu_status.is_active(MenuType::File) {
-            self.menu_status.reset();
-            match key {
-                KeyCode::Char('w') => {
-                    self.buffer = Rope::new();
-                    self.cursor_row = 0;
-                    self.cursor_col = 0;
-                    self.modified = false;
-
-                    // if self.file_path.is_some() {
-                    self.save_file()?;
-                    // }
 
-                    // Update syntax highlighting for the empty buffer
-                    self.update_syntax_highlighting();
+                Some(Command::ToggleWrap) => {
+                    self.soft_wrap_toggle = true;
+                    return Ok(false);
+                }
 
+                Some(Command::ToggleLineEnding) => {
+                    if self.history.session.is_some() {
+                        self.set_message(Level::Warning, "Sessions are always saved with LF");
+                    } else if self.read_only {
+                        self.set_message(Level::Warning, "Read-only view (File menu: R to edit)");
+                    } else {
+                        self.line_ending = self.line_ending.toggle();
+                        self.modified = true;
+                        let label = self.line_ending.label();
+                        self.set_message(Level::Info, format!("Line endings: {} on save", label));
+                    }
                     return Ok(false);
                 }
 
-                KeyCode::Char('s') => {
-                    self.save_file()?;
+                Some(Command::ToggleReadOnly) => {
+                    if !self.read_only && self.modified {
+                        self.set_message(Level::Warning, "Save the buffer before viewing it read-only");
+                    } else {
+                        self.set_read_only(!self.read_only);
+                    }
                     return Ok(false);
                 }
 
-                KeyCode::Char('S') => {
-                    self.menu_status.file_picker.init_file_save_as();
+                Some(Command::SwitchTheme) => {
+                    self.switch_theme();
                     return Ok(false);
                 }
 
-                KeyCode::Char('l') => {
-                    // self.menu_status.set_active_menu(MenuType::FilePicker);
-                    self.menu_status.file_picker.init_file_picker()?;
+                Some(Command::DiffSaved) => {
+                    self.open_diff()?;
                     return Ok(false);
                 }
 
-                KeyCode::Char('q') => return Ok(true),
+                Some(Command::Quit) => return Ok(self.request_quit()),
 
                 _ => return Ok(false),
             }
         }
-        match key {
-            KeyCode::Char('x') => return self.select_current_line(),
-
-            KeyCode::Char('g') => {
-                self.menu_status.set_active_menu(MenuType::GoTo);
-                return Ok(false);
+        if self.transcript_focused() {
+            if let Some(quit) = self.handle_transcript_key(key, modifiers) {
+                return Ok(quit);
             }
+        }
 
-            KeyCode::Char(' ') => {
-                self.menu_status.set_active_menu(MenuType::File);
+        // Count prefix, only `@` takes one so far
+        if let KeyCode::Char(c @ '0'..='9') = key {
+            if c != '0' || self.macros.has_count() {
+                self.macros.push_count_digit(c);
                 return Ok(false);
             }
+        }
+        let count = self.macros.take_count().unwrap_or(1);
 
-            KeyCode::Char('"') => {
-                self.menu_status.set_active_menu(MenuType::AI);
-                return Ok(false);
-            }
+        if let Some(command) = keymap::command(Context::Normal, key, modifiers) {
+            return self.run_normal_command(command, count);
+        }
 
-            // Mode switching
-            KeyCode::Char('v') => {
-                self.mode = Mode::Select;
-                self.selection_start = Some((self.cursor_row, self.cursor_col));
-                self.selection_active = true;
+        match key {
+            // Stop the running request, keeping what has arrived so far
+            KeyCode::Esc if self.get_request_state() == RequestState::Proccessing => {
+                self.cancel_request(false);
+                Ok(false)
+            }
+            // Kill the running shell command, dropping its output
+            KeyCode::Esc if self.shell_progress().is_some() => {
+                if let Some(command) = self.async_handler.cancel_shell() {
+                    self.set_message(Level::Info, format!("Stopped `{}`", command));
+                }
                 Ok(false)
             }
 
-            KeyCode::Char('y') => {
-                // In normal mode, try to copy selection if it exists
-                // This is useful if selection was made but user went back to normal mode
-                if self.selection_active && self.selection_start.is_some() {
-                    match self.copy_selection_to_clipboard() {
-                        Ok(_) => {}
-                        Err(e) => eprintln!("Clipboard error: {}", e),
-                    }
+            KeyCode::Tab | KeyCode::BackTab if self.table_around(self.cursor_row).is_some() => {
+                self.next_table_cell(key == KeyCode::Tab)
+            }
+            KeyCode::Tab if self.split.is_some() => {
+                if let Some(split) = &mut self.split {
+                    split.toggle_focus();
                 }
                 Ok(false)
             }
 
             // Navigation
+            KeyCode::Up if modifiers.contains(KeyModifiers::ALT) => self.move_lines(true),
+            KeyCode::Down if modifiers.contains(KeyModifiers::ALT) => self.move_lines(false),
             KeyCode::Up => self.move_cursor_up(),
             KeyCode::Down => self.move_cursor_down(),
             KeyCode::Left => self.move_cursor_left(),
             KeyCode::Right => self.move_cursor_right(),
 
-            KeyCode::Char('k') => self.move_cursor_up(),
-            KeyCode::Char('j') => self.move_cursor_down(),
-            KeyCode::Char('h') => self.move_cursor_left(),
-            KeyCode::Char('l') => self.move_cursor_right(),
+            _ => {
+                self.menu_status.reset();
+                Ok(false)
+            }
+        }
+    }
+
+    fn run_normal_command(&mut self, command: Command, count: usize) -> Result<bool> {
+        match command {
+            Command::SelectLine => self.select_current_line(),
+
+            // Search
+            Command::Search => {
+                self.search.open();
+                Ok(false)
+            }
+            Command::NextMatch => self.jump_to_match(true),
+            Command::PreviousMatch => self.jump_to_match(false),
+            Command::MatchingPair => self.jump_to_pair(),
+            Command::Replace => {
+                self.replace.start();
+                self.search.open();
+                Ok(false)
+            }
+
+            // Menus
+            Command::GoToMenu => {
+                self.menu_status.set_active_menu(MenuType::GoTo);
+                Ok(false)
+            }
+            Command::FoldMenu => {
+                self.menu_status.set_active_menu(MenuType::Fold);
+                Ok(false)
+            }
+            Command::TableMenu => {
+                self.menu_status.set_active_menu(MenuType::Table);
+                Ok(false)
+            }
+            Command::FileMenu => {
+                self.menu_status.set_active_menu(MenuType::File);
+                Ok(false)
+            }
+            Command::AIMenu => {
+                self.menu_status.set_active_menu(MenuType::AI);
+                Ok(false)
+            }
+            Command::GotoLine => {
+                self.menu_status.file_picker.init_goto_line("");
+                Ok(false)
+            }
+            Command::Help => {
+                self.help_view = Some(log_view::LogView::new());
+                Ok(false)
+            }
+            Command::Preview => {
+                self.preview = Some(preview::Preview::new(self.cursor_row));
+                Ok(false)
+            }
+
+            // Registers
+            Command::SelectRegister => {
+                self.registers.begin_select();
+                Ok(false)
+            }
+            Command::Paste => {
+                self.paste_from_register()?;
+                Ok(false)
+            }
+
+            // Macros
+            Command::RecordMacro => {
+                if self.macros.recording().is_some() {
+                    self.macros.stop_recording();
+                } else {
+                    self.macros.begin_record();
+                }
+                Ok(false)
+            }
+            Command::PlayMacro => {
+                if !self.macros.is_playing() {
+                    self.macros.begin_play(count);
+                }
+                Ok(false)
+            }
 
             // Mode switching
-            KeyCode::Char('i') => {
+            Command::SelectMode => {
+                self.mode = Mode::Select;
+                self.selection_start = Some((self.cursor_row, self.cursor_col));
+                self.selection_active = true;
+                Ok(false)
+            }
+            Command::InsertMode => {
+                if self.read_only {
+                    self.set_message(Level::Warning, "Read-only view (File menu: R to edit)");
+                    return Ok(false);
+                }
+                if self.load_blocks() {
+                    return Ok(false);
+                }
                 self.mode = Mode::Insert;
 
-                // println!("{}", self.buffer.len_lines());
-
                 if self.buffer.len_lines() == 1 && self.buffer.len_chars() == 0 {
+                    self.record_edit(0..0, "\n");
                     self.buffer.insert(0, "\n");
                     self.cursor_row = 0;
                     self.cursor_col = 0;
@@ -817,24 +5980,61 @@ impl Editor {
 
                 Ok(false)
             }
-            KeyCode::Char('v') => {
-                self.mode = Mode::Select;
+
+            // Run the code block under the cursor in the shell
+            Command::RunInShell => {
+                self.confirm_run_command();
+                Ok(false)
+            }
+
+            Command::YankBlock => {
+                if !self.yank_code_block() {
+                    self.set_message(Level::Warning, "No code block under the cursor");
+                }
+                Ok(false)
+            }
+
+            Command::Yank => {
+                // In normal mode, try to copy selection if it exists
+                // This is useful if selection was made but user went back to normal mode
+                if self.selection_active && self.selection_start.is_some() {
+                    match self.yank_selection() {
+                        Ok(_) => {}
+                        Err(e) => self.set_message(Level::Error, format!("Clipboard error: {}", e)),
+                    }
+                } else {
+                    self.text_object = Some(textobject::Pending::new(textobject::Operator::Yank));
+                }
                 Ok(false)
             }
 
+            // Navigation
+            Command::Up => self.move_cursor_up(),
+            Command::Down => self.move_cursor_down(),
+            Command::Left => self.move_cursor_left(),
+            Command::Right => self.move_cursor_right(),
+
+            Command::WordStart => self.move_to_next_word_start(),
+            Command::WordBack => self.move_to_previous_word_start(),
+            Command::WordEnd => self.move_to_word_end(),
+
             // File operations
-            KeyCode::Char('s') => {
+            Command::Save => {
                 self.save_file()?;
                 Ok(false)
             }
 
-            KeyCode::Char('d') => {
-                self.delete_char_at_cursor()?;
+            // Deletes once the next key turns out not to start a text object
+            Command::DeleteChar => {
+                self.text_object = Some(textobject::Pending::new(textobject::Operator::Delete));
                 Ok(false)
             }
 
-            // Quit
-            // KeyCode::Char('q') => Ok(true),
+            // Lines
+            Command::DuplicateLines => self.duplicate_lines(),
+            Command::MoveLinesUp => self.move_lines(true),
+            Command::MoveLinesDown => self.move_lines(false),
+
             _ => {
                 self.menu_status.reset();
                 Ok(false)
@@ -843,20 +6043,58 @@ impl Editor {
     }
 
     fn handle_insert_mode(&mut self, key: KeyCode, modifiers: KeyModifiers) -> Result<bool> {
+        // Tab takes the inline completion, Esc only drops it
+        if let Some(text) = self.ghost.take(self.get_char_idx()) {
+            match key {
+                KeyCode::Tab => {
+                    self.insert_at_cursor(&text)?;
+                    return Ok(false);
+                }
+                KeyCode::Esc => return Ok(false),
+                _ => {}
+            }
+        }
+        let edited = matches!(
+            key,
+            KeyCode::Char(_) | KeyCode::Enter | KeyCode::Backspace | KeyCode::Delete
+        );
+        self.ghost.typed(edited);
+
         if modifiers.contains(KeyModifiers::META) && key == KeyCode::Char('v') {
             match self.paste_from_clipboard() {
                 Ok(_) => return Ok(false),
-                Err(e) => eprintln!("Paste error: {}", e),
+                Err(e) => self.set_message(Level::Error, format!("Paste error: {}", e)),
             }
         }
 
+        let char_idx = self.get_char_idx();
+        let placeholder = self
+            .tab_stops
+            .as_mut()
+            .and_then(|stops| stops.take_placeholder(char_idx));
+
         match key {
             KeyCode::Esc => {
                 self.mode = Mode::Normal;
+                self.tab_stops = None;
+                Ok(false)
+            }
+            KeyCode::Char(' ') if modifiers.contains(KeyModifiers::CONTROL) => {
+                self.request_completion();
                 Ok(false)
             }
             KeyCode::Char(c) => {
-                self.insert_char(c)?;
+                if let Some(range) = placeholder {
+                    if self.edit_blocked(range.clone()) {
+                        return Ok(false);
+                    }
+                    self.record_edit(range.clone(), "");
+                    self.buffer.remove(range);
+                }
+                if c == ' ' {
+                    self.expand_abbreviation();
+                }
+                self.type_char(c)?;
                 Ok(false)
             }
             KeyCode::Enter => {
@@ -864,6 +6102,12 @@ impl Editor {
                 Ok(false)
             }
             KeyCode::Backspace => {
+                if config::get().auto_pairs {
+                    let (before, after) = self.chars_around_cursor();
+                    if pairs::is_empty_pair(before, after) {
+                        self.delete_char_at_cursor()?;
+                    }
+                }
                 self.delete_char_before_cursor()?;
                 Ok(false)
             }
@@ -871,6 +6115,13 @@ impl Editor {
                 self.delete_char_at_cursor()?;
                 Ok(false)
             }
+            KeyCode::Tab | KeyCode::BackTab if self.tab_stops.is_some() => {
+                self.jump_tab_stop(key == KeyCode::Tab)
+            }
+            KeyCode::Tab | KeyCode::BackTab if self.table_around(self.cursor_row).is_some() => {
+                self.next_table_cell(key == KeyCode::Tab)
+            }
+            KeyCode::Tab => self.expand_snippet(),
             KeyCode::Up => self.move_cursor_up(),
             KeyCode::Down => self.move_cursor_down(),
             KeyCode::Left => self.move_cursor_left(),
@@ -882,30 +6133,130 @@ impl Editor {
         }
     }
 
+    // Replaces the word before the cursor when it's one of the configured
+    // abbreviations. Pasted text and code blocks stay as they are.
+    fn expand_abbreviation(&mut self) {
+        let config = config::get();
+        if config.abbreviations.is_empty() || event::poll(Duration::ZERO).unwrap_or(false) {
+            return;
+        }
+
+        let line = self.line_text(self.cursor_row);
+        let before: String = line.chars().take(self.cursor_col).collect();
+        let word = before.rsplit(char::is_whitespace).next().unwrap_or("");
+        let Some(text) = config.abbreviations.get(word) else {
+            return;
+        };
+        if self.code_block_at(self.cursor_row).is_some() {
+            return;
+        }
+
+        let end = self.get_char_idx();
+        let start = end - word.chars().count();
+        if self.edit_blocked(start..end) {
+            return;
+        }
+        self.record_edit(start..end, text);
+        self.buffer.remove(start..end);
+        self.buffer.insert(start, text);
+        self.modified = true;
+        self.invalidate_syntax_at_line(self.cursor_row);
+        (self.cursor_row, self.cursor_col) = self.position_from_char_idx(start + text.chars().count());
+    }
+
+    /// Tab in Insert mode: replaces the snippet trigger before the cursor
+    /// with its body and goes to the first tab-stop
+    fn expand_snippet(&mut self) -> Result<bool> {
+        let snippets = match snippets::load() {
+            Ok(snippets) => snippets,
+            Err(e) => {
+                self.set_message(Level::Error, format!("Can't read snippets: {}", e));
+                return Ok(false);
+            }
+        };
+
+        let line = self.line_text(self.cursor_row);
+        let before: String = line.chars().take(self.cursor_col).collect();
+        let Some(trigger) = snippets::trigger(&snippets, &before) else {
+            return Ok(false);
+        };
+        let indent: String = before.chars().take_while(|c| c.is_whitespace()).collect();
+        let (text, stops) = snippets::expand(&snippets[trigger], &indent);
+
+        let end = self.get_char_idx();
+        let start = end - trigger.chars().count();
+        if self.edit_blocked(start..end) {
+            return Ok(false);
+        }
+        self.record_edit(start..end, &text);
+        self.buffer.remove(start..end);
+        self.buffer.insert(start, &text);
+        self.modified = true;
+        self.invalidate_syntax_at_line(self.cursor_row);
+
+        let stops = snippets::TabStops::new(start, stops, self.buffer.len_chars());
+        (self.cursor_row, self.cursor_col) = self.position_from_char_idx(stops.current().start);
+        self.tab_stops = (!stops.at_last()).then_some(stops);
+        Ok(false)
+    }
+
+    /// Tab and Shift+Tab after a snippet expansion: the next or previous
+    /// tab-stop. Reaching the final one ends the snippet.
+    fn jump_tab_stop(&mut self, forward: bool) -> Result<bool> {
+        let len_chars = self.buffer.len_chars();
+        let Some(stops) = &mut self.tab_stops else {
+            return Ok(false);
+        };
+        let stop = stops.jump(forward, len_chars);
+        if stops.at_last() {
+            self.tab_stops = None;
+        }
+
+        (self.cursor_row, self.cursor_col) = self.position_from_char_idx(stop.start);
+        Ok(false)
+    }
+
     fn handle_select_mode(&mut self, key: KeyCode, modifiers: KeyModifiers) -> Result<bool> {
+        if self.search.is_active() {
+            return self.handle_search_prompt(key, modifiers);
+        }
+
         if self.menu_status.is_active(MenuType::GoTo) {
             self.menu_status.reset();
 
             // Handle the key after 'g'
-            match key {
-                KeyCode::Char('l') => return self.move_to_end_of_line(),
-                KeyCode::Char('h') => return self.move_to_start_of_line(),
-                KeyCode::Char('g') => return self.move_to_start_of_buffer(),
-                KeyCode::Char('e') => return self.move_to_end_of_buffer(),
-                // Add more 'g' commands here as needed
+            // Buffers stay put while selecting
+            match keymap::command(Context::GoTo, key, modifiers) {
+                Some(Command::LineEnd) => return self.move_to_end_of_line(),
+                Some(Command::LineStart) => return self.move_to_start_of_line(),
+                Some(Command::BufferStart) => return self.move_to_start_of_buffer(),
+                Some(Command::BufferEnd) => return self.move_to_end_of_buffer(),
+                Some(Command::ToggleComment) => return self.toggle_comment(),
+                Some(Command::OpenFileUnderCursor) => return self.open_file_under_cursor(),
                 _ => return Ok(false), // Ignore other keys
             }
         }
 
-        match key {
-            KeyCode::Char('x') => return self.select_current_line(),
+        if self.registers.is_selecting() {
+            if let KeyCode::Char(c) = key {
+                self.registers.select(c);
+            } else {
+                self.registers.select(' ');
+            }
+            return Ok(false);
+        }
 
-            // Set GoTo Menu Is Active
-            KeyCode::Char('g') => {
-                self.menu_status.set_active_menu(MenuType::GoTo);
-                return Ok(false);
+        if let Some(pending) = self.text_object.take() {
+            if let Some(result) = self.handle_text_object(pending, key) {
+                return result;
             }
+        }
+
+        if let Some(command) = keymap::command(Context::Select, key, modifiers) {
+            return self.run_select_command(command);
+        }
 
+        match key {
             KeyCode::Esc => {
                 self.mode = Mode::Normal;
                 self.selection_active = false;
@@ -913,31 +6264,116 @@ impl Editor {
                 Ok(false)
             }
 
-            KeyCode::Char('y') => {
+            KeyCode::Up if modifiers.contains(KeyModifiers::ALT) => self.move_lines(true),
+            KeyCode::Down if modifiers.contains(KeyModifiers::ALT) => self.move_lines(false),
+            KeyCode::Up => self.move_cursor_up(),
+            KeyCode::Down => self.move_cursor_down(),
+            KeyCode::Left => self.move_cursor_left(),
+            KeyCode::Right => self.move_cursor_right(),
+            _ => {
+                self.menu_status.reset();
+                Ok(false)
+            }
+        }
+    }
+
+    fn run_select_command(&mut self, command: Command) -> Result<bool> {
+        match command {
+            Command::SelectLine => self.select_current_line(),
+            Command::SelectRegister => {
+                self.registers.begin_select();
+                Ok(false)
+            }
+
+            // Search, extending the selection to the match
+            Command::Search => {
+                self.search.open();
+                Ok(false)
+            }
+            Command::NextMatch => self.jump_to_match(true),
+            Command::PreviousMatch => self.jump_to_match(false),
+            Command::MatchingPair => self.jump_to_pair(),
+
+            // Rewrite the selection by an instruction
+            Command::InlineEdit => {
+                self.start_inline_edit();
+                Ok(false)
+            }
+
+            // Explain the selection, or write its doc comment or tests
+            Command::Explain => {
+                self.start_assist(assist::Assist::Explain);
+                Ok(false)
+            }
+            Command::DocComment => {
+                self.start_assist(assist::Assist::DocComment);
+                Ok(false)
+            }
+            Command::UnitTests => {
+                self.start_assist(assist::Assist::Tests);
+                Ok(false)
+            }
+
+            // Apply the selected unified diff to its files
+            Command::ApplySelectedDiff => {
+                if let Some(text) = self.get_selected_text() {
+                    self.open_patch(&text);
+                }
+                Ok(false)
+            }
+
+            // Replace the selection with a text object
+            Command::InnerObject | Command::AroundObject => {
+                let scope = match command {
+                    Command::InnerObject => textobject::Scope::Inner,
+                    _ => textobject::Scope::Around,
+                };
+                self.text_object = Some(textobject::Pending {
+                    operator: textobject::Operator::Select,
+                    scope: Some(scope),
+                });
+                Ok(false)
+            }
+
+            // Set GoTo Menu Is Active
+            Command::GoToMenu => {
+                self.menu_status.set_active_menu(MenuType::GoTo);
+                Ok(false)
+            }
+
+            // Run the selection in the shell
+            Command::RunInShell => {
+                self.confirm_run_command();
+                Ok(false)
+            }
+            Command::Yank => {
                 // Copy selection to clipboard and exit select mode
-                match self.copy_selection_to_clipboard() {
+                match self.yank_selection() {
                     Ok(_) => {}
-                    Err(e) => eprintln!("Clipboard error: {}", e),
+                    Err(e) => self.set_message(Level::Error, format!("Clipboard error: {}", e)),
                 }
                 Ok(false)
             }
-            KeyCode::Char('d') => {
+            Command::DeleteSelection => {
                 // Delete selection and exit select mode
                 match self.delete_selection() {
                     Ok(_) => {}
-                    Err(e) => eprintln!("Delete error: {}", e),
+                    Err(e) => self.set_message(Level::Error, format!("Delete error: {}", e)),
                 }
                 Ok(false)
             }
-
-            KeyCode::Up => self.move_cursor_up(),
-            KeyCode::Down => self.move_cursor_down(),
-            KeyCode::Left => self.move_cursor_left(),
-            KeyCode::Right => self.move_cursor_right(),
-            KeyCode::Char('k') => self.move_cursor_up(),
-            KeyCode::Char('j') => self.move_cursor_down(),
-            KeyCode::Char('h') => self.move_cursor_left(),
-            KeyCode::Char('l') => self.move_cursor_right(),
+            Command::DuplicateLines => self.duplicate_lines(),
+            Command::MoveLinesUp => self.move_lines(true),
+            Command::MoveLinesDown => self.move_lines(false),
+
+            Command::Up => self.move_cursor_up(),
+            Command::Down => self.move_cursor_down(),
+            Command::Left => self.move_cursor_left(),
+            Command::Right => self.move_cursor_right(),
+
+            Command::WordStart => self.move_to_next_word_start(),
+            Command::WordBack => self.move_to_previous_word_start(),
+            Command::WordEnd => self.move_to_word_end(),
             _ => {
                 self.menu_status.reset();
                 Ok(false)
@@ -955,6 +6391,7 @@ impl Editor {
             if self.cursor_col > line_len {
                 self.cursor_col = line_len;
             }
+            self.cursor_col = graphemes::snap(&self.buffer, self.cursor_row, self.cursor_col);
         }
         Ok(false)
     }
@@ -987,7 +6424,8 @@ impl Editor {
 
     fn move_cursor_left(&mut self) -> Result<bool> {
         if self.cursor_col > 0 {
-            self.cursor_col -= 1;
+            // Whole grapheme clusters, e.g. an emoji with its modifiers
+            self.cursor_col = graphemes::previous(&self.buffer, self.cursor_row, self.cursor_col);
         } else if self.cursor_row > 0 {
             // Move to end of previous line
             self.cursor_row -= 1;
@@ -1009,7 +6447,8 @@ impl Editor {
         }
 
         if self.cursor_col < line_len {
-            self.cursor_col += 1;
+            let next = graphemes::next(&self.buffer, self.cursor_row, self.cursor_col);
+            self.cursor_col = next.min(line_len);
         } else if self.cursor_row < self.buffer.len_lines().saturating_sub(1) {
             // Move to beginning of next line
             let total_lines = self.buffer.len_lines();
@@ -1031,6 +6470,7 @@ impl Editor {
                 if self.cursor_col > line_len {
                     self.cursor_col = line_len;
                 }
+                self.cursor_col = graphemes::snap(&self.buffer, self.cursor_row, self.cursor_col);
             }
 
             // self.cursor_row += 1;
@@ -1039,8 +6479,119 @@ impl Editor {
         Ok(false)
     }
 
+    fn char_class_at(&self, char_idx: usize) -> CharClass {
+        CharClass::of(self.buffer.char(char_idx))
+    }
+
+    /// `%`: jumps between the fences of a code block, or to the bracket
+    /// matching the next one on the line
+    fn jump_to_pair(&mut self) -> Result<bool> {
+        let target = match matching::matching_fence(&self.buffer, self.cursor_row) {
+            Some(line) => Some(self.buffer.line_to_char(line)),
+            None => self.matching_bracket(),
+        };
+
+        if let Some(char_idx) = target {
+            self.set_cursor_to_char_idx(char_idx);
+        }
+        Ok(false)
+    }
+
+    fn matching_bracket(&self) -> Option<usize> {
+        if self.buffer.len_chars() == 0 {
+            return None;
+        }
+        let char_idx = matching::bracket_on_line(&self.buffer, self.get_char_idx())?;
+
+        // The parse tree knows which brackets belong to strings and comments
+        let parsed = self.syntax_highlighter.as_ref().and_then(|highlighter| {
+            highlighter.matching_bracket(self.buffer.char_to_byte(char_idx))
+        });
+        match parsed {
+            Some(byte) => Some(self.buffer.byte_to_char(byte)),
+            None => matching::matching_bracket(&self.buffer, char_idx),
+        }
+    }
+
+    fn set_cursor_to_char_idx(&mut self, char_idx: usize) {
+        let (row, col) = self.position_from_char_idx(char_idx);
+        self.cursor_row = row;
+        self.cursor_col = col;
+    }
+
+    /// `w`: start of the next word, crossing line breaks
+    fn move_to_next_word_start(&mut self) -> Result<bool> {
+        let len = self.buffer.len_chars();
+        let mut idx = self.get_char_idx();
+        if idx >= len {
+            return Ok(false);
+        }
+
+        // Skip the rest of the current word
+        let class = self.char_class_at(idx);
+        if class != CharClass::Whitespace {
+            while idx < len && self.char_class_at(idx) == class {
+                idx += 1;
+            }
+        }
+
+        // Then the whitespace after it
+        while idx < len && self.char_class_at(idx) == CharClass::Whitespace {
+            idx += 1;
+        }
+
+        self.set_cursor_to_char_idx(idx);
+        Ok(false)
+    }
+
+    /// `b`: start of the current word, or of the previous one when already there
+    fn move_to_previous_word_start(&mut self) -> Result<bool> {
+        let mut idx = self.get_char_idx().min(self.buffer.len_chars());
+        if idx == 0 {
+            return Ok(false);
+        }
+        idx -= 1;
+
+        while idx > 0 && self.char_class_at(idx) == CharClass::Whitespace {
+            idx -= 1;
+        }
+
+        let class = self.char_class_at(idx);
+        while idx > 0 && self.char_class_at(idx - 1) == class {
+            idx -= 1;
+        }
+
+        self.set_cursor_to_char_idx(idx);
+        Ok(false)
+    }
+
+    /// `e`: last char of the current word, or of the next one when already there
+    fn move_to_word_end(&mut self) -> Result<bool> {
+        let len = self.buffer.len_chars();
+        let mut idx = self.get_char_idx() + 1;
+
+        while idx < len && self.char_class_at(idx) == CharClass::Whitespace {
+            idx += 1;
+        }
+        if idx >= len {
+            return Ok(false);
+        }
+
+        let class = self.char_class_at(idx);
+        while idx + 1 < len && self.char_class_at(idx + 1) == class {
+            idx += 1;
+        }
+
+        self.set_cursor_to_char_idx(idx);
+        Ok(false)
+    }
+
     fn insert_char(&mut self, c: char) -> Result<()> {
         let char_idx = self.get_char_idx();
+        if self.edit_blocked(char_idx..char_idx) {
+            return Ok(());
+        }
+        self.record_edit(char_idx..char_idx, c.encode_utf8(&mut [0; 4]));
         self.buffer.insert_char(char_idx, c);
         self.cursor_col += 1;
         self.modified = true;
@@ -1050,12 +6601,129 @@ impl Editor {
         Ok(())
     }
 
+    /// Indent for the line Enter starts: the current line's, one level deeper
+    /// after an opening bracket in a code block. Between a pair, e.g. `{|}`,
+    /// the second part puts the closer on a line of its own.
+    fn newline_indent(&mut self) -> (String, String) {
+        if !config::get().auto_indent {
+            return (String::new(), String::new());
+        }
+
+        let row = self.cursor_row;
+        let line: Vec<char> = self.line_text(row).chars().collect();
+        let col = self.cursor_col.min(line.len());
+        let base: String = line[..col]
+            .iter()
+            .take_while(|c| matches!(c, ' ' | '\t'))
+            .collect();
+
+        // Only on the code lines, not the fences
+        let Some(block) = self.code_block_at(row).filter(|block| {
+            let first = self.buffer.byte_to_line(block.start);
+            let last = self.buffer.byte_to_line(block.end);
+            (first..last).contains(&row)
+        }) else {
+            return (base, String::new());
+        };
+
+        let Some(open) = line[..col].iter().rposition(|c| !c.is_whitespace()) else {
+            return (base, String::new());
+        };
+        // The parse knows a bracket in a string or comment isn't one
+        let style = self.highlight_line(row).get(open).copied();
+        if !block.indent_after().contains(&line[open])
+            || matches!(style, Some(Style::String | Style::Comment))
+        {
+            return (base, String::new());
+        }
+
+        let indent = format!("{}{}", base, config::get().indent_unit(&block.language));
+        let closes = matches!(
+            (line[open], line.get(col)),
+            ('(', Some(')')) | ('[', Some(']')) | ('{', Some('}'))
+        );
+        let closer = if closes { format!("\n{}", base) } else { String::new() };
+        (indent, closer)
+    }
+
+    /// A character typed in Insert mode, closing brackets, quotes and fences
+    /// with `auto_pairs`
+    fn type_char(&mut self, c: char) -> Result<()> {
+        // Keys coming in a burst are a terminal paste, taken as they are
+        if !config::get().auto_pairs || event::poll(Duration::ZERO).unwrap_or(false) {
+            return self.insert_char(c);
+        }
+
+        let (before, after) = self.chars_around_cursor();
+        match pairs::typed(c, before, after) {
+            pairs::Typed::Over => {
+                self.cursor_col += 1;
+                Ok(())
+            }
+            pairs::Typed::Pair(close) => {
+                self.insert_char(c)?;
+                self.insert_after_cursor(&close.to_string());
+                Ok(())
+            }
+            pairs::Typed::Plain => {
+                self.insert_char(c)?;
+                if c == '`' {
+                    self.close_fence();
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // Characters left and right of the cursor on its line
+    fn chars_around_cursor(&self) -> (Option<char>, Option<char>) {
+        let char_idx = self.get_char_idx();
+        let before = (self.cursor_col > 0).then(|| self.buffer.char(char_idx - 1));
+        let after = (char_idx < self.buffer.len_chars())
+            .then(|| self.buffer.char(char_idx))
+            .filter(|c| !matches!(c, '\n' | '\r'));
+        (before, after)
+    }
+
+    // Inserts `text` at the cursor, leaving the cursor in front of it
+    fn insert_after_cursor(&mut self, text: &str) {
+        let char_idx = self.get_char_idx();
+        if self.edit_blocked(char_idx..char_idx) {
+            return;
+        }
+        self.record_edit(char_idx..char_idx, text);
+        self.buffer.insert(char_idx, text);
+        self.modified = true;
+        self.invalidate_syntax_at_line(self.cursor_row);
+    }
+
+    // A line that just became ``` and opens a block gets its closing fence
+    // below, the cursor stays where the language goes
+    fn close_fence(&mut self) {
+        let row = self.cursor_row;
+        if self.cursor_col != 3 || self.line_text(row) != "```" {
+            return;
+        }
+        let fences_above = (0..row)
+            .filter(|&r| self.line_text(r).starts_with("```"))
+            .count();
+        if fences_above % 2 == 0 {
+            self.insert_after_cursor("\n```");
+        }
+    }
+
     fn insert_newline(&mut self) -> Result<()> {
         let char_idx = self.get_char_idx();
+        if self.edit_blocked(char_idx..char_idx) {
+            return Ok(());
+        }
 
-        self.buffer.insert_char(char_idx, '\n');
+        let (indent, closer) = self.newline_indent();
+        let text = format!("\n{}{}", indent, closer);
+        self.record_edit(char_idx..char_idx, &text);
+        self.buffer.insert(char_idx, &text);
         self.cursor_row += 1;
-        self.cursor_col = 0;
+        self.cursor_col = indent.chars().count();
 
         self.modified = true;
 
@@ -1067,14 +6735,26 @@ impl Editor {
     fn delete_char_before_cursor(&mut self) -> Result<()> {
         let char_idx = self.get_char_idx();
         if char_idx > 0 {
+            // The whole cluster before the cursor, or the line break
+            let start = if self.cursor_col > 0 {
+                let col = graphemes::previous(&self.buffer, self.cursor_row, self.cursor_col);
+                char_idx - (self.cursor_col - col)
+            } else {
+                char_idx - 1
+            };
+            if self.edit_blocked(start..char_idx) {
+                return Ok(());
+            }
+
             // Get the current line before deletion
             let current_line = self.cursor_row;
 
-            self.buffer.remove(char_idx - 1..char_idx);
+            self.record_edit(start..char_idx, "");
+            self.buffer.remove(start..char_idx);
 
             // Update cursor position
             if self.cursor_col > 0 {
-                self.cursor_col -= 1;
+                self.cursor_col -= char_idx - start;
             } else if self.cursor_row > 0 {
                 self.cursor_row -= 1;
                 let line = self.buffer.line(self.cursor_row);
@@ -1092,10 +6772,17 @@ impl Editor {
     fn delete_char_at_cursor(&mut self) -> Result<()> {
         let char_idx = self.get_char_idx();
         if char_idx < self.buffer.len_chars() {
+            // The whole cluster under the cursor
+            let next = graphemes::next(&self.buffer, self.cursor_row, self.cursor_col);
+            let end = (char_idx + next - self.cursor_col).min(self.buffer.len_chars());
+            if self.edit_blocked(char_idx..end) {
+                return Ok(());
+            }
             let current_line = self.cursor_row;
 
             // Delete the character
-            self.buffer.remove(char_idx..char_idx + 1);
+            self.record_edit(char_idx..end, "");
+            self.buffer.remove(char_idx..end);
             self.modified = true;
 
             // Check if we need to update cursor position
@@ -1151,12 +6838,19 @@ impl Editor {
         if let Some(selection_range) = self.get_selection_range() {
             let start_idx = selection_range.start;
             let end_idx = selection_range.end;
+            if self.edit_blocked(start_idx..end_idx) {
+                return Ok(());
+            }
 
             // Get line numbers affected by the deletion
             let start_line = self.buffer.char_to_line(start_idx);
             let end_line = self.buffer.char_to_line(end_idx);
 
+            // Deleted text can be pasted back with `p`
+            self.registers.store(self.buffer.slice(start_idx..end_idx).to_string());
+
             // Remove the selected text from the buffer
+            self.record_edit(start_idx..end_idx, "");
             self.buffer.remove(start_idx..end_idx);
 
             // Update cursor position to the start of the selection
@@ -1214,10 +6908,28 @@ impl Editor {
         self.buffer.to_string()
     }
 
+    pub fn line_count(&self) -> usize {
+        self.buffer.len_lines()
+    }
+
+    /// Text of a buffer line without its line break
+    pub fn line_text(&self, row: usize) -> String {
+        if row >= self.buffer.len_lines() {
+            return String::new();
+        }
+        let line = self.buffer.line(row).to_string();
+        line.trim_end_matches(['\n', '\r']).to_string()
+    }
+
     pub fn get_cursor_position(&self) -> (usize, usize) {
         (self.cursor_row, self.cursor_col)
     }
 
+    /// Where answers go, for the status line
+    pub fn insertion(&self) -> Insertion {
+        self.insertion
+    }
+
     pub fn get_mode(&self) -> &Mode {
         &self.mode
     }
@@ -1230,6 +6942,54 @@ impl Editor {
         self.modified
     }
 
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    pub fn line_ending(&self) -> LineEnding {
+        self.line_ending
+    }
+
+    /// Switches the active buffer between editing and the read-only view,
+    /// which skips highlighting so huge files stay quick to scroll
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+        self.mode = Mode::Normal;
+        self.selection_active = false;
+        self.selection_start = None;
+        self.tab_stops = None;
+        self.syntax_cache.mark_all_dirty();
+        if !self.is_loading() {
+            self.update_syntax_highlighting();
+        }
+        // A viewer can't overwrite anything, an editor takes the lock again
+        if read_only && matches!(&self.confirm, Some(c) if matches!(c.action, ConfirmAction::OpenedElsewhere)) {
+            self.confirm = None;
+        }
+        self.lock_file();
+    }
+
+    pub fn is_scratch(&self) -> bool {
+        self.scratch
+    }
+
+    /// Lines as `str::lines` counts them, a final line break doesn't start
+    /// another one
+    pub fn display_line_count(&self) -> usize {
+        let lines = self.buffer.len_lines();
+        if self.buffer.len_chars() == 0 {
+            0
+        } else if self.buffer.line(lines - 1).len_chars() == 0 {
+            lines - 1
+        } else {
+            lines
+        }
+    }
+
     pub fn get_file_name(&self) -> Option<&str> {
         Some(self.history.file_path.as_str())
     }
@@ -1244,21 +7004,40 @@ impl Editor {
             .get_contents()
             .map_err(|e| format!("Failed to get clipboard contents: {}", e))?;
 
+        self.insert_at_cursor(&content)
+    }
+
+    /// Pastes the selected register, or the default one
+    fn paste_from_register(&mut self) -> Result<()> {
+        match self.registers.get() {
+            Some(content) => {
+                let content = content.to_string();
+                self.insert_at_cursor(&content)
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn insert_at_cursor(&mut self, content: &str) -> Result<()> {
         if content.is_empty() {
             return Ok(());
         }
 
         // Get current character index
         let char_idx = self.get_char_idx();
+        if self.edit_blocked(char_idx..char_idx) {
+            return Ok(());
+        }
 
         // Get current position before insertion
         let current_row = self.cursor_row;
 
         // Insert the content
-        self.buffer.insert(char_idx, &content);
+        self.record_edit(char_idx..char_idx, content);
+        self.buffer.insert(char_idx, content);
 
         // Update cursor position by counting newlines in pasted content
-        let new_position = self.position_from_char_idx(char_idx + content.len());
+        let new_position = self.position_from_char_idx(char_idx + content.chars().count());
         self.cursor_row = new_position.0;
         self.cursor_col = new_position.1;
 
@@ -1303,3 +7082,20 @@ impl Editor {
         }
     }
 }
+
+/// Moves a buffer position along with an edit that replaced `range` with
+/// `inserted` chars. Text inserted right at the position lands after it.
+fn shift_position(p: usize, range: &Range<usize>, inserted: usize) -> usize {
+    if p <= range.start {
+        p
+    } else if p >= range.end {
+        p - (range.end - range.start) + inserted
+    } else {
+        range.start
+    }
+}
+
+/// Both ends of `shifted` moved along like `shift_position`
+fn shift_range(shifted: &Range<usize>, range: &Range<usize>, inserted: usize) -> Range<usize> {
+    shift_position(shifted.start, range, inserted)..shift_position(shifted.end, range, inserted)
+}
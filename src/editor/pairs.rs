@@ -0,0 +1,41 @@
+const PAIRS: &[(char, char)] = &[('(', ')'), ('[', ']'), ('{', '}'), ('"', '"')];
+
+/// What typing a character does with `auto_pairs` on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Typed {
+    /// Inserted with this closer after the cursor
+    Pair(char),
+    /// Steps over the same closer right after the cursor
+    Over,
+    Plain,
+}
+
+fn is_closer(c: char) -> bool {
+    PAIRS.iter().any(|&(_, close)| close == c)
+}
+
+/// `c` typed between `before` and `after`, the characters next to the
+/// cursor on its line
+pub(super) fn typed(c: char, before: Option<char>, after: Option<char>) -> Typed {
+    if after == Some(c) && is_closer(c) {
+        return Typed::Over;
+    }
+    let Some(&(open, close)) = PAIRS.iter().find(|(open, _)| *open == c) else {
+        return Typed::Plain;
+    };
+
+    // A quote right after a word is more likely to close something
+    if open == close && before.is_some_and(|b| b.is_alphanumeric() || b == open) {
+        return Typed::Plain;
+    }
+    // Nothing to wrap after the cursor, a closer is fine
+    match after {
+        Some(a) if !a.is_whitespace() && !is_closer(a) => Typed::Plain,
+        _ => Typed::Pair(close),
+    }
+}
+
+/// The cursor sits in an empty pair, e.g. `(|)`, that Backspace removes whole
+pub(super) fn is_empty_pair(before: Option<char>, after: Option<char>) -> bool {
+    matches!((before, after), (Some(b), Some(a)) if PAIRS.contains(&(b, a)))
+}
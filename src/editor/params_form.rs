@@ -0,0 +1,153 @@
+use crate::chat::params::GenerationParams;
+use crate::error::{Error, Result};
+
+const LABELS: [&str; 4] = ["Temperature: ", "Top p:       ", "Max tokens:  ", "Stop:        "];
+
+/// Form popup for the generation parameters. Empty fields keep the
+/// provider's default; stop sequences are separated by commas, with `\n`
+/// for a line break.
+#[derive(Debug, Clone, Default)]
+pub struct ParamsForm {
+    active: bool,
+    fields: [String; 4],
+    field: usize,
+    cursor_pos: usize, // In chars, not bytes
+}
+
+impl ParamsForm {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(super) fn open(&mut self, params: &GenerationParams) {
+        let stop: Vec<String> = params.stop.iter().map(|s| s.replace('\n', "\\n")).collect();
+        self.fields = [
+            params.temperature.map(|v| v.to_string()).unwrap_or_default(),
+            params.top_p.map(|v| v.to_string()).unwrap_or_default(),
+            params.max_tokens.map(|v| v.to_string()).unwrap_or_default(),
+            stop.join(", "),
+        ];
+        self.active = true;
+        self.field = 0;
+        self.cursor_pos = self.fields[0].chars().count();
+    }
+
+    pub(super) fn close(&mut self) {
+        self.active = false;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// One `label: value` line per field
+    pub fn text(&self) -> String {
+        LABELS
+            .iter()
+            .zip(&self.fields)
+            .map(|(label, value)| format!("{}{}", label, value))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// Line and column (in chars) of the cursor in `text`
+    pub fn cursor_line_col(&self) -> (usize, usize) {
+        (self.field, LABELS[self.field].len() + self.cursor_pos)
+    }
+
+    /// The parameters in the form, or what's wrong with them
+    pub(super) fn params(&self) -> Result<GenerationParams> {
+        let temperature = parse_number(&self.fields[0], "Temperature")?;
+        if temperature.is_some_and(|t| !(0.0..=2.0).contains(&t)) {
+            return Err("Temperature must be between 0 and 2".into());
+        }
+        let top_p = parse_number(&self.fields[1], "Top p")?;
+        if top_p.is_some_and(|p| !(0.0..=1.0).contains(&p)) {
+            return Err("Top p must be between 0 and 1".into());
+        }
+        let max_tokens = parse_number(&self.fields[2], "Max tokens")?;
+        if max_tokens == Some(0) {
+            return Err("Max tokens must be at least 1".into());
+        }
+
+        let stop = self.fields[3]
+            .split(',')
+            .map(|s| s.trim().replace("\\n", "\n"))
+            .filter(|s| !s.is_empty())
+            .collect();
+
+        Ok(GenerationParams {
+            temperature,
+            top_p,
+            max_tokens,
+            stop,
+        })
+    }
+
+    fn value(&mut self) -> &mut String {
+        &mut self.fields[self.field]
+    }
+
+    fn byte_pos(&self) -> usize {
+        let value = &self.fields[self.field];
+        value
+            .char_indices()
+            .nth(self.cursor_pos)
+            .map(|(i, _)| i)
+            .unwrap_or(value.len())
+    }
+
+    pub(super) fn insert_char(&mut self, c: char) {
+        let pos = self.byte_pos();
+        self.value().insert(pos, c);
+        self.cursor_pos += 1;
+    }
+
+    pub(super) fn delete_previous_char(&mut self) {
+        if self.cursor_pos > 0 {
+            self.cursor_pos -= 1;
+            let pos = self.byte_pos();
+            self.value().remove(pos);
+        }
+    }
+
+    pub(super) fn delete_current_char(&mut self) {
+        if self.cursor_pos < self.fields[self.field].chars().count() {
+            let pos = self.byte_pos();
+            self.value().remove(pos);
+        }
+    }
+
+    pub(super) fn move_cursor_left(&mut self) {
+        self.cursor_pos = self.cursor_pos.saturating_sub(1);
+    }
+
+    pub(super) fn move_cursor_right(&mut self) {
+        if self.cursor_pos < self.fields[self.field].chars().count() {
+            self.cursor_pos += 1;
+        }
+    }
+
+    /// Moves to the next field, or the previous one with `back`, wrapping
+    /// around. The cursor goes to the end of the field.
+    pub(super) fn next_field(&mut self, back: bool) {
+        let count = self.fields.len();
+        self.field = if back {
+            (self.field + count - 1) % count
+        } else {
+            (self.field + 1) % count
+        };
+        self.cursor_pos = self.fields[self.field].chars().count();
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(value: &str, name: &str) -> Result<Option<T>> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    value
+        .parse()
+        .map(Some)
+        .map_err(|_| Error::Custom(format!("{} must be a number, not {}", name, value)))
+}
@@ -0,0 +1,277 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+
+use crate::error::{Error, Result};
+use crate::files::encoding::{self, Encoding};
+use crate::files::line_ending;
+use crate::syntax::Style;
+
+/// One `@@` section of a unified diff
+#[derive(Debug, Clone)]
+pub struct Hunk {
+    pub path: String,
+    header: String,
+    // 1-based, 0 for a new file
+    old_start: usize,
+    // ' ', '-' or '+' with the line's text
+    lines: Vec<(char, String)>,
+    pub accepted: bool,
+}
+
+impl Hunk {
+    fn old_lines(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter(|(sign, _)| *sign != '+')
+            .map(|(_, text)| text.as_str())
+            .collect()
+    }
+
+    fn new_lines(&self) -> Vec<&str> {
+        self.lines
+            .iter()
+            .filter(|(sign, _)| *sign != '-')
+            .map(|(_, text)| text.as_str())
+            .collect()
+    }
+}
+
+/// Reads the hunks of a unified diff, e.g. from an answer. Paths lose the
+/// `a/` and `b/` prefixes git puts in front.
+pub fn parse(text: &str) -> Result<Vec<Hunk>> {
+    let mut hunks: Vec<Hunk> = Vec::new();
+    let mut path: Option<String> = None;
+
+    for line in text.lines() {
+        if let Some(rest) = line.strip_prefix("+++ ") {
+            let name = rest.split('\t').next().unwrap_or(rest).trim();
+            let name = name
+                .strip_prefix("b/")
+                .or_else(|| name.strip_prefix("a/"))
+                .unwrap_or(name);
+            path = (name != "/dev/null").then(|| name.to_string());
+        } else if line.starts_with("--- ") {
+            continue;
+        } else if line.starts_with("@@") {
+            let path = path.clone().ok_or("Diff has a hunk before its +++ file line")?;
+            hunks.push(Hunk {
+                path,
+                header: line.to_string(),
+                old_start: old_start(line).ok_or("Can't read the @@ line of the diff")?,
+                lines: Vec::new(),
+                accepted: true,
+            });
+        } else if let Some(hunk) = hunks.last_mut() {
+            let mut chars = line.chars();
+            match chars.next() {
+                Some(sign @ (' ' | '-' | '+')) => hunk.lines.push((sign, chars.collect())),
+                // Some models drop the space of empty context lines
+                None => hunk.lines.push((' ', String::new())),
+                _ => {}
+            }
+        }
+    }
+
+    if hunks.is_empty() {
+        return Err("No unified diff found".into());
+    }
+    Ok(hunks)
+}
+
+// `@@ -12,7 +12,8 @@` -> 12
+fn old_start(header: &str) -> Option<usize> {
+    let old = header.split_whitespace().nth(1)?.strip_prefix('-')?;
+    old.split(',').next()?.parse().ok()
+}
+
+/// Applies hunks to a file's content. Each hunk is looked for at its line
+/// number first, then anywhere after the previous one, so diffs with
+/// slightly wrong numbers still apply.
+fn apply(content: &str, hunks: &[&Hunk]) -> Result<String> {
+    let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+    // Where the previous hunk ended, and how much earlier hunks moved lines
+    let mut from = 0;
+    let mut offset: isize = 0;
+
+    for hunk in hunks {
+        let old = hunk.old_lines();
+        let new = hunk.new_lines();
+
+        let expected = (hunk.old_start.saturating_sub(1) as isize + offset).max(0) as usize;
+        let matches_at = |at: usize| {
+            at + old.len() <= lines.len()
+                && lines[at..at + old.len()].iter().zip(&old).all(|(a, b)| a.trim_end() == b.trim_end())
+        };
+        let at = if matches_at(expected) {
+            expected
+        } else {
+            (from..=lines.len().saturating_sub(old.len()))
+                .find(|&at| matches_at(at))
+                .ok_or_else(|| Error::Custom(format!("{}: hunk {} doesn't match", hunk.path, hunk.header)))?
+        };
+
+        lines.splice(at..at + old.len(), new.iter().map(|l| l.to_string()));
+        from = at + new.len();
+        offset += new.len() as isize - old.len() as isize;
+    }
+
+    let mut result = lines.join("\n");
+    if content.is_empty() || content.ends_with('\n') {
+        result.push('\n');
+    }
+    Ok(result)
+}
+
+// Paths come from an answer: only relative ones that stay inside the
+// working directory, also through symlinks, are written
+fn check_path(path: &str) -> Result<()> {
+    let inside = Path::new(path)
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+    let resolved = match fs::canonicalize(path) {
+        Ok(resolved) => Some(resolved),
+        Err(e) if e.kind() == ErrorKind::NotFound => None,
+        Err(e) => return Err(e.into()),
+    };
+    let cwd = std::env::current_dir().and_then(fs::canonicalize)?;
+    if !inside || resolved.is_some_and(|resolved| !resolved.starts_with(&cwd)) {
+        return Err(Error::Custom(format!("{} is outside the working directory", path)));
+    }
+
+    Ok(())
+}
+
+/// Preview of a diff's hunks, each accepted or rejected before writing
+#[derive(Debug, Clone)]
+pub struct PatchPreview {
+    pub hunks: Vec<Hunk>,
+    selected: usize,
+    scroll: usize,
+}
+
+impl PatchPreview {
+    pub(super) fn new(hunks: Vec<Hunk>) -> Self {
+        Self {
+            hunks,
+            selected: 0,
+            scroll: 0,
+        }
+    }
+
+    pub fn title(&self) -> String {
+        let accepted = self.hunks.iter().filter(|h| h.accepted).count();
+        format!("Apply diff: {} of {} hunks accepted", accepted, self.hunks.len())
+    }
+
+    /// Lines of the popup; the selected hunk's header is highlighted
+    pub fn lines(&self) -> Vec<(Style, String)> {
+        let mut lines = Vec::new();
+
+        for (i, hunk) in self.hunks.iter().enumerate() {
+            let mark = if hunk.accepted { "[x]" } else { "[ ]" };
+            let style = if i == self.selected {
+                Style::Selection
+            } else {
+                Style::Heading
+            };
+            lines.push((style, format!("{} {} {}", mark, hunk.path, hunk.header)));
+
+            for (sign, text) in &hunk.lines {
+                let style = match sign {
+                    '+' => Style::DiffAdd,
+                    '-' => Style::DiffRemove,
+                    _ => Style::Normal,
+                };
+                lines.push((style, format!("{} {}", sign, text)));
+            }
+        }
+
+        lines
+    }
+
+    // Popup line of a hunk's header
+    fn header_line(&self, index: usize) -> usize {
+        self.hunks[..index].iter().map(|h| h.lines.len() + 1).sum()
+    }
+
+    pub fn scroll(&self) -> usize {
+        self.scroll
+    }
+
+    /// Scrolls so the selected hunk starts on screen
+    pub fn scroll_into_view(&mut self, rows: usize) {
+        let line = self.header_line(self.selected);
+        if line < self.scroll || line >= self.scroll + rows.max(1) {
+            self.scroll = line;
+        }
+    }
+
+    pub(super) fn select_next(&mut self) {
+        if self.selected + 1 < self.hunks.len() {
+            self.selected += 1;
+        }
+    }
+
+    pub(super) fn select_previous(&mut self) {
+        self.selected = self.selected.saturating_sub(1);
+    }
+
+    /// Answers the selected hunk and moves on to the next
+    pub(super) fn decide(&mut self, accept: bool) {
+        self.hunks[self.selected].accepted = accept;
+        self.select_next();
+    }
+
+    pub(super) fn set_all(&mut self, accept: bool) {
+        for hunk in &mut self.hunks {
+            hunk.accepted = accept;
+        }
+    }
+
+    /// Writes the accepted hunks to their files, in the encoding and line
+    /// breaks they have. Paths outside the working directory and files in
+    /// `unsaved`, open with edits of their own, are refused. Returns the
+    /// paths written.
+    pub(super) fn write(&self, unsaved: &[PathBuf]) -> Result<Vec<String>> {
+        let mut paths: Vec<&str> = Vec::new();
+        for hunk in self.hunks.iter().filter(|h| h.accepted) {
+            if !paths.contains(&hunk.path.as_str()) {
+                paths.push(&hunk.path);
+            }
+        }
+
+        // Everything is checked before the first file is touched
+        let mut patched = Vec::new();
+        for path in &paths {
+            check_path(path)?;
+            if fs::canonicalize(path).is_ok_and(|path| unsaved.contains(&path)) {
+                return Err(Error::Custom(format!("{} is open with unsaved changes", path)));
+            }
+
+            let (content, encoding) = match encoding::read(Path::new(path)) {
+                Ok(read) => read,
+                Err(Error::Io(e)) if e.kind() == ErrorKind::NotFound => (String::new(), Encoding::Utf8),
+                Err(e) => return Err(e),
+            };
+            let (content, line_ending) = line_ending::normalize(&content);
+            let hunks: Vec<&Hunk> = self
+                .hunks
+                .iter()
+                .filter(|h| h.accepted && h.path == *path)
+                .collect();
+            patched.push((*path, line_ending.apply(&apply(&content, &hunks)?), encoding));
+        }
+
+        for (path, content, encoding) in &patched {
+            if let Some(dir) = Path::new(path).parent() {
+                if !dir.as_os_str().is_empty() {
+                    fs::create_dir_all(dir)?;
+                }
+            }
+            encoding::write(Path::new(path), content, *encoding)?;
+        }
+
+        Ok(paths.into_iter().map(str::to_string).collect())
+    }
+}
@@ -0,0 +1,60 @@
+// Rows moved by PgUp/PgDn
+const PAGE_ROWS: usize = 20;
+
+/// Scroll position of the rendered markdown view. The layout depends on the
+/// terminal width, so the renderer hands over the buffer line of every row.
+#[derive(Debug, Clone)]
+pub struct Preview {
+    scroll: usize,
+    // Buffer line to show on top once the rows are known
+    anchor: Option<usize>,
+    // Buffer lines on screen in the last frame
+    shown: (usize, usize),
+}
+
+impl Preview {
+    /// Opens at buffer line `row`
+    pub(super) fn new(row: usize) -> Self {
+        Self {
+            scroll: 0,
+            anchor: Some(row),
+            shown: (row, row),
+        }
+    }
+
+    /// First row shown, given the buffer line of every row and the rows
+    /// that fit on screen
+    pub fn scroll(&mut self, sources: &[usize], rows: usize) -> usize {
+        if let Some(row) = self.anchor.take() {
+            self.scroll = sources.iter().position(|&line| line >= row).unwrap_or(0);
+        }
+        self.scroll = self.scroll.min(sources.len().saturating_sub(rows));
+
+        if let Some(&first) = sources.get(self.scroll) {
+            let last = sources[(self.scroll + rows).min(sources.len()) - 1];
+            self.shown = (first, last);
+        }
+        self.scroll
+    }
+
+    /// Buffer lines on screen, the cursor is moved among them on close
+    pub(super) fn shown(&self) -> (usize, usize) {
+        self.shown
+    }
+
+    pub(super) fn scroll_down(&mut self, rows: usize) {
+        self.scroll = self.scroll.saturating_add(rows);
+    }
+
+    pub(super) fn scroll_up(&mut self, rows: usize) {
+        self.scroll = self.scroll.saturating_sub(rows);
+    }
+
+    pub(super) fn page_down(&mut self) {
+        self.scroll_down(PAGE_ROWS);
+    }
+
+    pub(super) fn page_up(&mut self) {
+        self.scroll_up(PAGE_ROWS);
+    }
+}
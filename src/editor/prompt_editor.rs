@@ -0,0 +1,98 @@
+/// Small multi-line text editor for the system prompt popup
+#[derive(Debug, Clone, Default)]
+pub struct PromptEditor {
+    active: bool,
+    text: String,
+    cursor_pos: usize, // In chars, not bytes
+}
+
+impl PromptEditor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(super) fn open(&mut self, text: &str) {
+        self.active = true;
+        self.text = text.to_string();
+        self.cursor_pos = self.text.chars().count();
+    }
+
+    pub(super) fn close(&mut self) {
+        self.active = false;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub fn get_text(&self) -> &str {
+        &self.text
+    }
+
+    /// Line and column (in chars) of the cursor
+    pub fn cursor_line_col(&self) -> (usize, usize) {
+        let before: String = self.text.chars().take(self.cursor_pos).collect();
+        let line = before.matches('\n').count();
+        let col = before.chars().rev().take_while(|c| *c != '\n').count();
+        (line, col)
+    }
+
+    fn byte_pos(&self) -> usize {
+        self.text
+            .char_indices()
+            .nth(self.cursor_pos)
+            .map(|(i, _)| i)
+            .unwrap_or(self.text.len())
+    }
+
+    pub(super) fn insert_char(&mut self, c: char) {
+        let pos = self.byte_pos();
+        self.text.insert(pos, c);
+        self.cursor_pos += 1;
+    }
+
+    pub(super) fn delete_previous_char(&mut self) {
+        if self.cursor_pos > 0 {
+            self.cursor_pos -= 1;
+            let pos = self.byte_pos();
+            self.text.remove(pos);
+        }
+    }
+
+    pub(super) fn delete_current_char(&mut self) {
+        if self.cursor_pos < self.text.chars().count() {
+            let pos = self.byte_pos();
+            self.text.remove(pos);
+        }
+    }
+
+    pub(super) fn move_cursor_left(&mut self) {
+        self.cursor_pos = self.cursor_pos.saturating_sub(1);
+    }
+
+    pub(super) fn move_cursor_right(&mut self) {
+        if self.cursor_pos < self.text.chars().count() {
+            self.cursor_pos += 1;
+        }
+    }
+
+    /// Moves to the same column of the previous (`up`) or next line
+    pub(super) fn move_cursor_vertically(&mut self, up: bool) {
+        let (line, col) = self.cursor_line_col();
+        let lines: Vec<&str> = self.text.split('\n').collect();
+
+        let target = if up {
+            match line.checked_sub(1) {
+                Some(target) => target,
+                None => return,
+            }
+        } else if line + 1 < lines.len() {
+            line + 1
+        } else {
+            return;
+        };
+
+        let line_start: usize = lines[..target].iter().map(|l| l.chars().count() + 1).sum();
+        self.cursor_pos = line_start + col.min(lines[target].chars().count());
+    }
+}
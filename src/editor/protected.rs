@@ -0,0 +1,97 @@
+use std::ops::Range;
+
+/// Char ranges of assistant answers in a buffer. They can't be edited until
+/// unlocked, so a stray key doesn't mangle an answer.
+#[derive(Debug, Clone, Default)]
+pub struct Protected {
+    // Sorted and non-overlapping
+    regions: Vec<Range<usize>>,
+    unlocked: bool,
+}
+
+impl Protected {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records inserted answer text, after `shift` made room for it; a
+    /// streamed chunk extends the region it continues
+    pub(super) fn add(&mut self, range: Range<usize>) {
+        let i = self.regions.partition_point(|r| r.end < range.start);
+        match self.regions.get_mut(i) {
+            Some(region) if region.end == range.start => region.end = range.end,
+            _ => self.regions.insert(i, range),
+        }
+    }
+
+    /// Moves the regions along with an edit that replaced `range` with
+    /// `inserted` chars. Text inserted at the edge of a region stays outside.
+    pub(super) fn shift(&mut self, range: Range<usize>, inserted: usize) {
+        let removed = range.end - range.start;
+        let map_start = |p: usize| {
+            if p < range.start {
+                p
+            } else if p >= range.end {
+                p - removed + inserted
+            } else {
+                range.start + inserted
+            }
+        };
+        let map_end = |p: usize| {
+            if p <= range.start {
+                p
+            } else if p > range.end {
+                p - removed + inserted
+            } else {
+                range.start
+            }
+        };
+
+        for region in &mut self.regions {
+            *region = map_start(region.start)..map_end(region.end);
+        }
+        self.regions.retain(|region| region.start < region.end);
+    }
+
+    /// Whether replacing `range` would change answer text. Inserting right
+    /// before or after an answer is fine.
+    pub fn blocks(&self, range: &Range<usize>) -> bool {
+        if self.unlocked {
+            return false;
+        }
+
+        self.regions.iter().any(|region| {
+            if range.is_empty() {
+                region.start < range.start && range.start < region.end
+            } else {
+                region.start < range.end && range.start < region.end
+            }
+        })
+    }
+
+    pub fn contains(&self, char_idx: usize) -> bool {
+        let i = self.regions.partition_point(|r| r.end <= char_idx);
+        self.regions
+            .get(i)
+            .map(|r| r.contains(&char_idx))
+            .unwrap_or(false)
+    }
+
+    /// End of the last answer, where the text typed after it starts
+    pub fn last_end(&self) -> usize {
+        self.regions.last().map_or(0, |region| region.end)
+    }
+
+    pub fn is_unlocked(&self) -> bool {
+        self.unlocked
+    }
+
+    pub(super) fn toggle_unlocked(&mut self) {
+        self.unlocked = !self.unlocked;
+    }
+
+    /// Forgets the regions, e.g. when other content is loaded
+    pub(super) fn clear(&mut self) {
+        self.regions.clear();
+    }
+}
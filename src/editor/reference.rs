@@ -0,0 +1,80 @@
+use std::path::{Path, PathBuf};
+
+use crate::git;
+
+/// A file named in the text, like `src/editor/mod.rs:123:5`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Reference {
+    pub(super) path: String,
+    /// 1-based, as compilers and answers write them
+    pub(super) line: Option<usize>,
+    pub(super) column: Option<usize>,
+}
+
+// Quotes and brackets around a path in prose or markdown. Sentence
+// punctuation only goes at the end, `./` and `../` start paths.
+const OPENERS: &[char] = &['`', '"', '\'', '(', '[', '<', '{', '*'];
+const CLOSERS: &[char] = &['`', '"', '\'', ')', ']', '>', '}', '*', ',', ';', '.', ':', '!', '?'];
+
+/// Reference in the blank-separated word of `line` at char column `col`
+pub(super) fn at(line: &str, col: usize) -> Option<Reference> {
+    let chars: Vec<char> = line.chars().collect();
+    if !matches!(chars.get(col), Some(c) if !c.is_whitespace()) {
+        return None;
+    }
+
+    let mut start = col;
+    while start > 0 && !chars[start - 1].is_whitespace() {
+        start -= 1;
+    }
+    let mut end = col + 1;
+    while end < chars.len() && !chars[end].is_whitespace() {
+        end += 1;
+    }
+    let word: String = chars[start..end].iter().collect();
+
+    parse(&word)
+}
+
+fn parse(word: &str) -> Option<Reference> {
+    // `path:12:5`, `path:12` or `path`, the numbers read from the end
+    let word = word.trim_start_matches(OPENERS).trim_end_matches(CLOSERS);
+    let mut numbers = Vec::new();
+    let mut path = word;
+    while numbers.len() < 2 {
+        let Some((rest, number)) = path.rsplit_once(':') else {
+            break;
+        };
+        let Ok(number) = number.parse::<usize>() else {
+            break;
+        };
+        numbers.insert(0, number);
+        path = rest;
+    }
+
+    let path = path.trim_end_matches(CLOSERS);
+    if path.is_empty() {
+        return None;
+    }
+
+    Some(Reference {
+        path: path.to_string(),
+        line: numbers.first().copied(),
+        column: numbers.get(1).copied(),
+    })
+}
+
+/// Existing file for `path`: as given, from the working directory, or from
+/// the root of the repository it's in
+pub(super) fn resolve(path: &str) -> Option<PathBuf> {
+    let path = Path::new(path);
+    if path.is_file() {
+        return Some(path.to_path_buf());
+    }
+    if path.is_absolute() {
+        return None;
+    }
+
+    let in_repository = git::toplevel(Path::new("."))?.join(path);
+    in_repository.is_file().then_some(in_repository)
+}
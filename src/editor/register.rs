@@ -0,0 +1,58 @@
+use std::collections::HashMap;
+
+/// Yank/delete registers, separate from the system clipboard. `"` followed
+/// by `a`-`z` picks a named register for the next yank, delete or paste.
+#[derive(Debug, Clone, Default)]
+pub struct Registers {
+    default: String,
+    named: HashMap<char, String>,
+
+    // `"` was pressed, the next key names the register
+    pending: bool,
+    selected: Option<char>,
+}
+
+impl Registers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(super) fn begin_select(&mut self) {
+        self.pending = true;
+    }
+
+    pub fn is_selecting(&self) -> bool {
+        self.pending
+    }
+
+    /// Names the register for the next operation. Anything but `a`-`z`
+    /// cancels the selection.
+    pub(super) fn select(&mut self, name: char) {
+        self.pending = false;
+        self.selected = name.is_ascii_lowercase().then_some(name);
+    }
+
+    /// Register picked for the next operation, if any
+    pub fn selected(&self) -> Option<char> {
+        self.selected
+    }
+
+    /// Stores yanked or deleted text. It always goes to the default register,
+    /// and to the selected named one too.
+    pub(super) fn store(&mut self, text: String) {
+        if let Some(name) = self.selected.take() {
+            self.named.insert(name, text.clone());
+        }
+        self.default = text;
+    }
+
+    /// Content of the selected register, or the default one
+    pub(super) fn get(&mut self) -> Option<&str> {
+        let text = match self.selected.take() {
+            Some(name) => self.named.get(&name)?,
+            None => &self.default,
+        };
+
+        (!text.is_empty()).then_some(text.as_str())
+    }
+}
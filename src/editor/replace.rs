@@ -0,0 +1,137 @@
+use std::ops::Range;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum Step {
+    #[default]
+    Inactive,
+    // The pattern is typed into the search prompt, so matches show up while typing
+    Pattern,
+    Replacement,
+    Confirm,
+}
+
+/// Interactive search and replace, one question per match
+#[derive(Debug, Clone, Default)]
+pub struct Replace {
+    step: Step,
+
+    input: String,
+    cursor_pos: usize, // In chars, not bytes
+
+    // Match waiting for an answer
+    current: Option<Range<usize>>,
+    replaced: usize,
+}
+
+impl Replace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(super) fn start(&mut self) {
+        *self = Self {
+            step: Step::Pattern,
+            ..Self::default()
+        };
+    }
+
+    pub(super) fn cancel(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.step != Step::Inactive
+    }
+
+    pub fn is_entering_pattern(&self) -> bool {
+        self.step == Step::Pattern
+    }
+
+    pub fn is_entering_replacement(&self) -> bool {
+        self.step == Step::Replacement
+    }
+
+    pub fn is_confirming(&self) -> bool {
+        self.step == Step::Confirm
+    }
+
+    pub(super) fn enter_replacement(&mut self) {
+        self.step = Step::Replacement;
+    }
+
+    /// Asks about `current`; finishes when there's no match left
+    pub(super) fn confirm(&mut self, current: Option<Range<usize>>) {
+        self.step = if current.is_some() {
+            Step::Confirm
+        } else {
+            Step::Inactive
+        };
+        self.current = current;
+    }
+
+    pub(super) fn current(&self) -> Option<Range<usize>> {
+        self.current.clone()
+    }
+
+    pub fn is_current(&self, char_idx: usize) -> bool {
+        self.current
+            .as_ref()
+            .map(|r| r.contains(&char_idx))
+            .unwrap_or(false)
+    }
+
+    pub(super) fn count_replaced(&mut self, count: usize) {
+        self.replaced += count;
+    }
+
+    pub fn replaced(&self) -> usize {
+        self.replaced
+    }
+
+    pub fn get_input(&self) -> &str {
+        &self.input
+    }
+
+    pub fn cursor_pos(&self) -> usize {
+        self.cursor_pos
+    }
+
+    fn byte_pos(&self) -> usize {
+        self.input
+            .char_indices()
+            .nth(self.cursor_pos)
+            .map(|(i, _)| i)
+            .unwrap_or(self.input.len())
+    }
+
+    pub(super) fn insert_char(&mut self, c: char) {
+        let pos = self.byte_pos();
+        self.input.insert(pos, c);
+        self.cursor_pos += 1;
+    }
+
+    pub(super) fn delete_previous_char(&mut self) {
+        if self.cursor_pos > 0 {
+            self.cursor_pos -= 1;
+            let pos = self.byte_pos();
+            self.input.remove(pos);
+        }
+    }
+
+    pub(super) fn delete_current_char(&mut self) {
+        if self.cursor_pos < self.input.chars().count() {
+            let pos = self.byte_pos();
+            self.input.remove(pos);
+        }
+    }
+
+    pub(super) fn move_cursor_pos_left(&mut self) {
+        self.cursor_pos = self.cursor_pos.saturating_sub(1);
+    }
+
+    pub(super) fn move_cursor_pos_right(&mut self) {
+        if self.cursor_pos < self.input.chars().count() {
+            self.cursor_pos += 1;
+        }
+    }
+}
@@ -0,0 +1,244 @@
+use regex::{Regex, RegexBuilder};
+use ropey::Rope;
+use std::ops::Range;
+
+#[derive(Debug, Clone, Default)]
+pub struct Search {
+    // Prompt popup is open and receiving keys
+    active: bool,
+
+    input: String,
+    cursor_pos: usize, // In chars, not bytes
+
+    case_insensitive: bool,
+    regex: bool,
+    // Pattern doesn't compile (regex mode only)
+    invalid: bool,
+    // Compiled pattern, built again only when the pattern or its options
+    // change
+    compiled: Option<Regex>,
+
+    // Char ranges of every match, sorted and non-overlapping
+    matches: Vec<Range<usize>>,
+    // The pattern or the buffer changed since the matches were found
+    stale: bool,
+}
+
+impl Search {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Opens the prompt with an empty pattern
+    pub(super) fn open(&mut self) {
+        self.active = true;
+        self.input.clear();
+        self.cursor_pos = 0;
+        self.invalid = false;
+        self.compiled = None;
+        self.matches.clear();
+        self.stale = false;
+    }
+
+    /// Closes the prompt but keeps the pattern for `n`/`N`
+    pub(super) fn close(&mut self) {
+        self.active = false;
+    }
+
+    /// Closes the prompt and drops the pattern and its highlights
+    pub(super) fn clear(&mut self) {
+        self.open();
+        self.active = false;
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    pub fn get_input(&self) -> &str {
+        &self.input
+    }
+
+    pub fn cursor_pos(&self) -> usize {
+        self.cursor_pos
+    }
+
+    pub fn match_count(&self) -> usize {
+        self.matches.len()
+    }
+
+    pub fn is_case_insensitive(&self) -> bool {
+        self.case_insensitive
+    }
+
+    pub fn is_regex(&self) -> bool {
+        self.regex
+    }
+
+    pub fn is_invalid(&self) -> bool {
+        self.invalid
+    }
+
+    pub(super) fn toggle_case_insensitive(&mut self) {
+        self.case_insensitive = !self.case_insensitive;
+        self.compile();
+    }
+
+    pub(super) fn toggle_regex(&mut self) {
+        self.regex = !self.regex;
+        self.compile();
+    }
+
+    fn byte_pos(&self) -> usize {
+        self.input
+            .char_indices()
+            .nth(self.cursor_pos)
+            .map(|(i, _)| i)
+            .unwrap_or(self.input.len())
+    }
+
+    pub(super) fn insert_char(&mut self, c: char) {
+        let pos = self.byte_pos();
+        self.input.insert(pos, c);
+        self.cursor_pos += 1;
+        self.compile();
+    }
+
+    pub(super) fn delete_previous_char(&mut self) {
+        if self.cursor_pos > 0 {
+            self.cursor_pos -= 1;
+            let pos = self.byte_pos();
+            self.input.remove(pos);
+            self.compile();
+        }
+    }
+
+    pub(super) fn delete_current_char(&mut self) {
+        if self.cursor_pos < self.input.chars().count() {
+            let pos = self.byte_pos();
+            self.input.remove(pos);
+            self.compile();
+        }
+    }
+
+    pub(super) fn move_cursor_pos_left(&mut self) {
+        self.cursor_pos = self.cursor_pos.saturating_sub(1);
+    }
+
+    pub(super) fn move_cursor_pos_right(&mut self) {
+        if self.cursor_pos < self.input.chars().count() {
+            self.cursor_pos += 1;
+        }
+    }
+
+    // Builds the regex of the pattern, the matches are found again on the
+    // next `refresh`
+    fn compile(&mut self) {
+        let pattern = if self.regex {
+            self.input.clone()
+        } else {
+            regex::escape(&self.input)
+        };
+
+        self.compiled = if self.input.is_empty() {
+            None
+        } else {
+            RegexBuilder::new(&pattern)
+                .case_insensitive(self.case_insensitive)
+                .build()
+                .ok()
+        };
+        self.invalid = !self.input.is_empty() && self.compiled.is_none();
+        self.stale = true;
+    }
+
+    /// The buffer was edited, the matches are found again on the next
+    /// `refresh`
+    pub(super) fn mark_stale(&mut self) {
+        self.stale = true;
+    }
+
+    /// Finds the matches of the pattern in `buffer` again when the pattern
+    /// or the buffer changed since the last time
+    pub(super) fn refresh(&mut self, buffer: &Rope) {
+        if !std::mem::take(&mut self.stale) {
+            return;
+        }
+        self.matches.clear();
+        let Some(re) = &self.compiled else {
+            return;
+        };
+
+        let text = buffer.to_string();
+        self.matches = re
+            .find_iter(&text)
+            // Zero-width matches (e.g. `a*`) would highlight nothing
+            .filter(|m| !m.is_empty())
+            .map(|m| buffer.byte_to_char(m.start())..buffer.byte_to_char(m.end()))
+            .collect();
+    }
+
+    pub fn is_match(&self, char_idx: usize) -> bool {
+        let i = self.matches.partition_point(|r| r.end <= char_idx);
+        self.matches
+            .get(i)
+            .map(|r| r.contains(&char_idx))
+            .unwrap_or(false)
+    }
+
+    /// First match starting at or after `char_idx`, without wrapping
+    pub(super) fn match_from(&self, char_idx: usize) -> Option<Range<usize>> {
+        self.matches.iter().find(|r| r.start >= char_idx).cloned()
+    }
+
+    /// Every match starting at or after `from` with the text replacing it.
+    /// In regex mode `$1` and `${name}` expand to the capture groups.
+    pub(super) fn replacements(
+        &self,
+        buffer: &Rope,
+        from: usize,
+        replacement: &str,
+    ) -> Vec<(Range<usize>, String)> {
+        let Some(re) = &self.compiled else {
+            return Vec::new();
+        };
+
+        let text = buffer.to_string();
+        re.captures_iter(&text)
+            .filter_map(|caps| {
+                let m = caps.get(0).filter(|m| !m.is_empty())?;
+                let range = buffer.byte_to_char(m.start())..buffer.byte_to_char(m.end());
+                if range.start < from {
+                    return None;
+                }
+
+                let mut new_text = String::new();
+                if self.regex {
+                    caps.expand(replacement, &mut new_text);
+                } else {
+                    new_text.push_str(replacement);
+                }
+                Some((range, new_text))
+            })
+            .collect()
+    }
+
+    /// Start of the first match after `char_idx`, wrapping around
+    pub(super) fn next_match(&self, char_idx: usize) -> Option<usize> {
+        self.matches
+            .iter()
+            .find(|r| r.start > char_idx)
+            .or(self.matches.first())
+            .map(|r| r.start)
+    }
+
+    /// Start of the last match before `char_idx`, wrapping around
+    pub(super) fn previous_match(&self, char_idx: usize) -> Option<usize> {
+        self.matches
+            .iter()
+            .rev()
+            .find(|r| r.start < char_idx)
+            .or(self.matches.last())
+            .map(|r| r.start)
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
+use std::ops::Range;
+use std::path::PathBuf;
+
+use crate::config::{self, Config};
+use crate::error::Result;
+
+/// `~/.rusty/snippets.toml`, then `<history_dir>/snippets.toml` of the
+/// workspace, whose triggers win
+fn paths() -> Vec<PathBuf> {
+    let global = Config::path().and_then(|path| path.parent().map(|dir| dir.join("snippets.toml")));
+    let local = PathBuf::from(&config::get().history_dir).join("snippets.toml");
+    global.into_iter().chain([local]).collect()
+}
+
+/// Trigger to body, read again on every expansion so edits of the files
+/// apply right away
+pub(super) fn load() -> Result<BTreeMap<String, String>> {
+    let mut snippets = BTreeMap::new();
+    for path in paths() {
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        };
+        let table: BTreeMap<String, String> = toml::from_str(&content)?;
+        snippets.extend(table);
+    }
+    Ok(snippets)
+}
+
+/// Longest trigger `before` ends with. One starting with a letter must
+/// start a word too, so `;fn` expands after `x` but `fn` doesn't.
+pub(super) fn trigger<'a>(snippets: &'a BTreeMap<String, String>, before: &str) -> Option<&'a str> {
+    snippets
+        .keys()
+        .filter(|trigger| !trigger.is_empty() && before.ends_with(trigger.as_str()))
+        .filter(|trigger| {
+            let rest = &before[..before.len() - trigger.len()];
+            let word = |c: char| c.is_alphanumeric() || c == '_';
+            !trigger.starts_with(word) || !matches!(rest.chars().last(), Some(c) if word(c))
+        })
+        .max_by_key(|trigger| trigger.len())
+        .map(String::as_str)
+}
+
+/// Text of a snippet body and the char ranges of its tab-stops in jump
+/// order: `$1`, `${2:default}`, ... and `$0` last, the end of the text when
+/// there's no `$0`. `\$` is a dollar sign. Lines after the first get
+/// `indent` in front, a single trailing line break is dropped.
+pub(super) fn expand(body: &str, indent: &str) -> (String, Vec<Range<usize>>) {
+    let body = body.strip_suffix('\n').unwrap_or(body);
+    let mut text = String::new();
+    let mut len = 0;
+    let mut stops: BTreeMap<usize, Range<usize>> = BTreeMap::new();
+
+    let mut chars = body.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.peek() == Some(&'$') => {
+                chars.next();
+                push(&mut text, &mut len, "$");
+            }
+            '\n' => push(&mut text, &mut len, &format!("\n{}", indent)),
+            '$' => {
+                let braced = chars.peek() == Some(&'{');
+                let mut rest = chars.clone();
+                if braced {
+                    rest.next();
+                }
+                let mut digits = String::new();
+                while let Some(d) = rest.peek().filter(|d| d.is_ascii_digit()) {
+                    digits.push(*d);
+                    rest.next();
+                }
+                let Ok(number) = digits.parse::<usize>() else {
+                    push(&mut text, &mut len, "$");
+                    continue;
+                };
+
+                let mut default = String::new();
+                if braced {
+                    if rest.peek() == Some(&':') {
+                        rest.next();
+                        while let Some(d) = rest.peek().filter(|d| **d != '}') {
+                            default.push(*d);
+                            rest.next();
+                        }
+                    }
+                    if rest.next() != Some('}') {
+                        push(&mut text, &mut len, "$");
+                        continue;
+                    }
+                }
+                chars = rest;
+
+                let start = len;
+                push(&mut text, &mut len, &default.replace('\n', &format!("\n{}", indent)));
+                // A number used twice jumps to its first place only
+                stops.entry(number).or_insert(start..len);
+            }
+            c => {
+                text.push(c);
+                len += 1;
+            }
+        }
+    }
+
+    let last = stops.remove(&0).unwrap_or(len..len);
+    let mut stops: Vec<Range<usize>> = stops.into_values().collect();
+    stops.push(last);
+    (text, stops)
+}
+
+fn push(text: &mut String, len: &mut usize, s: &str) {
+    text.push_str(s);
+    *len += s.chars().count();
+}
+
+/// Tab-stops of an expanded snippet in the buffer. Edits are expected at
+/// the current stop, the ones after it move by the change of the buffer
+/// length.
+#[derive(Debug, Clone)]
+pub(super) struct TabStops {
+    stops: Vec<Range<usize>>,
+    current: usize,
+    // Buffer length when the cursor came to the current stop
+    len_chars: usize,
+    // The default text of the current stop goes away on the first key typed
+    placeholder: bool,
+}
+
+impl TabStops {
+    /// Stops of a snippet inserted at char `start`, the buffer being
+    /// `len_chars` long after the insert
+    pub(super) fn new(start: usize, stops: Vec<Range<usize>>, len_chars: usize) -> Self {
+        let stops = stops
+            .into_iter()
+            .map(|stop| start + stop.start..start + stop.end)
+            .collect();
+        Self {
+            stops,
+            current: 0,
+            len_chars,
+            placeholder: true,
+        }
+    }
+
+    /// Stop the cursor is at
+    pub(super) fn current(&self) -> Range<usize> {
+        self.stops[self.current].clone()
+    }
+
+    /// Whether the current stop is the final one, after which the
+    /// snippet is done
+    pub(super) fn at_last(&self) -> bool {
+        self.current + 1 >= self.stops.len()
+    }
+
+    /// Moves to the next or previous stop, accounting for what was typed
+    /// at the current one. Returns the stop now current.
+    pub(super) fn jump(&mut self, forward: bool, len_chars: usize) -> Range<usize> {
+        let delta = len_chars as isize - self.len_chars as isize;
+        let Range { start: at, end } = self.current();
+        for (index, stop) in self.stops.iter_mut().enumerate() {
+            let shift = |p: usize| (p as isize + delta).max(at as isize) as usize;
+            if index == self.current {
+                stop.end = shift(stop.end);
+            } else if stop.start >= end {
+                *stop = shift(stop.start)..shift(stop.end);
+            }
+        }
+
+        self.current = if forward {
+            (self.current + 1).min(self.stops.len() - 1)
+        } else {
+            self.current.saturating_sub(1)
+        };
+        self.len_chars = len_chars;
+        self.placeholder = true;
+        self.current()
+    }
+
+    /// The default text to replace when the key typed at char `at` is the
+    /// first one at the current stop. Any key ends the chance.
+    pub(super) fn take_placeholder(&mut self, at: usize) -> Option<Range<usize>> {
+        let stop = self.current();
+        let pending = std::mem::take(&mut self.placeholder);
+        (pending && at == stop.start && !stop.is_empty()).then_some(stop)
+    }
+}
@@ -0,0 +1,245 @@
+use ropey::Rope;
+use std::ops::Range;
+
+use crate::chat::{export, prompt_history, transcript};
+use crate::syntax::{Style, SyntaxHighlighter};
+
+use super::protected::Protected;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pane {
+    Prompt,
+    Transcript,
+}
+
+/// Where the prompt pane goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// Prompt on top, a third of the screen
+    Stacked,
+    /// Transcript fills the screen, a few rows of input box at the bottom
+    Chat,
+}
+
+/// Split view: the editor buffer becomes the prompt pane and the
+/// conversation moves to a read-only transcript pane next to it.
+pub struct SplitView {
+    transcript: Rope,
+    // Answer regions of the transcript, handed back with it
+    protected: Protected,
+    focus: Pane,
+    layout: Layout,
+
+    // Prompts sent so far, oldest first, recalled with Up and Down
+    prompts: Vec<String>,
+    // Prompt shown from the history and what was typed before browsing it
+    recalled: Option<usize>,
+    draft: String,
+
+    // First wrapped row shown in the transcript pane
+    scroll: usize,
+    // Stick to the bottom while answers arrive
+    follow: bool,
+
+    // Own highlighter, so its block cache isn't shared with the prompt
+    highlighter: Option<SyntaxHighlighter>,
+    highlights: Vec<(Range<usize>, Style)>, // char ranges
+}
+
+impl SplitView {
+    pub(super) fn new(transcript: Rope, protected: Protected, layout: Layout) -> Self {
+        // Prompts sent before, in any conversation, then the ones of this
+        // conversation the history doesn't know about
+        let mut prompts = prompt_history::load().unwrap_or_default();
+        for message in export::messages(&transcript.to_string()) {
+            let content = message.content.trim().to_string();
+            if message.role == "user" && !prompts.contains(&content) {
+                prompts.push(content);
+            }
+        }
+
+        let mut split = Self {
+            transcript,
+            protected,
+            focus: Pane::Prompt,
+            layout,
+            prompts,
+            recalled: None,
+            draft: String::new(),
+            scroll: 0,
+            follow: true,
+            highlighter: SyntaxHighlighter::new().ok(),
+            highlights: Vec::new(),
+        };
+        split.update_highlights();
+        split
+    }
+
+    pub fn transcript(&self) -> &Rope {
+        &self.transcript
+    }
+
+    pub fn protected(&self) -> &Protected {
+        &self.protected
+    }
+
+    /// Hands the conversation back when the split closes
+    pub(super) fn into_transcript(self) -> (Rope, Protected) {
+        (self.transcript, self.protected)
+    }
+
+    pub fn focus(&self) -> Pane {
+        self.focus
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    pub(super) fn toggle_focus(&mut self) {
+        self.focus = match self.focus {
+            Pane::Prompt => Pane::Transcript,
+            Pane::Transcript => Pane::Prompt,
+        };
+    }
+
+    /// Adds a sent prompt under a `## User` header, like answers get an
+    /// `## Assistant` one
+    pub(super) fn push_prompt(&mut self, prompt: &str) {
+        let separator = if self.transcript.len_chars() == 0 { "" } else { "\n\n" };
+        self.append(&format!("{}{}\n{}", separator, transcript::user_header(), prompt.trim_end()));
+        self.follow = true;
+
+        self.prompts.push(prompt.trim().to_string());
+        self.recalled = None;
+    }
+
+    /// The prompt sent before the one shown, `typed` is kept to come back
+    /// to. `None` at the oldest one.
+    pub(super) fn previous_prompt(&mut self, typed: &str) -> Option<String> {
+        let at = match self.recalled {
+            Some(0) => return None,
+            Some(at) => at - 1,
+            None => {
+                self.draft = typed.to_string();
+                self.prompts.len().checked_sub(1)?
+            }
+        };
+        self.recalled = Some(at);
+        Some(self.prompts[at].clone())
+    }
+
+    /// The prompt sent after the one shown, the draft after the newest.
+    /// `None` when no prompt from the history is shown.
+    pub(super) fn next_prompt(&mut self) -> Option<String> {
+        let at = self.recalled?;
+        if at + 1 < self.prompts.len() {
+            self.recalled = Some(at + 1);
+            Some(self.prompts[at + 1].clone())
+        } else {
+            self.recalled = None;
+            Some(std::mem::take(&mut self.draft))
+        }
+    }
+
+    pub(super) fn append(&mut self, text: &str) {
+        let char_idx = self.transcript.len_chars();
+        if let Some(highlighter) = &self.highlighter {
+            let byte_idx = self.transcript.char_to_byte(char_idx);
+            highlighter.edit(byte_idx, byte_idx, text);
+        }
+        self.transcript.insert(char_idx, text);
+        self.update_highlights();
+    }
+
+    /// Appends answer text, which stays read-only like in the buffer
+    pub(super) fn append_answer(&mut self, text: &str) {
+        let char_idx = self.transcript.len_chars();
+        let end = char_idx + text.chars().count();
+        self.append(text);
+        self.protected.add(char_idx..end);
+    }
+
+    /// Drops everything from `char_idx` on, e.g. a cancelled answer
+    pub(super) fn truncate(&mut self, char_idx: usize) {
+        let end = self.transcript.len_chars();
+        if char_idx >= end {
+            return;
+        }
+
+        if let Some(highlighter) = &self.highlighter {
+            highlighter.edit(
+                self.transcript.char_to_byte(char_idx),
+                self.transcript.len_bytes(),
+                "",
+            );
+        }
+        self.protected.shift(char_idx..end, 0);
+        self.transcript.remove(char_idx..end);
+        self.update_highlights();
+    }
+
+    pub(super) fn clear(&mut self) {
+        self.truncate(0);
+        self.scroll = 0;
+        self.follow = true;
+    }
+
+    fn update_highlights(&mut self) {
+        if let Some(highlighter) = &self.highlighter {
+            let highlights = highlighter.highlight_buffer(&self.transcript, None);
+            self.highlights =
+                highlighter.convert_highlights_to_char_ranges(&self.transcript, highlights);
+        }
+    }
+
+    /// Style of every char of a transcript line
+    pub fn line_styles(&self, line: usize) -> Vec<Style> {
+        let start = self.transcript.line_to_char(line);
+        let len = self.transcript.line(line).len_chars();
+        let mut styles = vec![Style::Normal; len];
+
+        for (range, style) in &self.highlights {
+            if range.end <= start || range.start >= start + len {
+                continue;
+            }
+            for i in range.start.max(start)..range.end.min(start + len) {
+                // First match wins, like in the prompt pane
+                if styles[i - start] == Style::Normal {
+                    styles[i - start] = *style;
+                }
+            }
+        }
+
+        styles
+    }
+
+    pub fn scroll(&self) -> usize {
+        self.scroll
+    }
+
+    pub(super) fn scroll_by(&mut self, rows: isize) {
+        self.scroll = self.scroll.saturating_add_signed(rows);
+        if rows < 0 {
+            self.follow = false;
+        }
+    }
+
+    pub(super) fn scroll_to_top(&mut self) {
+        self.scroll = 0;
+        self.follow = false;
+    }
+
+    pub(super) fn scroll_to_bottom(&mut self) {
+        self.follow = true;
+    }
+
+    /// Keeps the scroll inside the transcript once the renderer knows its
+    /// height. Reaching the bottom turns following back on.
+    pub fn clamp_scroll(&mut self, max_scroll: usize) {
+        if self.follow || self.scroll >= max_scroll {
+            self.scroll = max_scroll;
+            self.follow = true;
+        }
+    }
+}
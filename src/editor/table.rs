@@ -0,0 +1,184 @@
+use unicode_width::UnicodeWidthStr;
+
+// Narrowest column, so the separator keeps its `:-:`
+const MIN_WIDTH: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Align {
+    None,
+    Left,
+    Center,
+    Right,
+}
+
+/// A markdown table: a header row, the `|---|` separator row and the body.
+/// Cells are kept trimmed, row 1 stands for the separator.
+#[derive(Debug, Clone)]
+pub(super) struct Table {
+    indent: String,
+    rows: Vec<Vec<String>>,
+    aligns: Vec<Align>,
+}
+
+/// Whether `line` can be a table row
+pub(super) fn is_row(line: &str) -> bool {
+    line.trim_start().starts_with('|')
+}
+
+/// Cells of a row without the outer pipes. `\|` doesn't split.
+fn split(line: &str) -> Vec<String> {
+    let line = line.trim();
+    let line = line.strip_prefix('|').unwrap_or(line);
+    let line = line.strip_suffix('|').filter(|rest| !rest.ends_with('\\')).unwrap_or(line);
+
+    let mut cells = vec![String::new()];
+    let mut escaped = false;
+    for c in line.chars() {
+        if c == '|' && !escaped {
+            cells.push(String::new());
+        } else if let Some(cell) = cells.last_mut() {
+            cell.push(c);
+        }
+        escaped = c == '\\' && !escaped;
+    }
+    cells.iter().map(|cell| cell.trim().to_string()).collect()
+}
+
+fn separator(line: &str) -> Option<Vec<Align>> {
+    split(line)
+        .iter()
+        .map(|cell| {
+            let dashes = cell.trim_matches(':');
+            if dashes.is_empty() || !dashes.chars().all(|c| c == '-') {
+                return None;
+            }
+            Some(match (cell.starts_with(':'), cell.ends_with(':')) {
+                (true, true) => Align::Center,
+                (false, true) => Align::Right,
+                (true, false) => Align::Left,
+                (false, false) => Align::None,
+            })
+        })
+        .collect()
+}
+
+impl Table {
+    /// Reads the rows of a table, `None` unless the second one is a
+    /// separator
+    pub(super) fn parse(lines: &[String]) -> Option<Self> {
+        let aligns = separator(lines.get(1)?)?;
+        let first = lines.first()?;
+        let indent = first[..first.len() - first.trim_start().len()].to_string();
+
+        let mut rows: Vec<Vec<String>> = lines.iter().map(|line| split(line)).collect();
+        let columns = rows.iter().map(Vec::len).max().unwrap_or(0).max(aligns.len());
+        let mut aligns = aligns;
+        aligns.resize(columns, Align::None);
+        rows[1] = Vec::new();
+        for row in &mut rows {
+            row.resize(columns, String::new());
+        }
+
+        Some(Self {
+            indent,
+            rows,
+            aligns,
+        })
+    }
+
+    pub(super) fn rows(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub(super) fn columns(&self) -> usize {
+        self.aligns.len()
+    }
+
+    /// Cell of `line` a char column is in
+    pub(super) fn cell_at(&self, line: &str, col: usize) -> usize {
+        // The pipe under the cursor opens the cell after it
+        let before: String = line.chars().take(col).collect();
+        let cells = split(&format!("{}x", before)).len();
+        (cells - 1).min(self.columns().saturating_sub(1))
+    }
+
+    /// Adds an empty row after `row`, below the separator at the earliest.
+    /// Returns its index.
+    pub(super) fn insert_row(&mut self, row: usize) -> usize {
+        let at = (row + 1).max(2);
+        self.rows.insert(at, vec![String::new(); self.columns()]);
+        at
+    }
+
+    /// Adds an empty column after `column`. Returns its index.
+    pub(super) fn insert_column(&mut self, column: usize) -> usize {
+        let at = (column + 1).min(self.columns());
+        self.aligns.insert(at, Align::None);
+        for (index, row) in self.rows.iter_mut().enumerate() {
+            if index != 1 {
+                row.insert(at, String::new());
+            }
+        }
+        at
+    }
+
+    fn widths(&self) -> Vec<usize> {
+        (0..self.columns())
+            .map(|column| {
+                self.rows
+                    .iter()
+                    .filter_map(|row| row.get(column))
+                    .map(|cell| cell.width())
+                    .max()
+                    .unwrap_or(0)
+                    .max(MIN_WIDTH)
+            })
+            .collect()
+    }
+
+    /// Lines with every column padded to its widest cell, and the char
+    /// column each cell's text starts at
+    pub(super) fn format(&self) -> Vec<(String, Vec<usize>)> {
+        let widths = self.widths();
+
+        self.rows
+            .iter()
+            .enumerate()
+            .map(|(index, row)| {
+                let mut line = format!("{}|", self.indent);
+                let mut starts = Vec::new();
+                for (column, width) in widths.iter().enumerate() {
+                    line.push(' ');
+                    if index == 1 {
+                        starts.push(line.chars().count());
+                        line.push_str(&self.rule(column, *width));
+                    } else {
+                        let cell = &row[column];
+                        let pad = width - cell.width();
+                        let before = match self.aligns[column] {
+                            Align::Center => pad / 2,
+                            Align::Right => pad,
+                            Align::None | Align::Left => 0,
+                        };
+                        line.push_str(&" ".repeat(before));
+                        starts.push(line.chars().count());
+                        line.push_str(cell);
+                        line.push_str(&" ".repeat(pad - before));
+                    }
+                    line.push_str(" |");
+                }
+                (line, starts)
+            })
+            .collect()
+    }
+
+    fn rule(&self, column: usize, width: usize) -> String {
+        let (left, right) = match self.aligns[column] {
+            Align::None => ("-", "-"),
+            Align::Left => (":", "-"),
+            Align::Center => (":", ":"),
+            Align::Right => ("-", ":"),
+        };
+        format!("{}{}{}", left, "-".repeat(width - 2), right)
+    }
+}
@@ -0,0 +1,267 @@
+use std::ops::Range;
+
+use ropey::Rope;
+
+/// What a text object is taken for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Delete,
+    Yank,
+    Select,
+}
+
+/// `i` leaves out the brackets, quotes, fences or surrounding blanks that
+/// `a` takes along
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    Inner,
+    Around,
+}
+
+/// An operator waiting for `i`/`a` and the object's key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pending {
+    pub operator: Operator,
+    pub scope: Option<Scope>,
+}
+
+impl Pending {
+    pub fn new(operator: Operator) -> Self {
+        Self {
+            operator,
+            scope: None,
+        }
+    }
+
+    /// Keys typed so far, e.g. `di`, for the status line
+    pub fn label(&self) -> String {
+        let operator = match self.operator {
+            Operator::Delete => 'd',
+            Operator::Yank => 'y',
+            Operator::Select => 'v',
+        };
+        let scope = match self.scope {
+            Some(Scope::Inner) => "i",
+            Some(Scope::Around) => "a",
+            None => "",
+        };
+        format!("{}{}", operator, scope)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Object {
+    Word,
+    Pair(char, char),
+    Quote(char),
+    Paragraph,
+    CodeBlock,
+}
+
+impl Object {
+    fn from_key(key: char) -> Option<Self> {
+        let object = match key {
+            'w' => Object::Word,
+            '(' | ')' | 'b' => Object::Pair('(', ')'),
+            '[' | ']' => Object::Pair('[', ']'),
+            '{' | '}' | 'B' => Object::Pair('{', '}'),
+            '<' | '>' => Object::Pair('<', '>'),
+            '"' | '\'' | '`' => Object::Quote(key),
+            'p' => Object::Paragraph,
+            'c' => Object::CodeBlock,
+            _ => return None,
+        };
+        Some(object)
+    }
+}
+
+/// Char range of the object typed as `key` around `char_idx`. `None` for an
+/// unknown key or when the cursor isn't in such an object.
+pub(super) fn range(buffer: &Rope, char_idx: usize, scope: Scope, key: char) -> Option<Range<usize>> {
+    if buffer.len_chars() == 0 {
+        return None;
+    }
+    let char_idx = char_idx.min(buffer.len_chars() - 1);
+
+    match Object::from_key(key)? {
+        Object::Word => word(buffer, char_idx, scope),
+        Object::Pair(open, close) => pair(buffer, char_idx, scope, open, close),
+        Object::Quote(quote) => quoted(buffer, char_idx, scope, quote),
+        Object::Paragraph => paragraph(buffer, buffer.char_to_line(char_idx), scope),
+        Object::CodeBlock => code_block(buffer, buffer.char_to_line(char_idx), scope),
+    }
+}
+
+// Blanks, word characters and punctuation each make their own words
+fn class(c: char) -> u8 {
+    match c {
+        c if c.is_whitespace() => 0,
+        c if c.is_alphanumeric() || c == '_' => 1,
+        _ => 2,
+    }
+}
+
+// Chars of the line holding `char_idx`, without the line break
+fn line_bounds(buffer: &Rope, char_idx: usize) -> Range<usize> {
+    let line = buffer.char_to_line(char_idx);
+    let start = buffer.line_to_char(line);
+    let mut end = start + buffer.line(line).len_chars();
+    while end > start && matches!(buffer.char(end - 1), '\n' | '\r') {
+        end -= 1;
+    }
+    start..end
+}
+
+// Run of chars of the same class as the one at `from`, within `bounds`
+fn run(buffer: &Rope, from: usize, bounds: &Range<usize>) -> Range<usize> {
+    let kind = class(buffer.char(from));
+    let mut start = from;
+    while start > bounds.start && class(buffer.char(start - 1)) == kind {
+        start -= 1;
+    }
+    let mut end = from + 1;
+    while end < bounds.end && class(buffer.char(end)) == kind {
+        end += 1;
+    }
+    start..end
+}
+
+// `aw` takes the blanks after the word, or before it at the end of a line
+fn word(buffer: &Rope, char_idx: usize, scope: Scope) -> Option<Range<usize>> {
+    let bounds = line_bounds(buffer, char_idx);
+    if !bounds.contains(&char_idx) {
+        return None;
+    }
+
+    let inner = run(buffer, char_idx, &bounds);
+    if scope == Scope::Inner {
+        return Some(inner);
+    }
+
+    let blank = |idx: usize| class(buffer.char(idx)) == 0;
+    if blank(char_idx) {
+        // The blanks and the word after them
+        if inner.end < bounds.end {
+            return Some(inner.start..run(buffer, inner.end, &bounds).end);
+        }
+        return Some(inner);
+    }
+    if inner.end < bounds.end && blank(inner.end) {
+        return Some(inner.start..run(buffer, inner.end, &bounds).end);
+    }
+    if inner.start > bounds.start && blank(inner.start - 1) {
+        return Some(run(buffer, inner.start - 1, &bounds).start..inner.end);
+    }
+    Some(inner)
+}
+
+// Innermost `open`..`close` around the cursor, across lines and counting
+// nested pairs. Brackets in strings or comments aren't told apart.
+fn pair(buffer: &Rope, char_idx: usize, scope: Scope, open: char, close: char) -> Option<Range<usize>> {
+    let mut depth = 0usize;
+    let mut start = None;
+    for idx in (0..=char_idx).rev() {
+        match buffer.char(idx) {
+            // On the closing bracket, its pair is the one looked for
+            c if c == close && idx != char_idx => depth += 1,
+            c if c == open && depth == 0 => {
+                start = Some(idx);
+                break;
+            }
+            c if c == open => depth -= 1,
+            _ => {}
+        }
+    }
+    let start = start?;
+
+    let mut depth = 0usize;
+    let end = (start + 1..buffer.len_chars()).find(|&idx| match buffer.char(idx) {
+        c if c == open => {
+            depth += 1;
+            false
+        }
+        c if c == close && depth == 0 => true,
+        c if c == close => {
+            depth -= 1;
+            false
+        }
+        _ => false,
+    })?;
+
+    Some(match scope {
+        Scope::Inner => start + 1..end,
+        Scope::Around => start..end + 1,
+    })
+}
+
+// Quotes pair up in order on the cursor's line. Outside of a pair, the next
+// one on the line is taken.
+fn quoted(buffer: &Rope, char_idx: usize, scope: Scope, quote: char) -> Option<Range<usize>> {
+    let bounds = line_bounds(buffer, char_idx);
+    let mut quotes = Vec::new();
+    let mut escaped = false;
+    for idx in bounds.clone() {
+        match buffer.char(idx) {
+            '\\' if !escaped => {
+                escaped = true;
+                continue;
+            }
+            c if c == quote && !escaped => quotes.push(idx),
+            _ => {}
+        }
+        escaped = false;
+    }
+
+    let (start, end) = quotes
+        .chunks_exact(2)
+        .map(|pair| (pair[0], pair[1]))
+        .find(|&(_, end)| char_idx <= end)?;
+
+    Some(match scope {
+        Scope::Inner => start + 1..end,
+        Scope::Around => start..end + 1,
+    })
+}
+
+// Whole lines up to the blank ones; `ap` takes the blank lines after it. On
+// a blank line, the blank lines are the paragraph.
+fn paragraph(buffer: &Rope, row: usize, scope: Scope) -> Option<Range<usize>> {
+    let lines = buffer.len_lines();
+    let blank = |row: usize| buffer.line(row).chars().all(char::is_whitespace);
+    let kind = blank(row);
+
+    let mut first = row;
+    while first > 0 && blank(first - 1) == kind {
+        first -= 1;
+    }
+    let mut last = row;
+    while last + 1 < lines && blank(last + 1) == kind {
+        last += 1;
+    }
+
+    if scope == Scope::Around {
+        // The next run of the other kind, a blank run takes the paragraph
+        while last + 1 < lines && blank(last + 1) != kind {
+            last += 1;
+        }
+    }
+
+    Some(buffer.line_to_char(first)..buffer.line_to_char(last + 1))
+}
+
+// Fences pair up in order, like the highlighter reads them. `ic` is the code,
+// `ac` the block with its fence lines.
+fn code_block(buffer: &Rope, row: usize, scope: Scope) -> Option<Range<usize>> {
+    let is_fence = |line: usize| buffer.line(line).chars().take(3).eq("```".chars());
+    let fences: Vec<usize> = (0..buffer.len_lines()).filter(|&line| is_fence(line)).collect();
+
+    let (open, close) = fences
+        .chunks_exact(2)
+        .map(|pair| (pair[0], pair[1]))
+        .find(|&(open, close)| (open..=close).contains(&row))?;
+
+    Some(match scope {
+        Scope::Inner => buffer.line_to_char(open + 1)..buffer.line_to_char(close),
+        Scope::Around => buffer.line_to_char(open)..buffer.line_to_char(close + 1),
+    })
+}
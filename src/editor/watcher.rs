@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::time::SystemTime;
+
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+// Size and modification time, `None` when the file is gone
+type Stamp = Option<(u64, Option<SystemTime>)>;
+
+fn stamp(path: &Path) -> Stamp {
+    fs::metadata(path).ok().map(|m| (m.len(), m.modified().ok()))
+}
+
+/// Tells when the file of the active buffer changes on disk. Its directory
+/// is watched, so a file replaced by a rename, like most editors and git
+/// save, is still seen. Events only count when the file differs from how
+/// the editor last read or wrote it, which leaves out our own saves.
+pub(super) struct FileWatcher {
+    // `None` when the platform can't watch, nothing is reported then
+    watcher: Option<RecommendedWatcher>,
+    events: Receiver<notify::Result<Event>>,
+    // Watched directory and the file in it
+    dir: Option<PathBuf>,
+    path: Option<PathBuf>,
+    // How each file was when the editor last read or wrote it
+    known: HashMap<PathBuf, Stamp>,
+    // A change came in that wasn't asked about yet
+    stale: bool,
+}
+
+impl FileWatcher {
+    pub(super) fn new() -> Self {
+        let (sender, events) = mpsc::channel();
+        Self {
+            watcher: notify::recommended_watcher(sender).ok(),
+            events,
+            dir: None,
+            path: None,
+            known: HashMap::new(),
+            stale: false,
+        }
+    }
+
+    /// Takes the file as it's on disk now as the editor's version, after
+    /// loading or saving it, or when a change is kept
+    pub(super) fn remember(&mut self, path: &Path) {
+        self.known.insert(path.to_path_buf(), stamp(path));
+        if self.path.as_deref() == Some(path) {
+            self.stale = false;
+        }
+    }
+
+    /// Watches `path` instead of the file watched so far. A file that
+    /// changed while its buffer was in the background counts as changed.
+    pub(super) fn watch(&mut self, path: &Path) {
+        if self.path.as_deref() == Some(path) {
+            return;
+        }
+        self.path = Some(path.to_path_buf());
+        self.stale = self.changed();
+
+        let dir = match path.parent() {
+            Some(dir) if dir.as_os_str().is_empty() => PathBuf::from("."),
+            Some(dir) => dir.to_path_buf(),
+            None => return,
+        };
+        if self.dir.as_ref() == Some(&dir) {
+            return;
+        }
+        let Some(watcher) = &mut self.watcher else {
+            return;
+        };
+        if let Some(old) = self.dir.take() {
+            let _ = watcher.unwatch(&old);
+        }
+        // A file that isn't created yet has nothing to watch
+        if watcher.watch(&dir, RecursiveMode::NonRecursive).is_ok() {
+            self.dir = Some(dir);
+        }
+    }
+
+    /// Drains the events, returns whether the watched file changed since
+    /// the editor last had it. It's reported until remembered or `dismiss`ed.
+    pub(super) fn poll(&mut self) -> bool {
+        let name = self.path.as_ref().and_then(|path| path.file_name());
+        while let Ok(event) = self.events.try_recv() {
+            let Ok(event) = event else {
+                continue;
+            };
+            if matches!(event.kind, EventKind::Access(_)) {
+                continue;
+            }
+            if event.paths.iter().any(|path| path.file_name() == name && name.is_some()) {
+                self.stale = true;
+            }
+        }
+
+        self.stale && self.changed()
+    }
+
+    /// Stops reporting the current change, the next one is reported again
+    pub(super) fn dismiss(&mut self) {
+        self.stale = false;
+    }
+
+    /// Reports the current change again on the next poll
+    pub(super) fn ask_again(&mut self) {
+        self.stale = true;
+    }
+
+    fn changed(&self) -> bool {
+        let Some(path) = &self.path else {
+            return false;
+        };
+        // Files the editor never read, like a new buffer, have nothing to lose
+        match self.known.get(path) {
+            Some(known) => *known != stamp(path),
+            None => false,
+        }
+    }
+}
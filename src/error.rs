@@ -23,8 +23,20 @@ pub enum Error {
     #[from]
     Ollama(OllamaError),
 
+    #[from]
+    GenAI(genai::Error),
+
     #[from]
     TreeSitter(tree_sitter::QueryError),
+
+    #[from]
+    Toml(toml::de::Error),
+
+    #[from]
+    Http(reqwest::Error),
+
+    /// No answer within the provider's `request_timeouts`
+    TimedOut(std::time::Duration),
 }
 
 impl From<&str> for Error {
@@ -35,7 +47,10 @@ impl From<&str> for Error {
 
 impl core::fmt::Display for Error {
     fn fmt(&self, fmt: &mut core::fmt::Formatter) -> core::result::Result<(), core::fmt::Error> {
-        write!(fmt, "{self:?}")
+        match self {
+            Error::TimedOut(limit) => write!(fmt, "timed out after {}s", limit.as_secs()),
+            _ => write!(fmt, "{self:?}"),
+        }
     }
 }
 
@@ -0,0 +1,201 @@
+use std::fs;
+use std::path::Path;
+
+use crate::error::Result;
+
+const UTF8_BOM: &[u8] = &[0xEF, 0xBB, 0xBF];
+const UTF16LE_BOM: &[u8] = &[0xFF, 0xFE];
+const UTF16BE_BOM: &[u8] = &[0xFE, 0xFF];
+// Bytes looked at for NULs of UTF-16 without a BOM
+const SNIFF_BYTES: usize = 4096;
+
+/// How a file's text is stored on disk. The buffer is always UTF-8, files
+/// are decoded on load and encoded back on save.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    Utf8Bom,
+    Utf16Le,
+    Utf16Be,
+    /// Anything that isn't UTF-8 ends up here, every byte is a char
+    Latin1,
+}
+
+impl Encoding {
+    /// For the status line
+    pub fn label(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Utf8Bom => "utf-8 bom",
+            Encoding::Utf16Le => "utf-16le",
+            Encoding::Utf16Be => "utf-16be",
+            Encoding::Latin1 => "latin-1",
+        }
+    }
+
+    /// Guesses the encoding from the start of a file: a byte order mark,
+    /// UTF-16 by its NULs, UTF-8 when it's valid, Latin-1 otherwise. A
+    /// char cut off at the end of `bytes` still counts as UTF-8.
+    pub fn detect(bytes: &[u8]) -> Self {
+        if bytes.starts_with(UTF8_BOM) {
+            return Encoding::Utf8Bom;
+        }
+        if bytes.starts_with(UTF16LE_BOM) {
+            return Encoding::Utf16Le;
+        }
+        if bytes.starts_with(UTF16BE_BOM) {
+            return Encoding::Utf16Be;
+        }
+
+        // ASCII as UTF-16 has every other byte 0
+        let sniff = &bytes[..bytes.len().min(SNIFF_BYTES) & !1];
+        let pairs = sniff.len() / 2;
+        if pairs > 0 {
+            let even = sniff.iter().step_by(2).filter(|&&b| b == 0).count();
+            let odd = sniff.iter().skip(1).step_by(2).filter(|&&b| b == 0).count();
+            // Other scripts may have a NUL on the other side now and then
+            if odd * 2 > pairs && even * 8 < odd {
+                return Encoding::Utf16Le;
+            }
+            if even * 2 > pairs && odd * 8 < even {
+                return Encoding::Utf16Be;
+            }
+        }
+
+        match std::str::from_utf8(bytes) {
+            Ok(_) => Encoding::Utf8,
+            Err(e) if e.error_len().is_none() => Encoding::Utf8,
+            Err(_) => Encoding::Latin1,
+        }
+    }
+
+    fn bom(self) -> &'static [u8] {
+        match self {
+            Encoding::Utf8Bom => UTF8_BOM,
+            Encoding::Utf16Le => UTF16LE_BOM,
+            Encoding::Utf16Be => UTF16BE_BOM,
+            Encoding::Utf8 | Encoding::Latin1 => &[],
+        }
+    }
+
+    /// `text` as stored on disk. Latin-1 can't hold chars past U+00FF.
+    pub fn encode(self, text: &str) -> Result<Vec<u8>> {
+        let mut bytes = self.bom().to_vec();
+        match self {
+            Encoding::Utf8 | Encoding::Utf8Bom => bytes.extend_from_slice(text.as_bytes()),
+            Encoding::Utf16Le => bytes.extend(text.encode_utf16().flat_map(u16::to_le_bytes)),
+            Encoding::Utf16Be => bytes.extend(text.encode_utf16().flat_map(u16::to_be_bytes)),
+            Encoding::Latin1 => {
+                for c in text.chars() {
+                    let byte = u8::try_from(u32::from(c))
+                        .map_err(|_| format!("{:?} can't be saved as latin-1", c))?;
+                    bytes.push(byte);
+                }
+            }
+        }
+        Ok(bytes)
+    }
+}
+
+/// Decodes a file read in chunks. Bytes of a char split between two
+/// chunks wait for the next one.
+#[derive(Debug)]
+pub struct Decoder {
+    encoding: Encoding,
+    pending: Vec<u8>,
+    // The byte order mark is skipped once
+    started: bool,
+}
+
+impl Decoder {
+    pub fn new(encoding: Encoding) -> Self {
+        Self {
+            encoding,
+            pending: Vec::new(),
+            started: false,
+        }
+    }
+
+    pub fn encoding(&self) -> Encoding {
+        self.encoding
+    }
+
+    /// Text of `bytes` and the bytes left over from the last call
+    pub fn push(&mut self, bytes: &[u8]) -> Result<String> {
+        self.pending.extend_from_slice(bytes);
+        if !self.started {
+            let bom = self.encoding.bom();
+            if self.pending.len() < bom.len() {
+                return Ok(String::new());
+            }
+            if self.pending.starts_with(bom) {
+                self.pending.drain(..bom.len());
+            }
+            self.started = true;
+        }
+
+        match self.encoding {
+            Encoding::Utf8 | Encoding::Utf8Bom => {
+                let valid = match std::str::from_utf8(&self.pending) {
+                    Ok(text) => text.len(),
+                    Err(e) if e.error_len().is_none() => e.valid_up_to(),
+                    Err(_) => return Err("File is not valid UTF-8".into()),
+                };
+                // Checked above, only the bytes of an unfinished char are left over
+                let rest = self.pending.split_off(valid);
+                Ok(String::from_utf8(std::mem::replace(&mut self.pending, rest)).unwrap_or_default())
+            }
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                let mut units: Vec<u16> = self
+                    .pending
+                    .chunks_exact(2)
+                    .map(|pair| match self.encoding {
+                        Encoding::Utf16Le => u16::from_le_bytes([pair[0], pair[1]]),
+                        _ => u16::from_be_bytes([pair[0], pair[1]]),
+                    })
+                    .collect();
+                let mut used = units.len() * 2;
+                // A high surrogate waits for its other half
+                if matches!(units.last(), Some(unit) if (0xD800..0xDC00).contains(unit)) {
+                    units.pop();
+                    used -= 2;
+                }
+                self.pending.drain(..used);
+                String::from_utf16(&units).map_err(|_| "File is not valid UTF-16".into())
+            }
+            Encoding::Latin1 => Ok(self.pending.drain(..).map(char::from).collect()),
+        }
+    }
+
+    /// Fails when the file ended in the middle of a char
+    pub fn finish(&self) -> Result<()> {
+        if !self.pending.is_empty() {
+            return Err(format!("File is not valid {}", self.encoding.label()).into());
+        }
+        Ok(())
+    }
+}
+
+/// Reads and decodes a whole file. UTF-8 that turns out broken further in
+/// than `detect` looked is taken as Latin-1.
+pub fn read(path: &Path) -> Result<(String, Encoding)> {
+    let bytes = fs::read(path)?;
+    let encoding = Encoding::detect(&bytes);
+
+    let mut decoder = Decoder::new(encoding);
+    match decoder.push(&bytes).and_then(|text| decoder.finish().map(|()| text)) {
+        Ok(text) => Ok((text, encoding)),
+        Err(_) if encoding == Encoding::Utf8 => {
+            let mut decoder = Decoder::new(Encoding::Latin1);
+            Ok((decoder.push(&bytes)?, Encoding::Latin1))
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Encodes `text` and writes it to `path`
+pub fn write(path: &Path, text: &str, encoding: Encoding) -> Result<()> {
+    fs::write(path, encoding.encode(text)?)?;
+    Ok(())
+}
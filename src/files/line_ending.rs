@@ -0,0 +1,94 @@
+/// Line breaks of a file on disk. The buffer always has `\n`, CRLF files
+/// get theirs back on save.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    Crlf,
+}
+
+impl LineEnding {
+    /// For the status line
+    pub fn label(self) -> &'static str {
+        match self {
+            LineEnding::Lf => "LF",
+            LineEnding::Crlf => "CRLF",
+        }
+    }
+
+    pub fn toggle(self) -> Self {
+        match self {
+            LineEnding::Lf => LineEnding::Crlf,
+            LineEnding::Crlf => LineEnding::Lf,
+        }
+    }
+
+    /// `text` of the buffer with this style's line breaks
+    pub fn apply(self, text: &str) -> String {
+        match self {
+            LineEnding::Lf => text.to_string(),
+            LineEnding::Crlf => text.replace('\n', "\r\n"),
+        }
+    }
+}
+
+/// Turns `\r\n` into `\n` and counts both styles on the way, for text
+/// that comes in chunks. A `\r` at the end of a chunk waits for the next.
+#[derive(Debug, Default)]
+pub struct Normalizer {
+    pending_cr: bool,
+    crlf: usize,
+    lf: usize,
+}
+
+impl Normalizer {
+    pub fn push(&mut self, text: &str) -> String {
+        let mut out = String::with_capacity(text.len() + 1);
+        for c in text.chars() {
+            if std::mem::take(&mut self.pending_cr) {
+                if c == '\n' {
+                    self.crlf += 1;
+                    out.push('\n');
+                    continue;
+                }
+                // A lone `\r` stays as it is
+                out.push('\r');
+            }
+            match c {
+                '\r' => self.pending_cr = true,
+                '\n' => {
+                    self.lf += 1;
+                    out.push('\n');
+                }
+                c => out.push(c),
+            }
+        }
+        out
+    }
+
+    /// A `\r` the text ended with
+    pub fn finish(&mut self) -> String {
+        if std::mem::take(&mut self.pending_cr) {
+            "\r".to_string()
+        } else {
+            String::new()
+        }
+    }
+
+    /// The style most lines so far end with, LF when it's a tie
+    pub fn line_ending(&self) -> LineEnding {
+        if self.crlf > self.lf {
+            LineEnding::Crlf
+        } else {
+            LineEnding::Lf
+        }
+    }
+}
+
+/// `text` with `\n` line breaks and the style it had
+pub fn normalize(text: &str) -> (String, LineEnding) {
+    let mut normalizer = Normalizer::default();
+    let mut normalized = normalizer.push(text);
+    normalized.push_str(&normalizer.finish());
+    (normalized, normalizer.line_ending())
+}
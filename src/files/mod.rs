@@ -4,13 +4,16 @@ use std::{
     path::{Path, PathBuf},
 };
 
+use crate::config;
 use crate::error::{Error, Result};
 
 // pub mod error;
+pub mod encoding;
+pub mod line_ending;
 
 pub fn list_files() -> Result<Vec<String>> {
     let mut files = Vec::new();
-    for entry in fs::read_dir(".rusty/")? {
+    for entry in fs::read_dir(&config::get().history_dir)? {
         let entry = entry?;
         let path = entry.path();
         if path.is_file() {
@@ -54,6 +57,66 @@ pub fn load_file(filename: &str) -> Result<String> {
     Ok(content)
 }
 
+/// Writes a new file, creating missing parent directories. Never overwrites.
+pub fn create_file(filename: &str, content: &str) -> Result<()> {
+    let path = Path::new(filename);
+    if path.exists() {
+        return Err(Error::Custom(format!("{} already exists", filename)));
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, content)?;
+
+    Ok(())
+}
+
+/// Moves a file, creating missing parent directories. Never overwrites.
+pub fn rename_file(from: &Path, to: &Path) -> Result<()> {
+    if to.exists() {
+        return Err(Error::Custom(format!("{} already exists", to.display())));
+    }
+
+    if let Some(parent) = to.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::rename(from, to)?;
+
+    Ok(())
+}
+
+pub fn delete_file(path: &Path) -> Result<()> {
+    fs::remove_file(path)?;
+    Ok(())
+}
+
+/// Copies a file next to itself as `name copy.ext`, or `name copy 2.ext`
+/// when that's taken. Returns the path of the copy.
+pub fn duplicate_file(path: &Path) -> Result<PathBuf> {
+    let stem = path
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .ok_or_else(|| Error::Custom(format!("Invalid file name: {}", path.display())))?;
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| format!(".{}", e))
+        .unwrap_or_default();
+
+    let copy = (1..)
+        .map(|n| match n {
+            1 => format!("{} copy{}", stem, extension),
+            n => format!("{} copy {}{}", stem, n, extension),
+        })
+        .map(|name| path.with_file_name(name))
+        .find(|copy| !copy.exists())
+        .ok_or("No free name for the copy")?;
+    fs::copy(path, &copy)?;
+
+    Ok(copy)
+}
+
 pub fn change_dir(current_dir: &Path, path: &str) -> Result<PathBuf> {
     let new_path = if path == ".." {
         current_dir
@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::Duration;
+
+use similar::{DiffOp, TextDiff};
+
+use crate::Result;
+
+// A huge rewrite gets a rough diff rather than a stalled frame
+const DIFF_TIMEOUT: Duration = Duration::from_millis(50);
+// Never staged from the history directory, whatever its `.gitignore`
+// says: `credentials` holds the API keys in plain text, `locks` changes
+// with every file opened
+const EXCLUDED: &[&str] = &["credentials", "locks"];
+
+/// How a buffer line differs from HEAD, shown in the gutter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LineChange {
+    Added,
+    Modified,
+    /// Lines were removed right above this one
+    Removed,
+}
+
+impl LineChange {
+    pub fn marker(self) -> char {
+        match self {
+            LineChange::Added => '+',
+            LineChange::Modified => '~',
+            LineChange::Removed => '_',
+        }
+    }
+}
+
+/// Content of `path` in HEAD. `None` outside a repository and for files
+/// that aren't committed.
+pub fn head_text(path: &Path) -> Option<String> {
+    let dir = path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."));
+    let name = path.file_name()?.to_str()?;
+
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["show", &format!("HEAD:./{}", name)])
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Root of the repository `dir` is in, `None` outside one
+pub fn toplevel(dir: &Path) -> Option<PathBuf> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(["rev-parse", "--show-toplevel"])
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| PathBuf::from(String::from_utf8_lossy(&output.stdout).trim()))
+}
+
+/// Changed rows of `text` compared to `head`
+pub fn line_changes(head: &str, text: &str) -> HashMap<usize, LineChange> {
+    let diff = TextDiff::configure()
+        .timeout(DIFF_TIMEOUT)
+        .diff_lines(head, text);
+
+    let mut changes = HashMap::new();
+    for op in diff.ops() {
+        match *op {
+            DiffOp::Insert {
+                new_index, new_len, ..
+            } => {
+                for row in new_index..new_index + new_len {
+                    changes.insert(row, LineChange::Added);
+                }
+            }
+            DiffOp::Replace {
+                new_index, new_len, ..
+            } => {
+                for row in new_index..new_index + new_len {
+                    changes.insert(row, LineChange::Modified);
+                }
+            }
+            DiffOp::Delete { new_index, .. } => {
+                changes.entry(new_index).or_insert(LineChange::Removed);
+            }
+            DiffOp::Equal { .. } => {}
+        }
+    }
+    changes
+}
+
+/// Stages everything under `dir` but `EXCLUDED`. A directory that isn't
+/// the root of a repository gets one of its own first, so conversations
+/// are versioned apart from the project.
+pub fn stage_all(dir: &Path) -> Result<()> {
+    if !dir.join(".git").exists() {
+        std::fs::create_dir_all(dir)?;
+        git(dir, &["init", "--quiet"])?;
+        // Swap files and the language server's scratch file aren't history
+        let ignore = dir.join(".gitignore");
+        if !ignore.exists() {
+            std::fs::write(ignore, format!("autosave/\nlsp/\n{}\n", EXCLUDED.join("\n")))?;
+        }
+    }
+
+    // Staged by an older version, taken out of the index again
+    let mut untrack = vec!["rm", "-r", "--cached", "--quiet", "--ignore-unmatch", "--"];
+    untrack.extend(EXCLUDED);
+    git(dir, &untrack)?;
+
+    let excluded: Vec<String> = EXCLUDED.iter().map(|path| format!(":(exclude){}", path)).collect();
+    let mut add = vec!["add", "--all", "--", "."];
+    add.extend(excluded.iter().map(String::as_str));
+    git(dir, &add)?;
+    Ok(())
+}
+
+/// Stages and commits everything under `dir`. Returns git's summary line.
+pub fn commit_all(dir: &Path, message: &str) -> Result<String> {
+    stage_all(dir)?;
+    let output = git(dir, &["commit", "--quiet", "-m", message])?;
+    let summary = git(dir, &["log", "-1", "--format=%h %s"]).unwrap_or(output);
+    Ok(summary.trim().to_string())
+}
+
+// stdout of a git command in `dir`, its output as the error when it fails
+fn git(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .map_err(|e| format!("Can't run git: {}", e))?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout).into_owned();
+    if output.status.success() {
+        return Ok(stdout);
+    }
+
+    // `nothing to commit` goes to stdout, real errors to stderr
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let message = if stderr.trim().is_empty() { stdout.trim() } else { stderr.trim() };
+    Err(message.lines().next().unwrap_or("git failed").to_string().into())
+}
@@ -0,0 +1,432 @@
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use ropey::Rope;
+
+use crate::error::{Error, Result};
+
+// How long `start` waits for the server to answer `initialize`
+const INIT_TIMEOUT: Duration = Duration::from_secs(10);
+// Longer lists aren't useful in the popup
+const MAX_COMPLETIONS: usize = 50;
+
+/// A diagnostic of the synced document, positions in document chars
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub start: (usize, usize),
+    pub end: (usize, usize),
+    pub message: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct CompletionItem {
+    pub label: String,
+    pub detail: Option<String>,
+    // What replaces the word before the cursor
+    pub text: String,
+}
+
+// Filled by the reader thread
+#[derive(Default)]
+struct Shared {
+    // Latest publishDiagnostics per document URI, positions in UTF-16 units
+    diagnostics: HashMap<String, Vec<Value>>,
+    // Bumped whenever `diagnostics` changes
+    diagnostics_version: u64,
+    // Answer to the last completion request, by request id
+    completion: Option<(i64, Value)>,
+    // Sender waiting for the `initialize` answer
+    initialized: Option<mpsc::Sender<()>>,
+    // The server's stdout closed, it exited
+    closed: bool,
+}
+
+/// Minimal language server client over stdio: full document sync,
+/// completion and diagnostics. Messages are read on their own thread.
+pub struct LspClient {
+    child: Child,
+    stdin: Arc<Mutex<ChildStdin>>,
+    shared: Arc<Mutex<Shared>>,
+    next_id: i64,
+
+    // Open documents and the text last sent for them
+    documents: HashMap<String, (i32, String)>,
+    // Rope of each synced text, for position conversions
+    ropes: HashMap<String, Rope>,
+    pending_completion: Option<(i64, String)>,
+    seen_diagnostics: u64,
+}
+
+impl LspClient {
+    /// Spawns `command` (e.g. `rust-analyzer`) and waits for `initialize`.
+    /// `detached` are files the server should analyze outside a Cargo project.
+    /// Blocks until the answer, so it's called off the UI thread.
+    pub fn start(command: &str, root: &Path, detached: &[PathBuf]) -> Result<Self> {
+        let mut parts = command.split_whitespace();
+        let program = parts.next().ok_or("lsp: empty command")?;
+
+        let mut child = Command::new(program)
+            .args(parts)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+
+        let stdin = Arc::new(Mutex::new(child.stdin.take().ok_or("lsp: no stdin")?));
+        let stdout = child.stdout.take().ok_or("lsp: no stdout")?;
+
+        let (tx, rx) = mpsc::channel();
+        let shared = Arc::new(Mutex::new(Shared {
+            initialized: Some(tx),
+            ..Shared::default()
+        }));
+
+        let reader_stdin = Arc::clone(&stdin);
+        let reader_shared = Arc::clone(&shared);
+        thread::spawn(move || {
+            read_messages(stdout, reader_stdin, &reader_shared);
+            if let Ok(mut shared) = reader_shared.lock() {
+                shared.closed = true;
+                // Nobody answers `initialize` anymore
+                shared.initialized = None;
+            }
+        });
+
+        let mut client = Self {
+            child,
+            stdin,
+            shared,
+            next_id: 1,
+            documents: HashMap::new(),
+            ropes: HashMap::new(),
+            pending_completion: None,
+            seen_diagnostics: 0,
+        };
+
+        let root_uri = uri(root);
+        let detached: Vec<String> = detached.iter().map(|p| p.display().to_string()).collect();
+        client.send(&json!({
+            "jsonrpc": "2.0",
+            "id": 0,
+            "method": "initialize",
+            "params": {
+                "processId": std::process::id(),
+                "rootUri": root_uri,
+                "capabilities": {
+                    "textDocument": {
+                        "synchronization": { "didSave": false },
+                        "completion": { "completionItem": { "snippetSupport": false } },
+                        "publishDiagnostics": {},
+                    },
+                },
+                "initializationOptions": { "detachedFiles": detached },
+            },
+        }))?;
+
+        rx.recv_timeout(INIT_TIMEOUT)
+            .map_err(|_| Error::from("lsp: no answer to initialize"))?;
+        client.notify("initialized", json!({}))?;
+
+        Ok(client)
+    }
+
+    /// Opens or updates a document with its full text; unchanged text isn't sent
+    pub fn sync(&mut self, uri: &str, text: &str) -> Result<()> {
+        match self.documents.get_mut(uri) {
+            Some((_, sent)) if sent == text => return Ok(()),
+            Some((version, sent)) => {
+                *version += 1;
+                *sent = text.to_string();
+                let version = *version;
+                self.notify(
+                    "textDocument/didChange",
+                    json!({
+                        "textDocument": { "uri": uri, "version": version },
+                        "contentChanges": [{ "text": text }],
+                    }),
+                )?;
+            }
+            None => {
+                self.documents.insert(uri.to_string(), (1, text.to_string()));
+                self.notify(
+                    "textDocument/didOpen",
+                    json!({
+                        "textDocument": {
+                            "uri": uri,
+                            "languageId": "rust",
+                            "version": 1,
+                            "text": text,
+                        },
+                    }),
+                )?;
+            }
+        }
+
+        self.ropes.insert(uri.to_string(), Rope::from_str(text));
+        Ok(())
+    }
+
+    /// Asks for completions at a char position of the synced document. The
+    /// answer comes later from `take_completions`.
+    pub fn request_completion(&mut self, uri: &str, line: usize, col: usize) -> Result<()> {
+        let character = self
+            .ropes
+            .get(uri)
+            .map(|rope| utf16_col(rope, line, col))
+            .unwrap_or(col);
+
+        let id = self.next_id;
+        self.next_id += 1;
+        self.send(&json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": "textDocument/completion",
+            "params": {
+                "textDocument": { "uri": uri },
+                "position": { "line": line, "character": character },
+            },
+        }))?;
+        self.pending_completion = Some((id, uri.to_string()));
+
+        Ok(())
+    }
+
+    /// Items of the answered completion request, once
+    pub fn take_completions(&mut self) -> Option<Vec<CompletionItem>> {
+        let (id, _) = self.pending_completion.as_ref()?;
+        let result = {
+            let mut shared = self.shared.lock().ok()?;
+            match &shared.completion {
+                Some((answered, _)) if answered == id => shared.completion.take()?.1,
+                _ => return None,
+            }
+        };
+        self.pending_completion = None;
+
+        // Either a list or `{ isIncomplete, items }`
+        let items = match &result {
+            Value::Array(items) => items.as_slice(),
+            Value::Object(list) => list
+                .get("items")
+                .and_then(Value::as_array)
+                .map(Vec::as_slice)
+                .unwrap_or(&[]),
+            _ => &[],
+        };
+
+        Some(
+            items
+                .iter()
+                .filter_map(completion_item)
+                .take(MAX_COMPLETIONS)
+                .collect(),
+        )
+    }
+
+    /// Whether the server exited, nothing it's sent reaches it anymore
+    pub fn has_exited(&self) -> bool {
+        self.shared.lock().map_or(true, |shared| shared.closed)
+    }
+
+    /// Makes the next `take_diagnostics` return them even if unchanged
+    pub fn reread_diagnostics(&mut self) {
+        self.seen_diagnostics = 0;
+    }
+
+    /// Diagnostics of a document when they changed since the last call
+    pub fn take_diagnostics(&mut self, uri: &str) -> Option<Vec<Diagnostic>> {
+        let shared = self.shared.lock().ok()?;
+        if shared.diagnostics_version == self.seen_diagnostics {
+            return None;
+        }
+        self.seen_diagnostics = shared.diagnostics_version;
+
+        let rope = self.ropes.get(uri)?;
+        let diagnostics = shared.diagnostics.get(uri).cloned().unwrap_or_default();
+        Some(
+            diagnostics
+                .iter()
+                .filter_map(|d| diagnostic(rope, d))
+                .collect(),
+        )
+    }
+
+    fn notify(&mut self, method: &str, params: Value) -> Result<()> {
+        self.send(&json!({ "jsonrpc": "2.0", "method": method, "params": params }))
+    }
+
+    fn send(&self, message: &Value) -> Result<()> {
+        let mut stdin = self.stdin.lock().map_err(|_| "lsp: stdin poisoned")?;
+        write_message(&mut *stdin, message)
+    }
+}
+
+impl Drop for LspClient {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// `file://` URI of a path, made absolute
+pub fn uri(path: &Path) -> String {
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    format!("file://{}", path.display())
+}
+
+fn write_message(out: &mut impl Write, message: &Value) -> Result<()> {
+    let body = message.to_string();
+    write!(out, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    out.flush()?;
+    Ok(())
+}
+
+// Reads `Content-Length` framed messages until the server exits
+fn read_messages(stdout: ChildStdout, stdin: Arc<Mutex<ChildStdin>>, shared: &Mutex<Shared>) {
+    let mut reader = BufReader::new(stdout);
+
+    loop {
+        let mut length = None;
+        loop {
+            let mut header = String::new();
+            match reader.read_line(&mut header) {
+                Ok(0) | Err(_) => return,
+                Ok(_) => {}
+            }
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some(value) = header.strip_prefix("Content-Length:") {
+                length = value.trim().parse::<usize>().ok();
+            }
+        }
+
+        let Some(length) = length else {
+            continue;
+        };
+        let mut body = vec![0; length];
+        if reader.read_exact(&mut body).is_err() {
+            return;
+        }
+        let Ok(message) = serde_json::from_slice::<Value>(&body) else {
+            continue;
+        };
+
+        handle_message(message, &stdin, shared);
+    }
+}
+
+fn handle_message(message: Value, stdin: &Mutex<ChildStdin>, shared: &Mutex<Shared>) {
+    let method = message.get("method").and_then(Value::as_str);
+    let id = message.get("id");
+
+    match (method, id) {
+        // Requests of the server, answered with empty results
+        (Some(method), Some(id)) => {
+            let result = match method {
+                "workspace/configuration" => {
+                    let items = message["params"]["items"].as_array().map_or(0, Vec::len);
+                    Value::Array(vec![Value::Null; items])
+                }
+                _ => Value::Null,
+            };
+            if let Ok(mut stdin) = stdin.lock() {
+                let _ = write_message(
+                    &mut *stdin,
+                    &json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+                );
+            }
+        }
+        (Some("textDocument/publishDiagnostics"), None) => {
+            let params = &message["params"];
+            let Some(uri) = params["uri"].as_str() else {
+                return;
+            };
+            let diagnostics = params["diagnostics"].as_array().cloned().unwrap_or_default();
+            if let Ok(mut shared) = shared.lock() {
+                shared.diagnostics.insert(uri.to_string(), diagnostics);
+                shared.diagnostics_version += 1;
+            }
+        }
+        (None, Some(id)) => {
+            let Ok(mut shared) = shared.lock() else {
+                return;
+            };
+            match id.as_i64() {
+                Some(0) => {
+                    if let Some(tx) = shared.initialized.take() {
+                        let _ = tx.send(());
+                    }
+                }
+                Some(id) => {
+                    let result = message.get("result").cloned().unwrap_or(Value::Null);
+                    shared.completion = Some((id, result));
+                }
+                None => {}
+            }
+        }
+        _ => {}
+    }
+}
+
+fn completion_item(item: &Value) -> Option<CompletionItem> {
+    let label = item["label"].as_str()?.to_string();
+    let text = item["textEdit"]["newText"]
+        .as_str()
+        .or_else(|| item["insertText"].as_str())
+        .unwrap_or(&label)
+        .to_string();
+
+    Some(CompletionItem {
+        detail: item["detail"].as_str().map(str::to_string),
+        label,
+        text,
+    })
+}
+
+fn diagnostic(rope: &Rope, value: &Value) -> Option<Diagnostic> {
+    let range = &value["range"];
+    let position = |p: &Value| -> Option<(usize, usize)> {
+        let line = p["line"].as_u64()? as usize;
+        let character = p["character"].as_u64()? as usize;
+        Some((line, char_col(rope, line, character)))
+    };
+
+    Some(Diagnostic {
+        start: position(&range["start"])?,
+        end: position(&range["end"])?,
+        message: value["message"].as_str()?.lines().next()?.to_string(),
+    })
+}
+
+// LSP columns count UTF-16 units, the editor counts chars
+fn utf16_col(rope: &Rope, line: usize, col: usize) -> usize {
+    if line >= rope.len_lines() {
+        return col;
+    }
+    rope.line(line).chars().take(col).map(char::len_utf16).sum()
+}
+
+fn char_col(rope: &Rope, line: usize, character: usize) -> usize {
+    if line >= rope.len_lines() {
+        return character;
+    }
+
+    let mut units = 0;
+    let mut col = 0;
+    for c in rope.line(line).chars() {
+        if units >= character {
+            break;
+        }
+        units += c.len_utf16();
+        col += 1;
+    }
+    col
+}
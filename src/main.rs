@@ -1,61 +1,109 @@
+mod ask;
 mod async_handler;
 mod chat;
+mod cli;
+mod config;
 mod editor;
 mod error;
+mod lsp;
 mod render;
 mod syntax;
 
 mod files;
+mod git;
+mod shell;
 // use std::io::{self, Write};
 // mod commands;
-use error::Result;
+use editor::messages::Level;
+use error::{Error, Result};
 
 use crossterm::{
     event::{
         self, Event, KeyCode, KeyEvent, KeyModifiers, KeyboardEnhancementFlags,
-        PushKeyboardEnhancementFlags,
+        PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
     },
     execute, queue,
     terminal::{
-        disable_raw_mode, enable_raw_mode, DisableLineWrap, EnableLineWrap, EnterAlternateScreen,
-        LeaveAlternateScreen,
+        disable_raw_mode, enable_raw_mode, is_raw_mode_enabled, DisableLineWrap, EnableLineWrap,
+        EnterAlternateScreen, LeaveAlternateScreen,
     },
 };
-use std::io::{self, stdout};
+use std::io;
+use std::panic;
+use std::thread;
 use std::time::{Duration, Instant};
 
+// Input wait while nothing needs drawing
+const IDLE_POLL: Duration = Duration::from_millis(100);
+
 fn main() -> Result<()> {
-    // Process command-line arguments
-    // let args: Vec<String> = env::args().collect();
+    let args = cli::Args::parse();
 
-    let mut stdout = io::stdout();
-    // Setup terminal
-    enable_raw_mode()?;
-    execute!(stdout, EnterAlternateScreen, EnableLineWrap)?;
-
-    let supports_keyboard_enhancement = matches!(
-        crossterm::terminal::supports_keyboard_enhancement(),
-        Ok(true)
-    );
-
-    if supports_keyboard_enhancement {
-        queue!(
-            stdout,
-            PushKeyboardEnhancementFlags(
-                KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES // | KeyboardEnhancementFlags::REPORT_ALL_KEYS_AS_ESCAPE_CODES
-                                                                    // | KeyboardEnhancementFlags::REPORT_ALTERNATE_KEYS
-                                                                    // | KeyboardEnhancementFlags::REPORT_EVENT_TYPES
-            )
-        )?;
+    // Load ~/.rusty/config.toml before touching the terminal so errors stay readable
+    let history_note = config::init(|config| args.apply(config))?;
+    // A broken binding stops the start, with every problem listed
+    let key_warnings = match editor::keymap::init() {
+        Ok(warnings) => warnings,
+        Err(Error::Custom(message)) => {
+            eprintln!("{}", message);
+            std::process::exit(1);
+        }
+        Err(e) => return Err(e),
+    };
+    // Shown once the editor is up, the alternate screen would hide it
+    let credentials = chat::credentials::load();
+
+    // Scripts get the answer on stdout, without the editor
+    if args.headless() {
+        if let Err(e) = &credentials {
+            eprintln!("Error reading credentials: {}", e);
+        }
+        return ask::run(args.question.as_deref());
     }
 
+    // Setup terminal, restored when the guard drops or on a panic
+    let terminal = TerminalGuard::enter()?;
+
     // Create an editor instance
     // let editor = Arc::new(Mutex::new(Editor::new()));
     let mut editor = editor::Editor::new()?;
 
-    if let Err(e) = editor.open_file() {
-        // Handle file opening error (you might want to show this to the user)
-        eprintln!("Error opening file: {}", e);
+    if let Err(e) = credentials {
+        editor.set_message(Level::Error, format!("Error reading credentials: {}", e));
+    }
+    for warning in key_warnings {
+        editor.set_message(Level::Warning, warning);
+    }
+    if let Some(note) = history_note {
+        editor.set_message(Level::Info, note);
+    }
+
+    // A file or session from the command line, otherwise a new session
+    let opened = match (&args.path, &args.session) {
+        (Some(path), _) => editor.open_path(path).map(|()| {
+            if args.view {
+                editor.set_read_only(true);
+            }
+        }),
+        (None, Some(session)) => editor.resume_session(session),
+        (None, None) => editor.open_file(),
+    };
+    if let Err(e) = opened {
+        editor.set_message(Level::Error, format!("Error opening file: {}", e));
+    }
+
+    // Unsaved work of a run that didn't exit cleanly
+    if let Err(e) = editor.check_swap_files() {
+        editor.set_message(Level::Error, format!("Error reading swap files: {}", e));
+    }
+
+    editor.check_api_key();
+
+    // Offer to resume an earlier conversation, unless one was asked for
+    if args.path.is_none() && args.session.is_none() {
+        if let Err(e) = editor.open_session_picker() {
+            editor.set_message(Level::Error, format!("Error listing sessions: {}", e));
+        }
     }
 
     // Run editor
@@ -63,46 +111,167 @@ fn main() -> Result<()> {
 
     // Run editor
     let result = run_editor(&mut editor, &mut render_state);
+    if result.is_ok() && editor.is_detached() {
+        // Answers still coming are waited for on the plain terminal
+        drop(terminal);
+        editor.finish_detached();
+    }
+    if result.is_ok() {
+        editor.discard_swaps();
+    }
 
-    // Restore terminal
-    disable_raw_mode()?;
-    execute!(stdout, LeaveAlternateScreen, DisableLineWrap)?;
-
-    // Return any error that occurred
+    // Return any error that occurred, the guard restores the terminal first
     result
 }
 
+/// Raw mode, the alternate screen and keyboard enhancement for as long as
+/// it lives
+struct TerminalGuard {
+    keyboard_enhancement: bool,
+}
+
+impl TerminalGuard {
+    fn enter() -> Result<Self> {
+        let mut stdout = io::stdout();
+        enable_raw_mode()?;
+        execute!(stdout, EnterAlternateScreen, EnableLineWrap)?;
+
+        let keyboard_enhancement = matches!(
+            crossterm::terminal::supports_keyboard_enhancement(),
+            Ok(true)
+        );
+
+        if keyboard_enhancement {
+            queue!(
+                stdout,
+                PushKeyboardEnhancementFlags(
+                    KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES // | KeyboardEnhancementFlags::REPORT_ALL_KEYS_AS_ESCAPE_CODES
+                                                                        // | KeyboardEnhancementFlags::REPORT_ALTERNATE_KEYS
+                                                                        // | KeyboardEnhancementFlags::REPORT_EVENT_TYPES
+                )
+            )?;
+        }
+
+        // The panic message would land on the alternate screen and vanish
+        // with it, so the terminal is put back before it's printed. Panics
+        // of request threads leave the editor running and are left alone.
+        let default_hook = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            if thread::current().name() == Some("main") {
+                restore_terminal(keyboard_enhancement);
+            }
+            default_hook(info);
+        }));
+
+        Ok(Self {
+            keyboard_enhancement,
+        })
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        restore_terminal(self.keyboard_enhancement);
+    }
+}
+
+// Runs from the panic hook and again when the guard drops while unwinding,
+// only the first one does anything
+fn restore_terminal(keyboard_enhancement: bool) {
+    if !matches!(is_raw_mode_enabled(), Ok(true)) {
+        return;
+    }
+
+    let mut stdout = io::stdout();
+    if keyboard_enhancement {
+        let _ = execute!(stdout, PopKeyboardEnhancementFlags);
+    }
+    let _ = execute!(stdout, LeaveAlternateScreen, DisableLineWrap);
+    let _ = disable_raw_mode();
+}
+
 fn run_editor(editor: &mut editor::Editor, render_state: &mut render::RenderState) -> Result<()> {
-    let frame_duration = Duration::from_millis(16); // ~60 FPS
+    let frame_duration = Duration::from_millis(config::get().frame_duration_ms);
     let mut last_render = Instant::now();
+    // Set by input, answers, resizes and progress; frames are only drawn
+    // while it's set
+    let mut dirty = true;
+    let mut pulling = false;
 
     loop {
         // Check for any API responses that need to be processed
-        editor.check_api_responses();
+        dirty |= editor.check_api_responses();
+        dirty |= editor.poll_language_server();
+        dirty |= editor.poll_file_load();
+        dirty |= editor.poll_file_watcher();
+        // Messages come from request threads too, and expire on their own
+        dirty |= editor.poll_messages();
+        dirty |= editor.poll_titles();
+        dirty |= editor.poll_ghost();
+        dirty |= editor.poll_ollama_models();
+        dirty |= editor.poll_shell();
+        // Download progress moves on its own, and goes away when done
+        let was_pulling = pulling;
+        pulling = editor.pull_progress().is_some();
+        dirty |= pulling || was_pulling;
+        // The slow request warning counts the seconds
+        dirty |= editor.slow_request().is_some();
+        editor.autosave();
+        // A quit that waited for the answer
+        if editor.should_quit() {
+            break;
+        }
 
         // Render the screen at controlled intervals
         let now = Instant::now();
-        if now.duration_since(last_render) >= frame_duration {
+        if dirty && now.duration_since(last_render) >= frame_duration {
             render::draw_screen(editor, render_state)?;
             last_render = now;
+            dirty = false;
         }
 
+        // Nothing to draw and nothing running: block on input for longer
+        // instead of spinning through empty frames
+        let timeout = if dirty || editor.is_busy() {
+            Duration::from_millis(1)
+        } else {
+            IDLE_POLL
+        };
+
         // Handle user input with a timeout to maintain smooth rendering
-        if crossterm::event::poll(Duration::from_millis(1))? {
-            if let Event::Key(KeyEvent {
-                code, modifiers, ..
-            }) = event::read()?
-            {
-                // Check for Ctrl+Q to quit
-                if code == KeyCode::Char('q') && modifiers.contains(KeyModifiers::CONTROL) {
-                    break;
-                }
+        if crossterm::event::poll(timeout)? {
+            match event::read()? {
+                Event::Key(KeyEvent {
+                    code, modifiers, ..
+                }) => {
+                    dirty = true;
 
-                // Pass both the key and modifiers to the editor
-                let should_quit = editor.handle_key(code, modifiers)?;
-                if should_quit {
-                    break;
+                    // Check for Ctrl+Q to quit, unsaved changes ask first
+                    if code == KeyCode::Char('q') && modifiers.contains(KeyModifiers::CONTROL) {
+                        if editor.request_quit() {
+                            break;
+                        }
+                        continue;
+                    }
+
+                    // Pass both the key and modifiers to the editor. A failed
+                    // command, e.g. a save to a read-only file, is reported
+                    // instead of closing the editor.
+                    match editor.handle_key(code, modifiers) {
+                        Ok(true) => break,
+                        Ok(false) => {}
+                        Err(e) => editor.set_message(Level::Error, e.to_string()),
+                    }
+                }
+                // Redrawn right away, the cleared screen would show until
+                // the next frame otherwise
+                Event::Resize(width, height) => {
+                    render_state.resize(width, height)?;
+                    render::draw_screen(editor, render_state)?;
+                    last_render = Instant::now();
+                    dirty = false;
                 }
+                _ => {}
             }
         }
     }
@@ -0,0 +1,410 @@
+use crate::syntax::Style;
+
+use super::char_width;
+
+// Code lines expand tabs to this many cells
+const TAB_WIDTH: usize = 4;
+
+/// A cell of the preview. Code takes the highlighter's style of its column
+/// in the buffer line when it's drawn, so only the rows on screen ask for it.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct Cell {
+    pub ch: char,
+    pub style: Style,
+    pub source: Option<usize>,
+}
+
+impl Cell {
+    fn new(ch: char, style: Style) -> Self {
+        Self {
+            ch,
+            style,
+            source: None,
+        }
+    }
+}
+
+/// A screen row of the preview and the buffer line it comes from
+pub(super) struct Row {
+    pub line: usize,
+    pub cells: Vec<Cell>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+/// Lays out markdown `text` in rows of `width` cells: headings, lists,
+/// quotes, tables and fenced code lose their markup for box-drawing. Every
+/// source line starts its own rows, so rows map back to buffer lines.
+pub(super) fn layout(text: &str, width: usize) -> Vec<Row> {
+    let width = width.max(10);
+    let lines: Vec<&str> = text.lines().collect();
+    let mut rows = Vec::new();
+    let mut in_code = false;
+
+    let mut line = 0;
+    while line < lines.len() {
+        let source = lines[line];
+
+        // Fences pair up in order, like the highlighter reads them
+        if source.starts_with("```") {
+            let label = if in_code {
+                "└─".to_string()
+            } else {
+                match source.trim_start_matches('`').trim() {
+                    "" => "┌─".to_string(),
+                    language => format!("┌─ {}", language),
+                }
+            };
+            rows.push(Row {
+                line,
+                cells: styled(&label, Style::Comment),
+            });
+            in_code = !in_code;
+            line += 1;
+            continue;
+        }
+        if in_code {
+            rows.extend(code(source, line, width));
+            line += 1;
+            continue;
+        }
+
+        // A header row with a separator row under it starts a table
+        let table_end = (line..lines.len())
+            .find(|&end| !lines[end].trim_start().starts_with('|'))
+            .unwrap_or(lines.len());
+        if table_end > line + 1 {
+            if let Some(aligns) = separator(lines[line + 1]) {
+                rows.extend(table(&lines[line..table_end], line, &aligns));
+                line = table_end;
+                continue;
+            }
+        }
+
+        rows.extend(block(source, line, width));
+        line += 1;
+    }
+
+    rows
+}
+
+fn styled(text: &str, style: Style) -> Vec<Cell> {
+    text.chars().map(|ch| Cell::new(ch, style)).collect()
+}
+
+fn cells_width(cells: &[Cell]) -> usize {
+    cells.iter().map(|cell| char_width(cell.ch)).sum()
+}
+
+// Code keeps its blanks and is cut at the width, not at words
+fn code(source: &str, line: usize, width: usize) -> Vec<Row> {
+    let gutter = styled("│ ", Style::Comment);
+    let mut rows = Vec::new();
+    let mut cells = gutter.clone();
+    let mut col = 0;
+
+    for (source_col, ch) in source.chars().enumerate() {
+        let expanded = if ch == '\t' {
+            vec![' '; TAB_WIDTH - col % TAB_WIDTH]
+        } else {
+            vec![ch]
+        };
+        for ch in expanded {
+            if cells_width(&cells) + char_width(ch) > width {
+                rows.push(Row { line, cells });
+                cells = gutter.clone();
+            }
+            cells.push(Cell {
+                ch,
+                style: Style::Normal,
+                source: Some(source_col),
+            });
+            col += 1;
+        }
+    }
+    rows.push(Row { line, cells });
+
+    rows
+}
+
+// One line outside code and tables
+fn block(source: &str, line: usize, width: usize) -> Vec<Row> {
+    let trimmed = source.trim();
+    if trimmed.is_empty() {
+        return vec![Row {
+            line,
+            cells: Vec::new(),
+        }];
+    }
+
+    if is_rule(trimmed) {
+        return vec![Row {
+            line,
+            cells: styled(&"─".repeat(width), Style::Comment),
+        }];
+    }
+
+    // `# Title` .. `###### Title`, the top two underlined
+    let level = source.chars().take_while(|&c| c == '#').count();
+    if (1..=6).contains(&level) && source[level..].starts_with(' ') {
+        let text = inline(source[level..].trim(), Style::Heading);
+        let underline = match level {
+            1 => Some('═'),
+            2 => Some('─'),
+            _ => None,
+        };
+        let mut rows = wrap(text, Vec::new(), Vec::new(), width, line);
+        if let Some(ch) = underline {
+            let length = rows.iter().map(|row| cells_width(&row.cells)).max().unwrap_or(0);
+            rows.push(Row {
+                line,
+                cells: styled(&ch.to_string().repeat(length), Style::Heading),
+            });
+        }
+        return rows;
+    }
+
+    // `> quote`, nested ones get a bar each
+    if trimmed.starts_with('>') {
+        let mut rest = trimmed;
+        let mut depth = 0;
+        while let Some(inner) = rest.strip_prefix('>') {
+            rest = inner.trim_start();
+            depth += 1;
+        }
+        let bars = styled(&"│ ".repeat(depth), Style::Quote);
+        return wrap(inline(rest, Style::Quote), bars.clone(), bars, width, line);
+    }
+
+    if let Some((marker, rest)) = list_item(source) {
+        let hang = vec![Cell::new(' ', Style::Normal); cells_width(&marker)];
+        return wrap(inline(rest, Style::Normal), marker, hang, width, line);
+    }
+
+    wrap(inline(trimmed, Style::Normal), Vec::new(), Vec::new(), width, line)
+}
+
+// `---`, `***` or `___`, blanks allowed between
+fn is_rule(trimmed: &str) -> bool {
+    let marks: Vec<char> = trimmed.chars().filter(|c| !c.is_whitespace()).collect();
+    marks.len() >= 3
+        && matches!(marks[0], '-' | '*' | '_')
+        && marks.iter().all(|&c| c == marks[0])
+}
+
+// Bullet or number with its indent, and the item's text. Task boxes get
+// drawn ones.
+fn list_item(source: &str) -> Option<(Vec<Cell>, &str)> {
+    let body = source.trim_start();
+    let indent = source.len() - body.len();
+
+    let (mark, rest) = if let Some(rest) = ["- ", "* ", "+ "].iter().find_map(|m| body.strip_prefix(m)) {
+        ("•".to_string(), rest)
+    } else {
+        let digits = body.chars().take_while(char::is_ascii_digit).count();
+        let after = &body[digits..];
+        if digits == 0 || !(after.starts_with(". ") || after.starts_with(") ")) {
+            return None;
+        }
+        (body[..digits + 1].to_string(), &after[2..])
+    };
+
+    let (mark, rest) = if let Some(rest) = rest.strip_prefix("[ ] ") {
+        (format!("{} ☐", mark), rest)
+    } else if let Some(rest) = rest.strip_prefix("[x] ").or_else(|| rest.strip_prefix("[X] ")) {
+        (format!("{} ☑", mark), rest)
+    } else {
+        (mark, rest)
+    };
+
+    let mut marker = styled(&" ".repeat(indent), Style::Normal);
+    marker.extend(styled(&mark, Style::ListBullet));
+    marker.push(Cell::new(' ', Style::Normal));
+    Some((marker, rest.trim_start()))
+}
+
+// Word wraps `text` after `first`, later rows start with `hang`
+fn wrap(text: Vec<Cell>, first: Vec<Cell>, hang: Vec<Cell>, width: usize, line: usize) -> Vec<Row> {
+    let mut rows = Vec::new();
+    let mut prefix = first.len();
+    let mut cells = first;
+
+    for cell in text {
+        if cells_width(&cells) + char_width(cell.ch) > width && cells.len() > prefix {
+            // The word being written moves down, a blank at the break goes
+            let rest = match cells[prefix..].iter().rposition(|c| c.ch == ' ') {
+                Some(blank) => {
+                    let rest = cells.split_off(prefix + blank + 1);
+                    cells.pop();
+                    rest
+                }
+                None => Vec::new(),
+            };
+            rows.push(Row { line, cells });
+            cells = hang.clone();
+            prefix = cells.len();
+            cells.extend(rest);
+        }
+        if cell.ch == ' ' && cells.len() == prefix && !rows.is_empty() {
+            continue;
+        }
+        cells.push(cell);
+    }
+    rows.push(Row { line, cells });
+
+    rows
+}
+
+/// Emphasis, inline code and links without their markup
+fn inline(text: &str, base: Style) -> Vec<Cell> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut cells = Vec::new();
+    let find = |from: usize, pattern: &[char]| {
+        (from..chars.len()).find(|&at| chars[at..].starts_with(pattern))
+    };
+    let sub = |from: usize, to: usize| chars[from..to].iter().collect::<String>();
+
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+
+        if c == '\\' && next.is_some_and(|n| n.is_ascii_punctuation()) {
+            cells.push(Cell::new(chars[i + 1], base));
+            i += 2;
+            continue;
+        }
+        if c == '`' {
+            if let Some(end) = find(i + 1, &['`']) {
+                cells.extend(styled(&sub(i + 1, end), Style::InlineCode));
+                i = end + 1;
+                continue;
+            }
+        }
+        if (c == '*' || c == '_') && next == Some(c) {
+            if let Some(end) = find(i + 2, &[c, c]).filter(|&end| end > i + 2) {
+                cells.extend(inline(&sub(i + 2, end), Style::Bold));
+                i = end + 2;
+                continue;
+            }
+        }
+        // `_` inside a word, as in snake_case, stays
+        let opens = next.is_some_and(|n| !n.is_whitespace())
+            && (c == '*' || i == 0 || !chars[i - 1].is_alphanumeric());
+        if (c == '*' || c == '_') && opens {
+            if let Some(end) = find(i + 1, &[c]).filter(|&end| end > i + 1) {
+                let closes = c == '*' || !matches!(chars.get(end + 1), Some(n) if n.is_alphanumeric());
+                if closes {
+                    cells.extend(inline(&sub(i + 1, end), Style::Italic));
+                    i = end + 1;
+                    continue;
+                }
+            }
+        }
+        if c == '[' {
+            let link = find(i + 1, &[']', '(']).and_then(|close| Some((close, find(close + 2, &[')'])?)));
+            if let Some((close, end)) = link {
+                cells.extend(inline(&sub(i + 1, close), Style::Link));
+                i = end + 1;
+                continue;
+            }
+        }
+
+        cells.push(Cell::new(c, base));
+        i += 1;
+    }
+
+    cells
+}
+
+// Cells of a table row, without the outer pipes
+fn table_cells(line: &str) -> Vec<&str> {
+    let line = line.trim();
+    let line = line.strip_prefix('|').unwrap_or(line);
+    let line = line.strip_suffix('|').unwrap_or(line);
+    line.split('|').map(str::trim).collect()
+}
+
+// Column alignments when `line` is a `|---|:--:|` row
+fn separator(line: &str) -> Option<Vec<Align>> {
+    table_cells(line)
+        .into_iter()
+        .map(|cell| {
+            let dashes = cell.trim_matches(':');
+            if dashes.is_empty() || !dashes.chars().all(|c| c == '-') {
+                return None;
+            }
+            Some(match (cell.starts_with(':'), cell.ends_with(':')) {
+                (true, true) => Align::Center,
+                (false, true) => Align::Right,
+                _ => Align::Left,
+            })
+        })
+        .collect()
+}
+
+// Columns padded to their widest cell. Rows wider than the screen are cut
+// when drawn rather than wrapped.
+fn table(lines: &[&str], first_line: usize, aligns: &[Align]) -> Vec<Row> {
+    let columns = aligns.len();
+    // The separator row is drawn as a rule
+    let body: Vec<(usize, Vec<Vec<Cell>>)> = lines
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| i != 1)
+        .map(|(i, line)| {
+            let style = if i == 0 { Style::Bold } else { Style::Normal };
+            let mut cells: Vec<Vec<Cell>> = table_cells(line)
+                .into_iter()
+                .map(|cell| inline(cell, style))
+                .collect();
+            cells.resize(columns, Vec::new());
+            (first_line + i, cells)
+        })
+        .collect();
+
+    let widths: Vec<usize> = (0..columns)
+        .map(|column| {
+            body.iter()
+                .map(|(_, cells)| cells_width(&cells[column]))
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    let bar = || styled(" │ ", Style::Comment);
+    let mut rows = Vec::new();
+    for (index, (line, cells)) in body.into_iter().enumerate() {
+        let mut row = Vec::new();
+        for (column, cell) in cells.into_iter().enumerate() {
+            if column > 0 {
+                row.extend(bar());
+            }
+            let pad = widths[column] - cells_width(&cell);
+            let before = match aligns[column] {
+                Align::Left => 0,
+                Align::Center => pad / 2,
+                Align::Right => pad,
+            };
+            row.extend(styled(&" ".repeat(before), Style::Normal));
+            row.extend(cell);
+            row.extend(styled(&" ".repeat(pad - before), Style::Normal));
+        }
+        rows.push(Row { line, cells: row });
+
+        if index == 0 {
+            let rule: Vec<String> = widths.iter().map(|&width| "─".repeat(width)).collect();
+            rows.push(Row {
+                line: first_line + 1,
+                cells: styled(&rule.join("─┼─"), Style::Comment),
+            });
+        }
+    }
+
+    rows
+}
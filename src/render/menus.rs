@@ -1,16 +1,22 @@
 use crossterm::style::Color;
 
+use crate::config::theme;
 use crate::error::Result;
 
 use super::RenderState;
 
 pub(super) fn draw_file_picker_popup_to_buffer(
     render_state: &mut RenderState,
+    title: &str,
     files: &[String],
     selected_index: usize,
 ) -> Result<()> {
-    let max_file_len = files.iter().map(|f| f.len()).max().unwrap_or(0);
-    let title = "Pick a file";
+    let max_file_len = files
+        .iter()
+        .map(|f| f.len())
+        .max()
+        .unwrap_or(0)
+        .max(title.len());
 
     let popup_width = max_file_len + 4; // padding + borders
     let popup_height = files.len() + 2; // files + top & bottom borders
@@ -30,10 +36,11 @@ pub(super) fn draw_file_picker_popup_to_buffer(
         0
     };
 
-    let fg = Color::White;
-    let bg = Some(Color::DarkGrey);
-    let selection_fg = Color::Black;
-    let selection_bg = Some(Color::White);
+    let theme = theme::current();
+    let fg = theme.popup.fg;
+    let bg = theme.popup.bg;
+    let selection_fg = theme.popup_selected.fg;
+    let selection_bg = theme.popup_selected.bg;
 
     // Draw border
     render_state.set_cell(start_x, start_y, '┌', fg, bg);
@@ -123,8 +130,9 @@ pub(super) fn draw_help_popup_to_buffer(
         0
     };
 
-    let fg = Color::White;
-    let bg = Some(Color::DarkGrey);
+    let theme = theme::current();
+    let fg = theme.popup.fg;
+    let bg = theme.popup.bg;
 
     // Draw border: top line with title
     render_state.set_cell(start_x, start_y, '┌', fg, bg);
@@ -175,10 +183,12 @@ pub(super) fn draw_help_popup_to_buffer(
     Ok(())
 }
 
-pub(super) fn draw_file_save_as_popup_to_buffer(
+pub(super) fn draw_input_popup_to_buffer(
     render_state: &mut RenderState,
+    title: &str,
     input: &str,
     cursor_pos: usize,
+    hint: &str,
 ) -> Result<()> {
     // Determine popup size (fixed width or dynamic based on input length)
     let popup_width = 40;
@@ -200,8 +210,9 @@ pub(super) fn draw_file_save_as_popup_to_buffer(
         0
     };
 
-    let fg = Color::White;
-    let bg = Some(Color::DarkGrey);
+    let theme = theme::current();
+    let fg = theme.popup.fg;
+    let bg = theme.popup.bg;
 
     // Draw border
     render_state.set_cell(start_x, start_y, '┌', fg, bg);
@@ -227,8 +238,7 @@ pub(super) fn draw_file_save_as_popup_to_buffer(
         bg,
     );
 
-    // Title line - "Save As:"
-    let title = "Save As:";
+    // Title line, e.g. "Save As:"
     for (i, ch) in title.chars().enumerate() {
         render_state.set_cell(start_x + 2 + i, start_y + 1, ch, fg, bg);
     }
@@ -253,12 +263,12 @@ pub(super) fn draw_file_save_as_popup_to_buffer(
         if input_start_x + i >= render_state.term_width as usize - 1 {
             break;
         }
-        render_state.set_cell(input_start_x + i, input_y, ch, Color::White, bg);
+        render_state.set_cell(input_start_x + i, input_y, ch, fg, bg);
     }
 
     // Clear rest of input line
     for x in (input_start_x + input_display.len())..(start_x + popup_width - 2) {
-        render_state.set_cell(x, input_y, ' ', Color::White, bg);
+        render_state.set_cell(x, input_y, ' ', fg, bg);
     }
 
     // Draw cursor position (inverted color)
@@ -272,12 +282,11 @@ pub(super) fn draw_file_save_as_popup_to_buffer(
         cursor_visual_x,
         input_y,
         cursor_char,
-        Color::Black,
-        Some(Color::White),
+        theme.popup_selected.fg,
+        theme.popup_selected.bg,
     );
 
     // Optional message / hint line
-    let hint = "Enter: Save | Esc: Cancel";
     for (i, ch) in hint.chars().enumerate() {
         if start_x + 2 + i >= render_state.term_width as usize {
             break;
@@ -287,3 +296,415 @@ pub(super) fn draw_file_save_as_popup_to_buffer(
 
     Ok(())
 }
+
+/// Multi-line text popup, soft wrapped, scrolled to keep the cursor visible
+pub(super) fn draw_text_editor_popup_to_buffer(
+    render_state: &mut RenderState,
+    title: &str,
+    text: &str,
+    cursor: (usize, usize),
+    hint: &str,
+) -> Result<()> {
+    let term_width = render_state.term_width as usize;
+    let term_height = render_state.term_height as usize;
+
+    let popup_width = 70.min(term_width.saturating_sub(4)).max(20);
+    let popup_height = 16.min(term_height.saturating_sub(4)).max(6);
+    let text_width = popup_width - 4;
+    let text_height = popup_height - 4; // borders, title and hint
+
+    let start_x = term_width.saturating_sub(popup_width) / 2;
+    let start_y = term_height.saturating_sub(popup_height) / 2;
+
+    let theme = theme::current();
+    let fg = theme.popup.fg;
+    let bg = theme.popup.bg;
+
+    // Wrap lines into rows and find the row holding the cursor
+    let mut rows: Vec<String> = Vec::new();
+    let mut cursor_row = 0;
+    let mut cursor_x = 0;
+    for (i, line) in text.split('\n').enumerate() {
+        let chars: Vec<char> = line.chars().collect();
+        let chunks = chars.len() / text_width + 1;
+
+        if i == cursor.0 {
+            let chunk = (cursor.1 / text_width).min(chunks - 1);
+            cursor_row = rows.len() + chunk;
+            cursor_x = cursor.1 - chunk * text_width;
+        }
+        for chunk in 0..chunks {
+            let end = ((chunk + 1) * text_width).min(chars.len());
+            rows.push(chars[chunk * text_width..end].iter().collect());
+        }
+    }
+    let scroll = (cursor_row + 1).saturating_sub(text_height);
+
+    // Background and border
+    for y in start_y..start_y + popup_height {
+        for x in start_x..start_x + popup_width {
+            let ch = match (x == start_x, x == start_x + popup_width - 1, y) {
+                (true, _, y) if y == start_y => '┌',
+                (_, true, y) if y == start_y => '┐',
+                (true, _, y) if y == start_y + popup_height - 1 => '└',
+                (_, true, y) if y == start_y + popup_height - 1 => '┘',
+                (true, _, _) | (_, true, _) => '│',
+                _ if y == start_y || y == start_y + popup_height - 1 => '─',
+                _ => ' ',
+            };
+            render_state.set_cell(x, y, ch, fg, bg);
+        }
+    }
+
+    for (i, ch) in title.chars().take(text_width).enumerate() {
+        render_state.set_cell(start_x + 2 + i, start_y + 1, ch, fg, bg);
+    }
+
+    for (y, row) in rows.iter().skip(scroll).take(text_height).enumerate() {
+        for (x, ch) in row.chars().enumerate() {
+            render_state.set_cell(start_x + 2 + x, start_y + 2 + y, ch, fg, bg);
+        }
+    }
+
+    // Cursor (inverted color)
+    let cursor_y = start_y + 2 + cursor_row - scroll;
+    let cursor_char = rows
+        .get(cursor_row)
+        .and_then(|row| row.chars().nth(cursor_x))
+        .unwrap_or(' ');
+    render_state.set_cell(
+        start_x + 2 + cursor_x,
+        cursor_y,
+        cursor_char,
+        theme.popup_selected.fg,
+        theme.popup_selected.bg,
+    );
+
+    for (i, ch) in hint.chars().take(text_width).enumerate() {
+        render_state.set_cell(start_x + 2 + i, start_y + popup_height - 2, ch, fg, bg);
+    }
+
+    Ok(())
+}
+
+/// Box in the top right corner, below the tab bar, e.g. the request panel
+pub(super) fn draw_panel_to_buffer(
+    render_state: &mut RenderState,
+    title: &str,
+    lines: &[String],
+    start_y: usize,
+) -> Result<()> {
+    let max_line_length = lines.iter().map(|line| line.chars().count()).max().unwrap_or(0);
+
+    let popup_width = max_line_length.max(title.len()) + 4; // padding + borders
+    let popup_height = lines.len() + 2; // lines + top & bottom border
+
+    let term_width = render_state.term_width as usize;
+    let start_x = if term_width > popup_width + 1 {
+        term_width - popup_width - 1
+    } else {
+        0
+    };
+
+    let theme = theme::current();
+    let fg = theme.popup.fg;
+    let bg = theme.popup.bg;
+
+    // Top border with the title
+    render_state.set_cell(start_x, start_y, '┌', fg, bg);
+    for x in (start_x + 1)..(start_x + popup_width - 1) {
+        render_state.set_cell(x, start_y, '─', fg, bg);
+    }
+    for (i, ch) in title.chars().enumerate() {
+        render_state.set_cell(start_x + 2 + i, start_y, ch, fg, bg);
+    }
+    render_state.set_cell(start_x + popup_width - 1, start_y, '┐', fg, bg);
+
+    for (i, line) in lines.iter().enumerate() {
+        let y = start_y + 1 + i;
+        render_state.set_cell(start_x, y, '│', fg, bg);
+
+        let mut x = start_x + 2;
+        for ch in line.chars() {
+            render_state.set_cell(x, y, ch, fg, bg);
+            x += 1;
+        }
+        render_state.set_cell(start_x + 1, y, ' ', fg, bg);
+        for x in x..(start_x + popup_width - 1) {
+            render_state.set_cell(x, y, ' ', fg, bg);
+        }
+
+        render_state.set_cell(start_x + popup_width - 1, y, '│', fg, bg);
+    }
+
+    // Bottom border
+    let bottom_y = start_y + popup_height - 1;
+    render_state.set_cell(start_x, bottom_y, '└', fg, bg);
+    for x in (start_x + 1)..(start_x + popup_width - 1) {
+        render_state.set_cell(x, bottom_y, '─', fg, bg);
+    }
+    render_state.set_cell(start_x + popup_width - 1, bottom_y, '┘', fg, bg);
+
+    Ok(())
+}
+
+/// Centered box with a question, a few lines of detail and the answer keys
+pub(super) fn draw_confirm_popup_to_buffer(
+    render_state: &mut RenderState,
+    title: &str,
+    lines: &[String],
+    hint: &str,
+) -> Result<()> {
+    let content_width = lines
+        .iter()
+        .map(|line| line.chars().count())
+        .chain([title.chars().count(), hint.chars().count()])
+        .max()
+        .unwrap_or(0);
+
+    let popup_width = content_width + 4; // padding + borders
+    let popup_height = lines.len() + 5; // title, lines, blank, hint + borders
+
+    let term_width = render_state.term_width as usize;
+    let term_height = render_state.term_height as usize;
+
+    // Center the popup
+    let start_x = term_width.saturating_sub(popup_width) / 2;
+    let start_y = term_height.saturating_sub(popup_height) / 2;
+
+    let theme = theme::current();
+    let fg = theme.popup.fg;
+    let bg = theme.popup.bg;
+
+    let mut rows = vec![title.to_string()];
+    rows.extend(lines.iter().cloned());
+    rows.push(String::new());
+    rows.push(hint.to_string());
+
+    // Border
+    render_state.set_cell(start_x, start_y, '┌', fg, bg);
+    render_state.set_cell(start_x + popup_width - 1, start_y, '┐', fg, bg);
+    let bottom_y = start_y + popup_height - 1;
+    render_state.set_cell(start_x, bottom_y, '└', fg, bg);
+    render_state.set_cell(start_x + popup_width - 1, bottom_y, '┘', fg, bg);
+    for x in (start_x + 1)..(start_x + popup_width - 1) {
+        render_state.set_cell(x, start_y, '─', fg, bg);
+        render_state.set_cell(x, bottom_y, '─', fg, bg);
+    }
+
+    for (i, row) in rows.iter().enumerate() {
+        let y = start_y + 1 + i;
+        render_state.set_cell(start_x, y, '│', fg, bg);
+        render_state.set_cell(start_x + popup_width - 1, y, '│', fg, bg);
+
+        let mut chars = row.chars();
+        for x in (start_x + 1)..(start_x + popup_width - 1) {
+            let ch = if x >= start_x + 2 {
+                chars.next().unwrap_or(' ')
+            } else {
+                ' '
+            };
+            render_state.set_cell(x, y, ch, fg, bg);
+        }
+    }
+
+    Ok(())
+}
+
+// Width and height of the read-only pane, leaving the tab bar and status
+// lines visible
+fn pane_size(render_state: &RenderState) -> (usize, usize) {
+    let width = (render_state.term_width as usize).saturating_sub(4).max(20);
+    let height = (render_state.term_height as usize).saturating_sub(6).max(5);
+    (width, height)
+}
+
+/// Lines of content the read-only pane shows at once
+pub(super) fn pane_rows(render_state: &RenderState) -> usize {
+    // Borders and the hint row
+    pane_size(render_state).1 - 3
+}
+
+/// Read-only box filling most of the screen. Every line brings its own
+/// colors; `scroll` is the first line shown.
+pub(super) fn draw_pane_popup_to_buffer(
+    render_state: &mut RenderState,
+    title: &str,
+    lines: &[(String, Color, Option<Color>)],
+    scroll: usize,
+    hint: &str,
+) -> Result<()> {
+    let term_width = render_state.term_width as usize;
+    let term_height = render_state.term_height as usize;
+
+    let (popup_width, popup_height) = pane_size(render_state);
+    let start_x = term_width.saturating_sub(popup_width) / 2;
+    let start_y = term_height.saturating_sub(popup_height + 2) / 2 + 1;
+    let end_x = start_x + popup_width - 1;
+    let bottom_y = start_y + popup_height - 1;
+    let text_width = popup_width.saturating_sub(4);
+
+    let theme = theme::current();
+    let fg = theme.popup.fg;
+    let bg = theme.popup.bg;
+
+    let rows = pane_rows(render_state);
+
+    // Border with the title on top and the hint at the bottom
+    render_state.set_cell(start_x, start_y, '┌', fg, bg);
+    render_state.set_cell(end_x, start_y, '┐', fg, bg);
+    render_state.set_cell(start_x, bottom_y, '└', fg, bg);
+    render_state.set_cell(end_x, bottom_y, '┘', fg, bg);
+    let mut title_chars = title.chars().take(text_width);
+    for x in (start_x + 1)..end_x {
+        let ch = if x >= start_x + 2 {
+            title_chars.next().unwrap_or('─')
+        } else {
+            '─'
+        };
+        render_state.set_cell(x, start_y, ch, fg, bg);
+        render_state.set_cell(x, bottom_y, '─', fg, bg);
+    }
+
+    let empty = (String::new(), fg, bg);
+    for row in 0..=rows {
+        let y = start_y + 1 + row;
+        render_state.set_cell(start_x, y, '│', fg, bg);
+        render_state.set_cell(end_x, y, '│', fg, bg);
+
+        let (text, line_fg, line_bg) = if row == rows {
+            (hint.to_string(), fg, bg)
+        } else {
+            let line = lines.get(scroll + row).unwrap_or(&empty);
+            (line.0.clone(), line.1, line.2.or(bg))
+        };
+
+        let mut chars = text.chars().take(text_width);
+        for x in (start_x + 1)..end_x {
+            let ch = if x >= start_x + 2 {
+                chars.next().unwrap_or(' ')
+            } else {
+                ' '
+            };
+            render_state.set_cell(x, y, ch, line_fg, line_bg);
+        }
+    }
+
+    Ok(())
+}
+
+/// Centered finder: a query line over a scrolling list of matches
+pub(super) fn draw_finder_popup_to_buffer(
+    render_state: &mut RenderState,
+    title: &str,
+    query: &str,
+    cursor_pos: usize,
+    items: &[String],
+    selected_index: usize,
+    context: &[String],
+) -> Result<()> {
+    let term_width = render_state.term_width as usize;
+    let term_height = render_state.term_height as usize;
+
+    let popup_width = items
+        .iter()
+        .chain(context)
+        .map(|item| item.chars().count())
+        .chain([title.chars().count(), 40])
+        .max()
+        .unwrap_or(0)
+        .saturating_add(4)
+        .min(term_width.saturating_sub(2));
+    // A separator above the context
+    let context_height = if context.is_empty() { 0 } else { context.len() + 1 };
+    // Room for the status lines and a bit of the buffer
+    let max_rows = term_height.saturating_sub(10 + context_height).max(1);
+    let rows = items.len().clamp(1, max_rows);
+    let popup_height = rows + 4 + context_height; // query, separator and borders
+
+    let start_x = term_width.saturating_sub(popup_width) / 2;
+    let start_y = term_height.saturating_sub(popup_height) / 2;
+    let end_x = start_x + popup_width - 1;
+
+    let theme = theme::current();
+    let fg = theme.popup.fg;
+    let bg = theme.popup.bg;
+    let selection_fg = theme.popup_selected.fg;
+    let selection_bg = theme.popup_selected.bg;
+
+    // Keep the selection in the visible window
+    let first = (selected_index + 1).saturating_sub(rows);
+
+    let mut draw_row = |render_state: &mut RenderState, y: usize, text: &str, fg, bg| {
+        let mut chars = text.chars();
+        for x in (start_x + 1)..end_x {
+            let ch = if x >= start_x + 2 {
+                chars.next().unwrap_or(' ')
+            } else {
+                ' '
+            };
+            render_state.set_cell(x, y, ch, fg, bg);
+        }
+        render_state.set_cell(start_x, y, '│', fg, bg);
+        render_state.set_cell(end_x, y, '│', fg, bg);
+    };
+
+    // Top border with the title
+    render_state.set_cell(start_x, start_y, '┌', fg, bg);
+    for x in (start_x + 1)..end_x {
+        render_state.set_cell(x, start_y, '─', fg, bg);
+    }
+    for (i, ch) in title.chars().take(popup_width.saturating_sub(4)).enumerate() {
+        render_state.set_cell(start_x + 2 + i, start_y, ch, fg, bg);
+    }
+    render_state.set_cell(end_x, start_y, '┐', fg, bg);
+
+    draw_row(render_state, start_y + 1, &format!("> {}", query), fg, bg);
+
+    let separator_y = start_y + 2;
+    render_state.set_cell(start_x, separator_y, '├', fg, bg);
+    for x in (start_x + 1)..end_x {
+        render_state.set_cell(x, separator_y, '─', fg, bg);
+    }
+    render_state.set_cell(end_x, separator_y, '┤', fg, bg);
+
+    if items.is_empty() {
+        draw_row(render_state, separator_y + 1, "No matches", fg, bg);
+    }
+    for (i, item) in items.iter().enumerate().skip(first).take(rows) {
+        let y = separator_y + 1 + i - first;
+        if i == selected_index {
+            draw_row(render_state, y, item, selection_fg, selection_bg);
+        } else {
+            draw_row(render_state, y, item, fg, bg);
+        }
+    }
+
+    if !context.is_empty() {
+        let context_y = separator_y + 1 + rows;
+        render_state.set_cell(start_x, context_y, '├', fg, bg);
+        for x in (start_x + 1)..end_x {
+            render_state.set_cell(x, context_y, '─', fg, bg);
+        }
+        render_state.set_cell(end_x, context_y, '┤', fg, bg);
+        for (i, line) in context.iter().enumerate() {
+            draw_row(render_state, context_y + 1 + i, line, fg, bg);
+        }
+    }
+
+    let bottom_y = start_y + popup_height - 1;
+    render_state.set_cell(start_x, bottom_y, '└', fg, bg);
+    for x in (start_x + 1)..end_x {
+        render_state.set_cell(x, bottom_y, '─', fg, bg);
+    }
+    render_state.set_cell(end_x, bottom_y, '┘', fg, bg);
+
+    // Draw cursor position (inverted color)
+    let cursor_x = start_x + 4 + cursor_pos;
+    if cursor_x < end_x {
+        let cursor_char = query.chars().nth(cursor_pos).unwrap_or(' ');
+        render_state.set_cell(cursor_x, start_y + 1, cursor_char, selection_fg, selection_bg);
+    }
+
+    Ok(())
+}
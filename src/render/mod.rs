@@ -1,9 +1,16 @@
+mod markdown;
 pub mod menus;
+mod wrap;
 
+use crate::async_handler::RequestStatus;
 use crate::editor::filepicker::Action;
+use crate::editor::keymap;
 use crate::editor::menu::MenuType;
+use crate::editor::messages::Level;
+use crate::editor::split::{Layout, Pane};
 use crate::editor::{Editor, Mode, RequestState};
 use crate::error::Result;
+use crate::git::LineChange;
 
 use crossterm::{
     cursor::MoveTo,
@@ -13,9 +20,41 @@ use crossterm::{
 };
 use std::cmp::{max, min};
 use std::io::{self, stdout, Stdout, Write};
+use unicode_width::UnicodeWidthChar;
 
+use crate::config::{self, theme, ContextTrim, Insertion};
 use crate::syntax::Style;
 
+// Rows taken by the tab bar above the content
+const TAB_BAR_HEIGHT: usize = 1;
+// Smallest prompt pane in split view
+const MIN_PROMPT_HEIGHT: usize = 3;
+// Input box of the chat layout grows with the prompt up to this
+const MAX_CHAT_INPUT_HEIGHT: usize = 5;
+// Right half of a wide char, the terminal draws it with the left half
+const WIDE_TAIL: char = '\0';
+const TAB_WIDTH: usize = 4;
+
+/// Terminal cells taken by a char. Zero-width and control chars get one
+/// blank cell, so every char can still be put under the cursor.
+fn char_width(ch: char) -> usize {
+    ch.width().unwrap_or(0).max(1)
+}
+
+/// Cells of a char drawn `col` cells into a row, tabs stop every 4 cells
+fn cell_width(ch: char, col: usize) -> usize {
+    if ch == '\t' {
+        TAB_WIDTH - col % TAB_WIDTH
+    } else {
+        char_width(ch)
+    }
+}
+
+/// Cells taken by `chars` drawn from the start of a row
+fn display_width(chars: impl Iterator<Item = char>) -> usize {
+    chars.fold(0, |col, ch| col + cell_width(ch, col))
+}
+
 pub struct WrappedLineInfo {
     pub logical_line: usize,
     pub start_col: usize,
@@ -24,8 +63,13 @@ pub struct WrappedLineInfo {
 
 pub struct RenderState {
     wrapped_lines_info: Vec<WrappedLineInfo>,
+    // Rows of the lines wrapped in earlier frames
+    wrap_cache: wrap::WrapCache,
 
     scroll_offset: usize, // First line displayed (for scrolling)
+    // Long lines wrap; otherwise they're cut off and scroll horizontally
+    soft_wrap: bool,
+    h_scroll: usize, // First column displayed when not wrapping
     term_width: u16,
     term_height: u16,
     line_number_width: usize,
@@ -33,7 +77,6 @@ pub struct RenderState {
     force_full_redraw: bool,
 
     // Double buffering support
-    previous_content: String, // Stores the previously rendered content
     previous_cursor: (usize, usize), // Previous cursor position
     previous_mode: Mode,      // Previous editor mode
     previous_request_stae: RequestState,
@@ -55,12 +98,14 @@ impl RenderState {
 
         Ok(Self {
             wrapped_lines_info: Vec::new(),
+            wrap_cache: wrap::WrapCache::default(),
             scroll_offset: 0,
+            soft_wrap: true,
+            h_scroll: 0,
             term_width,
             term_height,
             force_full_redraw: false,
             line_number_width: 4,
-            previous_content: String::new(),
             previous_cursor: (0, 0),
             previous_mode: Mode::Normal,
             previous_request_stae: RequestState::Idle,
@@ -70,24 +115,25 @@ impl RenderState {
         })
     }
 
-    pub fn update_dimensions(&mut self) -> Result<()> {
-        let (width, height) = size()?;
+    /// Takes the new terminal size from an `Event::Resize`. Both buffers
+    /// are replaced together, so no frame ever mixes the two sizes, and the
+    /// next frame is drawn in full on a cleared screen.
+    pub fn resize(&mut self, width: u16, height: u16) -> Result<()> {
+        self.term_width = width;
+        self.term_height = height;
 
-        if width != self.term_width || height != self.term_height {
-            self.term_width = width;
-            self.term_height = height;
+        let default_cell = (' ', Color::Reset, None);
+        self.current_buffer = vec![vec![default_cell; width as usize]; height as usize];
+        self.previous_buffer = vec![vec![default_cell; width as usize]; height as usize];
 
-            // Resize buffers
-            let default_cell = (' ', Color::Reset, None);
-            self.current_buffer = vec![vec![default_cell; width as usize]; height as usize];
-            self.previous_buffer = vec![vec![default_cell; width as usize]; height as usize];
+        // Rows wrapped for the old width; the next frame wraps them again
+        // and clamps the scroll to them
+        self.wrapped_lines_info.clear();
+        self.h_scroll = 0;
 
-            // Force full redraw
-            self.previous_content = String::new();
-            self.force_full_redraw = true;
+        self.force_full_redraw = true;
 
-            stdout().queue(Clear(ClearType::All))?.flush()?;
-        }
+        stdout().queue(Clear(ClearType::All))?.flush()?;
         Ok(())
     }
 }
@@ -95,20 +141,53 @@ impl RenderState {
 impl RenderState {
     // Additional methods
 
-    // Set a character with style in the current buffer
+    pub fn toggle_soft_wrap(&mut self) {
+        self.soft_wrap = !self.soft_wrap;
+        self.h_scroll = 0;
+    }
+
+    /// Columns left for text next to the line numbers and the scrollbar
+    fn max_line_width(&self) -> usize {
+        let scrollbar = config::get().scrollbar as usize;
+        (self.term_width as usize).saturating_sub(self.line_number_width + 1 + scrollbar)
+    }
+
+    // Set a character with style in the current buffer. Wide chars take
+    // the next cell too.
     fn set_cell(&mut self, x: usize, y: usize, ch: char, fg: Color, bg: Option<Color>) {
-        if y < self.term_height as usize && x < self.term_width as usize {
-            self.current_buffer[y][x] = (ch, fg, bg);
+        let width = self.term_width as usize;
+        if y >= self.term_height as usize || x >= width {
+            return;
+        }
+        let row = &mut self.current_buffer[y];
+
+        // Overwriting half of a wide char blanks the other half
+        if row[x].0 == WIDE_TAIL && x > 0 {
+            row[x - 1].0 = ' ';
+        }
+        if x + 1 < width && row[x + 1].0 == WIDE_TAIL {
+            row[x + 1].0 = ' ';
+        }
+
+        match ch.width() {
+            Some(2) if x + 1 < width => {
+                row[x] = (ch, fg, bg);
+                row[x + 1] = (WIDE_TAIL, fg, bg);
+            }
+            // No room for the right half, or nothing to draw
+            Some(2) | Some(0) | None => row[x] = (' ', fg, bg),
+            _ => row[x] = (ch, fg, bg),
         }
     }
 
-    // Compare buffers and determine if a cell has changed
+    // Compare buffers and determine if a cell has changed. Everything has
+    // after a resize.
     fn cell_changed(&self, x: usize, y: usize) -> bool {
         if y >= self.term_height as usize || x >= self.term_width as usize {
             return false;
         }
 
-        self.current_buffer[y][x] != self.previous_buffer[y][x]
+        self.force_full_redraw || self.current_buffer[y][x] != self.previous_buffer[y][x]
     }
 
     // Swap buffers after drawing is complete
@@ -128,14 +207,15 @@ impl RenderState {
 }
 
 pub fn draw_screen(editor: &mut Editor, render_state: &mut RenderState) -> Result<()> {
-    // Update terminal dimensions in case of resize
-    render_state.update_dimensions()?;
+    if editor.take_soft_wrap_toggle() {
+        render_state.toggle_soft_wrap();
+    }
 
     // Update scroll position to ensure cursor is visible
     adjust_scroll(editor, render_state);
+    editor.refresh_search();
 
     // Get current editor state
-    let content = editor.get_content();
     let (cursor_row, cursor_col) = editor.get_cursor_position();
     let mode = editor.get_mode().clone();
     let request_state = editor.get_request_state().clone();
@@ -145,30 +225,270 @@ pub fn draw_screen(editor: &mut Editor, render_state: &mut RenderState) -> Resul
     render_state.clear_buffer();
 
     // Adjust line number width based on number of lines
-    let line_count = content.lines().count();
+    let line_count = editor.display_line_count();
     render_state.line_number_width = line_count.to_string().len().max(3);
 
     // Draw content into buffer
     draw_content_to_buffer(editor, render_state)?;
+    draw_transcript_to_buffer(editor, render_state)?;
+    draw_preview_to_buffer(editor, render_state)?;
+    draw_tab_bar_to_buffer(editor, render_state)?;
 
     // Draw status and message lines to buffer
     draw_status_line_to_buffer(editor, render_state)?;
     // draw_message_line_to_buffer(editor, render_state)?;
     draw_request_state_line_to_buffer(editor, render_state)?;
 
+    // Side panels stack down from the tab bar
+    let mut panel_y = TAB_BAR_HEIGHT;
+    if editor.request_panel_visible() {
+        panel_y = draw_request_panel_to_buffer(editor, render_state, panel_y)?;
+    }
+    if editor.pins_panel_visible() {
+        draw_pins_panel_to_buffer(editor, render_state, panel_y)?;
+    }
+
     if editor.menu_status.file_picker_state(Action::Save) {
-        menus::draw_file_save_as_popup_to_buffer(
+        menus::draw_input_popup_to_buffer(
+            render_state,
+            "Save As:",
+            &editor.menu_status.get_file_picker_input(),
+            editor.menu_status.get_file_picker_cursor_position(),
+            "Enter: Save | Esc: Cancel",
+        )?;
+    }
+
+    if editor.menu_status.file_picker_state(Action::SaveBlock) {
+        menus::draw_input_popup_to_buffer(
+            render_state,
+            "Extract block to:",
+            &editor.menu_status.get_file_picker_input(),
+            editor.menu_status.get_file_picker_cursor_position(),
+            "Enter: Write | Esc: Cancel",
+        )?;
+    }
+
+    if editor.menu_status.file_picker_state(Action::RenameFile) {
+        menus::draw_input_popup_to_buffer(
+            render_state,
+            "Rename to:",
+            &editor.menu_status.get_file_picker_input(),
+            editor.menu_status.get_file_picker_cursor_position(),
+            "Enter: Rename | Esc: Cancel",
+        )?;
+    }
+
+    if editor.menu_status.file_picker_state(Action::TagSession) {
+        menus::draw_input_popup_to_buffer(
             render_state,
+            "Session tags:",
             &editor.menu_status.get_file_picker_input(),
             editor.menu_status.get_file_picker_cursor_position(),
+            "Enter: Tag | Esc: Cancel",
+        )?;
+    }
+
+    if editor.menu_status.file_picker_state(Action::RenameSession) {
+        menus::draw_input_popup_to_buffer(
+            render_state,
+            "Session title:",
+            &editor.menu_status.get_file_picker_input(),
+            editor.menu_status.get_file_picker_cursor_position(),
+            "Enter: Rename | Esc: Cancel",
+        )?;
+    }
+
+    if editor.menu_status.file_picker_state(Action::RunAttach) {
+        menus::draw_input_popup_to_buffer(
+            render_state,
+            "Run and attach:",
+            &editor.menu_status.get_file_picker_input(),
+            editor.menu_status.get_file_picker_cursor_position(),
+            "Enter: Run | Esc: Cancel",
+        )?;
+    }
+
+    if editor.menu_status.file_picker_state(Action::CommitHistory) {
+        menus::draw_input_popup_to_buffer(
+            render_state,
+            "Commit message:",
+            &editor.menu_status.get_file_picker_input(),
+            editor.menu_status.get_file_picker_cursor_position(),
+            "Enter: Commit | Esc: Cancel",
+        )?;
+    }
+
+    if editor.menu_status.file_picker_state(Action::GotoLine) {
+        menus::draw_input_popup_to_buffer(
+            render_state,
+            "Go to line:",
+            &editor.menu_status.get_file_picker_input(),
+            editor.menu_status.get_file_picker_cursor_position(),
+            "Enter: Go | Esc: Cancel",
+        )?;
+    }
+
+    if editor.menu_status.file_picker_state(Action::PullModel) {
+        menus::draw_input_popup_to_buffer(
+            render_state,
+            "Pull Ollama model:",
+            &editor.menu_status.get_file_picker_input(),
+            editor.menu_status.get_file_picker_cursor_position(),
+            "Enter: Pull | Esc: Cancel",
+        )?;
+    }
+
+    if editor.menu_status.file_picker_state(Action::InlineEdit) {
+        menus::draw_input_popup_to_buffer(
+            render_state,
+            "Rewrite instruction:",
+            &editor.menu_status.get_file_picker_input(),
+            editor.menu_status.get_file_picker_cursor_position(),
+            "Enter: Rewrite | Esc: Cancel",
+        )?;
+    }
+
+    // Never draw the key itself
+    if editor.menu_status.file_picker_state(Action::ApiKey) {
+        let masked = "*".repeat(editor.menu_status.get_file_picker_input().chars().count());
+        menus::draw_input_popup_to_buffer(
+            render_state,
+            "API key:",
+            &masked,
+            editor.menu_status.get_file_picker_cursor_position(),
+            "Enter: Set | Esc: Cancel",
+        )?;
+    }
+
+    if editor.search.is_active() {
+        let search = &editor.search;
+        let prompt = if editor.replace.is_entering_pattern() {
+            "Replace"
+        } else {
+            "Search"
+        };
+        let title = if search.is_invalid() {
+            format!("{}: invalid regex", prompt)
+        } else {
+            format!("{}: {} matches", prompt, search.match_count())
+        };
+        let hint = format!(
+            "Alt+c nocase [{}] Alt+r regex [{}]",
+            if search.is_case_insensitive() { 'x' } else { ' ' },
+            if search.is_regex() { 'x' } else { ' ' },
+        );
+        menus::draw_input_popup_to_buffer(
+            render_state,
+            &title,
+            search.get_input(),
+            search.cursor_pos(),
+            &hint,
+        )?;
+    }
+
+    if editor.replace.is_entering_replacement() {
+        let hint = if editor.search.is_regex() {
+            "Enter: Next | $1: Group | Esc: Cancel"
+        } else {
+            "Enter: Next | Esc: Cancel"
+        };
+        menus::draw_input_popup_to_buffer(
+            render_state,
+            &format!("Replace {} with:", editor.search.get_input()),
+            editor.replace.get_input(),
+            editor.replace.cursor_pos(),
+            hint,
+        )?;
+    }
+
+    // Off to the side, so the match in question stays visible
+    if editor.replace.is_confirming() {
+        let lines = vec![
+            format!("With: {}", editor.replace.get_input()),
+            format!("Replaced so far: {}", editor.replace.replaced()),
+            "y: Yes | n: Skip | a: All | q: Stop".to_string(),
+        ];
+        menus::draw_panel_to_buffer(render_state, "Replace this match?", &lines, TAB_BAR_HEIGHT)?;
+    }
+
+    if editor.prompt_editor.is_active() {
+        let prompt_editor = &editor.prompt_editor;
+        menus::draw_text_editor_popup_to_buffer(
+            render_state,
+            "System prompt",
+            prompt_editor.get_text(),
+            prompt_editor.cursor_line_col(),
+            "Ctrl+s: Save | Esc: Cancel",
+        )?;
+    }
+
+    if editor.params_form.is_active() {
+        let params_form = &editor.params_form;
+        menus::draw_text_editor_popup_to_buffer(
+            render_state,
+            "Generation parameters (empty: provider default)",
+            &params_form.text(),
+            params_form.cursor_line_col(),
+            "Tab: Next field | Enter: Apply | Esc: Cancel",
         )?;
     }
 
     // Draw file picker popup if active
     if editor.menu_status.file_picker_state(Action::Load) {
         let files = &editor.menu_status.get_file_picker_files();
+        let hint = "^R: Rename | ^X: Delete | ^C: Duplicate";
+        draw_finder_to_buffer(editor, render_state, "Pick a file", hint, files, &[])?;
+    } else if editor.menu_status.file_picker_state(Action::Attach) {
+        // Mark the files that are already attached
+        let attached = editor.attached_files();
+        let files: Vec<String> = editor
+            .menu_status
+            .get_file_picker_files()
+            .iter()
+            .map(|f| {
+                let mark = if attached.contains(&f.as_str()) { 'x' } else { ' ' };
+                format!("[{}] {}", mark, f)
+            })
+            .collect();
+        draw_finder_to_buffer(editor, render_state, "Attach files", "", &files, &[])?;
+    } else if editor.menu_status.file_picker_state(Action::PromptHistory) {
+        let prompts = &editor.menu_status.get_file_picker_files();
+        draw_finder_to_buffer(editor, render_state, "Recall a prompt", "Enter: Edit", prompts, &[])?;
+    } else if editor.menu_status.file_picker_state(Action::SearchHistory) {
+        let hits = &editor.menu_status.get_file_picker_files();
+        let context = editor.history_search_context();
+        draw_finder_to_buffer(editor, render_state, "Search conversations", "Enter: Open", hits, &context)?;
+    } else if editor.menu_status.file_picker_state(Action::Import) {
+        let files = &editor.menu_status.get_file_picker_files();
+        draw_finder_to_buffer(editor, render_state, "Import a conversation", "", files, &[])?;
+    } else if editor.menu_status.file_picker_state(Action::ExtractBlock) {
+        let blocks = &editor.menu_status.get_file_picker_files();
+        let selected_idx = editor.menu_status.file_picker_selected_index();
+        menus::draw_file_picker_popup_to_buffer(render_state, "Pick a code block", blocks, selected_idx)?;
+    } else if editor.menu_status.file_picker_state(Action::PickSession) {
+        let sessions = &editor.menu_status.get_file_picker_files();
+        draw_finder_to_buffer(editor, render_state, "Resume a session", "#tag: Filter", sessions, &[])?;
+    } else if editor.menu_status.file_picker_state(Action::PickKeyProvider) {
+        let providers = &editor.menu_status.get_file_picker_files();
+        let selected_idx = editor.menu_status.file_picker_selected_index();
+        menus::draw_file_picker_popup_to_buffer(render_state, "Set API key for", providers, selected_idx)?;
+    } else if editor.menu_status.file_picker_state(Action::Completion) {
+        let items = &editor.menu_status.get_file_picker_files();
+        let selected_idx = editor.menu_status.file_picker_selected_index();
+        menus::draw_file_picker_popup_to_buffer(render_state, "Completions", items, selected_idx)?;
+    } else if editor.menu_status.file_picker_state(Action::OllamaModels) {
+        let models = &editor.menu_status.get_file_picker_files();
         let selected_idx = editor.menu_status.file_picker_selected_index();
-        menus::draw_file_picker_popup_to_buffer(render_state, files, selected_idx)?;
+        menus::draw_file_picker_popup_to_buffer(
+            render_state,
+            "Ollama models (Enter: use | p: pull)",
+            models,
+            selected_idx,
+        )?;
+    } else if editor.menu_status.file_picker_state(Action::PickModel) {
+        let models = &editor.menu_status.get_file_picker_files();
+        let selected_idx = editor.menu_status.file_picker_selected_index();
+        menus::draw_file_picker_popup_to_buffer(render_state, "Pick a model", models, selected_idx)?;
     } else if editor.is_waiting_for_command() {
         // existing help popup drawing here if needed
         if let (Some(title), Some(help)) = editor.get_help_content() {
@@ -176,6 +496,140 @@ pub fn draw_screen(editor: &mut Editor, render_state: &mut RenderState) -> Resul
         }
     }
 
+    let pane_rows = menus::pane_rows(render_state);
+    if let Some(diff) = editor.diff_view_mut() {
+        diff.clamp_scroll(diff.lines.len().saturating_sub(pane_rows));
+
+        let lines: Vec<(String, Color, Option<Color>)> = diff
+            .lines
+            .iter()
+            .map(|(style, text)| {
+                let (fg, bg) = match style {
+                    // Context lines blend in with the popup
+                    Style::Normal => (theme::current().popup.fg, None),
+                    _ => style_colors(*style),
+                };
+                (text.clone(), fg, bg)
+            })
+            .collect();
+        menus::draw_pane_popup_to_buffer(
+            render_state,
+            &diff.title,
+            &lines,
+            diff.scroll(),
+            "j/k: Scroll | PgUp/PgDn: Page | g/G: Top/Bottom | Esc: Close",
+        )?;
+    }
+
+    if let Some(patch) = editor.patch_preview_mut() {
+        patch.scroll_into_view(pane_rows);
+
+        let lines: Vec<(String, Color, Option<Color>)> = patch
+            .lines()
+            .into_iter()
+            .map(|(style, text)| {
+                let (fg, bg) = match style {
+                    Style::Normal => (theme::current().popup.fg, None),
+                    _ => style_colors(style),
+                };
+                (text, fg, bg)
+            })
+            .collect();
+        menus::draw_pane_popup_to_buffer(
+            render_state,
+            &patch.title(),
+            &lines,
+            patch.scroll(),
+            "j/k: Hunk | y/n: Accept/Reject | a/r: All | Enter: Write | Esc: Cancel",
+        )?;
+    }
+
+    let entries = editor.log_entries();
+    if let Some(view) = editor.log_view_mut() {
+        let lines: Vec<(String, Color, Option<Color>)> = if entries.is_empty() {
+            vec![("No requests yet".to_string(), theme::current().popup.fg, None)]
+        } else {
+            entries
+                .iter()
+                .map(|entry| {
+                    let fg = if entry.is_error() {
+                        style_colors(Style::Error).0
+                    } else {
+                        theme::current().popup.fg
+                    };
+                    (entry.line(), fg, None)
+                })
+                .collect()
+        };
+        menus::draw_pane_popup_to_buffer(
+            render_state,
+            "Request log",
+            &lines,
+            view.scroll(lines.len(), pane_rows),
+            "j/k: Scroll | PgUp/PgDn: Page | g/G: Top/Bottom | Esc: Close",
+        )?;
+    }
+
+    let messages = editor.message_history();
+    if let Some(view) = editor.message_view_mut() {
+        let lines: Vec<(String, Color, Option<Color>)> = if messages.is_empty() {
+            vec![("No messages yet".to_string(), theme::current().popup.fg, None)]
+        } else {
+            messages
+                .iter()
+                .map(|message| {
+                    let fg = match message.level {
+                        Level::Info => theme::current().popup.fg,
+                        Level::Warning => theme::current().message_warning.fg,
+                        Level::Error => style_colors(Style::Error).0,
+                    };
+                    (message.line(), fg, None)
+                })
+                .collect()
+        };
+        menus::draw_pane_popup_to_buffer(
+            render_state,
+            "Messages",
+            &lines,
+            view.scroll(lines.len(), pane_rows),
+            "j/k: Scroll | PgUp/PgDn: Page | g/G: Top/Bottom | Esc: Close",
+        )?;
+    }
+
+    let usage = editor.usage_lines();
+    if let Some(view) = editor.usage_view_mut() {
+        let lines: Vec<(String, Color, Option<Color>)> = usage
+            .into_iter()
+            .map(|line| (line, theme::current().popup.fg, None))
+            .collect();
+        menus::draw_pane_popup_to_buffer(
+            render_state,
+            "Usage",
+            &lines,
+            view.scroll(lines.len(), pane_rows),
+            "j/k: Scroll | PgUp/PgDn: Page | g/G: Top/Bottom | Esc: Close",
+        )?;
+    }
+
+    if let Some(view) = editor.help_view_mut() {
+        let lines: Vec<(String, Color, Option<Color>)> = keymap::overview()
+            .into_iter()
+            .map(|line| (line, theme::current().popup.fg, None))
+            .collect();
+        menus::draw_pane_popup_to_buffer(
+            render_state,
+            "Keys",
+            &lines,
+            view.scroll(lines.len(), pane_rows),
+            "j/k: Scroll | PgUp/PgDn: Page | g/G: Top/Bottom | Esc: Close",
+        )?;
+    }
+
+    // Questions go on top of everything else
+    if let Some(confirm) = editor.confirm_prompt() {
+        menus::draw_confirm_popup_to_buffer(render_state, &confirm.title, &confirm.lines, &confirm.hint)?;
+    }
+
     // Render the changes to the terminal
     render_buffer_changes(render_state)?;
 
@@ -198,17 +652,27 @@ pub fn draw_screen(editor: &mut Editor, render_state: &mut RenderState) -> Resul
     } else {
         0 // fallback
     };
+    let visual_row = visual_row + prompt_pane_top(editor, render_state);
 
-    let visual_col = cursor_col
-        - render_state
-            .wrapped_lines_info
-            .iter()
-            .filter(|wli| wli.logical_line == cursor_row && wli.start_col <= cursor_col)
-            .max_by_key(|wli| wli.start_col)
-            .map(|wli| wli.start_col)
-            .unwrap_or(0)
-        + render_state.line_number_width
-        + 1;
+    // Cells from the start of the cursor's row, wide chars and tabs count more
+    let row_start = render_state
+        .wrapped_lines_info
+        .iter()
+        .filter(|wli| wli.logical_line == cursor_row && wli.start_col <= cursor_col)
+        .max_by_key(|wli| wli.start_col)
+        .map(|wli| wli.start_col)
+        .unwrap_or(0);
+    let line = editor.line_text(cursor_row);
+    let before_cursor = line.chars().skip(row_start).take(cursor_col - row_start);
+    let visual_col = display_width(before_cursor) + render_state.line_number_width + 1;
+
+    // A focused transcript has no cursor, park it on the separator
+    let (visual_col, visual_row) = match editor.split_view() {
+        Some(split) if split.focus() == Pane::Transcript => {
+            (0, separator_row(editor, render_state))
+        }
+        _ => (visual_col, visual_row),
+    };
 
     let mut stdout = stdout();
     stdout.queue(MoveTo(visual_col as u16, visual_row as u16))?;
@@ -218,7 +682,6 @@ pub fn draw_screen(editor: &mut Editor, render_state: &mut RenderState) -> Resul
     render_state.swap_buffers();
 
     // Update previous state
-    render_state.previous_content = content;
     render_state.previous_cursor = (cursor_row, cursor_col);
     render_state.previous_mode = mode;
     render_state.previous_request_stae = request_state;
@@ -227,54 +690,115 @@ pub fn draw_screen(editor: &mut Editor, render_state: &mut RenderState) -> Resul
     Ok(())
 }
 
+/// Rows between the tab bar and the status lines
+fn content_height(render_state: &RenderState) -> usize {
+    (render_state.term_height as usize).saturating_sub(2 + TAB_BAR_HEIGHT)
+}
+
+/// Rows of the buffer view: all content rows, the top third in split view
+/// or the input box below the chat
+fn prompt_pane_height(editor: &Editor, render_state: &RenderState) -> usize {
+    let height = content_height(render_state);
+    match editor.split_view().map(|split| split.layout()) {
+        Some(Layout::Stacked) => (height / 3).max(MIN_PROMPT_HEIGHT).min(height),
+        // The transcript keeps a row and its separator
+        Some(Layout::Chat) => editor
+            .line_count()
+            .clamp(MIN_PROMPT_HEIGHT, MAX_CHAT_INPUT_HEIGHT)
+            .min(height.saturating_sub(2))
+            .max(1),
+        None => height,
+    }
+}
+
+/// First screen row of the buffer view
+fn prompt_pane_top(editor: &Editor, render_state: &RenderState) -> usize {
+    match editor.split_view().map(|split| split.layout()) {
+        Some(Layout::Chat) => {
+            TAB_BAR_HEIGHT + content_height(render_state) - prompt_pane_height(editor, render_state)
+        }
+        _ => TAB_BAR_HEIGHT,
+    }
+}
+
+/// Row of the line between the prompt pane and the transcript
+fn separator_row(editor: &Editor, render_state: &RenderState) -> usize {
+    match editor.split_view().map(|split| split.layout()) {
+        Some(Layout::Chat) => prompt_pane_top(editor, render_state) - 1,
+        _ => TAB_BAR_HEIGHT + prompt_pane_height(editor, render_state),
+    }
+}
+
 fn draw_content_to_buffer(editor: &mut Editor, render_state: &mut RenderState) -> Result<()> {
-    let content = editor.get_content();
-    let viewport_height = render_state.term_height as usize - 2;
+    let viewport_height = prompt_pane_height(editor, render_state);
+    let top = prompt_pane_top(editor, render_state);
     let line_number_width = render_state.line_number_width;
-    let max_line_width = render_state.term_width as usize - line_number_width - 1;
+    let max_line_width = render_state.max_line_width();
+    let line_number = theme::current().line_number;
 
     let selection_range = editor.get_selection_range();
+    let (cursor_row, _) = editor.get_cursor_position();
+    // Inline completion, drawn after the cursor at the end of its line
+    let ghost = editor.ghost_text();
+    // With the split open the answer streams into the transcript instead
+    let stream_point = editor.stream_point().filter(|_| editor.split_view().is_none());
+
+    // Screen column of the ruler, 1-based in the config
+    let ruler_x = match config::get().ruler_column {
+        0 => None,
+        column => {
+            let h_scroll = if render_state.soft_wrap {
+                0
+            } else {
+                render_state.h_scroll
+            };
+            (column - 1)
+                .checked_sub(h_scroll)
+                .map(|x| x + line_number_width + 1)
+        }
+    };
 
     // First, clear previous wrapped lines info
     render_state.wrapped_lines_info.clear();
 
-    // For **all** logical lines, build wrapped lines info
-    let lines: Vec<&str> = content.lines().collect();
-
-    let mut all_wrapped_lines = Vec::new();
+    // For **all** logical lines, build wrapped lines info. Only wrapping
+    // reads their text, without it just the visible ones are materialized.
+    let line_count = editor.display_line_count();
 
-    for (logical_line, line) in lines.iter().enumerate() {
-        let line_chars: Vec<char> = line.chars().collect();
-        let mut visual_col_in_line = 0;
+    // A fold is one row, its header drawn as a summary
+    let folded = editor.folded();
+    let hidden = |line: usize| {
+        folded
+            .iter()
+            .any(|fold| fold.header < line && line <= fold.last)
+    };
+    let summary = |line: usize| folded.iter().find(|fold| fold.header == line);
 
-        while visual_col_in_line < line_chars.len()
-            || (line_chars.is_empty() && visual_col_in_line == 0)
-        {
-            all_wrapped_lines.push((logical_line, visual_col_in_line));
-
-            let mut displayed_width = 0;
-            let mut chars_drawn = 0;
-            while visual_col_in_line + chars_drawn < line_chars.len() {
-                let ch = line_chars[visual_col_in_line + chars_drawn];
-                let width = if ch == '\t' {
-                    4 - (displayed_width % 4)
-                } else {
-                    1
-                };
-                if displayed_width + width > max_line_width {
-                    break;
-                }
+    let mut all_wrapped_lines = Vec::new();
 
-                displayed_width += width;
-                chars_drawn += 1;
+    if render_state.soft_wrap {
+        // Unchanged lines keep the rows of the previous frame
+        render_state.wrap_cache.begin(max_line_width);
+        for logical_line in 0..line_count {
+            if hidden(logical_line) {
+                continue;
             }
-
-            if chars_drawn == 0 && visual_col_in_line == 0 && line_chars.is_empty() {
-                chars_drawn = 1; // draw empty line chunk
+            if summary(logical_line).is_some() {
+                all_wrapped_lines.push((logical_line, 0));
+                continue;
+            }
+            let line = editor.line_text(logical_line);
+            for &start_col in render_state.wrap_cache.rows(&line) {
+                all_wrapped_lines.push((logical_line, start_col));
             }
-
-            visual_col_in_line += chars_drawn;
         }
+        render_state.wrap_cache.end(line_count);
+    } else {
+        // Every line is one row, starting at the horizontal scroll
+        all_wrapped_lines = (0..line_count)
+            .filter(|&logical_line| !hidden(logical_line))
+            .map(|logical_line| (logical_line, render_state.h_scroll))
+            .collect();
     }
 
     // Store the wrapped lines info with screen_row filled (relative to whole buffer)
@@ -292,6 +816,14 @@ fn draw_content_to_buffer(editor: &mut Editor, render_state: &mut RenderState) -
 
     render_state.clear_buffer();
 
+    // The scroll was adjusted to the previous frame's rows, which may be
+    // fewer now, e.g. after the terminal got wider
+    let max_scroll = render_state
+        .wrapped_lines_info
+        .len()
+        .saturating_sub(viewport_height);
+    render_state.scroll_offset = render_state.scroll_offset.min(max_scroll);
+
     let viewport_start = render_state.scroll_offset;
     let viewport_end =
         (viewport_start + viewport_height).min(render_state.wrapped_lines_info.len());
@@ -301,8 +833,20 @@ fn draw_content_to_buffer(editor: &mut Editor, render_state: &mut RenderState) -
         let logical_line = wli.logical_line;
         let start_col = wli.start_col;
 
+        // Background for cells without one of their own
+        let cursor_line = config::get().highlight_cursor_line && logical_line == cursor_row;
+        let row_bg = |x: usize| {
+            if cursor_line {
+                Some(theme::current().cursor_line_bg)
+            } else if ruler_x == Some(x) {
+                Some(theme::current().ruler_bg)
+            } else {
+                None
+            }
+        };
+
         // Draw line number only if first wrapped chunk in that logical line
-        let line_num_str = if start_col == 0 {
+        let line_num_str = if start_col == 0 || !render_state.soft_wrap {
             format!("{:>width$} ", logical_line + 1, width = line_number_width)
         } else {
             " ".repeat(line_number_width + 1)
@@ -310,15 +854,67 @@ fn draw_content_to_buffer(editor: &mut Editor, render_state: &mut RenderState) -
         for (x, ch) in line_num_str.chars().enumerate() {
             render_state.set_cell(
                 x,
-                (screen_row - viewport_start) as usize,
+                screen_row - viewport_start + top,
                 ch,
-                Color::DarkGrey,
-                None,
+                line_number.fg,
+                line_number.bg,
             );
         }
 
+        // Changes since HEAD take the space after the line number
+        if start_col == 0 || !render_state.soft_wrap {
+            if let Some(change) = editor.git_change(logical_line) {
+                let colors = match change {
+                    LineChange::Added => theme::current().diff_add,
+                    LineChange::Modified => theme::current().git_modified,
+                    LineChange::Removed => theme::current().diff_remove,
+                };
+                render_state.set_cell(
+                    line_number_width,
+                    screen_row - viewport_start + top,
+                    change.marker(),
+                    colors.fg,
+                    colors.bg.or(line_number.bg),
+                );
+            }
+        }
+
+        if let Some(fold) = summary(logical_line) {
+            let (fg, _) = style_colors(if fold.code {
+                Style::Comment
+            } else {
+                Style::Heading
+            });
+            let row = screen_row - viewport_start + top;
+            let mut col = line_number_width + 1;
+            for ch in fold.summary.chars() {
+                let width = cell_width(ch, col - line_number_width - 1);
+                if col - line_number_width - 1 + width > max_line_width {
+                    break;
+                }
+                render_state.set_cell(col, row, ch, fg, row_bg(col));
+                col += width;
+            }
+            while col < render_state.term_width as usize {
+                render_state.set_cell(col, row, ' ', Color::Reset, row_bg(col));
+                col += 1;
+            }
+            continue;
+        }
+
         // Draw wrapped line chunk content
-        let line_chars: Vec<char> = lines[logical_line].chars().collect();
+        let line_chars: Vec<char> = editor.line_text(logical_line).chars().collect();
+
+        // Blanks being typed at the cursor aren't trailing yet
+        let typing = *editor.get_mode() == Mode::Insert && logical_line == cursor_row;
+        let trailing_from = if config::get().show_trailing_whitespace && !typing {
+            line_chars
+                .iter()
+                .rposition(|c| !c.is_whitespace())
+                .map_or(0, |last| last + 1)
+        } else {
+            line_chars.len()
+        };
 
         let mut displayed_width = 0;
         let mut col = line_number_width + 1;
@@ -326,24 +922,23 @@ fn draw_content_to_buffer(editor: &mut Editor, render_state: &mut RenderState) -
         let mut chars_drawn = 0;
         while start_col + chars_drawn < line_chars.len() {
             let ch = line_chars[start_col + chars_drawn];
-            let width = if ch == '\t' {
-                4 - (displayed_width % 4)
-            } else {
-                1
-            };
+            let width = cell_width(ch, displayed_width);
             if displayed_width + width > max_line_width {
                 break;
             }
 
             // Determine style (selection, syntax, etc.)
+            let char_idx = editor.char_idx_from_position(logical_line, start_col + chars_drawn);
             let style = {
-                let char_idx = editor.char_idx_from_position(logical_line, start_col + chars_drawn);
-                if editor.is_position_selected(
-                    logical_line,
-                    start_col + chars_drawn,
-                    &selection_range,
-                ) {
+                // The match about to be replaced looks selected
+                if editor.is_position_selected(logical_line, start_col + chars_drawn, &selection_range)
+                    || editor.replace.is_current(char_idx)
+                {
                     Style::Selection
+                } else if editor.search.is_match(char_idx) {
+                    Style::SearchMatch
+                } else if editor.language_server.is_diagnostic(char_idx) {
+                    Style::Error
                 } else if let Some(cached_style) =
                     editor.get_syntax_cache_cached_style(logical_line, start_col + chars_drawn)
                 {
@@ -352,58 +947,80 @@ fn draw_content_to_buffer(editor: &mut Editor, render_state: &mut RenderState) -
                     editor.get_style_at(char_idx)
                 }
             };
-            let (fg_color, bg_color) = match style {
-                Style::Normal => (Color::White, None),
-                Style::Keyword => (Color::Magenta, None),
-                Style::Function => (Color::Blue, None),
-                Style::Type => (Color::Cyan, None),
-                Style::String => (Color::Green, None),
-                Style::Number => (Color::Yellow, None),
-                Style::Comment => (Color::DarkGrey, None),
-                Style::Variable => (Color::White, None),
-                Style::Constant => (Color::Yellow, None),
-                Style::Operator => (Color::White, None),
-                Style::Selection => (Color::Black, Some(Color::Grey)),
-                Style::Error => (Color::Red, Some(Color::White)),
-            };
+            let (fg_color, mut bg_color) = style_colors(style);
+            if bg_color.is_none() && editor.protected.contains(char_idx) && !cursor_line {
+                bg_color = Some(theme::current().answer_bg);
+            }
+            if bg_color.is_none() && start_col + chars_drawn >= trailing_from {
+                bg_color = Some(theme::current().trailing_whitespace_bg);
+            }
+            if stream_point == Some(char_idx) {
+                bg_color = Some(theme::current().typing_cursor);
+            }
 
-            for _ in 0..width {
-                render_state.set_cell(
-                    col,
-                    (screen_row - viewport_start) as usize,
-                    ' ',
-                    fg_color,
-                    bg_color,
-                );
-                col += 1;
-                displayed_width += 1;
-            }
-
-            if ch != '\t' {
-                // Overwrite last space with actual char
-                let x = col - width;
-                for i in 0..width {
-                    render_state.set_cell(
-                        x + i,
-                        (screen_row - viewport_start) as usize,
-                        ch,
-                        fg_color,
-                        bg_color,
-                    );
+            let row = screen_row - viewport_start + top;
+            if ch == '\t' {
+                for x in col..col + width {
+                    render_state.set_cell(x, row, ' ', fg_color, bg_color.or(row_bg(x)));
                 }
+            } else {
+                render_state.set_cell(col, row, ch, fg_color, bg_color.or(row_bg(col)));
             }
+            col += width;
+            displayed_width += width;
 
             chars_drawn += 1;
         }
 
+        // Typing cursor after the last char of a streamed answer
+        if start_col + chars_drawn == line_chars.len()
+            && stream_point == Some(editor.char_idx_from_position(logical_line, line_chars.len()))
+            && col < render_state.term_width as usize
+        {
+            let row = screen_row - viewport_start + top;
+            let bg = Some(theme::current().typing_cursor);
+            render_state.set_cell(col, row, ' ', Color::Reset, bg);
+            col += 1;
+        }
+
+        if let Some(ghost) = ghost.as_ref().filter(|_| logical_line == cursor_row) {
+            if start_col + chars_drawn == line_chars.len() {
+                let colors = theme::current().ghost_text;
+                let row = screen_row - viewport_start + top;
+                for ch in ghost.chars() {
+                    let width = char_width(ch);
+                    if col + width > render_state.term_width as usize {
+                        break;
+                    }
+                    render_state.set_cell(col, row, ch, colors.fg, colors.bg.or(row_bg(col)));
+                    col += width;
+                }
+            }
+        }
+
+        // Diagnostic message after the end of the line
+        if start_col + chars_drawn >= line_chars.len() {
+            if let Some(message) = editor.language_server.diagnostic_on_line(logical_line) {
+                let (fg, _) = style_colors(Style::Error);
+                let row = screen_row - viewport_start + top;
+                for ch in format!("  ■ {}", message).chars() {
+                    if col >= render_state.term_width as usize {
+                        break;
+                    }
+                    render_state.set_cell(col, row, ch, fg, row_bg(col));
+                    col += 1;
+                }
+            }
+        }
+
         // Fill end of line with spaces
         while col < render_state.term_width as usize {
             render_state.set_cell(
                 col,
-                (screen_row - viewport_start) as usize,
+                screen_row - viewport_start + top,
                 ' ',
                 Color::Reset,
-                None,
+                row_bg(col),
             );
             col += 1;
         }
@@ -412,13 +1029,44 @@ fn draw_content_to_buffer(editor: &mut Editor, render_state: &mut RenderState) -
     // Clear leftover lines if any
     for row in (viewport_end - viewport_start)..viewport_height {
         for x in 0..render_state.term_width as usize {
-            render_state.set_cell(x, row as usize, ' ', Color::Reset, None);
+            render_state.set_cell(x, row + top, ' ', Color::Reset, None);
         }
     }
 
+    if config::get().scrollbar {
+        draw_scrollbar_to_buffer(render_state, top, viewport_height);
+    }
+
     Ok(())
 }
 
+/// Thumb in the rightmost column, sized and placed like the viewport
+/// within all wrapped lines. Nothing is shown when everything fits.
+fn draw_scrollbar_to_buffer(render_state: &mut RenderState, top: usize, viewport_height: usize) {
+    let total = render_state.wrapped_lines_info.len();
+    let x = (render_state.term_width as usize).saturating_sub(1);
+    if total <= viewport_height || viewport_height == 0 {
+        return;
+    }
+
+    let thumb_height = (viewport_height * viewport_height / total).max(1);
+    let max_start = viewport_height - thumb_height;
+    let max_scroll = total - viewport_height;
+    // Rounded, so the thumb reaches the bottom exactly at the end
+    let thumb_start =
+        (render_state.scroll_offset.min(max_scroll) * max_start + max_scroll / 2) / max_scroll;
+
+    let theme = theme::current();
+    for row in 0..viewport_height {
+        let (ch, fg) = if (thumb_start..thumb_start + thumb_height).contains(&row) {
+            ('┃', theme.scrollbar.fg)
+        } else {
+            ('│', theme.line_number.fg)
+        };
+        render_state.set_cell(x, row + top, ch, fg, None);
+    }
+}
+
 // fn draw_help_popup_to_buffer(render_state: &mut RenderState, commands: Vec<String>) -> Result<()> {
 //     let max_line_length = commands.iter().map(|line| line.len()).max().unwrap_or(0);
 
@@ -479,11 +1127,304 @@ fn draw_content_to_buffer(editor: &mut Editor, render_state: &mut RenderState) -
 //     Ok(())
 // }
 
+/// Separator and read-only transcript next to the prompt pane in split view:
+/// below it, or above the input box of the chat layout
+fn draw_transcript_to_buffer(editor: &mut Editor, render_state: &mut RenderState) -> Result<()> {
+    let separator_row = separator_row(editor, render_state);
+    let (top, height) = match editor.split_view().map(|split| split.layout()) {
+        Some(Layout::Chat) => (TAB_BAR_HEIGHT, separator_row - TAB_BAR_HEIGHT),
+        _ => (
+            separator_row + 1,
+            content_height(render_state).saturating_sub(separator_row - TAB_BAR_HEIGHT + 1),
+        ),
+    };
+    let width = render_state.term_width as usize;
+    let theme = theme::current();
+
+    let Some(split) = editor.split_view_mut() else {
+        return Ok(());
+    };
+
+    // Separator, highlighted while the transcript has focus
+    let focused = split.focus() == Pane::Transcript;
+    let label = if focused {
+        " Conversation  j/k: Scroll  G: Follow  Tab: Prompt ".to_string()
+    } else {
+        " Conversation  Tab: Focus ".to_string()
+    };
+    let (fg, bg) = if focused {
+        (theme.status.fg, theme.status.bg)
+    } else {
+        (theme.line_number.fg, theme.line_number.bg)
+    };
+    let mut label_chars = label.chars();
+    for x in 0..width {
+        let ch = if x < 2 { '─' } else { label_chars.next().unwrap_or('─') };
+        render_state.set_cell(x, separator_row, ch, fg, bg);
+    }
+
+    // Wrap every line to the pane width: (line, first char, char count)
+    let transcript = split.transcript();
+    let mut rows = Vec::new();
+    for line in 0..transcript.len_lines() {
+        let chars: Vec<char> = transcript.line(line).chars().filter(|c| *c != '\n').collect();
+        let mut start = 0;
+        loop {
+            // At least one char per row, even one wider than the pane
+            let mut cells = 0;
+            let mut count = 0;
+            while start + count < chars.len() {
+                let w = char_width(chars[start + count]);
+                if count > 0 && cells + w > width {
+                    break;
+                }
+                cells += w;
+                count += 1;
+            }
+            rows.push((line, start, count));
+            start += count;
+            if start >= chars.len() {
+                break;
+            }
+        }
+    }
+
+    split.clamp_scroll(rows.len().saturating_sub(height));
+    let scroll = split.scroll();
+    let stream_point = editor.stream_point();
+    let split = editor.split_view().expect("split view checked above");
+    let transcript = split.transcript();
+
+    let mut styles: Option<(usize, Vec<Style>)> = None;
+    for y in 0..height {
+        let screen_row = top + y;
+        let Some(&(line, start, count)) = rows.get(scroll + y) else {
+            for x in 0..width {
+                render_state.set_cell(x, screen_row, ' ', Color::Reset, None);
+            }
+            continue;
+        };
+
+        // Styles are computed once per line, not per wrapped row
+        if styles.as_ref().map(|(l, _)| *l) != Some(line) {
+            styles = Some((line, split.line_styles(line)));
+        }
+        let line_styles = &styles.as_ref().expect("set above").1;
+
+        let line_start = transcript.line_to_char(line);
+        let chars = transcript.line(line).chars().skip(start).take(count);
+        let mut x = 0;
+        for (i, ch) in chars.enumerate() {
+            let style = line_styles.get(start + i).copied().unwrap_or(Style::Normal);
+            let (fg, mut bg) = style_colors(style);
+            if bg.is_none() && split.protected().contains(line_start + start + i) {
+                bg = Some(theme::current().answer_bg);
+            }
+            let ch = if ch == '\t' { ' ' } else { ch };
+            render_state.set_cell(x, screen_row, ch, fg, bg);
+            x += char_width(ch);
+        }
+        // Typing cursor at the end of a streamed answer
+        if stream_point == Some(line_start + start + count) && x < width {
+            render_state.set_cell(x, screen_row, ' ', Color::Reset, Some(theme.typing_cursor));
+            x += 1;
+        }
+        while x < width {
+            render_state.set_cell(x, screen_row, ' ', Color::Reset, None);
+            x += 1;
+        }
+    }
+
+    Ok(())
+}
+
+/// Fuzzy finder popup of the Load and Attach pickers. `context` goes
+/// below the list, about the selected item.
+fn draw_finder_to_buffer(
+    editor: &Editor,
+    render_state: &mut RenderState,
+    title: &str,
+    hint: &str,
+    items: &[String],
+    context: &[String],
+) -> Result<()> {
+    let menu = &editor.menu_status;
+    let mut title = match menu.get_file_picker_breadcrumb() {
+        Some(breadcrumb) => format!("{}: {}", title, breadcrumb),
+        None => title.to_string(),
+    };
+    if !hint.is_empty() {
+        title.push_str(&format!(" ({})", hint));
+    }
+
+    menus::draw_finder_popup_to_buffer(
+        render_state,
+        &title,
+        &menu.get_file_picker_input(),
+        menu.get_file_picker_cursor_position(),
+        items,
+        menu.file_picker_selected_index(),
+        context,
+    )
+}
+
+/// One line per queued, running or recently finished AI request
+/// Returns the row below the panel
+fn draw_request_panel_to_buffer(
+    editor: &Editor,
+    render_state: &mut RenderState,
+    start_y: usize,
+) -> Result<usize> {
+    let requests = editor.requests();
+    let lines: Vec<String> = if requests.is_empty() {
+        vec!["No requests".to_string()]
+    } else {
+        requests
+            .iter()
+            .map(|request| {
+                let status = match &request.status {
+                    RequestStatus::Queued => "queued".to_string(),
+                    RequestStatus::Running => "running".to_string(),
+                    RequestStatus::Done => "done".to_string(),
+                    RequestStatus::Cancelled => "cancelled".to_string(),
+                    RequestStatus::Error(e) => format!("error: {}", e),
+                };
+                let line = format!("#{} {} {}", request.id, request.model, status);
+                // Long error messages would cover the whole screen
+                line.chars().take(60).collect()
+            })
+            .collect()
+    };
+
+    menus::draw_panel_to_buffer(render_state, "Requests", &lines, start_y)?;
+    Ok(start_y + lines.len() + 2)
+}
+
+/// First line of every pin, numbered for Alt+1..9
+fn draw_pins_panel_to_buffer(editor: &Editor, render_state: &mut RenderState, start_y: usize) -> Result<()> {
+    let pins = editor.pins();
+    let lines: Vec<String> = if pins.is_empty() {
+        vec!["Nothing pinned (AI menu: P)".to_string()]
+    } else {
+        pins.iter()
+            .enumerate()
+            .map(|(i, pin)| {
+                let number = if i < 9 { format!("{}", i + 1) } else { " ".to_string() };
+                // Code pins start with their fence
+                let first_line = pin
+                    .lines()
+                    .find(|line| !line.trim().is_empty() && !line.starts_with("```"))
+                    .unwrap_or("");
+                format!("{} {}", number, first_line.chars().take(40).collect::<String>())
+            })
+            .collect()
+    };
+
+    menus::draw_panel_to_buffer(render_state, "Pinned", &lines, start_y)
+}
+
+/// Rendered markdown of the buffer over the whole content area while the
+/// preview is open
+fn draw_preview_to_buffer(editor: &mut Editor, render_state: &mut RenderState) -> Result<()> {
+    if editor.preview_mut().is_none() {
+        return Ok(());
+    }
+
+    let width = render_state.term_width as usize;
+    let height = content_height(render_state);
+    // A blank column on either side
+    let rows = markdown::layout(&editor.get_content(), width.saturating_sub(2));
+    let sources: Vec<usize> = rows.iter().map(|row| row.line).collect();
+    let scroll = match editor.preview_mut() {
+        Some(preview) => preview.scroll(&sources, height),
+        None => return Ok(()),
+    };
+
+    // Highlighter styles of the code line last drawn
+    let mut code_styles: Option<(usize, Vec<Style>)> = None;
+    for y in 0..height {
+        let screen_row = TAB_BAR_HEIGHT + y;
+        let mut x = 0;
+        render_state.set_cell(x, screen_row, ' ', Color::Reset, None);
+        x += 1;
+
+        for cell in rows.get(scroll + y).map_or(&[][..], |row| &row.cells[..]) {
+            let style = match cell.source {
+                Some(col) => {
+                    let line = sources[scroll + y];
+                    if code_styles.as_ref().map(|(cached, _)| *cached) != Some(line) {
+                        code_styles = Some((line, editor.syntax_styles(line)));
+                    }
+                    code_styles
+                        .as_ref()
+                        .and_then(|(_, styles)| styles.get(col).copied())
+                        .unwrap_or(Style::Normal)
+                }
+                None => cell.style,
+            };
+            let cell_width = char_width(cell.ch);
+            if x + cell_width > width {
+                break;
+            }
+            let (fg, bg) = style_colors(style);
+            render_state.set_cell(x, screen_row, cell.ch, fg, bg);
+            x += cell_width;
+        }
+
+        while x < width {
+            render_state.set_cell(x, screen_row, ' ', Color::Reset, None);
+            x += 1;
+        }
+    }
+
+    Ok(())
+}
+
+/// Foreground and optional background for a style, from the active theme
+fn style_colors(style: Style) -> (Color, Option<Color>) {
+    theme::current().style(style)
+}
+
+fn draw_tab_bar_to_buffer(editor: &Editor, render_state: &mut RenderState) -> Result<()> {
+    let term_width = render_state.term_width as usize;
+    let active = editor.active_buffer_index();
+    let theme = theme::current();
+
+    let mut x = 0;
+    for (i, (name, modified)) in editor.buffer_tabs().iter().enumerate() {
+        let label = format!(" {}{} ", name, if *modified { " [+]" } else { "" });
+        let (fg, bg) = if i == active {
+            (theme.tab_active.fg, theme.tab_active.bg)
+        } else {
+            (theme.tab.fg, theme.tab.bg)
+        };
+
+        for ch in label.chars() {
+            if x >= term_width {
+                break;
+            }
+            render_state.set_cell(x, 0, ch, fg, bg);
+            x += 1;
+        }
+    }
+
+    // Fill the rest of the row
+    while x < term_width {
+        render_state.set_cell(x, 0, ' ', theme.tab.fg, theme.tab.bg);
+        x += 1;
+    }
+
+    Ok(())
+}
+
 fn draw_status_line_to_buffer(editor: &Editor, render_state: &mut RenderState) -> Result<()> {
     let row = render_state.term_height as usize - 2;
 
-    // Filename or [No Name]
-    let filename = editor.get_file_name().unwrap_or("[No Name]");
+    // Session title, filename or [No Name]
+    let filename = editor
+        .session_title()
+        .or(editor.get_file_name())
+        .unwrap_or("[No Name]");
     let modified_indicator = if editor.is_modified() { " [+] " } else { " " };
 
     // Mode indicator
@@ -498,8 +1439,83 @@ fn draw_status_line_to_buffer(editor: &Editor, render_state: &mut RenderState) -
     let (cursor_row, cursor_col) = editor.get_cursor_position();
 
     // Format the status line
-    let left_status = format!("{}{} - {} ", filename, modified_indicator, mode);
-    let right_status = format!("  {}:{}  ", cursor_row + 1, cursor_col + 1);
+    let mut left_status = format!("{}{} - {} ", filename, modified_indicator, mode);
+
+    if !render_state.soft_wrap {
+        left_status.push_str("| nowrap ");
+    }
+    if editor.insertion() != Insertion::End {
+        left_status.push_str(&format!("| answers: {} ", editor.insertion().label()));
+    }
+    if editor.protected.is_unlocked() {
+        left_status.push_str("| answers unlocked ");
+    }
+    if editor.project.is_enabled() {
+        left_status.push_str("| project ");
+    }
+    // Messages the last request left out to fit the context window
+    if let Some((strategy, tokens)) = editor.context_trimmed() {
+        let how = match strategy {
+            ContextTrim::Drop => "dropped",
+            ContextTrim::Summarize => "summarized",
+        };
+        left_status.push_str(&format!("| ~{} tokens {} ", tokens, how));
+    }
+
+    // Register picked with `"` for the next yank/delete/paste
+    if editor.registers.is_selecting() {
+        left_status.push_str("| register \"_ ");
+    } else if let Some(name) = editor.registers.selected() {
+        left_status.push_str(&format!("| register \"{} ", name));
+    }
+    if let Some(keys) = editor.pending_text_object() {
+        left_status.push_str(&format!("| {}… ", keys));
+    }
+    if editor.is_previewing() {
+        left_status.push_str("| preview ");
+    }
+    if editor.is_read_only() {
+        left_status.push_str("| read-only ");
+    }
+    if editor.is_scratch() {
+        left_status.push_str("| scratch, not saved ");
+    }
+    if let Some(name) = editor.macros.recording() {
+        left_status.push_str(&format!("| recording @{} ", name));
+    }
+    if let Some(pull) = editor.pull_progress() {
+        left_status.push_str(&format!("| {} ", pull));
+    }
+    if let Some(load) = editor.load_progress() {
+        left_status.push_str(&format!("| {} ", load));
+    }
+    if let Some(run) = editor.shell_progress() {
+        left_status.push_str(&format!("| {} ", run));
+    }
+
+    // Token estimate, flagged when the next request would be truncated
+    let (tokens, limit) = editor.token_usage();
+    let over_limit = if tokens > limit { " (truncated)" } else { "" };
+
+    // Size of the selection, to judge it before sending
+    let selection = match editor.selection_stats() {
+        Some((lines, words, chars, tokens)) => format!(
+            "  sel {} lines, {} words, {} chars, ~{} tokens |",
+            lines, words, chars, tokens
+        ),
+        None => String::new(),
+    };
+    let right_status = format!(
+        "{}  ~{} / {} tokens{}  {} {}  {}:{}  ",
+        selection,
+        tokens,
+        limit,
+        over_limit,
+        editor.encoding().label(),
+        editor.line_ending().label(),
+        cursor_row + 1,
+        cursor_col + 1
+    );
 
     let term_width = render_state.term_width as usize;
 
@@ -515,17 +1531,19 @@ fn draw_status_line_to_buffer(editor: &Editor, render_state: &mut RenderState) -
         right_status
     );
 
+    let theme = theme::current();
+
     // Fill the entire status line
     for (x, ch) in status_line.chars().enumerate() {
         if x >= render_state.term_width as usize {
             break;
         }
-        render_state.set_cell(x, row, ch, Color::Black, Some(Color::White));
+        render_state.set_cell(x, row, ch, theme.status.fg, theme.status.bg);
     }
 
     // Fill any remaining space
     for x in status_line.len()..render_state.term_width as usize {
-        render_state.set_cell(x, row, ' ', Color::Black, Some(Color::White));
+        render_state.set_cell(x, row, ' ', theme.status.fg, theme.status.bg);
     }
 
     if editor.is_waiting_for_command() {
@@ -543,19 +1561,20 @@ fn draw_message_line_to_buffer(editor: &Editor, render_state: &mut RenderState)
 
     // Help message based on mode
     let help_msg = match editor.get_mode() {
-        Mode::Normal => "^Q: Quit | i: Insert | v: Select | s: Save | y: Copy selection",
+        Mode::Normal => "^Q: Quit | i: Insert | v: Select | s: Save | p: Paste | a: AI menu",
         Mode::Insert => "ESC: Normal mode | Arrow keys: Navigate",
         Mode::Select => {
             "ESC: Normal mode | Arrow keys: Extend selection | y: Copy and exit selection | d: Delete"
         }
     };
+    let hint = theme::current().hint;
 
     // Fill message line
     for (x, ch) in help_msg.chars().enumerate() {
         if x >= render_state.term_width as usize {
             break;
         }
-        render_state.set_cell(x, row, ch, Color::DarkGrey, None);
+        render_state.set_cell(x, row, ch, hint.fg, hint.bg);
     }
 
     // Clear any remaining part of the line
@@ -575,11 +1594,50 @@ fn draw_request_state_line_to_buffer(
 ) -> Result<()> {
     let row = render_state.term_height as usize - 1;
 
+    // A message takes the line until it expires
+    if let Some(message) = editor.current_message() {
+        let colors = match message.level {
+            Level::Info => theme::current().message_info,
+            Level::Warning => theme::current().message_warning,
+            Level::Error => theme::current().message_error,
+        };
+        let width = render_state.term_width as usize;
+        let text: Vec<char> = message.text.chars().collect();
+        for x in 0..width {
+            match text.get(x) {
+                Some(&ch) => render_state.set_cell(x, row, ch, colors.fg, colors.bg),
+                None => render_state.set_cell(x, row, ' ', Color::Reset, None),
+            }
+        }
+        return Ok(());
+    }
+
     // Help message based on mode
     let help_msg = match editor.get_request_state() {
-        RequestState::Idle => format!("Request Status: {}", "Idle"),
+        RequestState::Idle => {
+            let attached = editor.attached_files();
+            if attached.is_empty() {
+                format!("Request Status: {} | Model: {}", "Idle", editor.model_label())
+            } else {
+                format!(
+                    "Request Status: {} | Model: {} | Attached: {}",
+                    "Idle",
+                    editor.model_label(),
+                    attached.join(", ")
+                )
+            }
+        }
         //TODO PROVIDER
-        RequestState::Proccessing => format!("Request Status: {}", "In Progress"),
+        RequestState::Proccessing => match editor.slow_request() {
+            Some((waited, timeout)) => format!(
+                "Request Status: Slow, no answer after {}s of {}s (Esc to cancel)",
+                waited, timeout
+            ),
+            None => format!("Request Status: {}", "In Progress (Esc to cancel)"),
+        },
+        RequestState::TimedOut(secs) => {
+            format!("Request Status: Timed out after {}s (AI menu: R to retry)", secs)
+        }
         RequestState::Error(e) => {
             let msg = format!("Request Status: Error: {}", e);
             msg
@@ -587,13 +1645,14 @@ fn draw_request_state_line_to_buffer(
     };
 
     // let menu = editor.get_menu_state();
+    let message = theme::current().message;
 
     // Fill message line
     for (x, ch) in help_msg.chars().enumerate() {
         if x >= render_state.term_width as usize {
             break;
         }
-        render_state.set_cell(x, row, ch, Color::White, None);
+        render_state.set_cell(x, row, ch, message.fg, message.bg);
     }
 
     // Clear any remaining part of the line
@@ -617,7 +1676,6 @@ fn render_buffer_changes(render_state: &mut RenderState) -> Result<()> {
 
         while current_x < render_state.term_width as usize {
             // If this cell hasn't changed, skip it
-            // if !render_state.force_full_redraw && !render_state.cell_changed(current_x, y) {
             if !render_state.cell_changed(current_x, y) {
                 current_x += 1;
                 continue;
@@ -659,10 +1717,13 @@ fn render_buffer_changes(render_state: &mut RenderState) -> Result<()> {
                 current_bg = cell_bg;
             }
 
-            // Output the changed text
+            // Output the changed text, wide chars cover their tail cell
             let mut text = String::with_capacity(end_x - start_x);
             for x in start_x..end_x {
-                text.push(render_state.current_buffer[y][x].0);
+                let ch = render_state.current_buffer[y][x].0;
+                if ch != WIDE_TAIL {
+                    text.push(ch);
+                }
             }
             stdout.queue(Print(text))?;
 
@@ -682,7 +1743,41 @@ fn render_buffer_changes(render_state: &mut RenderState) -> Result<()> {
 
 fn adjust_scroll(editor: &Editor, render_state: &mut RenderState) {
     let (cursor_row, cursor_col) = editor.get_cursor_position();
-    let viewport_height = render_state.term_height as usize - 2; // Space for status/message lines
+    let viewport_height = prompt_pane_height(editor, render_state);
+
+    if !render_state.soft_wrap {
+        // Keep the cursor column in view, counting cells rather than chars
+        let max_line_width = render_state.max_line_width().max(1);
+        if cursor_col < render_state.h_scroll {
+            render_state.h_scroll = cursor_col;
+        }
+        let line: Vec<char> = editor.line_text(cursor_row).chars().collect();
+        let cursor_col = cursor_col.min(line.len());
+        while render_state.h_scroll < cursor_col
+            && display_width(line[render_state.h_scroll..cursor_col].iter().copied()) + 1
+                > max_line_width
+        {
+            render_state.h_scroll += 1;
+        }
+
+        // One row per line, less the lines hidden in folds above
+        let mut hidden = 0;
+        let mut covered = 0;
+        for fold in editor.folded() {
+            // Folds inside a counted one are hidden with it
+            if fold.last < cursor_row && fold.header >= covered {
+                hidden += fold.last - fold.header;
+                covered = fold.last + 1;
+            }
+        }
+        let cursor_row = cursor_row - hidden;
+        if cursor_row < render_state.scroll_offset {
+            render_state.scroll_offset = cursor_row;
+        } else if cursor_row >= render_state.scroll_offset + viewport_height {
+            render_state.scroll_offset = cursor_row + 1 - viewport_height;
+        }
+        return;
+    }
 
     // Find which visual line contains the cursor position
     // Find the visual line containing the cursor:
@@ -789,20 +1884,7 @@ fn draw_content(
             let style = editor.get_style_for_position(actual_row, actual_char_idx);
 
             // Set color based on style
-            let (fg_color, bg_color) = match style {
-                Style::Normal => (Color::White, None),
-                Style::Keyword => (Color::Magenta, None),
-                Style::Function => (Color::Blue, None),
-                Style::Type => (Color::Cyan, None),
-                Style::String => (Color::Green, None),
-                Style::Number => (Color::Yellow, None),
-                Style::Comment => (Color::DarkGrey, None),
-                Style::Variable => (Color::White, None),
-                Style::Constant => (Color::Yellow, None),
-                Style::Operator => (Color::White, None),
-                Style::Selection => (Color::Black, Some(Color::Grey)),
-                Style::Error => (Color::Red, Some(Color::White)),
-            };
+            let (fg_color, bg_color) = style_colors(style);
 
             stdout.queue(SetForegroundColor(fg_color))?;
             if let Some(bg) = bg_color {
@@ -0,0 +1,76 @@
+use std::collections::HashMap;
+
+use super::cell_width;
+
+/// Row starts of wrapped lines, kept between frames and looked up by the
+/// line's text. Only lines that are new or were edited get wrapped again;
+/// lines moved by an edit above them are still found.
+#[derive(Debug, Default)]
+pub(super) struct WrapCache {
+    width: usize,
+    // Line text -> (frame it was last used in, start column of each row)
+    rows: HashMap<String, (u64, Vec<usize>)>,
+    frame: u64,
+}
+
+impl WrapCache {
+    /// Starts a frame wrapping to `width` cells. A new width wraps
+    /// everything again.
+    pub(super) fn begin(&mut self, width: usize) {
+        if width != self.width {
+            self.rows.clear();
+            self.width = width;
+        }
+        self.frame += 1;
+    }
+
+    /// Start columns of the rows `line` wraps into
+    pub(super) fn rows(&mut self, line: &str) -> &[usize] {
+        let frame = self.frame;
+        if let Some(entry) = self.rows.get_mut(line) {
+            entry.0 = frame;
+        } else {
+            let starts = wrap(line, self.width);
+            self.rows.insert(line.to_string(), (frame, starts));
+        }
+        &self.rows[line].1
+    }
+
+    /// Drops lines that weren't drawn this frame once they pile up, e.g.
+    /// after typing along a line left every prefix of it behind
+    pub(super) fn end(&mut self, lines: usize) {
+        if self.rows.len() > 2 * lines + 64 {
+            let frame = self.frame;
+            self.rows.retain(|_, (used, _)| *used == frame);
+        }
+    }
+}
+
+// At least one char per row, even one wider than the whole row, and one
+// row for an empty line
+fn wrap(line: &str, width: usize) -> Vec<usize> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut starts = Vec::new();
+    let mut start = 0;
+
+    loop {
+        starts.push(start);
+
+        let mut displayed_width = 0;
+        let mut count = 0;
+        while start + count < chars.len() {
+            let width_here = cell_width(chars[start + count], displayed_width);
+            if displayed_width + width_here > width {
+                break;
+            }
+            displayed_width += width_here;
+            count += 1;
+        }
+
+        start += count.max(1);
+        if start >= chars.len() {
+            break;
+        }
+    }
+    starts
+}
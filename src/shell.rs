@@ -0,0 +1,49 @@
+use std::io;
+use std::process::{Command, Output};
+
+use tokio_util::sync::CancellationToken;
+
+/// Runs `command` through the user's shell and returns stdout followed by
+/// stderr, with the exit status when it failed. Always ends in a newline.
+/// The command is killed once `cancel` fires, `None` then.
+pub async fn run(command: &str, cancel: &CancellationToken) -> Option<String> {
+    let mut child = tokio::process::Command::from(shell(command));
+    child.kill_on_drop(true);
+
+    tokio::select! {
+        output = child.output() => Some(output_text(output)),
+        _ = cancel.cancelled() => None,
+    }
+}
+
+fn shell(command: &str) -> Command {
+    let (program, flag) = if cfg!(windows) {
+        ("cmd".to_string(), "/C")
+    } else {
+        (std::env::var("SHELL").unwrap_or_else(|_| "sh".to_string()), "-c")
+    };
+    let mut shell = Command::new(program);
+    shell.args([flag, command]);
+    shell
+}
+
+fn output_text(output: io::Result<Output>) -> String {
+    let mut result = match output {
+        Ok(output) => {
+            let mut text = String::from_utf8_lossy(&output.stdout).into_owned();
+            text.push_str(&String::from_utf8_lossy(&output.stderr));
+            if !output.status.success() {
+                if !text.is_empty() && !text.ends_with('\n') {
+                    text.push('\n');
+                }
+                text.push_str(&format!("[{}]", output.status));
+            }
+            text
+        }
+        Err(e) => format!("Failed to run command: {}", e),
+    };
+    if !result.ends_with('\n') {
+        result.push('\n');
+    }
+    result
+}
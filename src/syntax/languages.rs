@@ -0,0 +1,152 @@
+use tree_sitter_language::LanguageFn;
+
+/// A grammar and the fence tags / extensions it's used for
+pub struct Grammar {
+    pub names: &'static [&'static str],
+    pub language: LanguageFn,
+    pub highlights: String,
+}
+
+impl Grammar {
+    fn new(names: &'static [&'static str], language: LanguageFn, highlights: &str) -> Self {
+        Self {
+            names,
+            language,
+            highlights: highlights.to_string(),
+        }
+    }
+}
+
+/// Every grammar compiled in. Besides Rust each one sits behind a
+/// `lang-*` cargo feature.
+pub fn grammars() -> Vec<Grammar> {
+    // Stays untouched when built without any `lang-*` feature
+    #[allow(unused_mut)]
+    let mut grammars = vec![Grammar::new(
+        &["rust", "rs"],
+        tree_sitter_rust::LANGUAGE,
+        tree_sitter_rust::HIGHLIGHTS_QUERY,
+    )];
+
+    #[cfg(feature = "lang-go")]
+    grammars.push(Grammar::new(
+        &["go", "golang"],
+        tree_sitter_go::LANGUAGE,
+        tree_sitter_go::HIGHLIGHTS_QUERY,
+    ));
+
+    #[cfg(feature = "lang-python")]
+    grammars.push(Grammar::new(
+        &["python", "py"],
+        tree_sitter_python::LANGUAGE,
+        tree_sitter_python::HIGHLIGHTS_QUERY,
+    ));
+
+    #[cfg(feature = "lang-javascript")]
+    grammars.push(Grammar::new(
+        &["javascript", "js", "jsx"],
+        tree_sitter_javascript::LANGUAGE,
+        tree_sitter_javascript::HIGHLIGHT_QUERY,
+    ));
+
+    // The TypeScript query only covers what it adds on top of JavaScript
+    #[cfg(feature = "lang-typescript")]
+    grammars.push(Grammar::new(
+        &["typescript", "ts"],
+        tree_sitter_typescript::LANGUAGE_TYPESCRIPT,
+        &format!(
+            "{}\n{}",
+            tree_sitter_typescript::HIGHLIGHTS_QUERY,
+            tree_sitter_javascript::HIGHLIGHT_QUERY
+        ),
+    ));
+
+    #[cfg(feature = "lang-typescript")]
+    grammars.push(Grammar::new(
+        &["tsx"],
+        tree_sitter_typescript::LANGUAGE_TSX,
+        &format!(
+            "{}\n{}\n{}",
+            tree_sitter_typescript::HIGHLIGHTS_QUERY,
+            tree_sitter_javascript::HIGHLIGHT_QUERY,
+            tree_sitter_javascript::JSX_HIGHLIGHT_QUERY
+        ),
+    ));
+
+    #[cfg(feature = "lang-json")]
+    grammars.push(Grammar::new(
+        &["json"],
+        tree_sitter_json::LANGUAGE,
+        tree_sitter_json::HIGHLIGHTS_QUERY,
+    ));
+
+    #[cfg(feature = "lang-toml")]
+    grammars.push(Grammar::new(
+        &["toml"],
+        tree_sitter_toml_ng::LANGUAGE,
+        tree_sitter_toml_ng::HIGHLIGHTS_QUERY,
+    ));
+
+    grammars
+}
+
+/// Extension for files holding code of a fence tag. Unknown tags that look
+/// like an extension already are used as is.
+pub fn extension(language: &str) -> &str {
+    match language {
+        "" | "text" | "plaintext" => "txt",
+        "rust" => "rs",
+        "golang" => "go",
+        "python" => "py",
+        "javascript" => "js",
+        "typescript" => "ts",
+        "bash" | "shell" | "zsh" => "sh",
+        "markdown" => "md",
+        "yaml" => "yml",
+        "c++" => "cpp",
+        "csharp" | "c#" => "cs",
+        "ruby" => "rb",
+        "kotlin" => "kt",
+        _ if language.chars().all(|c| c.is_ascii_alphanumeric()) => language,
+        _ => "txt",
+    }
+}
+
+/// How code of a fence tag is commented out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommentStyle {
+    /// Marker at the start of each line, e.g. `//`
+    Line(&'static str),
+    /// Markers around each line, e.g. `<!--` and `-->`
+    Block(&'static str, &'static str),
+}
+
+/// Comment syntax of a fence tag, `None` for plain text and unknown tags
+pub fn comment_style(language: &str) -> Option<CommentStyle> {
+    let style = match extension(&language.to_ascii_lowercase()) {
+        "rs" | "go" | "js" | "jsx" | "ts" | "tsx" | "c" | "h" | "cpp" | "cc" | "hpp" | "cs"
+        | "java" | "kt" | "swift" | "scala" | "dart" | "zig" | "php" | "proto" | "jsonc" => {
+            CommentStyle::Line("//")
+        }
+        "py" | "sh" | "fish" | "rb" | "toml" | "yml" | "r" | "pl" | "ex" | "exs" | "nim"
+        | "dockerfile" | "makefile" | "make" | "cmake" | "ini" | "conf" | "nix" | "ps1" => {
+            CommentStyle::Line("#")
+        }
+        "sql" | "lua" | "hs" | "elm" => CommentStyle::Line("--"),
+        "lisp" | "clj" | "el" | "scm" | "asm" => CommentStyle::Line(";"),
+        "tex" | "erl" | "m" => CommentStyle::Line("%"),
+        "vim" => CommentStyle::Line("\""),
+        "html" | "xml" | "svg" | "vue" | "svelte" | "md" => CommentStyle::Block("<!--", "-->"),
+        "css" | "scss" | "less" => CommentStyle::Block("/*", "*/"),
+        _ => return None,
+    };
+    Some(style)
+}
+
+/// Characters that open a deeper level at the end of a line
+pub fn indent_after(language: &str) -> &'static [char] {
+    match extension(&language.to_ascii_lowercase()) {
+        "py" | "yml" | "nim" => &['(', '[', '{', ':'],
+        _ => &['(', '[', '{'],
+    }
+}
@@ -0,0 +1,90 @@
+use regex::Regex;
+use std::ops::Range;
+
+use super::Style;
+
+/// Line based markdown rules for the text around fenced code blocks
+pub struct MarkdownRules {
+    heading: Regex,
+    quote: Regex,
+    bullet: Regex,
+    // Inline spans, in priority order: earlier rules win on overlap
+    inline: Vec<(Regex, Style)>,
+}
+
+impl MarkdownRules {
+    pub fn new() -> Self {
+        Self {
+            heading: Regex::new(r"^#{1,6}\s").unwrap(),
+            quote: Regex::new(r"^\s*>").unwrap(),
+            bullet: Regex::new(r"^\s*([-*+]|\d+[.)])\s").unwrap(),
+            inline: vec![
+                (Regex::new(r"`[^`]+`").unwrap(), Style::InlineCode),
+                (Regex::new(r"\[[^\]]+\]\([^)]*\)").unwrap(), Style::Link),
+                (Regex::new(r"\*\*[^*]+\*\*|__[^_]+__").unwrap(), Style::Bold),
+                (
+                    Regex::new(r"\*[^*\s][^*]*\*|\b_[^_\s][^_]*_\b").unwrap(),
+                    Style::Italic,
+                ),
+            ],
+        }
+    }
+
+    /// Byte ranges of markdown styles, skipping fenced code blocks
+    pub fn highlight(&self, text: &str) -> Vec<(Range<usize>, Style)> {
+        let mut highlights = Vec::new();
+        let mut in_code_block = false;
+        let mut offset = 0;
+
+        for line in text.split('\n') {
+            let line_start = offset;
+            offset += line.len() + 1;
+
+            if line.starts_with("```") {
+                in_code_block = !in_code_block;
+                highlights.push((line_start..line_start + line.len(), Style::Comment));
+                continue;
+            }
+            if in_code_block || line.is_empty() {
+                continue;
+            }
+
+            // Whole-line blocks
+            if self.heading.is_match(line) {
+                highlights.push((line_start..line_start + line.len(), Style::Heading));
+                continue;
+            }
+            if self.quote.is_match(line) {
+                highlights.push((line_start..line_start + line.len(), Style::Quote));
+                continue;
+            }
+
+            let mut taken: Vec<Range<usize>> = Vec::new();
+
+            if let Some(bullet) = self.bullet.captures(line).and_then(|c| c.get(1)) {
+                taken.push(bullet.range());
+                highlights.push((
+                    (line_start + bullet.start())..(line_start + bullet.end()),
+                    Style::ListBullet,
+                ));
+            }
+
+            for (regex, style) in &self.inline {
+                for m in regex.find_iter(line) {
+                    let range = m.range();
+                    if taken
+                        .iter()
+                        .any(|t| t.start < range.end && range.start < t.end)
+                    {
+                        continue;
+                    }
+
+                    highlights.push(((line_start + range.start)..(line_start + range.end), *style));
+                    taken.push(range);
+                }
+            }
+        }
+
+        highlights
+    }
+}
@@ -5,16 +5,40 @@ use std::cell::RefCell;
 use std::collections::HashMap;
 use std::ops::Range;
 use std::path::Path;
-use tree_sitter::{Language, Parser, Query, QueryCursor, StreamingIterator, Tree};
+use tree_sitter::{
+    InputEdit, Language, Parser, Point, Query, QueryCursor, StreamingIterator, Tree,
+};
 use tree_sitter_language::LanguageFn;
 
 pub mod cache;
+mod languages;
+mod markdown;
 
-struct CodeBlock {
-    language: String,
-    start: usize,
-    end: usize,
-    code: String,
+pub use languages::CommentStyle;
+
+/// A fenced code block; `start..end` are the bytes of its code
+pub struct CodeBlock {
+    pub language: String,
+    pub start: usize,
+    pub end: usize,
+    pub code: String,
+}
+
+impl CodeBlock {
+    /// File extension for the block's fence tag, `txt` when there's none
+    pub fn extension(&self) -> &str {
+        languages::extension(&self.language)
+    }
+
+    /// Comment syntax for the fence tag, `None` when it isn't known
+    pub fn comment_style(&self) -> Option<CommentStyle> {
+        languages::comment_style(&self.language)
+    }
+
+    /// Characters that indent the next line when a line ends with one
+    pub fn indent_after(&self) -> &'static [char] {
+        languages::indent_after(&self.language)
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -31,13 +55,88 @@ pub enum Style {
     Operator,
     Error,
     Selection,
+    SearchMatch,
+    // Markdown outside code blocks
+    Heading,
+    Bold,
+    Italic,
+    InlineCode,
+    Quote,
+    ListBullet,
+    Link,
+    // Diff view
+    DiffAdd,
+    DiffRemove,
+}
+
+// Parse result of a code block, kept between highlights so edits can be
+// applied incrementally. Positions are relative to the block's code.
+struct BlockTree {
+    language: String,
+    start: usize,
+    code: String,
+    tree: Tree,
+    highlights: Vec<(Range<usize>, Style)>,
+    // Byte ranges typed since the last parse
+    edited: Vec<Range<usize>>,
+}
+
+impl BlockTree {
+    fn edit(&mut self, start: usize, old_end: usize, text: &str) {
+        let new_end = start + text.len();
+        let start_position = point_at(&self.code, start);
+        let old_end_position = point_at(&self.code, old_end);
+
+        self.code.replace_range(start..old_end, text);
+
+        self.tree.edit(&InputEdit {
+            start_byte: start,
+            old_end_byte: old_end,
+            new_end_byte: new_end,
+            start_position,
+            old_end_position,
+            new_end_position: point_at(&self.code, new_end),
+        });
+
+        // Highlights touching the edit are redone, the ones after it move
+        self.highlights.retain_mut(|(range, _)| {
+            if range.end <= start {
+                true
+            } else if range.start >= old_end {
+                *range = (range.start + new_end - old_end)..(range.end + new_end - old_end);
+                true
+            } else {
+                false
+            }
+        });
+
+        // Earlier edits move the same way, the ones it overlaps merge into it
+        let mut typed = start..new_end;
+        self.edited.retain_mut(|range| {
+            if range.end < start {
+                true
+            } else if range.start > old_end {
+                *range = (range.start + new_end - old_end)..(range.end + new_end - old_end);
+                true
+            } else {
+                typed.start = typed.start.min(range.start);
+                if range.end > old_end {
+                    typed.end = typed.end.max(range.end + new_end - old_end);
+                }
+                false
+            }
+        });
+        self.edited.push(typed);
+    }
 }
 
 pub struct SyntaxHighlighter {
     parser: RefCell<Parser>,
+    blocks: RefCell<Vec<BlockTree>>,
     languages: HashMap<String, LanguageFn>,
     queries: HashMap<Language, Query>,
     md_code_block_regex: Regex,
+    markdown: markdown::MarkdownRules,
 }
 
 impl SyntaxHighlighter {
@@ -48,24 +147,25 @@ impl SyntaxHighlighter {
         let mut languages = HashMap::new();
         let mut queries = HashMap::new();
 
-        // Register Rust language
-        let rust_language = tree_sitter_rust::LANGUAGE;
-        languages.insert("rust".to_string(), rust_language);
-
-        // Rust highlight query - simplified for demonstration
-        let rust_query = Query::new(&rust_language.into(), tree_sitter_rust::HIGHLIGHTS_QUERY)?;
-        queries.insert(rust_language.into(), rust_query);
+        // Register every compiled-in grammar under its fence tags
+        for grammar in languages::grammars() {
+            let query = Query::new(&grammar.language.into(), &grammar.highlights)?;
+            queries.insert(grammar.language.into(), query);
 
-        // Add other languages as needed
-        // ... (Python, JavaScript, etc.)
+            for name in grammar.names {
+                languages.insert(name.to_string(), grammar.language);
+            }
+        }
 
         let md_code_block_regex = Regex::new(r"(?m)^```([\w\+\-]+)").unwrap();
 
         Ok(Self {
             parser: RefCell::new(parser),
+            blocks: RefCell::new(Vec::new()),
             languages,
             queries,
             md_code_block_regex,
+            markdown: markdown::MarkdownRules::new(),
         })
     }
 
@@ -92,7 +192,8 @@ impl SyntaxHighlighter {
         self.languages.get(extension)
     }
 
-    fn extract_code_blocks(&self, text: &str) -> Vec<CodeBlock> {
+    /// Fenced code blocks of a markdown text, in order
+    pub fn extract_code_blocks(&self, text: &str) -> Vec<CodeBlock> {
         let mut blocks = Vec::new();
         let lines: Vec<&str> = text.lines().collect();
         let mut in_code_block = false;
@@ -146,6 +247,101 @@ impl SyntaxHighlighter {
         blocks
     }
 
+    /// Records an edit of the buffer, before it's applied. `start..old_end` are
+    /// the replaced bytes and `text` what replaces them. Cached trees inside the
+    /// edit get a tree-sitter `InputEdit`, blocks after it are shifted.
+    pub fn edit(&self, start: usize, old_end: usize, text: &str) {
+        let new_end = start + text.len();
+        let mut blocks = self.blocks.borrow_mut();
+
+        blocks.retain_mut(|block| {
+            let code_end = block.start + block.code.len();
+
+            if old_end <= block.start && start < block.start {
+                // Before the block, only its position changes
+                block.start = block.start + new_end - old_end;
+                true
+            } else if start >= block.start && old_end <= code_end {
+                block.edit(start - block.start, old_end - block.start, text);
+                true
+            } else {
+                // After the block it's unaffected, across a fence we reparse
+                start > code_end
+            }
+        });
+    }
+
+    /// Byte of the bracket matching the one at `byte`, taken from the parse
+    /// tree of the code block around it. `None` outside parsed blocks and for
+    /// brackets that aren't tokens, e.g. inside strings.
+    pub fn matching_bracket(&self, byte: usize) -> Option<usize> {
+        let blocks = self.blocks.borrow();
+        let block = blocks
+            .iter()
+            .find(|b| b.start <= byte && byte < b.start + b.code.len())?;
+
+        let offset = byte - block.start;
+        let node = block
+            .tree
+            .root_node()
+            .descendant_for_byte_range(offset, offset + 1)?;
+        let (partner, forward) = match node.kind() {
+            "(" => (")", true),
+            "[" => ("]", true),
+            "{" => ("}", true),
+            ")" => ("(", false),
+            "]" => ("[", false),
+            "}" => ("{", false),
+            _ => return None,
+        };
+
+        // Pairs are siblings, nested ones sit in child nodes
+        let parent = node.parent()?;
+        let mut cursor = parent.walk();
+        let siblings: Vec<_> = parent.children(&mut cursor).collect();
+        let at = siblings.iter().position(|n| n.id() == node.id())?;
+        let found = if forward {
+            siblings[at + 1..].iter().find(|n| n.kind() == partner)
+        } else {
+            siblings[..at].iter().rev().find(|n| n.kind() == partner)
+        };
+
+        found.map(|n| block.start + n.start_byte())
+    }
+
+    /// Bytes of the innermost function, impl, class or similar definition
+    /// around `byte` that spans several lines, from the parse tree of its
+    /// code block
+    pub fn foldable_range(&self, byte: usize) -> Option<Range<usize>> {
+        self.definition_around(byte, true)
+    }
+
+    /// Bytes of the innermost definition around `byte`, one-liners too
+    pub fn item_range(&self, byte: usize) -> Option<Range<usize>> {
+        self.definition_around(byte, false)
+    }
+
+    fn definition_around(&self, byte: usize, multiline_only: bool) -> Option<Range<usize>> {
+        let blocks = self.blocks.borrow();
+        let block = blocks
+            .iter()
+            .find(|b| b.start <= byte && byte <= b.start + b.code.len())?;
+
+        let offset = (byte - block.start).min(block.code.len());
+        let mut node = block
+            .tree
+            .root_node()
+            .descendant_for_byte_range(offset, offset);
+        while let Some(current) = node {
+            let multiline = current.start_position().row < current.end_position().row;
+            if (multiline || !multiline_only) && is_foldable(current.kind()) {
+                return Some(block.start + current.start_byte()..block.start + current.end_byte());
+            }
+            node = current.parent();
+        }
+        None
+    }
+
     pub fn highlight_buffer(
         &self,
         buffer: &Rope,
@@ -156,67 +352,113 @@ impl SyntaxHighlighter {
         // For Markdown, extract all code blocks with language and positions
         let code_blocks = self.extract_code_blocks(&text);
 
+        let mut cached = std::mem::take(&mut *self.blocks.borrow_mut());
+        let mut parsed = Vec::new();
         let mut highlights = Vec::new();
 
         // Highlight inside each code block
         for block in code_blocks {
             // Check if we have this language registered
-            if let Some(lang_fn) = self.languages.get(&block.language) {
-                // Setup parser
-                let mut parser = self.parser.borrow_mut();
-                if parser.set_language(&(*lang_fn).into()).is_err() {
-                    continue; // skip unknown languages
-                }
-
-                // Parse the code block
-                if let Some(tree) = parser.parse(&block.code, None) {
-                    if let Some(query) = self.queries.get(&(*lang_fn).into()) {
-                        let mut cursor = QueryCursor::new();
-
-                        let mut matches =
-                            cursor.matches(query, tree.root_node(), block.code.as_bytes());
-
-                        while let Some(match_) = matches.next() {
-                            for capture in match_.captures {
-                                let node = capture.node;
-                                if node.start_byte() == node.end_byte() {
-                                    continue;
-                                }
-                                let range = node.start_byte()..node.end_byte();
-
-                                let style = match query.capture_names()[capture.index as usize] {
-                                    "keyword" => Style::Keyword,
-                                    "function" | "function.macro" => Style::Function,
-                                    "type" => Style::Type,
-                                    "string" => Style::String,
-                                    "number" => Style::Number,
-                                    "comment" => Style::Comment,
-                                    "variable" | "variable.field" | "variable.builtin" => {
-                                        Style::Variable
-                                    }
-                                    "constant" => Style::Constant,
-                                    "operator" => Style::Operator,
-                                    _ => Style::Normal,
-                                };
-
-                                // Shift ranges by code block start offset
-                                let adjusted_range =
-                                    (range.start + block.start)..(range.end + block.start);
-
-                                highlights.push((adjusted_range, style));
-                            }
-                        }
-                    }
-                }
-            }
-            // else maybe treat as normal text
+            let Some(lang_fn) = self.languages.get(&block.language) else {
+                continue; // else maybe treat as normal text
+            };
+            let Some(query) = self.queries.get(&(*lang_fn).into()) else {
+                continue;
+            };
+
+            // A cached tree is only reused when the edits we were told about
+            // add up to the same code, otherwise parse from scratch
+            let old = cached
+                .iter()
+                .position(|c| {
+                    c.start == block.start && c.language == block.language && c.code == block.code
+                })
+                .map(|i| cached.swap_remove(i));
+
+            let tree = match old {
+                Some(old) if old.edited.is_empty() => old,
+                Some(old) => match self.reparse(lang_fn, query, old) {
+                    Some(tree) => tree,
+                    None => continue,
+                },
+                None => match self.parse(lang_fn, query, block) {
+                    Some(tree) => tree,
+                    None => continue,
+                },
+            };
+
+            // Shift ranges by code block start offset
+            highlights.extend(tree.highlights.iter().map(|(range, style)| {
+                ((range.start + tree.start)..(range.end + tree.start), *style)
+            }));
+            parsed.push(tree);
         }
 
-        // Optionally add highlighting for Markdown syntax outside code blocks
+        *self.blocks.borrow_mut() = parsed;
+
+        // Markdown syntax outside code blocks
+        highlights.extend(self.markdown.highlight(&text));
 
         highlights
     }
 
+    fn parse(&self, lang_fn: &LanguageFn, query: &Query, block: CodeBlock) -> Option<BlockTree> {
+        let mut parser = self.parser.borrow_mut();
+        parser.set_language(&(*lang_fn).into()).ok()?;
+
+        let tree = parser.parse(&block.code, None)?;
+        let highlights = run_query(query, &tree, &block.code, 0..block.code.len());
+
+        Some(BlockTree {
+            language: block.language,
+            start: block.start,
+            code: block.code,
+            tree,
+            highlights,
+            edited: Vec::new(),
+        })
+    }
+
+    // Parses again reusing the edited tree, and re-runs the query only where
+    // the syntax changed or text was typed
+    fn reparse(
+        &self,
+        lang_fn: &LanguageFn,
+        query: &Query,
+        mut block: BlockTree,
+    ) -> Option<BlockTree> {
+        let mut parser = self.parser.borrow_mut();
+        parser.set_language(&(*lang_fn).into()).ok()?;
+
+        let tree = parser.parse(&block.code, Some(&block.tree))?;
+
+        let mut ranges: Vec<Range<usize>> = block
+            .tree
+            .changed_ranges(&tree)
+            .map(|r| r.start_byte..r.end_byte)
+            .collect();
+        ranges.append(&mut block.edited);
+
+        for range in ranges {
+            // Whole lines, so tokens cut by the edit are captured again
+            let range = line_bounds(&block.code, range);
+            let (range, captures) = capture_span(query, &tree, &block.code, range);
+
+            block
+                .highlights
+                .retain(|(h, _)| h.end <= range.start || h.start >= range.end);
+            block.highlights.extend(captures);
+        }
+
+        block
+            .highlights
+            .sort_by_key(|(range, _)| (range.start, range.end));
+        block.highlights.dedup();
+        block.tree = tree;
+
+        Some(block)
+    }
+
     // Adjust highlight ranges to account for code block position in Markdown
     fn adjust_range_for_code_block(&self, text: &str, range: Range<usize>) -> Range<usize> {
         let lines: Vec<&str> = text.lines().collect();
@@ -257,6 +499,112 @@ impl SyntaxHighlighter {
     }
 }
 
+// Definitions across grammars: `function_item` and `impl_item` in Rust,
+// `function_definition` and `class_definition` in Python, `method_declaration`
+// in Java and so on
+fn is_foldable(kind: &str) -> bool {
+    const KINDS: &[&str] = &[
+        "function", "method", "impl", "class", "struct", "enum", "trait", "interface", "module",
+        "mod_item",
+    ];
+    !kind.ends_with("_expression")
+        && !kind.ends_with("_type")
+        && KINDS.iter().any(|name| kind.contains(name))
+}
+
+// Grammars name captures differently (`function.method`, `constant.builtin`,
+// ...), so only the part before the first dot picks the style
+fn capture_style(name: &str) -> Style {
+    match name.split('.').next().unwrap_or(name) {
+        "keyword" => Style::Keyword,
+        "function" | "constructor" => Style::Function,
+        "type" => Style::Type,
+        "string" => Style::String,
+        "number" | "float" => Style::Number,
+        "comment" => Style::Comment,
+        "variable" | "property" => Style::Variable,
+        "constant" | "boolean" => Style::Constant,
+        "operator" => Style::Operator,
+        _ => Style::Normal,
+    }
+}
+
+// Captures of `query` for nodes intersecting `range`
+fn run_query(
+    query: &Query,
+    tree: &Tree,
+    code: &str,
+    range: Range<usize>,
+) -> Vec<(Range<usize>, Style)> {
+    let mut cursor = QueryCursor::new();
+    cursor.set_byte_range(range);
+
+    let mut highlights = Vec::new();
+    let mut matches = cursor.matches(query, tree.root_node(), code.as_bytes());
+
+    while let Some(match_) = matches.next() {
+        for capture in match_.captures {
+            let node = capture.node;
+            if node.start_byte() == node.end_byte() {
+                continue;
+            }
+
+            let style = capture_style(query.capture_names()[capture.index as usize]);
+            highlights.push((node.start_byte()..node.end_byte(), style));
+        }
+    }
+
+    highlights
+}
+
+// Runs the query on `range`, widened until it covers every node it captures,
+// so the caller can replace all old highlights inside the returned range
+fn capture_span(
+    query: &Query,
+    tree: &Tree,
+    code: &str,
+    mut range: Range<usize>,
+) -> (Range<usize>, Vec<(Range<usize>, Style)>) {
+    loop {
+        let captures = run_query(query, tree, code, range.clone());
+
+        let start = captures
+            .iter()
+            .map(|(r, _)| r.start)
+            .fold(range.start, usize::min);
+        let end = captures
+            .iter()
+            .map(|(r, _)| r.end)
+            .fold(range.end, usize::max);
+
+        if start == range.start && end == range.end {
+            return (range, captures);
+        }
+        range = start..end;
+    }
+}
+
+// `range` grown to the start and end of the lines it touches
+fn line_bounds(code: &str, range: Range<usize>) -> Range<usize> {
+    let range = range.start.min(code.len())..range.end.min(code.len());
+    let start = code[..range.start].rfind('\n').map(|i| i + 1).unwrap_or(0);
+    let end = code[range.end..]
+        .find('\n')
+        .map(|i| range.end + i)
+        .unwrap_or(code.len());
+
+    start..end
+}
+
+// Row/column (in bytes) of a byte offset, as tree-sitter wants it
+fn point_at(code: &str, byte: usize) -> Point {
+    let before = &code[..byte];
+    let row = before.matches('\n').count();
+    let column = before.rfind('\n').map(|i| byte - i - 1).unwrap_or(byte);
+
+    Point::new(row, column)
+}
+
 // pub fn highlight_bufferv1(
 //     &self,
 //     buffer: &Rope,